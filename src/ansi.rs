@@ -0,0 +1,47 @@
+use std::fmt;
+
+// The VT sequences scip writes to the terminal. Terminals on Unix and Windows 10 and later
+// take them alike, so they don't depend on the terminal backend.
+
+pub const INVERT: &str = "\x1b[7m";
+pub const NO_INVERT: &str = "\x1b[27m";
+pub const FAINT: &str = "\x1b[2m";
+// all attributes and colors
+pub const RESET: &str = "\x1b[m";
+pub const FG_RESET: &str = "\x1b[39m";
+pub const CLEAR_ALL: &str = "\x1b[2J";
+pub const CLEAR_LINE: &str = "\x1b[2K";
+// DECSTR, the modes, attributes and scrolling region to their defaults without clearing
+pub const SOFT_RESET: &str = "\x1b[!p";
+// G0 as ASCII and shifted in, undoing a line drawing set left selected
+pub const ASCII_CHARSET: &str = "\x1b(B\x0f";
+pub const SHOW_CURSOR: &str = "\x1b[?25h";
+pub const TO_ALTERNATE_SCREEN: &str = "\x1b[?1049h";
+pub const TO_MAIN_SCREEN: &str = "\x1b[?1049l";
+
+// The foreground color with this index of the 8 basic ones, 0 is black and 7 white
+pub struct Fg(pub u8);
+
+impl fmt::Display for Fg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\x1b[38;5;{}m", self.0)
+    }
+}
+
+// Move the cursor to a column and row, both counted from 1
+pub struct Goto(pub u16, pub u16);
+
+impl fmt::Display for Goto {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\x1b[{};{}H", self.1, self.0)
+    }
+}
+
+// Move the cursor up this many rows
+pub struct Up(pub u16);
+
+impl fmt::Display for Up {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "\x1b[{}A", self.0)
+    }
+}
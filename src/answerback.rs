@@ -0,0 +1,187 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::term;
+
+const ESC: u8 = 0x1b;
+
+// A CSI sequence this long without its final byte isn't one of the queries
+const MAX_SEQUENCE: usize = 32;
+
+// The size told when there is no terminal to take it from, rows and columns
+const DEFAULT_SIZE: (u16, u16) = (24, 80);
+
+// What DA is answered with, a VT100 with advanced video
+const DEVICE_ATTRIBUTES: &[u8] = b"\x1b[?1;2c";
+
+// Set by the signal thread on SIGWINCH
+static RESIZED: AtomicBool = AtomicBool::new(false);
+
+// The size is read again for the next reply
+pub fn terminal_resized() {
+    RESIZED.store(true, Ordering::Relaxed);
+}
+
+// --answerback: answers the status, cursor position, device attributes and window size
+// queries of the device in place of the terminal, which doesn't get to see them. The cursor
+// position comes from following the output, what the size is from the terminal or --term-size.
+pub struct Answerback {
+    // --term-size, rows and columns
+    term_size: Option<(u16, u16)>,
+    // the terminal size last read, rows and columns
+    size: Option<(u16, u16)>,
+    // an escape sequence that a read ended in, it may still become a query
+    pending: Vec<u8>,
+    // row and column, from 1, the column is one past the last one while a wrap is due
+    cursor: (u16, u16),
+    saved: (u16, u16),
+}
+
+impl Answerback {
+    pub fn new(term_size: Option<(u16, u16)>) -> Answerback {
+        Answerback {
+            term_size,
+            size: None,
+            pending: Vec::new(),
+            cursor: (1, 1),
+            saved: (1, 1),
+        }
+    }
+
+    // Take the queries out of `data`, returning what is left to show and the replies. The
+    // terminal keeps `footer_rows` rows of its own below the output.
+    pub fn feed(&mut self, data: &[u8], footer_rows: u16) -> (Vec<u8>, Vec<u8>) {
+        let size = self.size(footer_rows);
+        let mut shown = Vec::with_capacity(data.len());
+        let mut replies = Vec::new();
+        for &byte in data {
+            if self.pending.is_empty() {
+                if byte == ESC {
+                    self.pending.push(byte);
+                } else {
+                    self.advance(byte, size);
+                    shown.push(byte);
+                }
+                continue;
+            }
+            self.pending.push(byte);
+            if self.pending.len() == 2 {
+                if byte != b'[' {
+                    self.escape(byte);
+                    shown.append(&mut self.pending);
+                }
+                continue;
+            }
+            match byte {
+                // parameters and intermediates
+                0x20..=0x3f if self.pending.len() < MAX_SEQUENCE => {}
+                0x40..=0x7e => {
+                    let params = String::from_utf8_lossy(&self.pending[2..self.pending.len() - 1])
+                        .into_owned();
+                    match self.reply(&params, byte, size) {
+                        Some(reply) => {
+                            replies.extend_from_slice(&reply);
+                            self.pending.clear();
+                        }
+                        None => {
+                            self.csi(&params, byte, size);
+                            shown.append(&mut self.pending);
+                        }
+                    }
+                }
+                // not a sequence the terminal would take
+                _ => shown.append(&mut self.pending),
+            }
+        }
+        (shown, replies)
+    }
+
+    // Rows and columns, --term-size is told as it is
+    fn size(&mut self, footer_rows: u16) -> (u16, u16) {
+        if let Some(size) = self.term_size {
+            return size;
+        }
+        // without SIGWINCH it is read for every read of the port
+        if RESIZED.swap(false, Ordering::Relaxed) || cfg!(not(unix)) {
+            self.size = None;
+        }
+        let (rows, cols) = *self
+            .size
+            .get_or_insert_with(|| match term::backend().size() {
+                Ok((cols, rows)) if cols > 0 && rows > 0 => (rows, cols),
+                _ => DEFAULT_SIZE,
+            });
+        (rows.saturating_sub(footer_rows).max(1), cols)
+    }
+
+    fn reply(&self, params: &str, end: u8, (rows, cols): (u16, u16)) -> Option<Vec<u8>> {
+        let (row, col) = (self.cursor.0.min(rows), self.cursor.1.min(cols));
+        let reply = match (params, end) {
+            // DSR, the device status
+            ("5", b'n') => "\x1b[0n".to_string(),
+            // CPR, the cursor position, and the DEC form of it
+            ("6", b'n') => format!("\x1b[{};{}R", row, col),
+            ("?6", b'n') => format!("\x1b[?{};{}R", row, col),
+            ("" | "0", b'c') => return Some(DEVICE_ATTRIBUTES.to_vec()),
+            // xterm window operations, the text area and the screen in characters
+            ("18", b't') => format!("\x1b[8;{};{}t", rows, cols),
+            ("19", b't') => format!("\x1b[9;{};{}t", rows, cols),
+            _ => return None,
+        };
+        Some(reply.into_bytes())
+    }
+
+    // Follow a shown byte that isn't part of an escape sequence
+    fn advance(&mut self, byte: u8, (rows, cols): (u16, u16)) {
+        let (row, col) = &mut self.cursor;
+        match byte {
+            b'\r' => *col = 1,
+            b'\n' | 0x0b | 0x0c => *row = (*row + 1).min(rows),
+            0x08 => *col = col.saturating_sub(1).clamp(1, cols),
+            b'\t' => *col = ((*col - 1) / 8 * 8 + 9).min(cols),
+            // UTF-8 continuation bytes take no column of their own
+            0x00..=0x1f | 0x7f | 0x80..=0xbf => {}
+            _ => {
+                if *col > cols {
+                    *row = (*row + 1).min(rows);
+                    *col = 1;
+                }
+                *col += 1;
+            }
+        }
+    }
+
+    // ESC followed by `byte`
+    fn escape(&mut self, byte: u8) {
+        match byte {
+            b'7' => self.saved = self.cursor,
+            b'8' => self.cursor = self.saved,
+            b'c' => self.cursor = (1, 1),
+            _ => {}
+        }
+    }
+
+    // The cursor movements among the sequences passed on
+    fn csi(&mut self, params: &str, end: u8, (rows, cols): (u16, u16)) {
+        if end == b's' || end == b'u' {
+            return self.escape(if end == b's' { b'7' } else { b'8' });
+        }
+        let mut values = params
+            .split(';')
+            .map(|value| value.parse::<u16>().unwrap_or(0));
+        let mut next = || values.next().unwrap_or(0).max(1);
+        let (row, col) = &mut self.cursor;
+        match end {
+            b'H' | b'f' => {
+                *row = next().min(rows);
+                *col = next().min(cols);
+            }
+            b'A' => *row = row.saturating_sub(next()).max(1),
+            b'B' => *row = row.saturating_add(next()).min(rows),
+            b'C' => *col = col.saturating_add(next()).min(cols),
+            b'D' => *col = (*col).min(cols).saturating_sub(next()).max(1),
+            b'G' => *col = next().min(cols),
+            b'd' => *row = next().min(rows),
+            _ => {}
+        }
+    }
+}
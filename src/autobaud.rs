@@ -0,0 +1,112 @@
+use std::io;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+use crate::transport::Transport;
+use crate::Event;
+
+// How long the port is listened to at each baud rate
+pub const LISTEN_TIME: Duration = Duration::from_millis(500);
+// The share of readable bytes a rate needs to be taken
+pub const MIN_SCORE: f64 = 0.8;
+// Fewer bytes than this say nothing about the rate
+const MIN_BYTES: usize = 4;
+
+// The rate that was found and the share of what it received that was readable
+pub struct Detected {
+    pub baud_rate: u32,
+    pub score: f64,
+}
+
+// Step through `rates`, sending `probe` at each and scoring what the reader with the id
+// `reader_id` gets within LISTEN_TIME. Other events are put back for the main loop, a
+// SIGINT ends the search. The port is left at the last rate tried.
+pub fn detect(
+    port: &mut dyn Transport,
+    events: (&Sender<Event>, &Receiver<Event>),
+    reader_id: u64,
+    rates: &[u32],
+    probe: &[u8],
+) -> io::Result<Option<Detected>> {
+    let (tx, rx) = events;
+    let mut other = Vec::new();
+    let mut best: Option<Detected> = None;
+    'rates: for &baud_rate in rates {
+        port.set_baud_rate(baud_rate)?;
+        // what is still queued was received at the rate before
+        while let Ok(event) = rx.try_recv() {
+            match event {
+                Event::Serial(..) | Event::ReceiveErrors(..) => {}
+                Event::SerialError(id, err) if id == reader_id => return Err(err),
+                event => other.push(event),
+            }
+        }
+        if !probe.is_empty() {
+            port.write_all(probe)?;
+            port.flush()?;
+        }
+
+        let mut received = Vec::new();
+        let deadline = Instant::now() + LISTEN_TIME;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match rx.recv_timeout(left) {
+                Ok(Event::Serial(id, data, _)) if id == reader_id => {
+                    received.extend_from_slice(&data)
+                }
+                Ok(Event::SerialError(id, err)) if id == reader_id => return Err(err),
+                // the rates tried first are wrong ones
+                Ok(Event::ReceiveErrors(..)) => {}
+                Ok(Event::Interrupted) => {
+                    other.push(Event::Interrupted);
+                    break 'rates;
+                }
+                Ok(event) => other.push(event),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => break 'rates,
+            }
+        }
+        if received.len() < MIN_BYTES {
+            continue;
+        }
+        let score = readable_share(&received);
+        if best.as_ref().is_none_or(|best| score > best.score) {
+            best = Some(Detected { baud_rate, score });
+        }
+    }
+    for event in other {
+        let _ = tx.send(event);
+    }
+    Ok(best.filter(|best| best.score >= MIN_SCORE))
+}
+
+// The share of `data` that is printable ASCII, whitespace or valid UTF-8. At a wrong rate
+// the bytes come out as random ones, with control characters and broken sequences.
+fn readable_share(data: &[u8]) -> f64 {
+    let mut readable = 0;
+    let mut rest = data;
+    while !rest.is_empty() {
+        match std::str::from_utf8(rest) {
+            Ok(text) => {
+                readable += count_readable(text);
+                break;
+            }
+            Err(err) => {
+                let valid = err.valid_up_to();
+                // valid_up_to() ends on a char boundary
+                readable += count_readable(std::str::from_utf8(&rest[..valid]).unwrap_or(""));
+                let skip = err.error_len().unwrap_or(rest.len() - valid);
+                rest = &rest[valid + skip..];
+            }
+        }
+    }
+    readable as f64 / data.len() as f64
+}
+
+// The bytes of the readable chars of `text`
+fn count_readable(text: &str) -> usize {
+    text.chars()
+        .filter(|&c| !c.is_control() || matches!(c, '\r' | '\n' | '\t' | '\x1b'))
+        .map(char::len_utf8)
+        .sum()
+}
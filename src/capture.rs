@@ -0,0 +1,50 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// A verbatim copy of received data, started and stopped with ~w and ~W
+pub struct Capture {
+    path: PathBuf,
+    // not flushed per chunk so it keeps up with fast dumps
+    writer: BufWriter<File>,
+    bytes: u64,
+}
+
+impl Capture {
+    pub fn create(path: &str) -> io::Result<Capture> {
+        let path = PathBuf::from(path);
+        let file = File::create(&path)?;
+        Ok(Capture {
+            path,
+            writer: BufWriter::with_capacity(64 * 1024, file),
+            bytes: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // The bytes exactly as the port delivered them
+    pub fn write_rx(&mut self, data: &[u8]) -> io::Result<()> {
+        self.writer.write_all(data)?;
+        self.bytes += data.len() as u64;
+        Ok(())
+    }
+
+    // A ~m marker on a line of its own, not counted as captured
+    pub fn write_marker(&mut self, line: &str) -> io::Result<()> {
+        write!(self.writer, "\n{}\n", line)?;
+        self.writer.flush()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    // Returns how many bytes were captured
+    pub fn close(mut self) -> io::Result<u64> {
+        self.writer.flush()?;
+        Ok(self.bytes)
+    }
+}
@@ -0,0 +1,493 @@
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use clap::ArgMatches;
+use regex::bytes::Regex;
+
+use crate::reset::{ResetSequence, ResetStep};
+use crate::transfer::pause;
+use crate::txhold::{self, HeldKeys};
+use crate::{
+    display_received, interrupted, next_event, port_error, run_reset, stdin_error,
+    write_to_serial_port, Event, NextStep, Screen, Session,
+};
+
+// Received data kept around for matching, older data is dropped
+const MAX_BUFFERED: usize = 64 * 1024;
+
+// How long a \p in a sent string waits
+const PAUSE: Duration = Duration::from_secs(1);
+
+pub enum Pattern {
+    Substring(Vec<u8>),
+    Regex(Regex),
+    // what ChatStep::Listen waits for
+    Never,
+}
+
+impl Pattern {
+    // The end of the first match in `data`
+    fn find(&self, data: &[u8]) -> Option<usize> {
+        match self {
+            Pattern::Substring(needle) if needle.is_empty() => Some(0),
+            Pattern::Substring(needle) => data
+                .windows(needle.len())
+                .position(|window| window == needle.as_slice())
+                .map(|start| start + needle.len()),
+            Pattern::Regex(regex) => regex.find(data).map(|found| found.end()),
+            Pattern::Never => None,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Pattern::Substring(needle) => format!("'{}'", String::from_utf8_lossy(needle)),
+            Pattern::Regex(regex) => format!("/{}/", regex.as_str()),
+            Pattern::Never => "the timeout".to_string(),
+        }
+    }
+}
+
+pub enum SendPart {
+    Bytes(Vec<u8>),
+    Pause,
+    // the Enter key, translated by --newline
+    Enter,
+}
+
+pub enum ChatStep {
+    // with a timeout of its own or that of --expect-timeout
+    Expect(Pattern, Option<Duration>),
+    Send(Vec<SendPart>),
+    // show what the device sends until the timeout passes, after a last --command
+    Listen,
+    // the steps of the startup actions of a profile
+    Reset(ResetSequence),
+    Line(ResetStep),
+}
+
+pub enum ChatError {
+    Timeout(String),
+    // setting DTR or RTS failed
+    Failed(String),
+    Cancelled,
+    Disconnected,
+    // the main loop has to handle this step, the error was already shown
+    Stopped(NextStep),
+}
+
+// The options that make up the chat script, by the name of their argument
+const SCRIPT_OPTIONS: [&str; 5] = ["expect", "expect-regex", "send", "command", "until"];
+
+// An option of the chat script as given, `name` is one of SCRIPT_OPTIONS
+#[derive(Clone, Debug)]
+pub struct ScriptArg {
+    pub name: &'static str,
+    pub value: String,
+}
+
+// --expect, --expect-regex, --send, --command and --until in the order they were given
+pub fn script_args(matches: &ArgMatches) -> Vec<ScriptArg> {
+    let mut args: Vec<(usize, ScriptArg)> = Vec::new();
+    for name in SCRIPT_OPTIONS {
+        let (values, indices) = match (matches.values_of(name), matches.indices_of(name)) {
+            (Some(values), Some(indices)) => (values, indices),
+            _ => continue,
+        };
+        for (value, index) in values.zip(indices) {
+            let value = value.to_string();
+            args.push((index, ScriptArg { name, value }));
+        }
+    }
+    args.sort_by_key(|(index, _)| *index);
+    args.into_iter().map(|(_, arg)| arg).collect()
+}
+
+// The steps of the chat script, from the options in the order given
+pub fn script(args: &[ScriptArg]) -> Result<Vec<ChatStep>, String> {
+    let mut steps: Vec<ChatStep> = Vec::with_capacity(args.len() + 1);
+    for arg in args {
+        let (name, value) = (arg.name, arg.value.as_str());
+        let flag = format!("--{}", name);
+        let step = match name {
+            "expect" | "until" => {
+                ChatStep::Expect(Pattern::Substring(parse_escapes(value, &flag)?), None)
+            }
+            "expect-regex" => ChatStep::Expect(
+                Pattern::Regex(
+                    Regex::new(value).map_err(|err| format!("in {} {}: {}", flag, value, err))?,
+                ),
+                None,
+            ),
+            "command" => {
+                let mut parts = parse_send(value, &flag)?;
+                parts.push(SendPart::Enter);
+                ChatStep::Send(parts)
+            }
+            _ => ChatStep::Send(parse_send(value, &flag)?),
+        };
+        steps.push(step);
+    }
+    // the reply to a --command without --until is shown until the timeout
+    let command = args.iter().any(|arg| arg.name == "command");
+    if command && matches!(steps.last(), Some(ChatStep::Send(_))) {
+        steps.push(ChatStep::Listen);
+    }
+    Ok(steps)
+}
+
+// The startup = [...] list of a profile: reset:PROFILE, send:STRING, expect:REGEX[:TIMEOUT_MS],
+// dtr:0|1, rts:0|1 and sleep:MS. `dtr` and `rts` are the levels a reset leaves the lines at.
+pub fn startup_script(actions: &[String], dtr: bool, rts: bool) -> Result<Vec<ChatStep>, String> {
+    actions
+        .iter()
+        .enumerate()
+        .map(|(i, action)| {
+            startup_step(action, dtr, rts)
+                .map_err(|err| format!("in startup step {} {}: {}", i + 1, action, err))
+        })
+        .collect()
+}
+
+fn startup_step(action: &str, dtr: bool, rts: bool) -> Result<ChatStep, String> {
+    let level = |value: &str| match value {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err("the level has to be 0 or 1".to_string()),
+    };
+    let step = match action.split_once(':') {
+        Some(("reset", profile)) => ChatStep::Reset(ResetSequence::parse(profile, dtr, rts)?),
+        Some(("send", value)) => ChatStep::Send(parse_send(value, "send")?),
+        Some(("expect", value)) => {
+            // a regex ending in :DIGITS needs the timeout after it
+            let (regex, timeout) = match value.rsplit_once(':') {
+                Some((regex, ms)) => match ms.parse::<u64>() {
+                    Ok(ms) => (regex, Some(Duration::from_millis(ms))),
+                    Err(_) => (value, None),
+                },
+                None => (value, None),
+            };
+            let regex = Regex::new(regex).map_err(|err| err.to_string())?;
+            ChatStep::Expect(Pattern::Regex(regex), timeout)
+        }
+        Some(("dtr", value)) => ChatStep::Line(ResetStep::Dtr(level(value)?)),
+        Some(("rts", value)) => ChatStep::Line(ResetStep::Rts(level(value)?)),
+        Some(("sleep", ms)) => match ms.parse::<u64>() {
+            Ok(ms) => ChatStep::Line(ResetStep::Sleep(Duration::from_millis(ms))),
+            Err(_) => return Err("expected milliseconds".to_string()),
+        },
+        _ => {
+            return Err(
+                "expected reset:PROFILE, send:STRING, expect:REGEX[:TIMEOUT_MS], dtr:0|1, \
+                 rts:0|1 or sleep:MS"
+                    .to_string(),
+            )
+        }
+    };
+    Ok(step)
+}
+
+fn parse_send(value: &str, option: &str) -> Result<Vec<SendPart>, String> {
+    let mut parts = Vec::new();
+    for (i, piece) in split_pauses(value).into_iter().enumerate() {
+        if i > 0 {
+            parts.push(SendPart::Pause);
+        }
+        let bytes = parse_escapes(&piece, option)?;
+        if !bytes.is_empty() {
+            parts.push(SendPart::Bytes(bytes));
+        }
+    }
+    Ok(parts)
+}
+
+// Split at every \p that isn't itself escaped as \\p
+fn split_pauses(value: &str) -> Vec<String> {
+    let mut pieces = vec![String::new()];
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            pieces.last_mut().unwrap().push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('p') => pieces.push(String::new()),
+            Some(next) => {
+                let piece = pieces.last_mut().unwrap();
+                piece.push('\\');
+                piece.push(next);
+            }
+            None => pieces.last_mut().unwrap().push('\\'),
+        }
+    }
+    pieces
+}
+
+// Replace \r, \n, \t, \\ and \xNN, `option` is named in the errors
+pub fn parse_escapes(value: &str, option: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut utf8 = [0; 4];
+            bytes.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('r') => bytes.push(b'\r'),
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('\\') => bytes.push(b'\\'),
+            Some('x') => {
+                let hex: String = chars.by_ref().take(2).collect();
+                match u8::from_str_radix(&hex, 16) {
+                    Ok(byte) if hex.len() == 2 && hex.chars().all(|c| c.is_ascii_hexdigit()) => {
+                        bytes.push(byte)
+                    }
+                    _ => return Err(format!("in {} {}: \\x needs two hex digits", option, value)),
+                }
+            }
+            Some(other) => {
+                return Err(format!(
+                    "in {} {}: unknown escape \\{}",
+                    option, value, other
+                ))
+            }
+            None => return Err(format!("in {} {}: trailing \\", option, value)),
+        }
+    }
+    Ok(bytes)
+}
+
+// Run the script while showing what the device sends, keystrokes other than Ctrl-C are dropped.
+// A failure comes with the index of its step.
+pub fn run(
+    steps: &[ChatStep],
+    timeout: Duration,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> Result<(), (usize, ChatError)> {
+    session.chat_input = Some(Vec::new());
+    let result = run_steps(steps, timeout, session, screen, rx);
+    session.chat_input = None;
+    result
+}
+
+fn run_steps(
+    steps: &[ChatStep],
+    timeout: Duration,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> Result<(), (usize, ChatError)> {
+    for (i, step) in steps.iter().enumerate() {
+        run_step(step, timeout, session, screen, rx).map_err(|err| (i, err))?;
+    }
+    Ok(())
+}
+
+fn run_step(
+    step: &ChatStep,
+    timeout: Duration,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> Result<(), ChatError> {
+    match step {
+        ChatStep::Expect(pattern, own) => {
+            expect(pattern, own.unwrap_or(timeout), session, screen, rx)?
+        }
+        ChatStep::Listen => match expect(&Pattern::Never, timeout, session, screen, rx) {
+            Err(ChatError::Timeout(_)) => {}
+            result => result?,
+        },
+        ChatStep::Send(parts) => {
+            for part in parts {
+                match part {
+                    SendPart::Bytes(bytes) => match write_to_serial_port(session, screen, bytes) {
+                        NextStep::None => {}
+                        next_step => return Err(stopped(next_step)),
+                    },
+                    SendPart::Enter => {
+                        let enter = session.tx_newline.translate(b"\r");
+                        match write_to_serial_port(session, screen, &enter) {
+                            NextStep::None => {}
+                            next_step => return Err(stopped(next_step)),
+                        }
+                    }
+                    SendPart::Pause => sleep(PAUSE, session, screen, rx)?,
+                }
+            }
+        }
+        ChatStep::Reset(sequence) => match run_reset(sequence, session, screen, rx) {
+            NextStep::None => {}
+            next_step => return Err(stopped(next_step)),
+        },
+        ChatStep::Line(ResetStep::Dtr(level)) => {
+            let result = session.serial_port.write_data_terminal_ready(*level);
+            result.map_err(|err| ChatError::Failed(format!("setting DTR: {}", err)))?;
+            session.dtr = *level;
+        }
+        ChatStep::Line(ResetStep::Rts(level)) => {
+            let result = session.serial_port.write_request_to_send(*level);
+            result.map_err(|err| ChatError::Failed(format!("setting RTS: {}", err)))?;
+            session.rts = *level;
+        }
+        ChatStep::Line(ResetStep::Sleep(duration)) => sleep(*duration, session, screen, rx)?,
+    }
+    Ok(())
+}
+
+fn sleep(
+    duration: Duration,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> Result<(), ChatError> {
+    match pause(session, screen, rx, duration) {
+        Ok(false) => Ok(()),
+        Ok(true) => Err(ChatError::Cancelled),
+        Err(next_step) => Err(stopped(next_step)),
+    }
+}
+
+pub fn expect(
+    pattern: &Pattern,
+    timeout: Duration,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> Result<(), ChatError> {
+    let found = wait_for(
+        |buffer| pattern.find(buffer).map(|end| (end, ())),
+        timeout,
+        session,
+        screen,
+        rx,
+    )?;
+    found.ok_or_else(|| ChatError::Timeout(pattern.describe()))
+}
+
+// Show what arrives until `find` finds something in it, returning where that ends and what
+// it is, or None after the timeout. Later steps only look at what came after the end.
+pub fn wait_for<T>(
+    find: impl Fn(&[u8]) -> Option<(usize, T)>,
+    timeout: Duration,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> Result<Option<T>, ChatError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(buffer) = &mut session.chat_input {
+            if let Some((end, found)) = find(buffer) {
+                buffer.drain(..end);
+                return Ok(Some(found));
+            }
+            if buffer.len() > MAX_BUFFERED {
+                buffer.drain(..buffer.len() - MAX_BUFFERED);
+            }
+        }
+        match next_event(session, screen, rx, Some(deadline)) {
+            Some(Event::Serial(_, data, _)) => match display_received(session, screen, &data) {
+                NextStep::None => {}
+                next_step => return Err(stopped(next_step)),
+            },
+            Some(Event::Stdin(data)) => match txhold::handle_keys(session, screen, rx, &data) {
+                HeldKeys::Kept => {}
+                HeldKeys::Abort => return Err(ChatError::Cancelled),
+                HeldKeys::Stop(next_step) => return Err(stopped(next_step)),
+            },
+            Some(Event::SerialError(_, err)) => return Err(stopped(port_error(err))),
+            Some(Event::StdinError(err)) => return Err(stopped(stdin_error(err))),
+            Some(Event::Interrupted) => return Err(stopped(interrupted())),
+            Some(_) => {}
+            None => return Ok(None),
+        }
+    }
+}
+
+pub fn stopped(next_step: NextStep) -> ChatError {
+    match next_step {
+        NextStep::Disconnected => ChatError::Disconnected,
+        next_step => ChatError::Stopped(next_step),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_become_their_bytes() {
+        for (value, bytes) in [
+            ("", b"".as_slice()),
+            ("plain", b"plain"),
+            ("a\\rb\\nc\\td", b"a\rb\nc\td"),
+            ("back\\\\slash", b"back\\slash"),
+            ("\\x00\\x1b\\xFf\\x7e", b"\x00\x1b\xff~"),
+            ("\\x41\\x4a", b"AJ"),
+            ("\u{e9}", "\u{e9}".as_bytes()),
+        ] {
+            assert_eq!(
+                parse_escapes(value, "--send").as_deref(),
+                Ok(bytes),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn bad_escapes_name_the_option() {
+        for (value, msg) in [
+            ("\\x4", "in --expect \\x4: \\x needs two hex digits"),
+            ("a\\x", "in --expect a\\x: \\x needs two hex digits"),
+            ("\\xg1", "in --expect \\xg1: \\x needs two hex digits"),
+            ("\\x+1", "in --expect \\x+1: \\x needs two hex digits"),
+            ("ok\\", "in --expect ok\\: trailing \\"),
+            ("\\q", "in --expect \\q: unknown escape \\q"),
+        ] {
+            assert_eq!(
+                parse_escapes(value, "--expect"),
+                Err(msg.to_string()),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn only_an_unescaped_p_pauses() {
+        for (value, pieces) in [
+            ("abc", vec!["abc"]),
+            ("a\\pb", vec!["a", "b"]),
+            ("\\p\\p", vec!["", "", ""]),
+            ("a\\\\pb", vec!["a\\\\pb"]),
+            ("a\\\\\\pb", vec!["a\\\\", "b"]),
+            ("a\\rb", vec!["a\\rb"]),
+            ("end\\", vec!["end\\"]),
+        ] {
+            assert_eq!(split_pauses(value), pieces, "{}", value);
+        }
+    }
+
+    #[test]
+    fn a_send_pauses_between_its_parts() {
+        let parts = parse_send("\\x03\\p\\\\p\\r", "--send").unwrap();
+        assert!(matches!(
+            &parts[..],
+            [SendPart::Bytes(first), SendPart::Pause, SendPart::Bytes(second)]
+                if first == b"\x03" && second == b"\\p\r"
+        ));
+        assert!(matches!(
+            &parse_send("\\p", "--send").unwrap()[..],
+            [SendPart::Pause]
+        ));
+        assert_eq!(
+            parse_send("\\pa\\x", "--send").err().unwrap(),
+            "in --send a\\x: \\x needs two hex digits"
+        );
+    }
+}
@@ -0,0 +1,126 @@
+use std::io::{stdout, Write};
+
+use clap::App;
+use clap_complete::{generate, Shell};
+
+use crate::{EXIT_ERROR, PROGRAM_NAME};
+
+// Write the completion script for `shell` to stdout. clap_complete only knows the values
+// listed in the parser, so the device completion of bash, zsh and fish is patched in to
+// ask --complete-devices each time.
+pub fn write_script(shell: Shell, app: App) -> u8 {
+    if let Err(err) = stdout().write_all(script(shell, app).as_bytes()) {
+        eprintln!("Error writing the completion script: {}", err);
+        return EXIT_ERROR;
+    }
+    0
+}
+
+fn script(shell: Shell, mut app: App) -> String {
+    let mut script: Vec<u8> = Vec::new();
+    generate(shell, &mut app, PROGRAM_NAME, &mut script);
+    let mut script = String::from_utf8_lossy(&script).into_owned();
+    let devices = format!("{} --complete-devices 2>/dev/null", PROGRAM_NAME);
+    match shell {
+        Shell::Bash => {
+            script = script.replace("<DEVICE> ", &format!("$({}) ", devices));
+        }
+        Shell::Zsh => {
+            let function = format!("_{}_devices", PROGRAM_NAME);
+            script = script
+                .lines()
+                .map(|line| match line.strip_suffix(":' \\") {
+                    Some(start) if line.starts_with("'::device -- ") => {
+                        format!("{}:{}' \\", start, function)
+                    }
+                    _ => line.to_string(),
+                })
+                .collect::<Vec<String>>()
+                .join("\n");
+            // defined before the last line calls the completion function
+            let call = format!("\n_{} \"$@\"", PROGRAM_NAME);
+            let definition = format!(
+                "\n{}() {{\n    local -a devices\n    devices=(${{(f)\"$({})\"}})\n    \
+                 _alternative 'devices:serial port:compadd -a devices' 'files:file:_files'\n}}\n",
+                function, devices
+            );
+            match script.rfind(&call) {
+                Some(at) => script.insert_str(at, &definition),
+                None => script.push_str(&definition),
+            }
+            script.push('\n');
+        }
+        Shell::Fish => {
+            script.push_str(&format!(
+                "complete -c {} -n \"__fish_use_subcommand\" -a \"({})\" -d 'serial port'\n",
+                PROGRAM_NAME, devices
+            ));
+        }
+        _ => {}
+    }
+    script
+}
+
+// The port paths for completing the device, one per line. The ports are only listed,
+// none of them is opened.
+pub fn list_devices() -> u8 {
+    match serialport::available_ports() {
+        Ok(ports) => {
+            for port in ports {
+                println!("{}", port.port_name);
+            }
+            0
+        }
+        Err(_) => EXIT_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app;
+
+    #[test]
+    fn bash_asks_for_the_devices() {
+        let script = script(Shell::Bash, app());
+        assert!(script.contains("$(scip --complete-devices 2>/dev/null) "));
+        assert!(!script.contains("<DEVICE> "));
+        assert!(script.contains("--baud"));
+        assert!(script.contains("complete -F _scip"));
+    }
+
+    #[test]
+    fn zsh_defines_the_device_function_before_calling_the_completion() {
+        let script = script(Shell::Zsh, app());
+        assert!(script.contains(":_scip_devices' \\\n"));
+        let definition = script.find("\n_scip_devices() {").unwrap();
+        let call = script.rfind("\n_scip \"$@\"").unwrap();
+        assert!(definition < call);
+        assert!(script.contains("--complete-devices 2>/dev/null"));
+        assert!(script.ends_with('\n'));
+    }
+
+    #[test]
+    fn fish_completes_the_devices_as_the_first_argument() {
+        let script = script(Shell::Fish, app());
+        assert!(script.ends_with(
+            "complete -c scip -n \"__fish_use_subcommand\" \
+             -a \"(scip --complete-devices 2>/dev/null)\" -d 'serial port'\n"
+        ));
+        assert!(script.contains("-l baud"));
+    }
+
+    #[test]
+    fn the_other_shells_get_the_script_of_clap() {
+        for shell in [Shell::PowerShell, Shell::Elvish] {
+            let script = script(shell, app());
+            assert!(script.contains("scip"), "{}", shell);
+            assert!(script.contains("--baud"), "{}", shell);
+            assert!(
+                !script.contains("--complete-devices 2>/dev/null"),
+                "{}",
+                shell
+            );
+        }
+    }
+}
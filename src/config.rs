@@ -0,0 +1,674 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use clap::{App, ArgMatches, ArgSettings, ValueSource};
+use toml::Value;
+
+// Options that only make sense on the command line, the chat script steps
+// also need their order on the command line
+const COMMAND_LINE_ONLY: &[&str] = &[
+    "help",
+    "version",
+    "list",
+    "complete_devices",
+    "profile",
+    "no_config",
+    "show_config",
+    "config_origin",
+    "expect",
+    "expect_regex",
+    "send",
+    "command",
+    "until",
+    "startup_step",
+];
+
+pub fn config_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("scipio").join("config.toml"))
+}
+
+// Apply the config file to `app` so that its values act as defaults the command line and the
+// environment override. Flags in the config file are added to the returned arguments, and so
+// is where each setting came from, for --show-config.
+pub fn apply_config<'help>(
+    app: App<'help>,
+    args: Vec<OsString>,
+) -> Result<(App<'help>, Vec<OsString>), String> {
+    let (no_config, profile) = scan_args(&args);
+    if no_config {
+        return Ok((app, args));
+    }
+    let path = match config_path() {
+        Some(path) => path,
+        None => return Ok((app, args)),
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound && profile.is_none() => {
+            return Ok((app, args))
+        }
+        Err(err) => return Err(format!("{}: {}", path.display(), err)),
+    };
+    apply_text(
+        app,
+        args,
+        &text,
+        &path.display().to_string(),
+        profile.as_deref(),
+    )
+}
+
+// apply_config() with the text of the config file read from `file`
+fn apply_text<'help>(
+    app: App<'help>,
+    args: Vec<OsString>,
+    text: &str,
+    file: &str,
+    profile: Option<&str>,
+) -> Result<(App<'help>, Vec<OsString>), String> {
+    let table: toml::value::Table =
+        toml::from_str(text).map_err(|err| format!("{}: {}", file, err))?;
+    let settings = profile_settings(text, &table, profile, file)
+        .map_err(|err| format!("{}: {}", file, err))?;
+    let rules = profile_rules(&table, profile, file).map_err(|err| format!("{}: {}", file, err))?;
+    let log_colors = log_colors(&table).map_err(|err| format!("{}: {}", file, err))?;
+    let replies = auto_replies(&table).map_err(|err| format!("{}: {}", file, err))?;
+    // the startup list of the profile replaces that of [default]
+    let (startup, settings): (Vec<Setting>, Vec<Setting>) = settings
+        .into_iter()
+        .partition(|setting| setting.key == "startup");
+    let startup = match startup.last() {
+        Some(setting) => startup_steps(setting, file)?,
+        None => Vec::new(),
+    };
+    let (app, mut args, mut origins) = apply_settings(app, args, settings, file);
+    origins.extend(rules.iter().map(|_| format!("rule+={} [[rules]]", file)));
+    origins.extend(
+        replies
+            .iter()
+            .map(|_| format!("auto-respond+={} [autorespond]", file)),
+    );
+    // before those of the command line, so they apply first
+    let at = 1.min(args.len());
+    args.splice(
+        at..at,
+        rules
+            .into_iter()
+            .flat_map(|rule| [OsString::from("--rule"), OsString::from(rule)])
+            .chain(
+                log_colors
+                    .into_iter()
+                    .flat_map(|entry| [OsString::from("--log-color-entry"), OsString::from(entry)]),
+            )
+            .chain(
+                replies
+                    .into_iter()
+                    .flat_map(|reply| [OsString::from("--auto-respond"), OsString::from(reply)]),
+            )
+            .chain(
+                startup
+                    .into_iter()
+                    .flat_map(|step| [OsString::from("--startup-step"), OsString::from(step)]),
+            )
+            .chain(
+                origins
+                    .into_iter()
+                    .flat_map(|origin| [OsString::from("--config-origin"), OsString::from(origin)]),
+            ),
+    );
+    Ok((app, args))
+}
+
+// Find --no-config and --profile before clap parses the arguments
+fn scan_args(args: &[OsString]) -> (bool, Option<String>) {
+    let mut no_config = false;
+    let mut profile = None;
+    let mut iter = args.iter().skip(1);
+    while let Some(arg) = iter.next() {
+        let arg = arg.to_string_lossy();
+        if arg == "--" {
+            break;
+        } else if arg == "--no-config" {
+            no_config = true;
+        } else if arg == "--profile" {
+            profile = iter.next().map(|name| name.to_string_lossy().into_owned());
+        } else if let Some(name) = arg.strip_prefix("--profile=") {
+            profile = Some(name.to_string());
+        }
+    }
+    (no_config, profile)
+}
+
+struct Setting {
+    section: String,
+    key: String,
+    value: Value,
+    line: usize,
+}
+
+// The [default] settings merged with those of the selected profile, a key of the profile
+// replacing that of [default]. Only the merged values are applied, so a profile can also
+// turn a flag of [default] off.
+fn profile_settings(
+    text: &str,
+    table: &toml::value::Table,
+    profile: Option<&str>,
+    file: &str,
+) -> Result<Vec<Setting>, String> {
+    let mut settings = Vec::new();
+
+    if let Some(default) = table.get("default") {
+        collect_settings(text, "default", default, &mut settings)?;
+    }
+    let profiles = table.get("profile").and_then(Value::as_table);
+    if let Some(name) = profile {
+        let mut overrides = Vec::new();
+        match profiles.and_then(|profiles| profiles.get(name)) {
+            Some(section) => {
+                collect_settings(text, &format!("profile.{}", name), section, &mut overrides)?
+            }
+            None => return Err(format!("no profile named {}", name)),
+        }
+        settings.retain(|setting| {
+            let key = normalize(&setting.key);
+            !overrides.iter().any(|over| normalize(&over.key) == key)
+        });
+        settings.extend(overrides);
+    }
+    for key in table.keys() {
+        if !["default", "profile", "rules", "log_colors", "autorespond"].contains(&key.as_str()) {
+            eprintln!(
+                "Warning: {}:{}: unknown section [{}]",
+                file,
+                line_label(find_section_line(text, key)),
+                key
+            );
+        }
+    }
+    Ok(settings)
+}
+
+// The [[rules]] as --rule values, leaving out those restricted to another profile. A rule
+// has a regex, an action and optionally the profile it applies to.
+fn profile_rules(
+    table: &toml::value::Table,
+    profile: Option<&str>,
+    file: &str,
+) -> Result<Vec<String>, String> {
+    let rules = match table.get("rules") {
+        Some(Value::Array(rules)) => rules,
+        Some(_) => return Err("rules has to be an array of tables, [[rules]]".to_string()),
+        None => return Ok(Vec::new()),
+    };
+    let mut specs = Vec::new();
+    for (i, rule) in rules.iter().enumerate() {
+        let rule = rule
+            .as_table()
+            .ok_or_else(|| format!("rule {} is not a table", i + 1))?;
+        let field = |key: &str| match rule.get(key) {
+            Some(Value::String(value)) => Ok(Some(value.as_str())),
+            Some(_) => Err(format!("{} of rule {} has to be a string", key, i + 1)),
+            None => Ok(None),
+        };
+        let regex = field("regex")?.ok_or_else(|| format!("rule {} has no regex", i + 1))?;
+        let action = field("action")?.ok_or_else(|| format!("rule {} has no action", i + 1))?;
+        for key in rule.keys() {
+            if !["regex", "action", "profile"].contains(&key.as_str()) {
+                eprintln!("Warning: {}: unknown key '{}' in rule {}", file, key, i + 1);
+            }
+        }
+        match field("profile")? {
+            Some(only) if Some(only) != profile => {}
+            _ => specs.push(format!("{}:{}", action, regex)),
+        }
+    }
+    Ok(specs)
+}
+
+// The startup = [...] list of a section as --startup-step values, checked when they are run
+fn startup_steps(setting: &Setting, file: &str) -> Result<Vec<String>, String> {
+    let error = || {
+        format!(
+            "{}:{}: startup in [{}] has to be an array of strings",
+            file,
+            line_label(setting.line),
+            setting.section
+        )
+    };
+    let steps = setting.value.as_array().ok_or_else(error)?;
+    steps
+        .iter()
+        .map(|step| step.as_str().map(str::to_string).ok_or_else(error))
+        .collect()
+}
+
+// The [log_colors.NAME] tables of regular expressions and colors as NAME:COLOR:REGEX
+fn log_colors(table: &toml::value::Table) -> Result<Vec<String>, String> {
+    let presets = match table.get("log_colors") {
+        Some(Value::Table(presets)) => presets,
+        Some(_) => return Err("log_colors has to be a table, [log_colors.NAME]".to_string()),
+        None => return Ok(Vec::new()),
+    };
+    let mut entries = Vec::new();
+    for (name, preset) in presets {
+        if name.contains(':') {
+            return Err(format!("[log_colors.{}]: the name can't contain ':'", name));
+        }
+        let preset = preset
+            .as_table()
+            .ok_or_else(|| format!("log_colors.{} is not a table", name))?;
+        for (regex, color) in preset {
+            let color = color.as_str().ok_or_else(|| {
+                format!(
+                    "the color of {} in [log_colors.{}] has to be a string",
+                    regex, name
+                )
+            })?;
+            entries.push(format!("{}:{}:{}", name, color, regex));
+        }
+    }
+    Ok(entries)
+}
+
+// The [autorespond] table of regular expressions and replies as REGEX=REPLY, an = in the
+// regex written as \= so it doesn't end it
+fn auto_replies(table: &toml::value::Table) -> Result<Vec<String>, String> {
+    let replies = match table.get("autorespond") {
+        Some(Value::Table(replies)) => replies,
+        Some(_) => return Err("autorespond has to be a table, [autorespond]".to_string()),
+        None => return Ok(Vec::new()),
+    };
+    replies
+        .iter()
+        .map(|(regex, reply)| match reply.as_str() {
+            Some(reply) => Ok(format!("{}={}", regex.replace('=', "\\="), reply)),
+            None => Err(format!(
+                "the reply to {} in [autorespond] has to be a string",
+                regex
+            )),
+        })
+        .collect()
+}
+
+fn collect_settings(
+    text: &str,
+    section: &str,
+    value: &Value,
+    settings: &mut Vec<Setting>,
+) -> Result<(), String> {
+    let table = value
+        .as_table()
+        .ok_or_else(|| format!("[{}] is not a table", section))?;
+    for (key, value) in table {
+        settings.push(Setting {
+            section: section.to_string(),
+            key: key.clone(),
+            value: value.clone(),
+            line: find_key_line(text, section, key),
+        });
+    }
+    Ok(())
+}
+
+// Returns the origins of the settings applied as NAME=WHERE for a default value and as
+// NAME+=WHERE for an added argument
+fn apply_settings<'help>(
+    mut app: App<'help>,
+    mut args: Vec<OsString>,
+    settings: Vec<Setting>,
+    file: &str,
+) -> (App<'help>, Vec<OsString>, Vec<String>) {
+    let mut origins = Vec::new();
+    for setting in settings {
+        let key = normalize(&setting.key);
+        let arg = app
+            .get_arguments()
+            .find(|arg| normalize(arg.get_name()) == key)
+            .filter(|arg| !COMMAND_LINE_ONLY.contains(&normalize(arg.get_name()).as_str()));
+        let (name, long, takes_value, env_set) = match arg {
+            Some(arg) => (
+                arg.get_name(),
+                arg.get_long(),
+                arg.is_set(ArgSettings::TakesValue),
+                arg.get_env().is_some_and(|var| env::var_os(var).is_some()),
+            ),
+            None => {
+                eprintln!(
+                    "Warning: {}:{}: unknown key '{}' in [{}]",
+                    file,
+                    line_label(setting.line),
+                    setting.key,
+                    setting.section
+                );
+                continue;
+            }
+        };
+
+        if takes_value {
+            match value_to_string(&setting.value) {
+                // clap keeps references to its defaults for the whole run
+                Some(value) => {
+                    let value: &'help str = Box::leak(value.into_boxed_str());
+                    origins.push(format!("{}={}", name, setting_origin(&setting, file)));
+                    app = app.mut_arg(name, |arg| arg.default_value(value));
+                }
+                None => eprintln!(
+                    "Warning: {}:{}: '{}' must be a string or number",
+                    file,
+                    line_label(setting.line),
+                    setting.key
+                ),
+            }
+        } else {
+            match (setting.value.as_bool(), long) {
+                // the environment sets the flag either way
+                (Some(_), _) if env_set => {}
+                (Some(true), Some(long)) => {
+                    let flag = OsString::from(format!("--{}", long));
+                    if !args.contains(&flag) {
+                        origins.push(format!("{}+={}", name, setting_origin(&setting, file)));
+                        args.insert(1, flag);
+                    }
+                }
+                (Some(false), _) => {}
+                _ => eprintln!(
+                    "Warning: {}:{}: '{}' must be true or false",
+                    file,
+                    line_label(setting.line),
+                    setting.key
+                ),
+            }
+        }
+    }
+    (app, args, origins)
+}
+
+fn setting_origin(setting: &Setting, file: &str) -> String {
+    format!(
+        "{}:{} [{}]",
+        file,
+        line_label(setting.line),
+        setting.section
+    )
+}
+
+// --show-config: the value of every option that has one, and whether it was given on the
+// command line, comes from the environment or the config file, or is the default
+pub fn show_config(app: &App, matches: &ArgMatches, origins: &[String]) -> u8 {
+    let origins: Vec<(&str, bool, &str)> = origins
+        .iter()
+        .filter_map(|origin| origin.split_once('='))
+        .map(|(name, place)| match name.strip_suffix('+') {
+            Some(name) => (name, true, place),
+            None => (name, false, place),
+        })
+        .collect();
+    let mut lines = Vec::new();
+    for arg in app.get_arguments() {
+        let name = arg.get_name();
+        if arg.is_set(ArgSettings::Hidden) || ["help", "version", "show-config"].contains(&name) {
+            continue;
+        }
+        let source = match matches.value_source(name) {
+            Some(source) => source,
+            None => continue,
+        };
+        let values: Vec<String> = if arg.is_set(ArgSettings::TakesValue) {
+            matches
+                .values_of(name)
+                .map(|values| values.map(str::to_string).collect())
+                .unwrap_or_default()
+        } else {
+            vec!["true".to_string()]
+        };
+        let defaulted = origins
+            .iter()
+            .rev()
+            .find(|(origin, added, _)| *origin == name && !added);
+        let added: Vec<&str> = origins
+            .iter()
+            .filter(|(origin, added, _)| *origin == name && *added)
+            .map(|(_, _, place)| *place)
+            .collect();
+        let key = normalize(name);
+        match source {
+            ValueSource::DefaultValue => {
+                let place = defaulted.map_or("default", |(_, _, place)| place);
+                lines.push((
+                    format!("{} = {}", key, values.join(", ")),
+                    place.to_string(),
+                ));
+            }
+            ValueSource::EnvVariable => {
+                let var = arg.get_env().map(|var| var.to_string_lossy().into_owned());
+                let place = format!("environment, {}", var.unwrap_or_default());
+                lines.push((format!("{} = {}", key, values.join(", ")), place));
+            }
+            // the added arguments come first
+            _ if !arg.is_set(ArgSettings::TakesValue) => {
+                let place = added.first().copied().unwrap_or("command line");
+                lines.push((format!("{} = true", key), place.to_string()));
+            }
+            _ => {
+                for (i, value) in values.iter().enumerate() {
+                    let place = added.get(i).copied().unwrap_or("command line");
+                    lines.push((format!("{} = {}", key, value), place.to_string()));
+                }
+            }
+        }
+    }
+    let width = lines.iter().map(|(line, _)| line.len()).max().unwrap_or(0);
+    for (line, place) in lines {
+        println!("{:width$}  {}", line, place, width = width);
+    }
+    0
+}
+
+fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Integer(i) => Some(i.to_string()),
+        Value::Float(f) => Some(f.to_string()),
+        _ => None,
+    }
+}
+
+// Config keys use underscores or dashes, positional arguments are named with spaces
+fn normalize(name: &str) -> String {
+    name.replace(['-', ' '], "_")
+}
+
+fn line_label(line: usize) -> String {
+    if line == 0 {
+        "?".to_string()
+    } else {
+        line.to_string()
+    }
+}
+
+fn find_section_line(text: &str, section: &str) -> usize {
+    text.lines()
+        .position(|line| section_name(line).as_deref() == Some(section))
+        .map_or(0, |i| i + 1)
+}
+
+// Best effort line number of `key` inside [section], 0 if it can't be found
+fn find_key_line(text: &str, section: &str, key: &str) -> usize {
+    let mut current = String::new();
+    for (i, line) in text.lines().enumerate() {
+        if let Some(name) = section_name(line) {
+            current = name;
+            continue;
+        }
+        if current != section {
+            continue;
+        }
+        if let Some((line_key, _)) = line.split_once('=') {
+            if line_key.trim().trim_matches('"') == key {
+                return i + 1;
+            }
+        }
+    }
+    0
+}
+
+fn section_name(line: &str) -> Option<String> {
+    let line = line.trim();
+    let name = line.strip_prefix('[')?.split(']').next()?;
+    Some(name.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{app, RateOrDevice, SessionConfig};
+
+    const CONFIG: &str = r#"
+[default]
+baud_rate = 115200
+echo = true
+
+[profile.esp]
+baud_rate = 921600
+parity = "even"
+startup = ["send:reset\\r"]
+"#;
+
+    fn load(text: &str, args: &[&str]) -> Result<SessionConfig, String> {
+        let args: Vec<OsString> = ["scip"].iter().chain(args).map(OsString::from).collect();
+        let (_, profile) = scan_args(&args);
+        let (app, args) = apply_text(app(), args, text, "config.toml", profile.as_deref())?;
+        let matches = app
+            .try_get_matches_from(args)
+            .map_err(|err| err.to_string())?;
+        SessionConfig::from_matches(&matches).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn the_default_section_sets_values_and_flags() {
+        let sc_args = load(CONFIG, &["/dev/ttyUSB0"]).unwrap();
+        assert_eq!(sc_args.baud_rate, RateOrDevice::Rate(115200));
+        assert!(sc_args.echo);
+        assert_eq!(sc_args.parity, "none");
+        assert!(sc_args.startup_step.is_empty());
+        assert!(sc_args
+            .config_origin
+            .contains(&"baud rate=config.toml:3 [default]".to_string()));
+        assert!(sc_args
+            .config_origin
+            .contains(&"echo+=config.toml:4 [default]".to_string()));
+    }
+
+    #[test]
+    fn a_profile_goes_over_the_default_section() {
+        let sc_args = load(CONFIG, &["--profile", "esp", "/dev/ttyUSB0"]).unwrap();
+        assert_eq!(sc_args.baud_rate, RateOrDevice::Rate(921600));
+        assert_eq!(sc_args.parity, "even");
+        assert!(sc_args.echo);
+        assert_eq!(sc_args.startup_step, ["send:reset\\r"]);
+        let sc_args = load(CONFIG, &["--profile=esp", "/dev/ttyUSB0"]).unwrap();
+        assert_eq!(sc_args.baud_rate, RateOrDevice::Rate(921600));
+        assert_eq!(
+            load(CONFIG, &["--profile", "stm32"]).err().unwrap(),
+            "config.toml: no profile named stm32"
+        );
+    }
+
+    #[test]
+    fn a_profile_turns_a_flag_of_the_default_section_off() {
+        let text = format!("{}\n[profile.quiet]\necho = false\n", CONFIG);
+        let sc_args = load(&text, &["--profile", "quiet", "/dev/ttyUSB0"]).unwrap();
+        assert!(!sc_args.echo);
+        assert_eq!(sc_args.baud_rate, RateOrDevice::Rate(115200));
+        assert!(!sc_args
+            .config_origin
+            .iter()
+            .any(|origin| origin.starts_with("echo")));
+        assert!(load(&text, &["/dev/ttyUSB0"]).unwrap().echo);
+    }
+
+    #[test]
+    fn the_command_line_goes_over_the_config_file() {
+        let sc_args = load(
+            CONFIG,
+            &["--profile", "esp", "/dev/ttyUSB0", "57600", "8", "odd"],
+        );
+        let sc_args = sc_args.unwrap();
+        assert_eq!(sc_args.baud_rate, RateOrDevice::Rate(57600));
+        assert_eq!(sc_args.parity, "odd");
+        // a flag given on the command line as well isn't added twice
+        assert!(load(CONFIG, &["--echo", "/dev/ttyUSB0"]).unwrap().echo);
+    }
+
+    // the only test setting these variables, the others don't look at stop bits or the bar
+    #[test]
+    fn the_environment_goes_over_the_config_file_and_under_the_command_line() {
+        let config = "[default]\nstop_bits = 1\nstatus_bar = true\n";
+        env::set_var("SCIPIO_STOP_BITS", "2");
+        env::set_var("SCIPIO_STATUS_BAR", "false");
+        let from_env = load(config, &["/dev/ttyUSB0"]);
+        let from_cli = load(
+            config,
+            &["/dev/ttyUSB0", "9600", "8", "none", "1", "--status-bar"],
+        );
+        env::remove_var("SCIPIO_STOP_BITS");
+        env::remove_var("SCIPIO_STATUS_BAR");
+        let from_config = load(config, &["/dev/ttyUSB0"]).unwrap();
+
+        let from_env = from_env.unwrap();
+        assert_eq!(from_env.stop_bits, 2);
+        // the environment sets the flag either way, the config file doesn't add it
+        assert!(!from_env.status_bar);
+        assert!(!from_env
+            .config_origin
+            .iter()
+            .any(|origin| origin.starts_with("status_bar")));
+        let from_cli = from_cli.unwrap();
+        assert_eq!(from_cli.stop_bits, 1);
+        assert!(from_cli.status_bar);
+        assert_eq!((from_config.stop_bits, from_config.status_bar), (1, true));
+    }
+
+    #[test]
+    fn rules_come_before_those_of_the_command_line() {
+        let config = r#"
+[[rules]]
+regex = "ERROR"
+action = "color=red"
+
+[[rules]]
+regex = "^boot"
+action = "bell"
+profile = "esp"
+"#;
+        let sc_args = load(config, &["--rule", "hide:^DEBUG", "/dev/ttyUSB0"]).unwrap();
+        assert_eq!(sc_args.rule, ["color=red:ERROR", "hide:^DEBUG"]);
+        let sc_args = load(&format!("{}[profile.esp]\n", config), &["--profile", "esp"]);
+        let sc_args = sc_args.unwrap();
+        assert_eq!(sc_args.rule, ["color=red:ERROR", "bell:^boot"]);
+        let err = load("[[rules]]\nregex = \"x\"\n", &[]).err().unwrap();
+        assert_eq!(err, "config.toml: rule 1 has no action");
+    }
+
+    #[test]
+    fn a_broken_config_file_names_the_file() {
+        assert!(load("[default\n", &[])
+            .err()
+            .unwrap()
+            .starts_with("config.toml: "));
+        let err = load("[default]\nstartup = \"send:x\"\n", &[])
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            "config.toml:2: startup in [default] has to be an array of strings"
+        );
+    }
+}
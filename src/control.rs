@@ -0,0 +1,110 @@
+use crate::ansi;
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+
+#[derive(Clone, Copy)]
+pub enum ControlStyle {
+    // ^M, ^[ and ^@
+    Caret,
+    // <1B> in reverse video
+    Hex,
+}
+
+// Where the filter is within an escape sequence the device sent
+#[derive(Clone, Copy, PartialEq)]
+enum AnsiState {
+    Text,
+    Escape,
+    // ESC followed by bytes like ( before the final one
+    Intermediate,
+    Csi,
+    // OSC and the other strings ended by BEL or ESC \
+    String,
+    StringEscape,
+}
+
+// Makes what the device sends safe to show: --strip-ansi drops escape sequences and
+// --show-control shows the remaining control characters. Only the screen gets this.
+pub struct ControlFilter {
+    // the style, and whether newlines, carriage returns and tabs are shown too
+    show: Option<(ControlStyle, bool)>,
+    strip_ansi: bool,
+    ansi: AnsiState,
+}
+
+impl ControlFilter {
+    pub fn new(show: Option<(ControlStyle, bool)>, strip_ansi: bool) -> ControlFilter {
+        ControlFilter {
+            show,
+            strip_ansi,
+            ansi: AnsiState::Text,
+        }
+    }
+
+    // A sequence split across reads is recognized across calls
+    pub fn filter(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut shown = Vec::with_capacity(data.len());
+        for &byte in data {
+            if self.strip_ansi && self.in_sequence(byte) {
+                continue;
+            }
+            match self.show {
+                Some((style, all)) if is_control(byte, all) => {
+                    show_control(&mut shown, byte, style);
+                    // the line still ends where it did
+                    if byte == b'\n' {
+                        shown.extend_from_slice(b"\r\n");
+                    }
+                }
+                _ => shown.push(byte),
+            }
+        }
+        shown
+    }
+
+    // Take `byte` into the escape sequence state, returning whether it belongs to one.
+    // Control characters within a sequence are passed on like a terminal carries them out.
+    fn in_sequence(&mut self, byte: u8) -> bool {
+        let (next, dropped) = match (self.ansi, byte) {
+            (AnsiState::StringEscape, b'\\') => (AnsiState::Text, true),
+            (AnsiState::String | AnsiState::StringEscape, ESC) => (AnsiState::StringEscape, true),
+            (AnsiState::String | AnsiState::StringEscape, BEL) => (AnsiState::Text, true),
+            (AnsiState::String | AnsiState::StringEscape, _) => (AnsiState::String, true),
+            (_, ESC) => (AnsiState::Escape, true),
+            (state, byte) if byte < 0x20 || byte == 0x7f => (state, false),
+            (AnsiState::Text, _) => (AnsiState::Text, false),
+            (AnsiState::Escape, b'[') => (AnsiState::Csi, true),
+            (AnsiState::Escape, b']' | b'P' | b'X' | b'^' | b'_') => (AnsiState::String, true),
+            (AnsiState::Escape | AnsiState::Intermediate, 0x20..=0x2f) => {
+                (AnsiState::Intermediate, true)
+            }
+            (AnsiState::Escape | AnsiState::Intermediate, _) => (AnsiState::Text, true),
+            (AnsiState::Csi, 0x40..=0x7e) => (AnsiState::Text, true),
+            (AnsiState::Csi, _) => (AnsiState::Csi, true),
+        };
+        self.ansi = next;
+        dropped
+    }
+}
+
+pub fn is_control(byte: u8, all: bool) -> bool {
+    match byte {
+        b'\n' | b'\r' | b'\t' => all,
+        0x00..=0x1f | 0x7f => true,
+        _ => false,
+    }
+}
+
+pub fn show_control(shown: &mut Vec<u8>, byte: u8, style: ControlStyle) {
+    match style {
+        ControlStyle::Caret => {
+            shown.push(b'^');
+            shown.push(if byte == 0x7f { b'?' } else { byte + b'@' });
+        }
+        ControlStyle::Hex => {
+            let hex = format!("{}<{:02X}>{}", ansi::INVERT, byte, ansi::NO_INVERT);
+            shown.extend_from_slice(hex.as_bytes());
+        }
+    }
+}
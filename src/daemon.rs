@@ -0,0 +1,129 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+// The --pid-file of --daemon, removed again when it is dropped
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    pub fn create(path: &Path) -> io::Result<PidFile> {
+        fs::write(path, format!("{}\n", process::id()))?;
+        Ok(PidFile {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// --detach, the child in the background that the parent waits for until it is ready
+#[cfg(unix)]
+pub struct Detached {
+    ready: fs::File,
+}
+
+// Fork with the parent waiting in the foreground: it exits once the child called ready(),
+// or with the status of the child if that failed first, so the errors of opening the port
+// and the log file still reach the terminal. This has to run before any thread is started.
+#[cfg(unix)]
+pub fn detach() -> io::Result<Detached> {
+    use std::io::Read;
+    use std::os::unix::io::FromRawFd;
+
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (reading, writing) =
+        unsafe { (fs::File::from_raw_fd(fds[0]), fs::File::from_raw_fd(fds[1])) };
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            drop(reading);
+            // no terminal to be hung up with, the port is opened with O_NOCTTY
+            if unsafe { libc::setsid() } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Detached { ready: writing })
+        }
+        pid => {
+            drop(writing);
+            let mut byte = [0];
+            if let Ok(1) = (&reading).read(&mut byte) {
+                eprintln!("[daemon: running in the background as PID {}]", pid);
+                process::exit(0);
+            }
+            let mut status = 0;
+            unsafe { libc::waitpid(pid, &mut status, 0) };
+            let code = if libc::WIFEXITED(status) {
+                libc::WEXITSTATUS(status)
+            } else {
+                crate::EXIT_ERROR.into()
+            };
+            process::exit(code);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub struct Detached;
+
+#[cfg(not(unix))]
+pub fn detach() -> io::Result<Detached> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "--detach is only available on Unix",
+    ))
+}
+
+impl Detached {
+    // Let the parent exit. Stdin and stdout go to /dev/null, and so does stderr if it is the
+    // terminal left behind, otherwise the messages keep going where it was redirected.
+    #[cfg(unix)]
+    pub fn ready(self) -> io::Result<()> {
+        use std::io::{IsTerminal, Write};
+        use std::os::unix::io::AsRawFd;
+
+        let null = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open("/dev/null")?;
+        let mut redirected = vec![libc::STDIN_FILENO, libc::STDOUT_FILENO];
+        if io::stderr().is_terminal() {
+            redirected.push(libc::STDERR_FILENO);
+        }
+        for fd in redirected {
+            if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        (&self.ready).write_all(b"\n")
+    }
+
+    #[cfg(not(unix))]
+    pub fn ready(self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_pid_file_holds_the_pid_until_dropped() {
+        let path = std::env::temp_dir().join(format!("scip-pid-{}", std::process::id()));
+        let pid_file = PidFile::create(&path).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text, format!("{}\n", std::process::id()));
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+}
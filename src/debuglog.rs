@@ -0,0 +1,168 @@
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, stderr, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use chrono::{SecondsFormat, Utc};
+use log::{LevelFilter, Log, Metadata, Record};
+
+// -v and -vv, or RUST_LOG: what the program does inside, for finding out why a session ended.
+// The lines go to --debug-log, or to stderr when that isn't the terminal of the session, never
+// between the data on the screen.
+struct DebugLog {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Log for DebugLog {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let ts = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        if let Ok(mut out) = self.out.lock() {
+            // nowhere left to report a failing debug log
+            let _ = writeln!(
+                out,
+                "{} {:5} {}: {}",
+                ts,
+                record.level(),
+                record.target(),
+                record.args()
+            );
+            let _ = out.flush();
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut out) = self.out.lock() {
+            let _ = out.flush();
+        }
+    }
+}
+
+// The level of `-v` given `count` times, at least debug for a --debug-log and raised by a
+// level in RUST_LOG
+pub fn level(count: u64, to_file: bool) -> LevelFilter {
+    let rust_log = env::var("RUST_LOG").ok();
+    level_with(count, to_file, rust_log.as_deref())
+}
+
+fn level_with(count: u64, to_file: bool, rust_log: Option<&str>) -> LevelFilter {
+    let from_flags = match count {
+        0 => LevelFilter::Off,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    let least = if to_file {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Off
+    };
+    from_flags.max(least).max(
+        rust_log
+            .and_then(rust_log_level)
+            .unwrap_or(LevelFilter::Off),
+    )
+}
+
+// A level in RUST_LOG, plain like debug or for this crate like serial_console=trace. Other
+// crates don't log anything worth having.
+fn rust_log_level(spec: &str) -> Option<LevelFilter> {
+    spec.split(',')
+        .filter_map(|part| match part.split_once('=') {
+            Some((target, level)) if target.trim() == "serial_console" => level.parse().ok(),
+            Some(_) => None,
+            None => part.trim().parse().ok(),
+        })
+        .max()
+}
+
+// Start logging at `level` to `path`, or to stderr. Ok(false) when there is no place for it,
+// stderr being the terminal of the session.
+pub fn init(level: LevelFilter, path: Option<&Path>, stderr_free: bool) -> io::Result<bool> {
+    if level == LevelFilter::Off {
+        return Ok(true);
+    }
+    let out: Box<dyn Write + Send> = match path {
+        Some(path) => Box::new(OpenOptions::new().create(true).append(true).open(path)?),
+        None if stderr_free => Box::new(stderr()),
+        None => return Ok(false),
+    };
+    let logger = DebugLog {
+        out: Mutex::new(out),
+    };
+    // the logger lives as long as the program. Setting it only fails when one was set already,
+    // which is kept then.
+    if log::set_logger(Box::leak(Box::new(logger))).is_ok() {
+        log::set_max_level(level);
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_v_raises_the_level() {
+        assert_eq!(level_with(0, false, None), LevelFilter::Off);
+        assert_eq!(level_with(1, false, None), LevelFilter::Info);
+        assert_eq!(level_with(2, false, None), LevelFilter::Debug);
+        assert_eq!(level_with(3, false, None), LevelFilter::Trace);
+        assert_eq!(level_with(7, false, None), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn a_debug_log_is_at_least_at_debug() {
+        assert_eq!(level_with(0, true, None), LevelFilter::Debug);
+        assert_eq!(level_with(1, true, None), LevelFilter::Debug);
+        assert_eq!(level_with(3, true, None), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn rust_log_raises_the_level_for_this_crate_only() {
+        assert_eq!(level_with(0, false, Some("warn")), LevelFilter::Warn);
+        assert_eq!(level_with(2, false, Some("warn")), LevelFilter::Debug);
+        let spec = Some("regex=trace, serial_console=trace");
+        assert_eq!(level_with(0, false, spec), LevelFilter::Trace);
+        assert_eq!(level_with(0, false, Some("regex=trace")), LevelFilter::Off);
+        assert_eq!(level_with(1, false, Some("loud")), LevelFilter::Info);
+    }
+
+    // the only test setting the logger, which can be set once in a process
+    #[test]
+    fn the_lines_go_to_the_debug_log_file() {
+        let path = std::env::temp_dir().join(format!("scip-debug-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        assert!(!init(LevelFilter::Debug, None, false).unwrap());
+        assert!(init(LevelFilter::Off, Some(&path), false).unwrap());
+        assert!(!path.exists());
+
+        assert!(init(LevelFilter::Debug, Some(&path), false).unwrap());
+        log::debug!("opened {}", "/dev/ttyUSB0");
+        log::trace!("left out");
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // the other tests running meanwhile may log too
+        let mut lines = text
+            .lines()
+            .filter(|line| line.contains("::debuglog::tests: "));
+        assert!(text.lines().all(|line| !line.contains("left out")));
+        let (ts, rest) = lines.next().unwrap().split_once(' ').unwrap();
+        assert!(
+            ts.ends_with('Z') && ts.len() == "2026-01-01T00:00:00.000Z".len(),
+            "{}",
+            ts
+        );
+        assert_eq!(
+            rest,
+            "DEBUG serial_console::debuglog::tests: opened /dev/ttyUSB0"
+        );
+    }
+}
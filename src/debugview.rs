@@ -0,0 +1,80 @@
+use chrono::Local;
+
+use crate::ansi::{Fg, FG_RESET};
+use crate::record::Direction;
+
+// The bytes on a line, a longer chunk goes on with lines of its own
+const ROW_LEN: usize = 16;
+
+// --debug-view: every chunk read from or written to the port as hex with its direction,
+// like -> 01 03 00 10 00 02 c5 ce for sent and <- 01 03 04 ... for received, in place of the
+// received data. A chunk is what a single read returned or a single write took.
+pub struct DebugView {
+    // --debug-ascii, the printable characters after the hex like hexdump -C
+    ascii: bool,
+    color: bool,
+}
+
+impl DebugView {
+    pub fn new(ascii: bool, color: bool) -> DebugView {
+        DebugView { ascii, color }
+    }
+
+    // The lines of a chunk without the time and the colors, as the pretty log gets them
+    pub fn lines(&self, direction: Direction, data: &[u8]) -> Vec<String> {
+        let arrow = match direction {
+            Direction::Tx => "->",
+            Direction::Rx => "<-",
+        };
+        data.chunks(ROW_LEN)
+            .enumerate()
+            .map(|(row, bytes)| {
+                let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+                let hex = hex.join(" ");
+                let start = if row == 0 { arrow } else { "  " };
+                if !self.ascii {
+                    return format!("{} {}", start, hex);
+                }
+                let text: String = bytes
+                    .iter()
+                    .map(|&byte| {
+                        if (0x20..0x7f).contains(&byte) {
+                            byte as char
+                        } else {
+                            '.'
+                        }
+                    })
+                    .collect();
+                format!(
+                    "{} {:width$}  |{}|",
+                    start,
+                    hex,
+                    text,
+                    width = ROW_LEN * 3 - 1
+                )
+            })
+            .collect()
+    }
+
+    // The lines for the screen, with the time of day and sent and received in their colors
+    pub fn show(&self, direction: Direction, lines: &[String]) -> Vec<u8> {
+        let time = Local::now().format("%H:%M:%S%.3f").to_string();
+        let color = match direction {
+            Direction::Tx => Fg(3),
+            Direction::Rx => Fg(2),
+        };
+        let mut out = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            // the rows going on with a chunk leave the time out
+            let time = if i == 0 { time.as_str() } else { "" };
+            let line = format!("{:12} {}", time, line);
+            if self.color {
+                out.extend_from_slice(format!("{}{}{}", color, line, FG_RESET).as_bytes());
+            } else {
+                out.extend_from_slice(line.as_bytes());
+            }
+            out.extend_from_slice(b"\r\n");
+        }
+        out
+    }
+}
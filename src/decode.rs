@@ -0,0 +1,102 @@
+use std::time::{Duration, Instant};
+
+use crate::info;
+use crate::modbus::ModbusRtu;
+use crate::transport::{PortParity, PortSettings};
+
+// --decode: shows the received data as the frames of a protocol, one per line, in place of
+// the data itself. Only the screen shows them, the log, the capture and the clients get the
+// bytes as received.
+pub trait Decoder {
+    // The lines of the frames that ended before or with `data`, read at `at`
+    fn feed(&mut self, data: &[u8], at: Instant) -> Vec<u8>;
+    // When the frame read so far is over unless more data arrives
+    fn due_at(&self) -> Option<Instant>;
+    // The line of the frame read so far, a silence ended it
+    fn flush(&mut self) -> Vec<u8>;
+    // ~B changed the speed the frames come at
+    fn set_baud_rate(&mut self, baud_rate: u32);
+}
+
+// The decoder for the protocol of --decode, None for one there is none for
+pub fn new(protocol: &str, settings: &PortSettings, color: bool) -> Option<Box<dyn Decoder>> {
+    match protocol {
+        "modbus-rtu" => Some(Box::new(ModbusRtu::new(settings, color))),
+        _ => None,
+    }
+}
+
+// Splits the received data into frames where the line was silent for `chars` character
+// times, at least `min_gap`. The data arrives in reads timed only when they returned, so the
+// silence before a read is what is left of the time since the previous one after its bytes
+// were on the line.
+pub struct SilenceFramer {
+    chars: f64,
+    min_gap: Duration,
+    // start, data, parity and stop bits
+    bits: u32,
+    char_time: Duration,
+    frame: Vec<u8>,
+    last_read: Option<Instant>,
+}
+
+impl SilenceFramer {
+    pub fn new(settings: &PortSettings, chars: f64, min_gap: Duration) -> SilenceFramer {
+        let parity = match settings.parity {
+            PortParity::None => 0,
+            _ => 1,
+        };
+        let bits = 1
+            + u32::from(info::data_bits(settings.data_bits))
+            + parity
+            + u32::from(info::stop_bits(settings.stop_bits));
+        let mut framer = SilenceFramer {
+            chars,
+            min_gap,
+            bits,
+            char_time: Duration::ZERO,
+            frame: Vec::new(),
+            last_read: None,
+        };
+        framer.set_baud_rate(settings.baud_rate);
+        framer
+    }
+
+    pub fn set_baud_rate(&mut self, baud_rate: u32) {
+        self.char_time =
+            Duration::from_secs_f64(f64::from(self.bits) / f64::from(baud_rate.max(1)));
+    }
+
+    // The silence that ends a frame
+    fn gap(&self) -> Duration {
+        self.char_time.mul_f64(self.chars).max(self.min_gap)
+    }
+
+    // The frames `data` ended, the frame it is part of is kept for more data or flush()
+    pub fn feed(&mut self, data: &[u8], at: Instant) -> Vec<Vec<u8>> {
+        let mut frames = Vec::new();
+        if let Some(last_read) = self.last_read {
+            let on_line = self.char_time.saturating_mul(data.len() as u32);
+            let silence = at
+                .saturating_duration_since(last_read)
+                .saturating_sub(on_line);
+            if silence >= self.gap() && !self.frame.is_empty() {
+                frames.push(std::mem::take(&mut self.frame));
+            }
+        }
+        self.frame.extend_from_slice(data);
+        self.last_read = Some(at);
+        frames
+    }
+
+    pub fn due_at(&self) -> Option<Instant> {
+        if self.frame.is_empty() {
+            return None;
+        }
+        self.last_read.map(|last_read| last_read + self.gap())
+    }
+
+    pub fn flush(&mut self) -> Option<Vec<u8>> {
+        (!self.frame.is_empty()).then(|| std::mem::take(&mut self.frame))
+    }
+}
@@ -0,0 +1,301 @@
+use std::fmt;
+use std::io;
+
+use crate::transport::Device;
+use crate::{EXIT_DISCONNECTED, EXIT_ERROR, EXIT_IO_ERROR, EXIT_SCRIPT_FAILED, EXIT_WAIT_TIMEOUT};
+
+// Why the program stops other than the user quitting, or why a transfer failed.
+// The message and the exit status of each are decided here.
+#[derive(Debug)]
+pub enum ScipioError {
+    // an option or argument doesn't make sense, the message names it
+    InvalidArgument(String),
+    Config(String),
+    InvalidDevice(String),
+    DeviceNotFound(String),
+    // no device was given and there is no port to pick
+    NoPorts,
+    // usually the user isn't in the group owning the device node
+    PermissionDenied(String),
+    ConnectionRefused(String),
+    // another program running holds the UUCP lock of the port
+    Locked {
+        device: String,
+        pid: i32,
+        program: Option<String>,
+    },
+    Open {
+        device: String,
+        source: io::Error,
+    },
+    Connect {
+        device: String,
+        source: io::Error,
+    },
+    // an open failure nothing above describes
+    UnexpectedOpen(io::Error),
+    // setting up the log, the listener, the terminal and the like
+    Setup {
+        what: String,
+        source: io::Error,
+    },
+    Disconnected,
+    GaveUpReconnecting {
+        device: String,
+        source: io::Error,
+    },
+    WaitTimeout(String),
+    Port(io::Error),
+    // the stdin thread stopped
+    Stdin(io::Error),
+    Terminal(io::Error),
+    Script(String),
+    // shown as a status line, the session goes on
+    Transfer {
+        what: String,
+        reason: String,
+    },
+}
+
+impl ScipioError {
+    // Sort an error from opening `device`
+    pub fn open_failed(device: &Device, err: io::Error) -> ScipioError {
+        let name = device.to_string();
+        match err.kind() {
+            io::ErrorKind::NotFound => ScipioError::DeviceNotFound(name),
+            io::ErrorKind::PermissionDenied if !device.is_remote() && !device.is_local_stream() => {
+                ScipioError::PermissionDenied(name)
+            }
+            io::ErrorKind::ConnectionRefused => ScipioError::ConnectionRefused(name),
+            io::ErrorKind::Unsupported | io::ErrorKind::InvalidInput => ScipioError::Open {
+                device: name,
+                source: err,
+            },
+            _ if device.is_remote() || device.is_local_stream() => ScipioError::Connect {
+                device: name,
+                source: err,
+            },
+            _ => ScipioError::UnexpectedOpen(err),
+        }
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            ScipioError::InvalidArgument(_)
+            | ScipioError::Config(_)
+            | ScipioError::InvalidDevice(_)
+            | ScipioError::DeviceNotFound(_)
+            | ScipioError::NoPorts
+            | ScipioError::PermissionDenied(_)
+            | ScipioError::ConnectionRefused(_)
+            | ScipioError::Locked { .. }
+            | ScipioError::Open { .. }
+            | ScipioError::Connect { .. }
+            | ScipioError::UnexpectedOpen(_)
+            | ScipioError::Setup { .. } => EXIT_ERROR,
+            ScipioError::Disconnected | ScipioError::GaveUpReconnecting { .. } => EXIT_DISCONNECTED,
+            ScipioError::WaitTimeout(_) => EXIT_WAIT_TIMEOUT,
+            ScipioError::Port(_)
+            | ScipioError::Stdin(_)
+            | ScipioError::Terminal(_)
+            | ScipioError::Transfer { .. } => EXIT_IO_ERROR,
+            ScipioError::Script(_) => EXIT_SCRIPT_FAILED,
+        }
+    }
+}
+
+impl fmt::Display for ScipioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScipioError::InvalidArgument(msg) => write!(f, "Error {}", msg),
+            ScipioError::Config(msg) => write!(f, "Error reading config file {}", msg),
+            ScipioError::InvalidDevice(msg) => write!(f, "Invalid device {}", msg),
+            ScipioError::DeviceNotFound(device) => write!(f, "Device not found: {}", device),
+            ScipioError::NoPorts => write!(
+                f,
+                "No serial ports found, give the device or wait for one with --wait"
+            ),
+            ScipioError::PermissionDenied(device) => write!(
+                f,
+                "Permission denied opening {}, check that you are in the group owning it \
+                 (usually dialout or uucp, see ls -l), then log in again",
+                device
+            ),
+            ScipioError::ConnectionRefused(device) => write!(f, "Connection refused: {}", device),
+            ScipioError::Locked {
+                device,
+                pid,
+                program,
+            } => {
+                let program = match program {
+                    Some(program) => format!(" ({})", program),
+                    None => String::new(),
+                };
+                write!(
+                    f,
+                    "{} is locked by PID {}{}, which is still running, \
+                     use --no-lock to open it anyway",
+                    device, pid, program
+                )
+            }
+            ScipioError::Open { device, source } => {
+                write!(f, "Error opening {}: {}", device, source)
+            }
+            ScipioError::Connect { device, source } => {
+                write!(f, "Error connecting to {}: {}", device, source)
+            }
+            ScipioError::UnexpectedOpen(err) => {
+                write!(f, "Error opening port, please report this: {:?}", err)
+            }
+            ScipioError::Setup { what, source } => write!(f, "Error {}: {}", what, source),
+            ScipioError::Disconnected => write!(f, "Device disconnected"),
+            ScipioError::GaveUpReconnecting { device, source } => {
+                write!(f, "Giving up reconnecting to {}: {}", device, source)
+            }
+            ScipioError::WaitTimeout(device) => write!(f, "Timed out waiting for {}", device),
+            ScipioError::Port(err) => write!(f, "Error using the port: {}", err),
+            ScipioError::Stdin(err) => write!(f, "Error reading from the terminal: {}", err),
+            ScipioError::Terminal(err) => write!(f, "Error writing to the terminal: {}", err),
+            ScipioError::Script(msg) => write!(f, "{}", msg),
+            ScipioError::Transfer { what, reason } => write!(f, "{} failed: {}", what, reason),
+        }
+    }
+}
+
+impl std::error::Error for ScipioError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScipioError::Open { source, .. }
+            | ScipioError::Connect { source, .. }
+            | ScipioError::Setup { source, .. }
+            | ScipioError::GaveUpReconnecting { source, .. } => Some(source),
+            ScipioError::UnexpectedOpen(err)
+            | ScipioError::Port(err)
+            | ScipioError::Stdin(err)
+            | ScipioError::Terminal(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn io_error(kind: io::ErrorKind) -> io::Error {
+        io::Error::new(kind, "oops")
+    }
+
+    #[test]
+    fn every_error_has_its_exit_status() {
+        let cases = [
+            (ScipioError::InvalidArgument("--speed 0".into()), EXIT_ERROR),
+            (ScipioError::Config("x: y".into()), EXIT_ERROR),
+            (ScipioError::NoPorts, EXIT_ERROR),
+            (
+                ScipioError::DeviceNotFound("/dev/ttyUSB9".into()),
+                EXIT_ERROR,
+            ),
+            (
+                ScipioError::Setup {
+                    what: "opening the log".into(),
+                    source: io_error(io::ErrorKind::Other),
+                },
+                EXIT_ERROR,
+            ),
+            (ScipioError::Disconnected, EXIT_DISCONNECTED),
+            (
+                ScipioError::GaveUpReconnecting {
+                    device: "/dev/ttyUSB0".into(),
+                    source: io_error(io::ErrorKind::NotFound),
+                },
+                EXIT_DISCONNECTED,
+            ),
+            (
+                ScipioError::WaitTimeout("/dev/ttyUSB0".into()),
+                EXIT_WAIT_TIMEOUT,
+            ),
+            (
+                ScipioError::Port(io_error(io::ErrorKind::BrokenPipe)),
+                EXIT_IO_ERROR,
+            ),
+            (
+                ScipioError::Stdin(io_error(io::ErrorKind::Other)),
+                EXIT_IO_ERROR,
+            ),
+            (
+                ScipioError::Terminal(io_error(io::ErrorKind::Other)),
+                EXIT_IO_ERROR,
+            ),
+            (
+                ScipioError::Transfer {
+                    what: "sending a.bin".into(),
+                    reason: "cancelled".into(),
+                },
+                EXIT_IO_ERROR,
+            ),
+            (ScipioError::Script("no match".into()), EXIT_SCRIPT_FAILED),
+        ];
+        for (err, code) in cases {
+            assert_eq!(err.exit_code(), code, "{}", err);
+        }
+    }
+
+    #[test]
+    fn the_messages_name_what_failed() {
+        let locked = ScipioError::Locked {
+            device: "/dev/ttyUSB0".into(),
+            pid: 4242,
+            program: Some("minicom".into()),
+        };
+        assert_eq!(
+            locked.to_string(),
+            "/dev/ttyUSB0 is locked by PID 4242 (minicom), which is still running, \
+             use --no-lock to open it anyway"
+        );
+        let open = ScipioError::Open {
+            device: "/dev/ttyS0".into(),
+            source: io_error(io::ErrorKind::InvalidInput),
+        };
+        assert_eq!(open.to_string(), "Error opening /dev/ttyS0: oops");
+        assert!(std::error::Error::source(&open).is_some());
+        assert_eq!(ScipioError::Disconnected.to_string(), "Device disconnected");
+        assert_eq!(
+            ScipioError::InvalidArgument("--speed 0 is not above 0".into()).to_string(),
+            "Error --speed 0 is not above 0"
+        );
+        let transfer = ScipioError::Transfer {
+            what: "XMODEM send".into(),
+            reason: "too many retries".into(),
+        };
+        assert_eq!(transfer.to_string(), "XMODEM send failed: too many retries");
+        assert!(std::error::Error::source(&transfer).is_none());
+    }
+
+    #[test]
+    fn open_failures_are_sorted_by_kind_and_device() {
+        let local = Device::parse("/dev/ttyUSB0").unwrap();
+        let remote = Device::parse("tcp://localhost:2000").unwrap();
+        let sorted = |device: &Device, kind| ScipioError::open_failed(device, io_error(kind));
+        assert!(matches!(
+            sorted(&local, io::ErrorKind::NotFound),
+            ScipioError::DeviceNotFound(_)
+        ));
+        assert!(matches!(
+            sorted(&local, io::ErrorKind::PermissionDenied),
+            ScipioError::PermissionDenied(_)
+        ));
+        assert!(matches!(
+            sorted(&remote, io::ErrorKind::PermissionDenied),
+            ScipioError::Connect { .. }
+        ));
+        assert!(matches!(
+            sorted(&remote, io::ErrorKind::ConnectionRefused),
+            ScipioError::ConnectionRefused(_)
+        ));
+        assert!(matches!(
+            sorted(&local, io::ErrorKind::Other),
+            ScipioError::UnexpectedOpen(_)
+        ));
+    }
+}
@@ -0,0 +1,807 @@
+//! The escape commands: the state machine that finds them in what is typed, the --quit-key
+//! prefix and the help listing them.
+
+use std::io::{self, Write};
+
+use log::{log_enabled, trace, Level};
+
+use crate::info;
+use crate::transfer::Transfer;
+use crate::{line_level, NextStep, Session};
+
+/// Where key_state_machine() is in an escape command, a session starts at WaitForEnter
+#[derive(Debug)]
+pub enum EscapeState {
+    /// Wait for Enter
+    WaitForEnter,
+    /// Wait for escape character
+    WaitForEC,
+    /// Ready to process command, holding back the escape character
+    ProcessCMD(u8),
+    /// Wait for the second character of a two character command
+    ProcessSubCMD(u8, u8),
+}
+
+/// An escape command the session runs itself, rather than sending keys to the port
+#[derive(Clone, Copy, Debug)]
+pub enum EscapeCommand {
+    SendBreak,
+    ToggleDtr,
+    ToggleRts,
+    ToggleEcho,
+    SendFile,
+    SendFileVerify,
+    SendText,
+    StartTransfer(Transfer),
+    ReceiveYmodem,
+    ShowHelp,
+    ShowInfo,
+    ChangeBaudRate,
+    SendXonXoff,
+    CycleFlowControl,
+    PortSettings,
+    StartCapture,
+    StopCapture,
+    ToggleLog,
+    InsertMarker,
+    ToggleStopwatch,
+    RepeatLine,
+    ToggleLineMode,
+    SendHex,
+    SendNmea,
+    SendKey,
+    ResetBoard,
+    ToggleKeepalive,
+    ToggleAutoRespond,
+    AbortQueue,
+    RunShell,
+    PipeCommand,
+    ShowHeldBack,
+    ResetTerminal,
+    TogglePause,
+    ScrollBack,
+    SwitchPane,
+}
+
+impl EscapeCommand {
+    /// Whether the command sends to the port or changes its lines, refused with --read-only
+    pub fn transmits(self) -> bool {
+        matches!(
+            self,
+            EscapeCommand::SendBreak
+                | EscapeCommand::ToggleDtr
+                | EscapeCommand::ToggleRts
+                | EscapeCommand::SendFile
+                | EscapeCommand::SendFileVerify
+                | EscapeCommand::SendText
+                | EscapeCommand::StartTransfer(_)
+                | EscapeCommand::ReceiveYmodem
+                | EscapeCommand::SendHex
+                | EscapeCommand::SendNmea
+                | EscapeCommand::SendKey
+                | EscapeCommand::RepeatLine
+                | EscapeCommand::ResetBoard
+                | EscapeCommand::ToggleKeepalive
+                | EscapeCommand::ToggleAutoRespond
+                | EscapeCommand::PipeCommand
+                | EscapeCommand::SendXonXoff
+        )
+    }
+
+    /// Whether the split view of two devices has the command
+    pub fn works_split(self) -> bool {
+        matches!(
+            self,
+            EscapeCommand::SendBreak
+                | EscapeCommand::TogglePause
+                | EscapeCommand::SwitchPane
+                | EscapeCommand::ShowHelp
+        )
+    }
+}
+
+#[derive(Clone, Copy)]
+enum EscapeAction {
+    // a doubled escape character sends a single one
+    SendEscapeChar,
+    Terminate,
+    Run(EscapeCommand),
+}
+
+struct EscapeEntry {
+    // the characters typed after the escape character
+    keys: &'static str,
+    description: &'static str,
+    action: EscapeAction,
+    // shown next to the command in the ~? list
+    state: Option<fn(&Session) -> String>,
+}
+
+// Every escape command, this drives the parser, the ~? list and the --help text
+const ESCAPE_COMMANDS: &[EscapeEntry] = &[
+    EscapeEntry {
+        keys: "~",
+        description: "send the escape character itself",
+        action: EscapeAction::SendEscapeChar,
+        state: None,
+    },
+    EscapeEntry {
+        keys: ".",
+        description: "terminate the connection",
+        action: EscapeAction::Terminate,
+        state: None,
+    },
+    EscapeEntry {
+        keys: "b",
+        description: "send a break",
+        action: EscapeAction::Run(EscapeCommand::SendBreak),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "d",
+        description: "toggle DTR",
+        action: EscapeAction::Run(EscapeCommand::ToggleDtr),
+        state: Some(|session| line_level(session.dtr).to_string()),
+    },
+    EscapeEntry {
+        keys: "r",
+        description: "toggle RTS",
+        action: EscapeAction::Run(EscapeCommand::ToggleRts),
+        state: Some(|session| line_level(session.rts).to_string()),
+    },
+    EscapeEntry {
+        keys: "e",
+        description: "toggle local echo",
+        action: EscapeAction::Run(EscapeCommand::ToggleEcho),
+        state: Some(|session| on_off(session.echo).to_string()),
+    },
+    EscapeEntry {
+        keys: "l",
+        description: "toggle line mode",
+        action: EscapeAction::Run(EscapeCommand::ToggleLineMode),
+        state: Some(|session| on_off(session.line_mode).to_string()),
+    },
+    EscapeEntry {
+        keys: "k",
+        description: "toggle the keep-alive",
+        action: EscapeAction::Run(EscapeCommand::ToggleKeepalive),
+        state: Some(|session| {
+            let enabled = session.keepalive.as_ref().is_some_and(|k| k.enabled);
+            on_off(enabled).to_string()
+        }),
+    },
+    EscapeEntry {
+        keys: "y",
+        description: "toggle the --auto-respond replies",
+        action: EscapeAction::Run(EscapeCommand::ToggleAutoRespond),
+        state: Some(|session| on_off(session.triggers.is_replying()).to_string()),
+    },
+    EscapeEntry {
+        keys: "a",
+        description: "drop the queued input",
+        action: EscapeAction::Run(EscapeCommand::AbortQueue),
+        state: Some(|session| format!("{} bytes", session.tx_queue.len())),
+    },
+    EscapeEntry {
+        keys: "f",
+        description: "send a file",
+        action: EscapeAction::Run(EscapeCommand::SendFile),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "V",
+        description: "send a file and check its SHA-256 on the device",
+        action: EscapeAction::Run(EscapeCommand::SendFileVerify),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "t",
+        description: "send a text file line by line",
+        action: EscapeAction::Run(EscapeCommand::SendText),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "h",
+        description: "send bytes typed in hex",
+        action: EscapeAction::Run(EscapeCommand::SendHex),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "n",
+        description: "send an NMEA sentence, adding its checksum",
+        action: EscapeAction::Run(EscapeCommand::SendNmea),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "K",
+        description: "send the next key as it is, like Ctrl-C or Esc",
+        action: EscapeAction::Run(EscapeCommand::SendKey),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "A",
+        description: "send one of the last lines typed again",
+        action: EscapeAction::Run(EscapeCommand::RepeatLine),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "sx",
+        description: "send a file with XMODEM",
+        action: EscapeAction::Run(EscapeCommand::StartTransfer(Transfer::XmodemSend)),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "gx",
+        description: "receive (get) a file with XMODEM",
+        action: EscapeAction::Run(EscapeCommand::StartTransfer(Transfer::XmodemReceive)),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "sy",
+        description: "send files with YMODEM",
+        action: EscapeAction::Run(EscapeCommand::StartTransfer(Transfer::YmodemSend)),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "gy",
+        description: "receive (get) files with YMODEM into the download directory",
+        action: EscapeAction::Run(EscapeCommand::ReceiveYmodem),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "w",
+        description: "capture received data to a file",
+        action: EscapeAction::Run(EscapeCommand::StartCapture),
+        state: Some(|session| on_off(session.capture.is_some()).to_string()),
+    },
+    EscapeEntry {
+        keys: "W",
+        description: "stop capturing",
+        action: EscapeAction::Run(EscapeCommand::StopCapture),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "o",
+        description: "start or stop logging to a file",
+        action: EscapeAction::Run(EscapeCommand::ToggleLog),
+        state: Some(|session| match &session.log {
+            Some(log_file) => log_file.path().display().to_string(),
+            None => "off".to_string(),
+        }),
+    },
+    EscapeEntry {
+        keys: "m",
+        description: "insert a marker with the time and a note",
+        action: EscapeAction::Run(EscapeCommand::InsertMarker),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "T",
+        description: "start or stop a stopwatch",
+        action: EscapeAction::Run(EscapeCommand::ToggleStopwatch),
+        state: Some(|session| on_off(session.stopwatch.is_some()).to_string()),
+    },
+    EscapeEntry {
+        keys: "R",
+        description: "reset the board",
+        action: EscapeAction::Run(EscapeCommand::ResetBoard),
+        state: Some(|session| match &session.reset_sequence {
+            Some(sequence) => sequence.name().to_string(),
+            None => "asks for a profile".to_string(),
+        }),
+    },
+    EscapeEntry {
+        keys: "B",
+        description: "change the baud rate",
+        action: EscapeAction::Run(EscapeCommand::ChangeBaudRate),
+        state: Some(|session| session.settings.baud_rate.to_string()),
+    },
+    EscapeEntry {
+        keys: "S",
+        description: "send XON or XOFF",
+        action: EscapeAction::Run(EscapeCommand::SendXonXoff),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "F",
+        description: "switch to the next flow control",
+        action: EscapeAction::Run(EscapeCommand::CycleFlowControl),
+        state: Some(|session| info::flow_control(session.settings.flow_control).to_string()),
+    },
+    EscapeEntry {
+        keys: "O",
+        description: "show and change the port settings",
+        action: EscapeAction::Run(EscapeCommand::PortSettings),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "!",
+        description: "run a local command, sending its output",
+        action: EscapeAction::Run(EscapeCommand::PipeCommand),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "z",
+        description: "run a local shell, the port stays open",
+        action: EscapeAction::Run(EscapeCommand::RunShell),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "v",
+        description: "show what arrived while in the shell",
+        action: EscapeAction::Run(EscapeCommand::ShowHeldBack),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "p",
+        description: "pause the view, the data is kept until it resumes",
+        action: EscapeAction::Run(EscapeCommand::TogglePause),
+        state: Some(|session| on_off(session.pause_row.is_some()).to_string()),
+    },
+    EscapeEntry {
+        keys: "[",
+        description: "page through and search the output",
+        action: EscapeAction::Run(EscapeCommand::ScrollBack),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "\t",
+        description: "move the keyboard to the other pane of two devices",
+        action: EscapeAction::Run(EscapeCommand::SwitchPane),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "L",
+        description: "clear the screen and reset the terminal",
+        action: EscapeAction::Run(EscapeCommand::ResetTerminal),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "i",
+        description: "show the port settings and session counters",
+        action: EscapeAction::Run(EscapeCommand::ShowInfo),
+        state: None,
+    },
+    EscapeEntry {
+        keys: "?",
+        description: "list the escape commands",
+        action: EscapeAction::Run(EscapeCommand::ShowHelp),
+        state: None,
+    },
+];
+
+pub fn on_off(on: bool) -> &'static str {
+    if on {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+/// A printable character, or caret notation like ^] for a control character
+pub fn parse_escape_char(text: &str) -> Result<u8, String> {
+    let escape_char = match text.as_bytes() {
+        [byte] if byte.is_ascii_graphic() => *byte,
+        [b'^', b'?'] => 0x7f,
+        [b'^', byte @ b'@'..=b'_'] => byte - b'@',
+        [b'^', byte @ b'a'..=b'z'] => byte - b'a' + 1,
+        _ => {
+            return Err(format!(
+                "{}: expected a printable character or caret notation like ^]",
+                text
+            ))
+        }
+    };
+    // typing it twice sends it, so it can't also start a command
+    let command = ESCAPE_COMMANDS.iter().any(|entry| {
+        !matches!(entry.action, EscapeAction::SendEscapeChar)
+            && entry.keys.as_bytes()[0] == escape_char
+    });
+    if escape_char == b'\r' || escape_char == b'\n' {
+        return Err(format!(
+            "{}: escape commands begin with Enter already",
+            text
+        ));
+    }
+    if command {
+        return Err(format!("{}: this key is taken by an escape command", text));
+    }
+    Ok(escape_char)
+}
+
+/// --quit-key: a control key like Ctrl-A that starts an escape command without the Enter, for
+/// keyboards where ~ is awkward. The key after it quits, runs the command of that key, or
+/// sends the prefix itself when it is the prefix again. A prefix alone quits right away.
+#[derive(Clone, Copy)]
+pub struct QuitKey {
+    prefix: u8,
+    quit: Option<u8>,
+}
+
+impl QuitKey {
+    /// `ctrl-a q` or `^A q`, or just `ctrl-]` for a prefix that quits by itself
+    pub fn parse(spec: &str, escape_char: Option<u8>) -> Result<QuitKey, String> {
+        let mut words = spec.split_whitespace();
+        let prefix = match words.next().map(|word| word.to_ascii_lowercase()) {
+            Some(word) => match word.as_bytes() {
+                [b'c', b't', b'r', b'l', b'-', key] | [b'^', key] => match key {
+                    b'@'..=b'_' => key - b'@',
+                    b'a'..=b'z' => key - b'a' + 1,
+                    _ => return Err(format!("{}: no control key like ctrl-a or ^A", spec)),
+                },
+                _ => return Err(format!("{}: no control key like ctrl-a or ^A", spec)),
+            },
+            None => return Err("expected a key like ctrl-a q".to_string()),
+        };
+        let quit = match (words.next(), words.next()) {
+            (None, _) => None,
+            (Some(key), None) => match key.as_bytes() {
+                [byte] if byte.is_ascii_graphic() => Some(*byte),
+                _ => return Err(format!("{}: {} is no printable character", spec, key)),
+            },
+            (Some(_), Some(_)) => return Err(format!("{}: expected two keys at most", spec)),
+        };
+        if prefix == b'\r' || prefix == b'\n' || prefix == 0x1b {
+            return Err(format!("{}: Enter and Escape can't begin a command", spec));
+        }
+        if Some(prefix) == escape_char {
+            return Err(format!("{}: this is the escape character already", spec));
+        }
+        Ok(QuitKey { prefix, quit })
+    }
+
+    /// Like ^A q
+    pub fn name(&self) -> String {
+        match self.quit {
+            Some(quit) => format!("{} {}", escape_char_name(self.prefix), quit as char),
+            None => escape_char_name(self.prefix),
+        }
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Whether the prefix starts commands, rather than quitting by itself
+    pub fn has_commands(&self) -> bool {
+        self.quit.is_some()
+    }
+}
+
+/// How the escape character is shown, in caret notation if it's a control character
+pub fn escape_char_name(escape_char: u8) -> String {
+    match escape_char {
+        0x7f => "^?".to_string(),
+        byte if byte < 0x20 => format!("^{}", (byte + b'@') as char),
+        byte => (byte as char).to_string(),
+    }
+}
+
+fn entry_keys(entry: &EscapeEntry, escape_char: &str) -> String {
+    match entry.action {
+        EscapeAction::SendEscapeChar => escape_char.to_string(),
+        _ => entry.keys.replace('\t', "<Tab>"),
+    }
+}
+
+/// The escape command list at the end of --help
+pub fn after_help() -> String {
+    let mut help =
+        "Escape commands begin with <Enter> and end with one of the following sequences:\n"
+            .to_string();
+    for entry in ESCAPE_COMMANDS {
+        help.push_str(&format!(
+            "    ~{} - {}\n",
+            entry_keys(entry, "~"),
+            entry.description
+        ));
+    }
+    help.push_str("The ~ is replaced by the character given with --escape-char.\n");
+    help
+}
+
+/// Print the escape commands inline, so the session output above stays in place
+pub fn write_help(session: &Session, screen: &mut impl Write) -> io::Result<()> {
+    let name = session
+        .escape_char
+        .map(escape_char_name)
+        .unwrap_or_default();
+    let after = match (session.quit_key, session.escape_char) {
+        (Some(quit_key), Some(_)) if quit_key.has_commands() => format!(
+            "after <Enter>, or with {} in place of <Enter> {}",
+            escape_char_name(quit_key.prefix()),
+            name
+        ),
+        (Some(quit_key), None) if quit_key.has_commands() => {
+            format!("after {}", escape_char_name(quit_key.prefix()))
+        }
+        _ => "after <Enter>".to_string(),
+    };
+    write!(screen, "\r\n--- escape commands, {} ---\r\n", after)?;
+    if let Some(quit_key) = session.quit_key {
+        write!(screen, "    {} - quit\r\n", quit_key.name())?;
+    }
+    for entry in ESCAPE_COMMANDS {
+        // --no-escape with a --quit-key
+        if name.is_empty() && matches!(entry.action, EscapeAction::SendEscapeChar) {
+            continue;
+        }
+        let line = format!(
+            "{}{} - {}",
+            name,
+            entry_keys(entry, &name),
+            entry.description
+        );
+        match entry.state {
+            Some(state) => write!(screen, "    {:40} [{}]\r\n", line, state(session))?,
+            None => write!(screen, "    {}\r\n", line)?,
+        }
+    }
+    write!(screen, "---\r\n")?;
+    screen.flush()
+}
+
+/// The escape commands of the split view, which has neither the session state nor the other
+/// commands
+pub fn write_split_help(escape_char: Option<u8>, out: &mut impl Write) -> io::Result<()> {
+    let name = escape_char.map(escape_char_name).unwrap_or_default();
+    write!(out, "\r\n--- escape commands, after <Enter> ---\r\n")?;
+    for entry in ESCAPE_COMMANDS {
+        let split = match entry.action {
+            EscapeAction::SendEscapeChar | EscapeAction::Terminate => true,
+            EscapeAction::Run(command) => command.works_split(),
+        };
+        if split {
+            let keys = entry_keys(entry, &name);
+            write!(out, "    {}{} - {}\r\n", name, keys, entry.description)?;
+        }
+    }
+    write!(out, "---\r\n")
+}
+
+fn action_step(action: EscapeAction, escape_char: u8) -> NextStep {
+    match action {
+        EscapeAction::SendEscapeChar => NextStep::Send(vec![escape_char]),
+        EscapeAction::Terminate => NextStep::LoopBreak,
+        EscapeAction::Run(command) => NextStep::Command(command),
+    }
+}
+
+/// Look for escape commands after the escape character and after the --quit-key prefix,
+/// whichever of them is set
+pub fn key_state_machine(
+    character: &u8,
+    escape_char: Option<u8>,
+    quit_key: Option<QuitKey>,
+    escape_state: &mut EscapeState,
+) -> NextStep {
+    if !log_enabled!(Level::Trace) {
+        return next_key_state(character, escape_char, quit_key, escape_state);
+    }
+    let before = format!("{:?}", escape_state);
+    let step = next_key_state(character, escape_char, quit_key, escape_state);
+    let after = format!("{:?}", escape_state);
+    if before != after {
+        trace!("escape state {} -> {} on {:#04x}", before, after, character);
+    }
+    step
+}
+
+fn next_key_state(
+    character: &u8,
+    escape_char: Option<u8>,
+    quit_key: Option<QuitKey>,
+    escape_state: &mut EscapeState,
+) -> NextStep {
+    if let Some(quit_key) = quit_key {
+        match escape_state {
+            EscapeState::ProcessCMD(held) if *held == quit_key.prefix => {
+                if Some(*character) == quit_key.quit {
+                    *escape_state = EscapeState::WaitForEnter;
+                    return NextStep::LoopBreak;
+                }
+                return escape_state_machine(character, quit_key.prefix, escape_state);
+            }
+            EscapeState::ProcessSubCMD(held, _) if *held == quit_key.prefix => {
+                return escape_state_machine(character, quit_key.prefix, escape_state);
+            }
+            // a command after the escape character goes on
+            EscapeState::ProcessCMD(_) | EscapeState::ProcessSubCMD(..) => {}
+            _ if *character == quit_key.prefix => {
+                if quit_key.quit.is_none() {
+                    return NextStep::LoopBreak;
+                }
+                *escape_state = EscapeState::ProcessCMD(quit_key.prefix);
+                return NextStep::LoopContinue;
+            }
+            _ => {}
+        }
+    }
+    match escape_char {
+        Some(escape_char) => escape_state_machine(character, escape_char, escape_state),
+        None => NextStep::None,
+    }
+}
+
+pub fn escape_state_machine(
+    character: &u8,
+    escape_char: u8,
+    escape_state: &mut EscapeState,
+) -> NextStep {
+    match escape_state {
+        EscapeState::WaitForEnter => {
+            if *character == b'\r' || *character == b'\n' {
+                *escape_state = EscapeState::WaitForEC;
+            }
+        }
+        EscapeState::WaitForEC => match *character {
+            character if character == escape_char => {
+                *escape_state = EscapeState::ProcessCMD(character);
+                return NextStep::LoopContinue;
+            }
+            b'\r' => {
+                *escape_state = EscapeState::WaitForEC;
+            }
+            _ => {
+                *escape_state = EscapeState::WaitForEnter;
+            }
+        },
+        EscapeState::ProcessCMD(escape_char) => {
+            let escape_char = *escape_char;
+            if *character == escape_char {
+                *escape_state = EscapeState::WaitForEnter;
+                return action_step(EscapeAction::SendEscapeChar, escape_char);
+            }
+            let mut matching = ESCAPE_COMMANDS.iter().filter(|entry| {
+                !matches!(entry.action, EscapeAction::SendEscapeChar)
+                    && entry.keys.as_bytes()[0] == *character
+            });
+            match matching.next() {
+                Some(entry) if entry.keys.len() == 1 => {
+                    *escape_state = EscapeState::WaitForEnter;
+                    return action_step(entry.action, escape_char);
+                }
+                Some(_) => {
+                    *escape_state = EscapeState::ProcessSubCMD(escape_char, *character);
+                    return NextStep::LoopContinue;
+                }
+                // not a command, so forward what was held back
+                None => {
+                    *escape_state = if *character == b'\r' {
+                        EscapeState::WaitForEC
+                    } else {
+                        EscapeState::WaitForEnter
+                    };
+                    return NextStep::Send(vec![escape_char, *character]);
+                }
+            }
+        }
+        EscapeState::ProcessSubCMD(escape_char, command) => {
+            let keys = [*command, *character];
+            let next_step = match ESCAPE_COMMANDS
+                .iter()
+                .find(|entry| entry.keys.as_bytes() == keys)
+            {
+                Some(entry) => action_step(entry.action, *escape_char),
+                None => NextStep::Send(vec![*escape_char, *command, *character]),
+            };
+            *escape_state = if *character == b'\r' {
+                EscapeState::WaitForEC
+            } else {
+                EscapeState::WaitForEnter
+            };
+            return next_step;
+        }
+    }
+    NextStep::None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Type `keys` one at a time, like separate reads, and keep what was not passed through
+    fn type_keys(keys: &[u8], quit_key: Option<QuitKey>, state: &mut EscapeState) -> Vec<NextStep> {
+        keys.iter()
+            .map(|key| key_state_machine(key, Some(b'~'), quit_key, state))
+            .filter(|step| !matches!(step, NextStep::None))
+            .collect()
+    }
+
+    fn sent(step: &NextStep) -> Option<&[u8]> {
+        match step {
+            NextStep::Send(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn a_command_needs_enter_before_it() {
+        let mut state = EscapeState::WaitForEnter;
+        // the ~ in the middle of a line is typed
+        assert!(type_keys(b"a~.", None, &mut state).is_empty());
+        let steps = type_keys(b"\r~.", None, &mut state);
+        assert!(matches!(
+            steps[..],
+            [NextStep::LoopContinue, NextStep::LoopBreak]
+        ));
+    }
+
+    #[test]
+    fn a_doubled_escape_character_sends_one() {
+        let mut state = EscapeState::WaitForEC;
+        let steps = type_keys(b"~~", None, &mut state);
+        assert_eq!(sent(&steps[1]), Some(b"~".as_slice()));
+        // the line goes on, another ~ is typed
+        assert!(type_keys(b"~.", None, &mut state).is_empty());
+    }
+
+    #[test]
+    fn a_key_that_is_no_command_goes_out_with_the_escape_character() {
+        let mut state = EscapeState::WaitForEC;
+        let steps = type_keys(b"~x", None, &mut state);
+        assert_eq!(sent(&steps[1]), Some(b"~x".as_slice()));
+        assert!(matches!(state, EscapeState::WaitForEnter));
+
+        // ~ Enter ends the line too, so a command may follow right away
+        let mut state = EscapeState::WaitForEC;
+        let steps = type_keys(b"~\r~.", None, &mut state);
+        let forwarded: Vec<u8> = steps.iter().filter_map(sent).flatten().copied().collect();
+        assert_eq!(forwarded, b"~\r");
+        assert!(matches!(steps.last(), Some(NextStep::LoopBreak)));
+    }
+
+    #[test]
+    fn a_trailing_escape_character_waits_for_the_next_read() {
+        let mut state = EscapeState::WaitForEnter;
+        let steps = type_keys(b"ls\r~", None, &mut state);
+        assert!(matches!(steps[..], [NextStep::LoopContinue]));
+        assert!(matches!(state, EscapeState::ProcessCMD(b'~')));
+        assert!(matches!(
+            type_keys(b".", None, &mut state)[..],
+            [NextStep::LoopBreak]
+        ));
+
+        let mut state = EscapeState::WaitForEC;
+        type_keys(b"~", None, &mut state);
+        assert_eq!(
+            sent(&type_keys(b"q", None, &mut state)[0]),
+            Some(b"~q".as_slice())
+        );
+    }
+
+    #[test]
+    fn two_key_commands_and_their_misses() {
+        let mut state = EscapeState::WaitForEC;
+        let steps = type_keys(b"~sx", None, &mut state);
+        assert!(matches!(
+            steps[..],
+            [
+                NextStep::LoopContinue,
+                NextStep::LoopContinue,
+                NextStep::Command(EscapeCommand::StartTransfer(_))
+            ]
+        ));
+        let mut state = EscapeState::WaitForEC;
+        let steps = type_keys(b"~sq", None, &mut state);
+        assert_eq!(sent(&steps[2]), Some(b"~sq".as_slice()));
+    }
+
+    #[test]
+    fn the_quit_key_works_anywhere_in_a_line() {
+        let quit_key = QuitKey::parse("ctrl-a q", Some(b'~')).unwrap();
+        let mut state = EscapeState::WaitForEnter;
+        let steps = type_keys(b"abc\x01q", Some(quit_key), &mut state);
+        assert!(matches!(
+            steps[..],
+            [NextStep::LoopContinue, NextStep::LoopBreak]
+        ));
+        // another key after the prefix is a command after the escape character
+        let mut state = EscapeState::WaitForEnter;
+        let steps = type_keys(b"\x01.", Some(quit_key), &mut state);
+        assert!(matches!(
+            steps[..],
+            [NextStep::LoopContinue, NextStep::LoopBreak]
+        ));
+    }
+}
@@ -0,0 +1,284 @@
+// Intel HEX and Motorola S-record files, checked before --send-ihex and --send-srec send a
+// single record of them
+
+#[derive(Clone, Copy)]
+pub enum Format {
+    IntelHex,
+    Srec,
+}
+
+// A file whose records all parsed and had the right checksum
+pub struct Firmware {
+    // the records as they are sent, without line endings
+    pub records: Vec<Vec<u8>>,
+    // the bytes the data records hold
+    pub data_bytes: u64,
+    // the lowest and highest address written
+    pub range: Option<(u64, u64)>,
+}
+
+impl Firmware {
+    // Like "0x08000000-0x08003fff"
+    pub fn range_text(&self) -> String {
+        match self.range {
+            Some((low, high)) => format!("{:#010x}-{:#010x}", low, high),
+            None => "no data".to_string(),
+        }
+    }
+
+    fn add_data(&mut self, address: u64, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let end = address + len as u64 - 1;
+        self.data_bytes += len as u64;
+        self.range = Some(match self.range {
+            Some((low, high)) => (low.min(address), high.max(end)),
+            None => (address, end),
+        });
+    }
+}
+
+// Check every record of `data`, the errors name the line
+pub fn parse(data: &[u8], format: Format) -> Result<Firmware, String> {
+    let mut firmware = Firmware {
+        records: Vec::new(),
+        data_bytes: 0,
+        range: None,
+    };
+    let mut ihex = IntelHex::default();
+    for (i, line) in data.split(|&byte| byte == b'\n').enumerate() {
+        let line = line.trim_ascii();
+        if line.is_empty() {
+            continue;
+        }
+        let parsed = match format {
+            Format::IntelHex => ihex.record(line, &mut firmware),
+            Format::Srec => srec_record(line, &mut firmware),
+        };
+        parsed.map_err(|err| format!("line {}: {}", i + 1, err))?;
+        firmware.records.push(line.to_vec());
+    }
+    if firmware.records.is_empty() {
+        return Err("no records".to_string());
+    }
+    if let (Format::IntelHex, false) = (format, ihex.ended) {
+        return Err("no end of file record".to_string());
+    }
+    Ok(firmware)
+}
+
+// The bytes of a record after its start code, with the count or length byte first
+fn hex_bytes(digits: &[u8]) -> Result<Vec<u8>, String> {
+    if !digits.len().is_multiple_of(2) {
+        return Err("odd number of hex digits".to_string());
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| format!("{:?} is not hex", String::from_utf8_lossy(pair)))
+        })
+        .collect()
+}
+
+fn be_value(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0, |value, &byte| (value << 8) | u64::from(byte))
+}
+
+// The address the records of an Intel HEX file are relative to
+#[derive(Default)]
+struct IntelHex {
+    base: u64,
+    ended: bool,
+}
+
+impl IntelHex {
+    // :LLAAAATT<data>CC, all bytes add up to 0
+    fn record(&mut self, line: &[u8], firmware: &mut Firmware) -> Result<(), String> {
+        let digits = line
+            .strip_prefix(b":")
+            .ok_or("doesn't start with ':'".to_string())?;
+        let bytes = hex_bytes(digits)?;
+        if bytes.len() < 5 {
+            return Err("too short for a record".to_string());
+        }
+        let len = bytes[0] as usize;
+        if bytes.len() != len + 5 {
+            return Err(format!(
+                "the length byte says {} data bytes, there are {}",
+                len,
+                bytes.len() - 5
+            ));
+        }
+        let sum = bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        if sum != 0 {
+            let expected = bytes[bytes.len() - 1].wrapping_sub(sum);
+            return Err(format!(
+                "bad checksum {:02X}, expected {:02X}",
+                bytes[bytes.len() - 1],
+                expected
+            ));
+        }
+        if self.ended {
+            return Err("record after the end of file record".to_string());
+        }
+        let address = be_value(&bytes[1..3]);
+        let data = &bytes[4..4 + len];
+        match (bytes[3], len) {
+            (0x00, _) => firmware.add_data(self.base + address, len),
+            (0x01, 0) => self.ended = true,
+            // extended segment address, in paragraphs
+            (0x02, 2) => self.base = be_value(data) << 4,
+            // extended linear address, the upper 16 bits
+            (0x04, 2) => self.base = be_value(data) << 16,
+            // start addresses, CS:IP and EIP
+            (0x03, 4) | (0x05, 4) => {}
+            (0x00..=0x05, _) => {
+                return Err(format!(
+                    "record type {:02X} can't have {} data bytes",
+                    bytes[3], len
+                ))
+            }
+            (kind, _) => return Err(format!("unknown record type {:02X}", kind)),
+        }
+        Ok(())
+    }
+}
+
+// S<type><count><address><data><checksum>, the count covers the bytes after it and the
+// checksum is the ones' complement of their sum
+fn srec_record(line: &[u8], firmware: &mut Firmware) -> Result<(), String> {
+    let rest = line
+        .strip_prefix(b"S")
+        .ok_or("doesn't start with 'S'".to_string())?;
+    let (&kind, digits) = rest
+        .split_first()
+        .ok_or("too short for a record".to_string())?;
+    let address_len = match kind {
+        b'0' | b'1' | b'5' | b'9' => 2,
+        b'2' | b'6' | b'8' => 3,
+        b'3' | b'7' => 4,
+        _ => return Err(format!("unknown record type S{}", kind as char)),
+    };
+    let bytes = hex_bytes(digits)?;
+    if bytes.len() < address_len + 2 {
+        return Err("too short for a record".to_string());
+    }
+    let count = bytes[0] as usize;
+    if bytes.len() != count + 1 {
+        return Err(format!(
+            "the count byte says {} bytes follow, there are {}",
+            count,
+            bytes.len() - 1
+        ));
+    }
+    let (checksum, summed) = bytes.split_last().unwrap_or((&0, &[]));
+    let expected = !summed.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    if *checksum != expected {
+        return Err(format!(
+            "bad checksum {:02X}, expected {:02X}",
+            checksum, expected
+        ));
+    }
+    if let b'1' | b'2' | b'3' = kind {
+        let address = be_value(&bytes[1..1 + address_len]);
+        firmware.add_data(address, count - address_len - 1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IHEX: &str = ":020000040800F2\r\n\
+                        :10010000214601360121470136007EFE09D2190140\r\n\
+                        :00000001FF\r\n";
+    const SREC: &str = "S00F000068656C6C6F202020202000003C\n\
+                        S11F00007C0802A6900100049421FFF07C6C1B787C8C23783C6000003863000026\n\
+                        S9030000FC\n";
+
+    fn ihex_error(file: &str) -> String {
+        parse(file.as_bytes(), Format::IntelHex).err().unwrap()
+    }
+
+    #[test]
+    fn good_intel_hex_records() {
+        let firmware = parse(IHEX.as_bytes(), Format::IntelHex).unwrap();
+        assert_eq!(firmware.records.len(), 3);
+        assert_eq!(firmware.records[2], b":00000001FF");
+        assert_eq!(firmware.data_bytes, 16);
+        assert_eq!(firmware.range_text(), "0x08000100-0x0800010f");
+    }
+
+    #[test]
+    fn good_s_records() {
+        let firmware = parse(SREC.as_bytes(), Format::Srec).unwrap();
+        assert_eq!(firmware.records.len(), 3);
+        assert_eq!(firmware.data_bytes, 28);
+        assert_eq!(firmware.range_text(), "0x00000000-0x0000001b");
+    }
+
+    #[test]
+    fn a_bad_checksum_names_the_line_and_the_expected_one() {
+        assert_eq!(
+            ihex_error(&IHEX.replace("190140", "190141")),
+            "line 2: bad checksum 41, expected 40"
+        );
+        assert_eq!(
+            parse(SREC.replace("FC", "FD").as_bytes(), Format::Srec)
+                .err()
+                .unwrap(),
+            "line 3: bad checksum FD, expected FC"
+        );
+    }
+
+    #[test]
+    fn a_bad_length_is_refused() {
+        assert_eq!(
+            ihex_error(&IHEX.replace(":10010000", ":0F010000")),
+            "line 2: the length byte says 15 data bytes, there are 16"
+        );
+        assert_eq!(
+            parse(b"S9040000FC\n", Format::Srec).err().unwrap(),
+            "line 1: the count byte says 4 bytes follow, there are 3"
+        );
+        assert_eq!(ihex_error(":0000\n"), "line 1: too short for a record");
+        assert_eq!(
+            ihex_error(":00000001F\n"),
+            "line 1: odd number of hex digits"
+        );
+    }
+
+    #[test]
+    fn an_unknown_record_type_is_refused() {
+        assert_eq!(
+            ihex_error(":00000006FA\n:00000001FF\n"),
+            "line 1: unknown record type 06"
+        );
+        assert_eq!(
+            ihex_error(":01000001AA54\n"),
+            "line 1: record type 01 can't have 1 data bytes"
+        );
+        assert_eq!(
+            parse(b"S4030000FC\n", Format::Srec).err().unwrap(),
+            "line 1: unknown record type S4"
+        );
+    }
+
+    #[test]
+    fn intel_hex_needs_its_end_of_file_record() {
+        let data_only = IHEX.replace(":00000001FF\r\n", "");
+        assert_eq!(ihex_error(&data_only), "no end of file record");
+        assert_eq!(
+            ihex_error(&format!("{}:00000001FF\n", IHEX)),
+            "line 4: record after the end of file record"
+        );
+        assert_eq!(ihex_error("\n\n"), "no records");
+    }
+}
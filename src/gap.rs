@@ -0,0 +1,90 @@
+use std::time::{Duration, Instant};
+
+use crate::ansi;
+
+// Ends the faint intensity of the marker without touching the color
+const NORMAL_INTENSITY: &str = "\x1b[22m";
+
+// Puts a line like "--- 38 ms ---" into the shown data where the port was silent for longer
+// than --gap-marker between two reads
+pub struct GapMarker {
+    min_gap: Duration,
+    last_read: Option<Instant>,
+    // the shown data ended with a line ending, or nothing was shown yet
+    at_line_start: bool,
+    // the continuation bytes the UTF-8 sequence the shown data ended in still needs
+    utf8_missing: usize,
+}
+
+impl GapMarker {
+    pub fn new(min_gap: Duration) -> GapMarker {
+        GapMarker {
+            min_gap,
+            last_read: None,
+            at_line_start: true,
+            utf8_missing: 0,
+        }
+    }
+
+    // The silence before a read at `at` if it is long enough to be marked
+    pub fn gap(&mut self, at: Instant) -> Option<Duration> {
+        let last_read = self.last_read.replace(at)?;
+        let gap = at.saturating_duration_since(last_read);
+        (gap > self.min_gap).then_some(gap)
+    }
+
+    // `shown` with the marker for `gap` in front, on a line of its own. The end of a UTF-8
+    // sequence the previous data began stays in front of the marker.
+    pub fn mark(&mut self, gap: Option<Duration>, shown: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(shown.len() + 32);
+        let mut rest = shown;
+        if let Some(gap) = gap {
+            let split = rest
+                .iter()
+                .take(self.utf8_missing)
+                .take_while(|&&byte| byte & 0xc0 == 0x80)
+                .count();
+            out.extend_from_slice(&rest[..split]);
+            rest = &rest[split..];
+            if !self.at_line_start {
+                out.extend_from_slice(b"\r\n");
+            }
+            out.extend_from_slice(
+                format!(
+                    "{}--- {} ms ---{}\r\n",
+                    ansi::FAINT,
+                    gap.as_millis(),
+                    NORMAL_INTENSITY
+                )
+                .as_bytes(),
+            );
+            self.at_line_start = true;
+        }
+        out.extend_from_slice(rest);
+        self.track(shown);
+        out
+    }
+
+    fn track(&mut self, shown: &[u8]) {
+        if let Some(&last) = shown.last() {
+            self.at_line_start = last == b'\n' || last == b'\r';
+        }
+        // a lead byte within the last 3 bytes whose sequence isn't complete yet, or just
+        // more continuation bytes of the one before
+        let previous = self.utf8_missing;
+        self.utf8_missing = previous.saturating_sub(shown.len());
+        for (back, &byte) in shown.iter().rev().take(3).enumerate() {
+            if byte & 0xc0 == 0x80 {
+                continue;
+            }
+            let len: usize = match byte {
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                0xf0..=0xf7 => 4,
+                _ => 1,
+            };
+            self.utf8_missing = len.saturating_sub(back + 1);
+            break;
+        }
+    }
+}
@@ -0,0 +1,51 @@
+// Bytes typed as hex, like "01 05 ff 0a", "0x01 0x05" or "0105ff0a"
+pub fn parse_hex(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    for word in text.split_whitespace() {
+        let digits = word
+            .strip_prefix("0x")
+            .or_else(|| word.strip_prefix("0X"))
+            .unwrap_or(word);
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("{} is not hex", word));
+        }
+        // a lone digit is a byte of its own, longer runs are read in pairs
+        if digits.len() == 1 {
+            bytes.push(u8::from_str_radix(digits, 16).unwrap());
+            continue;
+        }
+        if digits.len() % 2 != 0 {
+            return Err(format!("{} has an odd number of digits", word));
+        }
+        for pair in digits.as_bytes().chunks(2) {
+            let pair = std::str::from_utf8(pair).unwrap();
+            bytes.push(u8::from_str_radix(pair, 16).unwrap());
+        }
+    }
+    if bytes.is_empty() {
+        return Err("no bytes given".to_string());
+    }
+    Ok(bytes)
+}
+
+// Rows of 16 bytes with their offset and the printable characters, like hexdump -C
+pub fn hex_dump(bytes: &[u8]) -> Vec<String> {
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(row, chunk)| {
+            let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+            let text: String = chunk
+                .iter()
+                .map(|&byte| {
+                    if byte.is_ascii_graphic() || byte == b' ' {
+                        byte as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            format!("{:04x}  {:47}  |{}|", row * 16, hex.join(" "), text)
+        })
+        .collect()
+}
@@ -0,0 +1,409 @@
+use std::borrow::Cow;
+use std::time::{Duration, Instant};
+
+use regex::bytes::{Regex, RegexBuilder};
+
+use crate::ansi::{self, Fg};
+
+// How long the start of a line is held back waiting for the rest of it
+const HOLD_TIME: Duration = Duration::from_millis(30);
+// A partial line longer than this is shown without waiting for its end
+const MAX_HELD: usize = 1024;
+// Keeps a pathological --highlight pattern from taking over the display path
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+const COLORS: &[&str] = &[
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white", "dim",
+];
+
+// Ends the faint intensity of "dim" without touching the color
+const NORMAL_INTENSITY: &str = "\x1b[22m";
+
+#[derive(Clone)]
+enum Action {
+    // the sequences put before and after a match
+    Color(String, &'static str),
+    // the same for the whole line, of --log-colors
+    LineColor(String, &'static str),
+    // the line isn't shown and isn't logged
+    Hide,
+    // the line is logged but not shown
+    LogOnly,
+    Bell,
+}
+
+#[derive(Clone)]
+pub struct Rule {
+    regex: Regex,
+    action: Action,
+}
+
+impl Rule {
+    // `regex[:color]` of --highlight, the color is red if it's left out
+    pub fn parse(spec: &str) -> Result<Rule, String> {
+        let (pattern, name) = match spec.rsplit_once(':') {
+            Some((pattern, name)) if COLORS.contains(&name) => (pattern, name),
+            _ => (spec, "red"),
+        };
+        let regex =
+            build_regex(pattern).map_err(|err| format!("in --highlight {}: {}", spec, err))?;
+        Ok(Rule {
+            regex,
+            action: color_action(name),
+        })
+    }
+
+    // `action:regex` of --rule and the [[rules]] of the config file, the action being
+    // color=NAME, hide, log-only or bell
+    pub fn parse_rule(spec: &str) -> Result<Rule, String> {
+        let (action, pattern) = spec
+            .split_once(':')
+            .ok_or_else(|| format!("in --rule {}: expected ACTION:REGEX", spec))?;
+        let action = match action {
+            "hide" => Action::Hide,
+            "log-only" => Action::LogOnly,
+            "bell" => Action::Bell,
+            _ => match action.strip_prefix("color=") {
+                Some(name) if COLORS.contains(&name) => color_action(name),
+                _ => {
+                    return Err(format!(
+                    "in --rule {}: unknown action {}, expected color=NAME, hide, log-only or bell",
+                    spec, action
+                ))
+                }
+            },
+        };
+        let regex = build_regex(pattern).map_err(|err| format!("in --rule {}: {}", spec, err))?;
+        Ok(Rule { regex, action })
+    }
+
+    // Color whole lines matching `pattern` with the color called `name`
+    pub fn line_color(pattern: &str, name: &str) -> Result<Rule, String> {
+        if !COLORS.contains(&name) {
+            return Err(format!(
+                "unknown color {} for {}, expected one of {}",
+                name,
+                pattern,
+                COLORS.join(", ")
+            ));
+        }
+        let regex = build_regex(pattern).map_err(|err| format!("in {}: {}", pattern, err))?;
+        let action = match color_action(name) {
+            Action::Color(start, end) => Action::LineColor(start, end),
+            action => action,
+        };
+        Ok(Rule { regex, action })
+    }
+
+    fn hides(&self) -> bool {
+        matches!(self.action, Action::Hide | Action::LogOnly)
+    }
+}
+
+fn build_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .dfa_size_limit(REGEX_SIZE_LIMIT)
+        .build()
+}
+
+fn color_action(name: &str) -> Action {
+    let start = match name {
+        "black" => Fg(0).to_string(),
+        "green" => Fg(2).to_string(),
+        "yellow" => Fg(3).to_string(),
+        "blue" => Fg(4).to_string(),
+        "magenta" => Fg(5).to_string(),
+        "cyan" => Fg(6).to_string(),
+        "white" => Fg(7).to_string(),
+        "dim" => return Action::Color(ansi::FAINT.to_string(), NORMAL_INTENSITY),
+        _ => Fg(1).to_string(),
+    };
+    Action::Color(start, ansi::FG_RESET)
+}
+
+// The first of `rules` hiding the line decides whether it's hidden, and from what
+fn hiding_rule<'a>(rules: &'a [Rule], line: &[u8]) -> Option<&'a Rule> {
+    let line = without_ending(line);
+    rules
+        .iter()
+        .filter(|rule| rule.hides())
+        .find(|rule| rule.regex.is_match(line))
+}
+
+// The hide rules applied to the log file, a line matching log-only first is still logged
+#[derive(Clone)]
+pub struct LogFilter {
+    rules: Vec<Rule>,
+}
+
+impl LogFilter {
+    // None when no rule hides anything
+    pub fn new(rules: &[Rule]) -> Option<LogFilter> {
+        let rules: Vec<Rule> = rules.iter().filter(|rule| rule.hides()).cloned().collect();
+        (!rules.is_empty()).then_some(LogFilter { rules })
+    }
+
+    pub fn hides(&self, line: &[u8]) -> bool {
+        hiding_rule(&self.rules, line).is_some_and(|rule| matches!(rule.action, Action::Hide))
+    }
+}
+
+// Applies the --rule and --highlight rules to received data, line by line: colors the
+// matches, hides lines and rings the bell. The last partial line is held back briefly so a
+// match split across reads is still found.
+pub struct Highlighter {
+    rules: Vec<Rule>,
+    // without --color the color rules are left out, the others still apply
+    color: bool,
+    held: Vec<u8>,
+    held_since: Option<Instant>,
+    // a hidden line ended with \r, the \n of the next read belongs to it
+    hidden_cr: bool,
+    // the start of the current line when it was shown without waiting for the rest, the
+    // line colors match the whole line
+    shown: Vec<u8>,
+}
+
+impl Highlighter {
+    pub fn new(rules: Vec<Rule>, color: bool) -> Highlighter {
+        Highlighter {
+            rules,
+            color,
+            held: Vec::new(),
+            held_since: None,
+            hidden_cr: false,
+            shown: Vec::new(),
+        }
+    }
+
+    // The complete lines so far, highlighted
+    pub fn feed(&mut self, data: &[u8]) -> Vec<u8> {
+        if self.held.is_empty() {
+            self.held_since = Some(Instant::now());
+        }
+        self.held.extend_from_slice(data);
+        let end = match self
+            .held
+            .iter()
+            .rposition(|&byte| byte == b'\n' || byte == b'\r')
+        {
+            Some(last) if self.held.len() <= MAX_HELD => last + 1,
+            _ if self.held.len() <= MAX_HELD => return Vec::new(),
+            _ => self.held.len(),
+        };
+        let rest = self.held.split_off(end);
+        let lines = std::mem::replace(&mut self.held, rest);
+        self.held_since = if self.held.is_empty() {
+            None
+        } else {
+            Some(Instant::now())
+        };
+        self.highlight(&lines)
+    }
+
+    // The partial line held back, highlighted as far as it goes
+    pub fn release(&mut self) -> Vec<u8> {
+        self.held_since = None;
+        let held = std::mem::take(&mut self.held);
+        self.highlight(&held)
+    }
+
+    // When the held partial line should be shown even without the rest of it
+    pub fn release_at(&self) -> Option<Instant> {
+        self.held_since.map(|since| since + HOLD_TIME)
+    }
+
+    fn highlight(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len());
+        let mut data = data;
+        if self.hidden_cr && !data.is_empty() {
+            self.hidden_cr = false;
+            data = data.strip_prefix(b"\n").unwrap_or(data);
+        }
+        for line in split_lines(data) {
+            if hiding_rule(&self.rules, line).is_some() {
+                self.hidden_cr = line.ends_with(b"\r");
+                continue;
+            }
+            self.show_line(line, &mut out);
+            if line.ends_with(b"\n") || line.ends_with(b"\r") {
+                self.shown.clear();
+            } else if self.shown.len() < MAX_HELD {
+                self.shown.extend_from_slice(line);
+            }
+        }
+        out
+    }
+
+    fn show_line(&self, line: &[u8], out: &mut Vec<u8>) {
+        let mut matches: Vec<(usize, usize, &str, &str)> = Vec::new();
+        // the line color whose match starts first, then the earlier rule
+        let mut line_color: Option<(usize, &str, &str)> = None;
+        let mut bell = false;
+        // the line ending is left alone, so `.*` doesn't color the \r
+        let text = without_ending(line);
+        let whole_line = if self.shown.is_empty() {
+            Cow::Borrowed(text)
+        } else {
+            Cow::Owned([&self.shown, text].concat())
+        };
+        for rule in &self.rules {
+            match &rule.action {
+                Action::Color(start, end) if self.color => {
+                    for found in rule.regex.find_iter(text) {
+                        if found.start() < found.end() {
+                            matches.push((found.start(), found.end(), start, end));
+                        }
+                    }
+                }
+                Action::LineColor(start, end) if self.color => {
+                    if let Some(found) = rule.regex.find(&whole_line) {
+                        if line_color.is_none_or(|(at, _, _)| found.start() < at) {
+                            line_color = Some((found.start(), start, end));
+                        }
+                    }
+                }
+                Action::Bell => bell = bell || rule.regex.is_match(text),
+                _ => {}
+            }
+        }
+        // overlapping matches go to the one that starts first, then to the earlier rule
+        matches.sort_by_key(|&(start, end, _, _)| (start, std::cmp::Reverse(end)));
+        let line_start = line_color.map_or("", |(_, start, _)| start);
+        out.extend_from_slice(line_start.as_bytes());
+        let mut pos = 0;
+        for (start, end, color, reset) in matches {
+            if start < pos {
+                continue;
+            }
+            out.extend_from_slice(&line[pos..start]);
+            out.extend_from_slice(color.as_bytes());
+            out.extend_from_slice(&line[start..end]);
+            out.extend_from_slice(reset.as_bytes());
+            // back to the color of the line
+            out.extend_from_slice(line_start.as_bytes());
+            pos = end;
+        }
+        out.extend_from_slice(&line[pos..text.len()]);
+        if let Some((_, _, end)) = line_color {
+            out.extend_from_slice(end.as_bytes());
+        }
+        out.extend_from_slice(&line[text.len()..]);
+        if bell {
+            out.push(0x07);
+        }
+    }
+}
+
+fn without_ending(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+// The lines of `data` with their endings, \r\n ends a line like \n and a lone \r
+fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    let mut i = 0;
+    while i < data.len() {
+        let end = match data[i] {
+            b'\r' if data.get(i + 1) == Some(&b'\n') => i + 2,
+            b'\r' | b'\n' => i + 1,
+            _ => {
+                i += 1;
+                continue;
+            }
+        };
+        lines.push(&data[start..end]);
+        start = end;
+        i = end;
+    }
+    if start < data.len() {
+        lines.push(&data[start..]);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(specs: &[&str]) -> Vec<Rule> {
+        specs
+            .iter()
+            .map(|spec| Rule::parse_rule(spec).unwrap())
+            .collect()
+    }
+
+    fn shown(specs: &[&str], reads: &[&[u8]]) -> String {
+        let mut highlighter = Highlighter::new(rules(specs), true);
+        let mut out: Vec<u8> = reads
+            .iter()
+            .flat_map(|data| highlighter.feed(data))
+            .collect();
+        out.extend(highlighter.release());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn rules_are_an_action_and_a_regex() {
+        assert!(Rule::parse_rule("color=green:OK|PASS").is_ok());
+        assert!(Rule::parse_rule("hide:^DEBUG:").is_ok());
+        assert!(Rule::parse_rule("log-only:heartbeat").is_ok());
+        assert!(Rule::parse_rule("bell:panic").is_ok());
+        assert_eq!(
+            Rule::parse_rule("ERROR").err().unwrap(),
+            "in --rule ERROR: expected ACTION:REGEX"
+        );
+        let err = Rule::parse_rule("color=pink:ERROR").err().unwrap();
+        assert!(err.contains("unknown action color=pink"), "{}", err);
+        assert!(Rule::parse_rule("bell:(")
+            .err()
+            .unwrap()
+            .starts_with("in --rule bell:(: "));
+    }
+
+    #[test]
+    fn a_color_rule_colors_the_matches_only() {
+        let expected = format!("a {}ERROR{} b\r\n", Fg(1), ansi::FG_RESET);
+        assert_eq!(shown(&["color=red:ERROR"], &[b"a ERROR b\r\n"]), expected);
+        // a match split across reads is still found
+        assert_eq!(
+            shown(&["color=red:ERROR"], &[b"a ER", b"ROR b\r\n"]),
+            expected
+        );
+        let dim = format!("{}x{} y\n", ansi::FAINT, NORMAL_INTENSITY);
+        assert_eq!(shown(&["color=dim:x"], &[b"x y\n"]), dim);
+        // without --color the colors are left out
+        let mut plain = Highlighter::new(rules(&["color=red:ERROR"]), false);
+        assert_eq!(plain.feed(b"ERROR\n"), b"ERROR\n");
+    }
+
+    #[test]
+    fn a_hide_rule_drops_the_whole_line() {
+        let data: &[&[u8]] = &[b"one\r\nDEBUG: x\r", b"\ntwo\r\n"];
+        assert_eq!(shown(&["hide:^DEBUG"], data), "one\r\ntwo\r\n");
+        assert_eq!(shown(&["log-only:^DEBUG"], data), "one\r\ntwo\r\n");
+    }
+
+    #[test]
+    fn log_only_lines_still_go_to_the_log() {
+        let specs = ["log-only:heartbeat", "hide:secret", "color=red:.*"];
+        let filter = LogFilter::new(&rules(&specs)).unwrap();
+        assert!(!filter.hides(b"heartbeat 1\r\n"));
+        assert!(filter.hides(b"the secret\r\n"));
+        assert!(!filter.hides(b"other\r\n"));
+        // the first rule hiding the line decides
+        assert!(!filter.hides(b"heartbeat secret\n"));
+        assert!(LogFilter::new(&rules(&["bell:x"])).is_none());
+    }
+
+    #[test]
+    fn a_bell_rule_rings_after_the_line() {
+        assert_eq!(
+            shown(&["bell:panic"], &[b"kernel panic\r\nok\r\n"]),
+            "kernel panic\r\n\x07ok\r\n"
+        );
+    }
+}
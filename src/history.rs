@@ -0,0 +1,152 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+// The entries kept when --history-size isn't given
+pub const DEFAULT_SIZE: usize = 1000;
+
+// The directory of the history files, $XDG_STATE_HOME/scipio
+pub fn state_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?)
+            .join(".local")
+            .join("state"),
+    };
+    Some(base.join("scipio"))
+}
+
+// The lines typed in line mode or at a prompt, oldest first, without consecutive repeats.
+// With a file every new entry is saved right away, merged into what other sessions saved
+// meanwhile.
+pub struct History {
+    entries: Vec<String>,
+    max: usize,
+    // None once saving failed, the entries are then kept for this session only
+    path: Option<PathBuf>,
+    error: Option<String>,
+}
+
+impl History {
+    // A history that is forgotten at exit
+    pub fn new(max: usize) -> History {
+        History {
+            entries: Vec::new(),
+            max,
+            path: None,
+            error: None,
+        }
+    }
+
+    // The history saved as `name` in the state directory, empty if there is none yet
+    pub fn load(name: &str, max: usize) -> History {
+        let mut history = History::new(max);
+        let path = match state_dir() {
+            Some(dir) => dir.join(name),
+            None => return history,
+        };
+        history.entries = read_entries(&path, max);
+        history.path = Some(path);
+        history
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(String::as_str)
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    // The newest entry before `before` containing `query`
+    pub fn search(&self, query: &str, before: usize) -> Option<usize> {
+        self.entries[..before.min(self.entries.len())]
+            .iter()
+            .rposition(|entry| entry.contains(query))
+    }
+
+    pub fn add(&mut self, line: &str) {
+        if line.is_empty() || line.contains('\n') {
+            return;
+        }
+        push(&mut self.entries, line, self.max);
+        if let Some(path) = &self.path {
+            if let Err(err) = save(path, line, self.max) {
+                self.error = Some(format!(
+                    "saving the history to {} failed: {}",
+                    path.display(),
+                    err
+                ));
+                self.path = None;
+            }
+        }
+    }
+
+    // Why saving failed, once
+    pub fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+}
+
+fn push(entries: &mut Vec<String>, line: &str, max: usize) {
+    if entries.last().map(String::as_str) != Some(line) {
+        entries.push(line.to_string());
+    }
+    if entries.len() > max {
+        let extra = entries.len() - max;
+        entries.drain(..extra);
+    }
+}
+
+fn read_entries(path: &Path, max: usize) -> Vec<String> {
+    let text = match fs::read(path) {
+        Ok(data) => String::from_utf8_lossy(&data).into_owned(),
+        Err(_) => return Vec::new(),
+    };
+    let mut entries = Vec::new();
+    for line in text.lines().filter(|line| !line.is_empty()) {
+        push(&mut entries, line, max);
+    }
+    entries
+}
+
+// Add `line` to the file at `path`. A temporary file is renamed over it, so a session saving
+// at the same time never finds it cut short.
+fn save(path: &Path, line: &str, max: usize) -> io::Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir)?;
+    let mut entries = read_entries(path, max);
+    push(&mut entries, line, max);
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = dir.join(format!(".{}.{}.tmp", name, process::id()));
+    let written = write_entries(&temp, &entries).and_then(|()| fs::rename(&temp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    written
+}
+
+fn write_entries(path: &Path, entries: &[String]) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    // only for the user, like the history of a shell
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = io::BufWriter::new(options.open(path)?);
+    for entry in entries {
+        writeln!(file, "{}", entry)?;
+    }
+    file.into_inner()
+        .map_err(|err| err.into_error())?
+        .sync_all()
+}
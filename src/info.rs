@@ -0,0 +1,116 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use serialport::{DataBits, FlowControl, SerialPortType, StopBits};
+
+use crate::stats::format_duration;
+use crate::transport::PortParity;
+use crate::{line_level, Session};
+
+// Print what the session is actually connected with, aligned like the ~? list
+pub fn write_info(session: &Session, screen: &mut impl Write) -> io::Result<()> {
+    let mut rows: Vec<(&str, String)> = vec![("device", session.port_description())];
+    if let Some(path) = session.serial_port.local_path() {
+        if let Some(usb) = usb_info(path) {
+            rows.push(("usb", usb));
+        }
+    }
+    match session.serial_port.settings() {
+        Ok(settings) => {
+            rows.push(("baud rate", settings.baud_rate.to_string()));
+            rows.push(("data bits", data_bits(settings.data_bits).to_string()));
+            rows.push(("parity", parity(settings.parity).to_string()));
+            rows.push(("stop bits", stop_bits(settings.stop_bits).to_string()));
+            rows.push((
+                "flow control",
+                flow_control(settings.flow_control).to_string(),
+            ));
+        }
+        Err(err) => rows.push(("settings", format!("unknown: {}", err))),
+    }
+    rows.push(("DTR", line_level(session.dtr).to_string()));
+    rows.push(("RTS", line_level(session.rts).to_string()));
+    rows.push(("sent", format!("{} bytes", session.stats.tx_bytes)));
+    rows.push(("received", format!("{} bytes", session.stats.rx_bytes)));
+    let rx_errors = if session.serial_port.marks_errors() {
+        format!("{} framing or parity errors", session.stats.rx_errors)
+    } else {
+        // counted where they could be told apart, the port may have changed since
+        format!(
+            "not marked by the port, {} suspect bytes",
+            session.stats.suspect_bytes
+        )
+    };
+    rows.push(("rx errors", rx_errors));
+    rows.push(("session", format_duration(session.stats.elapsed())));
+    if let Some(log_file) = &session.log {
+        let log = format!(
+            "{}, {} bytes",
+            log_file.path().display(),
+            log_file.written()
+        );
+        rows.push(("log", log));
+    }
+
+    write!(screen, "\r\n--- session info ---\r\n")?;
+    for (name, value) in rows {
+        write!(screen, "    {:14}{}\r\n", format!("{}:", name), value)?;
+    }
+    write!(screen, "---\r\n")?;
+    screen.flush()
+}
+
+// VID:PID and serial number, if the port is a USB adapter
+fn usb_info(path: &Path) -> Option<String> {
+    // the device may be given through a symlink like /dev/serial/by-id/...
+    let path = fs::canonicalize(path).ok()?;
+    let ports = serialport::available_ports().ok()?;
+    let port = ports
+        .into_iter()
+        .find(|port| fs::canonicalize(&port.port_name).is_ok_and(|port_path| port_path == path))?;
+    match port.port_type {
+        SerialPortType::UsbPort(usb) => {
+            let mut info = format!("{:04x}:{:04x}", usb.vid, usb.pid);
+            if let Some(serial_number) = usb.serial_number {
+                info.push_str(&format!(" serial {}", serial_number));
+            }
+            Some(info)
+        }
+        _ => None,
+    }
+}
+
+pub fn data_bits(data_bits: DataBits) -> u8 {
+    match data_bits {
+        DataBits::Five => 5,
+        DataBits::Six => 6,
+        DataBits::Seven => 7,
+        DataBits::Eight => 8,
+    }
+}
+
+pub fn parity(parity: PortParity) -> &'static str {
+    match parity {
+        PortParity::None => "none",
+        PortParity::Odd => "odd",
+        PortParity::Even => "even",
+        PortParity::Mark => "mark",
+        PortParity::Space => "space",
+    }
+}
+
+pub fn stop_bits(stop_bits: StopBits) -> u8 {
+    match stop_bits {
+        StopBits::One => 1,
+        StopBits::Two => 2,
+    }
+}
+
+pub fn flow_control(flow_control: FlowControl) -> &'static str {
+    match flow_control {
+        FlowControl::None => "none",
+        FlowControl::Software => "software (XON/XOFF)",
+        FlowControl::Hardware => "hardware (RTS/CTS)",
+    }
+}
@@ -0,0 +1,114 @@
+use std::io::{self, Write};
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use crate::ansi;
+use crate::chat;
+use crate::control::{ControlFilter, ControlStyle};
+use crate::transfer::pause;
+use crate::{screen_error, write_status, write_to_serial_port, Event, NextStep, Screen, Session};
+
+pub enum InitPart {
+    Bytes(Vec<u8>),
+    Pause(Duration),
+}
+
+// The parts of --init: the escapes of --send, and \~MS for a pause of MS milliseconds
+pub fn parse(value: &str) -> Result<Vec<InitPart>, String> {
+    let mut parts = Vec::new();
+    let mut piece = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            piece.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('~') => {
+                let mut digits = String::new();
+                while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                    digits.push(digit);
+                }
+                let ms: u64 = digits.parse().map_err(|_| {
+                    format!(
+                        "in --init {}: \\~ needs the pause in milliseconds, like \\~1000",
+                        value
+                    )
+                })?;
+                push_bytes(&mut parts, &std::mem::take(&mut piece))?;
+                parts.push(InitPart::Pause(Duration::from_millis(ms)));
+            }
+            // parse_escapes takes the other escapes, and the errors of a trailing \
+            Some(next) => {
+                piece.push('\\');
+                piece.push(next);
+            }
+            None => piece.push('\\'),
+        }
+    }
+    push_bytes(&mut parts, &piece)?;
+    Ok(parts)
+}
+
+fn push_bytes(parts: &mut Vec<InitPart>, piece: &str) -> Result<(), String> {
+    let bytes = chat::parse_escapes(piece, "--init")?;
+    if !bytes.is_empty() {
+        parts.push(InitPart::Bytes(bytes));
+    }
+    Ok(())
+}
+
+// Send --init right after the port is open. The bytes go out as given, without the pauses of
+// --char-delay, and a pause starts once they left the port, so the guard times around a +++
+// hold. The bytes are shown dimmed, typed keys wait like during a transfer and Ctrl-C stops.
+pub fn send(
+    parts: &[InitPart],
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> NextStep {
+    for (i, part) in parts.iter().enumerate() {
+        match part {
+            InitPart::Bytes(bytes) => {
+                if let Err(err) = show(session, screen, bytes) {
+                    return screen_error(err);
+                }
+                match write_to_serial_port(session, screen, bytes) {
+                    NextStep::None => {}
+                    next_step => return next_step,
+                }
+                if let Some(InitPart::Pause(_)) = parts.get(i + 1) {
+                    if let Err(err) = session.serial_port.flush() {
+                        let msg = format!("waiting for --init to be sent failed: {}", err);
+                        write_status(screen, &msg);
+                    }
+                }
+            }
+            InitPart::Pause(delay) => match pause(session, screen, rx, *delay) {
+                Ok(false) => {}
+                Ok(true) => {
+                    write_status(screen, "--init cancelled");
+                    return NextStep::None;
+                }
+                Err(next_step) => return next_step,
+            },
+        }
+    }
+    NextStep::None
+}
+
+// The bytes of --init with the control characters in hex, --debug-view shows them already
+fn show(session: &Session, screen: &mut Screen, bytes: &[u8]) -> io::Result<()> {
+    if !screen.is_terminal() || session.debug_view.is_some() {
+        return Ok(());
+    }
+    let shown = ControlFilter::new(Some((ControlStyle::Hex, false)), false).filter(bytes);
+    if session.color {
+        write!(screen, "{}", ansi::FAINT)?;
+        screen.write_all(&shown)?;
+        write!(screen, "{}", ansi::RESET)?;
+    } else {
+        screen.write_all(&shown)?;
+    }
+    screen.flush()
+}
@@ -0,0 +1,254 @@
+use std::io::{self, stdout, Write};
+
+use chrono::{SecondsFormat, Utc};
+
+use crate::record::{escape_json, Direction};
+use crate::stats::Stats;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Clone, Copy)]
+pub enum DataEncoding {
+    // "data_b64", the bytes as they are
+    Base64,
+    // "data", lossy UTF-8 for the humans reading along
+    Text,
+}
+
+// --output json: a line with a JSON object on stdout for everything that happens, for a
+// program driving scip. Every object has its type in "t" and the time in "ts".
+pub struct JsonOutput {
+    encoding: DataEncoding,
+    out: Box<dyn Write + Send>,
+}
+
+impl JsonOutput {
+    pub fn new(encoding: DataEncoding) -> JsonOutput {
+        JsonOutput {
+            encoding,
+            out: Box::new(stdout()),
+        }
+    }
+
+    // {"t":"rx"} for what the port received, {"t":"tx"} for what was sent to it
+    pub fn data(&mut self, direction: Direction, data: &[u8]) -> io::Result<()> {
+        let kind = match direction {
+            Direction::Rx => "rx",
+            Direction::Tx => "tx",
+        };
+        let field = match self.encoding {
+            DataEncoding::Base64 => format!("\"data_b64\":\"{}\"", base64(data)),
+            DataEncoding::Text => {
+                let text = String::from_utf8_lossy(data);
+                format!("\"data\":\"{}\"", escape_json(&text))
+            }
+        };
+        self.write_event(kind, &field)
+    }
+
+    pub fn connect(&mut self, device: &str) -> io::Result<()> {
+        self.write_event(
+            "connect",
+            &format!("\"device\":\"{}\"", escape_json(device)),
+        )
+    }
+
+    pub fn disconnect(&mut self, reason: &str) -> io::Result<()> {
+        self.write_event(
+            "disconnect",
+            &format!("\"reason\":\"{}\"", escape_json(reason)),
+        )
+    }
+
+    // An --on-match rule matched `line`
+    pub fn matched(&mut self, rule: &str, line: &str) -> io::Result<()> {
+        let fields = format!(
+            "\"rule\":\"{}\",\"line\":\"{}\"",
+            escape_json(rule),
+            escape_json(line)
+        );
+        self.write_event("match", &fields)
+    }
+
+    // The last object, with the exit status, the message of stderr and the session counters
+    pub fn exit(&mut self, code: u8, message: Option<&str>, stats: &Stats) -> io::Result<()> {
+        let message = match message {
+            Some(message) => format!("\"{}\"", escape_json(message)),
+            None => "null".to_string(),
+        };
+        let fields = format!(
+            "\"code\":{},\"message\":{},\"stats\":{{\"tx_bytes\":{},\"rx_bytes\":{},\"read_errors\":{},\"write_errors\":{},\"reconnects\":{},\"rx_errors\":{},\"seconds\":{:.3}}}",
+            code,
+            message,
+            stats.tx_bytes,
+            stats.rx_bytes,
+            stats.read_errors,
+            stats.write_errors,
+            stats.reconnects,
+            stats.rx_errors,
+            stats.elapsed().as_secs_f64()
+        );
+        self.write_event("exit", &fields)
+    }
+
+    // One object, with `fields` after the type and the time, written at once so nothing
+    // else on stdout comes between
+    fn write_event(&mut self, kind: &str, fields: &str) -> io::Result<()> {
+        let ts = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        let line = format!("{{\"t\":\"{}\",\"ts\":\"{}\",{}}}\n", kind, ts, fields);
+        self.out.write_all(line.as_bytes())?;
+        self.out.flush()
+    }
+}
+
+// Standard base64 with padding
+pub fn base64(data: &[u8]) -> String {
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let group = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Lines(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Lines {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn output(encoding: DataEncoding) -> (JsonOutput, Lines) {
+        let lines = Lines::default();
+        let json = JsonOutput {
+            encoding,
+            out: Box::new(lines.clone()),
+        };
+        (json, lines)
+    }
+
+    // Each line parsed as one JSON object, with "ts" checked and taken out as it changes
+    fn records(lines: &Lines) -> Vec<Value> {
+        let text = String::from_utf8(lines.0.lock().unwrap().clone()).unwrap();
+        assert!(text.ends_with('\n'));
+        text.lines()
+            .map(|line| {
+                let mut record: Value = serde_json::from_str(line).unwrap();
+                let ts = record.as_object_mut().unwrap().remove("ts").unwrap();
+                let ts = ts.as_str().unwrap();
+                assert_eq!(ts.len(), "2026-01-01T00:00:00.000Z".len(), "{}", line);
+                assert!(ts.ends_with('Z'), "{}", line);
+                record
+            })
+            .collect()
+    }
+
+    #[test]
+    fn control_bytes_are_escaped_in_text() {
+        let (mut json, lines) = output(DataEncoding::Text);
+        let data = "a\"b\\c\r\n\t\x1b[0m\x00\x7f";
+        json.data(Direction::Rx, data.as_bytes()).unwrap();
+        assert_eq!(records(&lines), [json!({"t": "rx", "data": data})]);
+        let text = String::from_utf8(lines.0.lock().unwrap().clone()).unwrap();
+        assert!(text.contains("\\u001b[0m\\u0000\\u007f"), "{}", text);
+    }
+
+    #[test]
+    fn bytes_that_arent_utf8() {
+        let (mut json, lines) = output(DataEncoding::Text);
+        json.data(Direction::Tx, b"\xffok\xc3").unwrap();
+        let (mut b64, b64_lines) = output(DataEncoding::Base64);
+        b64.data(Direction::Tx, b"\xffok\xc3").unwrap();
+        b64.data(Direction::Rx, b"").unwrap();
+        assert_eq!(
+            records(&lines),
+            [json!({"t": "tx", "data": "\u{fffd}ok\u{fffd}"})]
+        );
+        assert_eq!(
+            records(&b64_lines),
+            [
+                json!({"t": "tx", "data_b64": "/29rww=="}),
+                json!({"t": "rx", "data_b64": ""})
+            ]
+        );
+    }
+
+    #[test]
+    fn every_event_has_its_fields() {
+        let (mut json, lines) = output(DataEncoding::Base64);
+        json.connect("/dev/ttyUSB0").unwrap();
+        json.matched("^login:", "login: \"root\"").unwrap();
+        json.disconnect("the device went away").unwrap();
+        let mut stats = Stats::new(None);
+        stats.tx_bytes = 5;
+        stats.rx_bytes = 1234;
+        json.exit(3, Some("no match for \"#\""), &stats).unwrap();
+        json.exit(0, None, &stats).unwrap();
+        let mut records = records(&lines);
+        assert_eq!(records.len(), 5);
+        assert_eq!(
+            records[0],
+            json!({"t": "connect", "device": "/dev/ttyUSB0"})
+        );
+        assert_eq!(
+            records[1],
+            json!({"t": "match", "rule": "^login:", "line": "login: \"root\""})
+        );
+        assert_eq!(
+            records[2],
+            json!({"t": "disconnect", "reason": "the device went away"})
+        );
+        for record in &mut records[3..] {
+            let seconds = record["stats"].as_object_mut().unwrap().remove("seconds");
+            assert!(seconds.unwrap().as_f64().unwrap() >= 0.0);
+        }
+        let stats = json!({
+            "tx_bytes": 5,
+            "rx_bytes": 1234,
+            "read_errors": 0,
+            "write_errors": 0,
+            "reconnects": 0,
+            "rx_errors": 0
+        });
+        assert_eq!(
+            records[3],
+            json!({"t": "exit", "code": 3, "message": "no match for \"#\"", "stats": stats})
+        );
+        assert_eq!(
+            records[4],
+            json!({"t": "exit", "code": 0, "message": null, "stats": stats})
+        );
+    }
+
+    #[test]
+    fn base64_pads_the_last_group() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+}
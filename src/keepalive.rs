@@ -0,0 +1,47 @@
+use std::time::{Duration, Instant};
+
+use crate::chat::parse_escapes;
+
+// What --keepalive sends when nothing was written to the port for a while
+pub struct Keepalive {
+    interval: Duration,
+    bytes: Vec<u8>,
+    // toggled with ~k
+    pub enabled: bool,
+}
+
+impl Keepalive {
+    // `secs[:bytes]`, the bytes take the escapes of --send and are a NUL if left out
+    pub fn parse(spec: &str) -> Result<Keepalive, String> {
+        let (secs, bytes) = match spec.split_once(':') {
+            Some((secs, bytes)) => (secs, parse_escapes(bytes, "--keepalive")?),
+            None => (spec, vec![0]),
+        };
+        let interval = match secs.parse::<u64>() {
+            Ok(secs) if secs > 0 => Duration::from_secs(secs),
+            _ => {
+                return Err(format!(
+                    "in --keepalive {}: expected SECS[:BYTES] with SECS above 0",
+                    spec
+                ))
+            }
+        };
+        if bytes.is_empty() {
+            return Err(format!("in --keepalive {}: no bytes given", spec));
+        }
+        Ok(Keepalive {
+            interval,
+            bytes,
+            enabled: true,
+        })
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    // When to send, given the time of the last write to the port
+    pub fn due_at(&self, last_write: Instant) -> Option<Instant> {
+        self.enabled.then(|| last_write + self.interval)
+    }
+}
@@ -0,0 +1,5486 @@
+//! The serial console session behind scip: escape commands, reconnecting, logging and
+//! file transfers over a serial port or the network. The scip binary is a frontend that
+//! parses the command line and puts the terminal into raw mode.
+
+pub mod ansi;
+pub mod answerback;
+pub mod autobaud;
+pub mod capture;
+pub mod chat;
+pub mod completion;
+pub mod config;
+pub mod control;
+pub mod daemon;
+pub mod debuglog;
+pub mod debugview;
+pub mod decode;
+pub mod error;
+pub mod escape;
+pub mod firmware;
+pub mod gap;
+pub mod hex;
+pub mod highlight;
+pub mod history;
+pub mod info;
+pub mod init;
+pub mod jsonout;
+pub mod keepalive;
+pub mod lineedit;
+pub mod listen;
+pub mod lock;
+pub mod logcolors;
+pub mod logfile;
+pub mod modbus;
+pub mod modem;
+pub mod newline;
+pub mod nmea;
+pub mod notify;
+pub mod pacing;
+pub mod picker;
+pub mod pipecommand;
+pub mod portpanel;
+pub mod prompt;
+#[cfg(all(unix, feature = "pty"))]
+pub mod pty;
+pub mod record;
+pub mod repeat;
+pub mod reset;
+pub mod rfc2217;
+pub mod rxerrors;
+pub mod screen;
+pub mod scrollback;
+pub mod sha256;
+#[cfg(unix)]
+pub mod shell;
+pub mod split;
+pub mod stats;
+pub mod statusbar;
+pub mod term;
+pub mod textsend;
+pub mod timestamp;
+pub mod transfer;
+pub mod transport;
+pub mod trigger;
+pub mod txhold;
+pub mod utf8;
+pub mod verify;
+pub mod watch;
+pub mod xmodem;
+pub mod ymodem;
+pub mod zmodem;
+
+use std::fs;
+use std::io::{self, stdin, stdout, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use answerback::Answerback;
+use capture::Capture;
+use chat::{ChatError, ChatStep, ScriptArg};
+use chrono::Local;
+use clap::{
+    App, ArgMatches, FromArgMatches, IntoApp, Parser, PossibleValue, Subcommand, ValueSource,
+};
+use clap_complete::Shell;
+use control::{ControlFilter, ControlStyle};
+use daemon::PidFile;
+use debugview::DebugView;
+use decode::Decoder;
+use error::ScipioError;
+use escape::{key_state_machine, on_off, EscapeCommand, EscapeState, QuitKey};
+use firmware::{Firmware, Format};
+use gap::GapMarker;
+use highlight::{Highlighter, LogFilter, Rule};
+use history::History;
+use init::InitPart;
+use jsonout::{DataEncoding, JsonOutput};
+use keepalive::Keepalive;
+use lineedit::{LineEditor, LineInput};
+use listen::{ClientEvent, Listener};
+use log::{debug, info, trace, LevelFilter};
+use logfile::{LogFile, LogFormat, Rotation};
+use modem::LineMonitor;
+use newline::{NewlineMode, NewlineTranslator};
+use nmea::NmeaDecoder;
+use notify::Notifier;
+use pacing::TxQueue;
+use portpanel::PortPanel;
+use prompt::{Prompt, PromptStep};
+use record::{Direction, RecordFormat, Recorder};
+use repeat::SentLines;
+use reset::{ResetRun, ResetSequence, ResetStep};
+use rxerrors::ErrorMonitor;
+use screen::{OutputSink, Screen};
+use scrollback::{ScrollView, Scrollback};
+use serialport::{DataBits, FlowControl, SerialPortType, StopBits};
+use stats::Stats;
+use statusbar::StatusBar;
+use textsend::TextSend;
+use timestamp::{LineStamper, Stopwatch, TimestampMode};
+use transfer::Transfer;
+use transport::{Device, OpenLines, PortParity, PortSettings, Transport};
+use trigger::{PendingReply, Trigger, TriggerAction, Triggers};
+use txhold::{HeldKeys, TxHold};
+use utf8::{InvalidStyle, Utf8Filter};
+use verify::FileVerify;
+use zmodem::StartDetector;
+
+// The options of a session, a doc comment would become the about of the help
+#[derive(Clone, Debug, Parser)]
+#[clap(
+    author,
+    mut_arg(
+        "help",
+        |a| a.help("Print help information,\nPrint verbose help information with --help")
+    ),
+    name = PROGRAM_NAME,
+    subcommand_negates_reqs = true,
+    version
+)]
+pub struct SessionConfig {
+    #[clap(subcommand)]
+    pub subcommand: Option<Command>,
+
+    /// List the available serial ports and exit
+    #[clap(short, long)]
+    pub list: bool,
+    /// Print the paths of the available serial ports for shell completion
+    #[clap(long, hide = true)]
+    pub complete_devices: bool,
+
+    /// Use the settings of a profile from the config file
+    #[clap(
+        long,
+        value_name = "NAME",
+        long_help = r"Use the settings of a profile from the config file
+
+Settings are read from $XDG_CONFIG_HOME/scipio/config.toml, where a [default] section applies to every session and [profile.<NAME>] sections are selected with this option. Keys are the option names, e.g. baud_rate = 115200 or echo = true. Options given on the command line or in the environment take precedence, see --show-config.
+"
+    )]
+    pub profile: Option<String>,
+    /// Do not read the config file
+    #[clap(long, conflicts_with = "profile")]
+    pub no_config: bool,
+    /// Print the value of every option and where it comes from, then exit
+    #[clap(
+        long,
+        long_help = r"Print the value of every option and where it comes from, then exit
+
+An option is taken from the command line first, then from its environment variable, like SCIPIO_DEVICE or SCIPIO_BAUD, then from the config file, with the --profile section over [default], and else it has its default.
+"
+    )]
+    pub show_config: bool,
+    /// Where a setting of the config file comes from, as NAME=FILE:LINE [SECTION]
+    #[clap(long, hide = true, multiple_occurrences = true, number_of_values = 1)]
+    pub config_origin: Vec<String>,
+    /// The options of the chat script in the order they were given, set by from_matches()
+    #[clap(skip)]
+    pub script_order: Vec<ScriptArg>,
+    /// The port settings that were given rather than left at their default, set by
+    /// from_matches()
+    #[clap(skip)]
+    pub given_settings: Vec<&'static str>,
+
+    /// Set the device path to a serial port, or usb:VID:PID, serial:NUMBER, tcp://HOST:PORT or rfc2217://HOST:PORT
+    #[clap(
+        parse(from_str),
+        // fixed, the config file moves the positional arguments it sets a default for
+        index = 1,
+        env = "SCIPIO_DEVICE",
+        long_help = r"Set the device path to a serial port, or usb:VID:PID, serial:NUMBER, tcp://HOST:PORT or rfc2217://HOST:PORT
+
+Without a device the available serial ports are listed on the terminal to pick one from, the port settings then come from the config file or their defaults.
+usb:VID:PID and serial:NUMBER pick the USB adapter with these IDs in hex or this serial number, as shown by --list, whatever its path is. It is looked up again when reconnecting.
+A tcp:// device is a raw TCP connection to a serial server such as ser2net, the port settings and the control lines then have to be set on the server side. An rfc2217:// device negotiates the Telnet COM-PORT option, so the port settings are applied and ~b, ~d and ~r work as on a local port.
+"
+    )]
+    pub device: Option<String>,
+
+    /// Set the baud rate to connect at, or the device of a second port
+    #[clap(
+        name = "baud rate",
+        index = 2,
+        default_value = "9600",
+        env = "SCIPIO_BAUD",
+        long_help = r"Set the baud rate to connect at, or the device of a second port
+
+Common values: 300, 1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200, 230400, 460800, 500000, 576000, 921600, 1000000, 1152000, 1500000, 2000000, 2500000, 3000000, 3500000, 4000000
+A value that doesn't start with a digit is a second device, both ports are then shown in a split view, one pane above the other: scip /dev/ttyUSB0 /dev/ttyUSB1 --baud 115200,921600. What each port receives goes only to its pane and its log file, --log for the first and --split-log for the second. The keyboard goes to the pane with the highlighted title, ~<Tab> moves it to the other one, and ~p pauses the focused pane alone. Escape sequences the devices send are dropped and the other control characters are shown in caret notation, so neither pane can draw over the other. A port that goes away leaves the other one running, with --reconnect it is opened again once it is back. The other escape commands and the session options for a single port aren't available. Two devices at most can be given.
+"
+    )]
+    pub baud_rate: RateOrDevice,
+    /// Set the baud rate of each device, separated by commas
+    #[clap(
+        long,
+        value_name = "RATES",
+        long_help = r"Set the baud rate of each device, separated by commas
+
+Like 115200,921600 for the two devices of a split view, or a single rate for every device. It takes precedence over the baud rate argument and SCIPIO_BAUD, and a second device given in place of the baud rate connects both ports at 9600 without it.
+"
+    )]
+    pub baud: Option<String>,
+
+    /// Connect at a baud rate that looks like a mistyped common one
+    #[clap(
+        long,
+        long_help = r"Connect at a baud rate that looks like a mistyped common one
+
+A rate that isn't one of the common values but is within 5% of one, or a zero short of one or a zero too many, like 11520 or 96000, is refused without this. Other unusual rates connect with a note, as do rates devices use on purpose like 74880, 250000 or 256000.
+"
+    )]
+    pub force_baud: bool,
+
+    /// Set the number of bits used per character, 5 to 8
+    #[clap(
+        name = "data bits",
+        index = 3,
+        default_value = "8",
+        env = "SCIPIO_DATA_BITS",
+        validator = data_bits_value,
+    )]
+    pub data_bits: u8,
+    /// Set the parity checking mode
+    #[clap(
+        name = "parity",
+        index = 4,
+        default_value = "none",
+        env = "SCIPIO_PARITY",
+        ignore_case = true,
+        possible_values = [
+            PossibleValue::new("none").alias("n"),
+            PossibleValue::new("odd").alias("o"),
+            PossibleValue::new("even").alias("e"),
+            PossibleValue::new("mark").alias("m"),
+            PossibleValue::new("space").alias("s"),
+        ],
+        long_help = r"Set the parity checking mode
+
+Possible values, in any case:
+    - none, N  => No parity bit
+    - odd, O   => Odd
+    - even, E  => Even
+    - mark, M  => Mark, the parity bit is always 1 (Linux and rfc2217:// only)
+    - space, S => Space, the parity bit is always 0 (Linux and rfc2217:// only)
+"
+    )]
+    pub parity: String,
+    /// Set the number of stop bits transmitted after every character
+    #[clap(
+        name = "stop bits",
+        index = 5,
+        default_value = "1",
+        env = "SCIPIO_STOP_BITS",
+        possible_values = &["1", "2"],
+    )]
+    pub stop_bits: u8,
+    /// Set the flow control mode
+    #[clap(
+        name = "flow control",
+        index = 6,
+        default_value = "none",
+        env = "SCIPIO_FLOW_CONTROL",
+        ignore_case = true,
+        possible_values = [
+            PossibleValue::new("none").alias("n"),
+            PossibleValue::new("rtscts").aliases(["hardware", "h"]),
+            PossibleValue::new("xonxoff").aliases(["software", "s"]),
+        ],
+        long_help = r"Set the flow control mode
+
+Possible values, in any case:
+    - none, N               => No flow control
+    - rtscts, hardware, H   => The RTS and CTS lines
+    - xonxoff, software, S  => The XON and XOFF bytes sent in the data
+"
+    )]
+    pub flow_control: String,
+
+    /// Without a device, connect to the only serial port there is without asking
+    #[clap(long)]
+    pub auto: bool,
+    /// Without a device, wait for a serial port to show up when there is none
+    #[clap(long)]
+    pub wait: bool,
+    /// Talk to the device through two FIFOs, reading RX and writing TX
+    #[cfg(unix)]
+    #[clap(
+        long,
+        value_name = "RX:TX",
+        conflicts_with = "device",
+        long_help = r"Talk to the device through two FIFOs, reading RX and writing TX
+
+Instead of a device: for an emulator or a program that makes its console a pair of named pipes. A device path PATH where PATH.in and PATH.out are FIFOs, as made for QEMU's -serial pipe:PATH, is taken the same way, as --fifo PATH.out:PATH.in. An existing Unix domain socket as the device, like that of QEMU's -serial unix:PATH,server, is connected to.
+The bytes go through as they are, so the port settings don't apply and a baud rate or parity given is ignored with a warning, and ~b, ~d and ~r aren't available. The other end closing the socket or the RX FIFO is a disconnect, ended or taken up again as --reconnect says.
+"
+    )]
+    pub fifo: Option<String>,
+
+    /// Keep trying to open a busy port for this many seconds
+    #[clap(
+        long,
+        value_name = "SECS",
+        long_help = r"Keep trying to open a busy port for this many seconds
+
+ModemManager and udev rules often hold a USB adapter, or haven't given it its group yet, for a few seconds after it was plugged in. Opening the port is then retried with growing pauses while it fails as busy, with permission denied or an I/O error. A port that doesn't exist or settings it doesn't take still fail right away.
+"
+    )]
+    pub retry_open: Option<u64>,
+    /// Don't create a UUCP lock file for the port, nor look for one
+    #[clap(long)]
+    pub no_lock: bool,
+    /// Put the lock file of the port into this directory
+    #[clap(
+        long,
+        value_name = "DIR",
+        conflicts_with = "no-lock",
+        long_help = r"Put the lock file of the port into this directory
+
+The port is locked with a file like LCK..ttyUSB0 holding the PID, as minicom, picocom and most flashing tools do, in /var/lock or else /run/lock. A lock of a program that's still running keeps the port from being opened, a stale one is removed.
+"
+    )]
+    pub lock_dir: Option<PathBuf>,
+    /// Find the baud rate from what the device sends
+    #[clap(
+        long,
+        long_help = r"Find the baud rate from what the device sends
+
+Before the session starts, the port is listened to for half a second at each of the common baud rates, or those of --auto-baud-rates, after sending --auto-baud-probe if given. The rate at which most of the received bytes are printable text or valid UTF-8 is used. When nothing readable arrives at any of them, the baud rate given is used.
+"
+    )]
+    pub auto_baud: bool,
+    /// Send this to the device at each rate --auto-baud tries, like \r to get a prompt
+    #[clap(long, value_name = "STRING", requires = "auto-baud")]
+    pub auto_baud_probe: Option<String>,
+    /// The comma separated baud rates for --auto-baud to try
+    #[clap(long, value_name = "LIST", requires = "auto-baud")]
+    pub auto_baud_rates: Option<String>,
+
+    /// Wait for the device and reconnect when it disappears
+    #[clap(long, env = "SCIPIO_RECONNECT")]
+    pub reconnect: bool,
+    /// Set the time in milliseconds to wait after a failed reconnection attempt
+    #[clap(long, default_value = "1000", value_name = "MS")]
+    pub reconnect_delay: u64,
+    /// Give up after this many failed reconnection attempts
+    #[clap(long, value_name = "COUNT", requires = "reconnect")]
+    pub reconnect_retries: Option<u32>,
+    /// Give up waiting for the device after this many seconds
+    #[clap(
+        long,
+        value_name = "SECS",
+        requires = "reconnect",
+        long_help = r"Give up waiting for the device after this many seconds
+
+The program then exits with status 4.
+"
+    )]
+    pub wait_timeout: Option<u64>,
+    /// Drop what the port received before it was opened, again after every reconnect
+    #[clap(
+        long,
+        long_help = r"Drop what the port received before it was opened, again after every reconnect
+
+A device that was talking for hours has filled the buffer of the port with old output, which would all show at once. How many bytes were dropped is shown. Only a local port can be flushed, use --settle over the network.
+"
+    )]
+    pub flush_on_connect: bool,
+    /// Drop what the device sends during the first MS milliseconds as well
+    #[clap(
+        long,
+        value_name = "MS",
+        long_help = r"Drop what the device sends during the first MS milliseconds as well
+
+The port is flushed as with --flush-on-connect, then what arrives until the time is up is dropped too, like the rest of a line the device was in the middle of. The escape commands work meanwhile, ~. ends the session. Only the first connection settles, what a device sends after a reconnect is shown.
+"
+    )]
+    pub settle: Option<u64>,
+    /// Ring the bell or show a desktop notification when the device connects or disconnects
+    #[clap(
+        long,
+        value_name = "HOW",
+        possible_values = &["bell", "desktop", "both"],
+        long_help = r"Ring the bell or show a desktop notification when the device connects or disconnects
+
+Possible values:
+    - bell    => Ring the terminal bell
+    - desktop => Show a desktop notification with notify-send, naming the device and the profile
+    - both    => Do both
+
+This happens when the port was opened, when the device disconnected and when --reconnect opened it again. A desktop notification that can't be shown is left out silently.
+"
+    )]
+    pub notify: Option<String>,
+    /// Never send anything to the port, only show what it receives
+    #[clap(
+        long,
+        env = "SCIPIO_READ_ONLY",
+        conflicts_with_all = &[
+            "write-only", "dtr", "rts", "hangup-on-exit", "reset", "keepalive", "auto-baud-probe",
+            "send-hex", "send-file", "send-file-verify", "send-text", "send-ihex", "send-srec",
+            "send", "command", "answerback", "auto-respond", "init",
+        ],
+        long_help = r"Never send anything to the port, only show what it receives
+
+For a port another program drives, or a sniffer tap. Typed keys are dropped, and so is what --listen clients send, only the escape commands that act locally work. The output lines are left alone, also when the port is closed (Linux only), and ZMODEM transfers aren't started.
+"
+    )]
+    pub read_only: bool,
+    /// Don't show what the port receives, only send to it
+    #[clap(
+        long,
+        long_help = r"Don't show what the port receives, only send to it
+
+For a port whose RX is wired elsewhere. The received data still goes to the log file, captures and --listen clients.
+"
+    )]
+    pub write_only: bool,
+    /// Answer the cursor position, device attributes and window size queries of the device
+    #[clap(
+        long,
+        long_help = r"Answer the cursor position, device attributes and window size queries of the device
+
+For the consoles of embedded Linux and full-screen programs on the device, like vi or menuconfig, that find out the terminal size by asking. scip answers DSR (ESC[5n), the cursor position report (ESC[6n), DA (ESC[c) and the xterm window size reports (ESC[18t, ESC[19t) itself and takes them out of the output, all other escape sequences are passed on. The size is the one of the terminal without the rows scip keeps at the bottom, read again when it is resized, or the one of --term-size. The cursor position follows the output of the device, which doesn't know about the lines scip shows.
+"
+    )]
+    pub answerback: bool,
+    /// Tell the device a terminal size of ROWS,COLS with --answerback
+    #[clap(long, value_name = "ROWS,COLS", requires = "answerback")]
+    pub term_size: Option<String>,
+
+    /// Append all received data to a log file
+    #[clap(
+        long,
+        value_name = "PATH",
+        long_help = r"Append all received data to a log file
+
+Placeholders like %Y%m%d-%H%M%S in the path are replaced with the date and time the session started.
+"
+    )]
+    pub log: Option<String>,
+    /// Truncate the log file instead of appending to it
+    #[clap(long, requires = "log")]
+    pub log_truncate: bool,
+    /// Set how the log file is written, by --log and by ~o
+    #[clap(
+        long,
+        value_name = "FORMAT",
+        default_value = "raw",
+        possible_values = &["raw", "pretty"],
+        long_help = r"Set how the log file is written, by --log and by ~o
+
+Possible values:
+    - raw    => The received data byte for byte, as shown
+    - pretty => A line per record with the time and direction, like
+                2024-05-01T12:00:00.123Z RX <data>
+
+A pretty log has the sent data as TX records between the received data. A record ends at a newline or when the direction changes, bytes other than printable ASCII are written as \xNN.
+"
+    )]
+    pub log_format: String,
+    /// Move the log to NAME.1 and start a new one when it grows past this many bytes
+    #[clap(
+        long,
+        value_name = "BYTES",
+        long_help = r"Move the log to NAME.1 and start a new one when it grows past this many bytes
+
+The older ones move on to NAME.2 and so on, up to --log-rotate-keep files, the oldest is removed. Each step is a rename, so a crash during the rotation leaves the data in one of the files. A pretty log starts every new file with a line noting the rotation. This applies to --log and the logs ~o starts.
+"
+    )]
+    pub log_rotate_size: Option<u64>,
+    /// Keep this many rotated logs beside the current one
+    #[clap(
+        long,
+        value_name = "N",
+        default_value = "5",
+        requires = "log-rotate-size"
+    )]
+    pub log_rotate_keep: u32,
+    /// Put the log files started with ~o into this directory
+    #[clap(long, value_name = "DIR")]
+    pub log_dir: Option<PathBuf>,
+    /// Leave the ~m markers out of a raw log and a capture, keeping them to the device's data
+    #[clap(long)]
+    pub no_raw_marks: bool,
+    /// Record the session with its timing, for scip replay
+    #[clap(
+        long,
+        value_name = "PATH",
+        long_help = r"Record the session with its timing, for scip replay
+
+All received and sent data is written as it went over the port together with the time between the chunks, so `scip replay PATH` shows the session again as it happened. Placeholders like %Y%m%d-%H%M%S in the path are replaced like in --log, an existing file is overwritten.
+"
+    )]
+    pub record: Option<String>,
+    /// Set how the recording is written
+    #[clap(
+        long,
+        value_name = "FORMAT",
+        default_value = "binary",
+        possible_values = &["binary", "asciicast"],
+        requires = "record",
+        long_help = r"Set how the recording is written
+
+Possible values:
+    - binary    => Frames of the direction, the microseconds since the previous frame and the
+                   data, played by scip replay
+    - asciicast => asciinema v2, played by asciinema play or its web player
+
+An asciicast recording has the received data as output and the sent data as input events, with the size of the terminal at the start.
+"
+    )]
+    pub record_format: String,
+
+    /// Keep a bar with the port, the byte counts, the session time and the lines at the bottom
+    #[clap(
+        long,
+        env = "SCIPIO_STATUS_BAR",
+        long_help = r"Keep a bar with the port, the byte counts, the session time and the lines at the bottom
+
+Like /dev/ttyUSB0 @ 115200 8N1 | RX 12.3 KB | TX 0.4 KB | 00:12:41 | DTR RTS CTS, followed by LOG while logging, CAPTURE while capturing, LINE in line mode, HEX when --control-style is hex, READ-ONLY with --read-only and XOFF while the device holds off what is sent with software flow control. The lines listed are the asserted ones. The bar is brought up to date a few times a second and left out when the output goes to a pipe.
+"
+    )]
+    pub status_bar: bool,
+    /// Show CTS, DSR, DCD and RI in a status row at the bottom
+    #[clap(
+        long,
+        long_help = r"Show CTS, DSR, DCD and RI in a status row at the bottom
+
+The lines are read every 100 ms, one that just changed is shown inverted and one that can't be read as ?. Without a terminal the changes are reported on stderr.
+"
+    )]
+    pub show_lines: bool,
+    /// Write every change of CTS, DSR, DCD and RI to the log file
+    #[clap(long, requires = "log")]
+    pub log_lines: bool,
+    /// Show the throughput of the last SECS seconds in a status row at the bottom
+    #[clap(
+        long,
+        value_name = "SECS",
+        long_help = r"Show the throughput of the last SECS seconds in a status row at the bottom
+
+The bytes per second sent and received are shown next to the lines of --show-lines. Without a terminal they are reported on stderr.
+"
+    )]
+    pub stats_interval: Option<u64>,
+
+    /// Report every step of a reset sequence, -vv and -vvv write debug output as well
+    #[clap(
+        short,
+        long,
+        parse(from_occurrences),
+        long_help = r"Report every step of a reset sequence, -vv and -vvv write debug output as well
+
+The debug output tells how the port was opened, each reconnect attempt, the sizes of the reads and writes, the errors and their kinds, the escape commands and the steps of the transfers. -v has the main events, -vv adds the details and -vvv every read and write. RUST_LOG=debug or RUST_LOG=serial_console=trace sets the level too.
+
+It goes to --debug-log, or to stderr when stderr isn't the terminal of the session, like with 2>scip.debug.
+"
+    )]
+    pub verbose: u64,
+    /// Append the debug output to this file, at least at the level of -vv
+    #[clap(long, value_name = "PATH")]
+    pub debug_log: Option<PathBuf>,
+
+    /// Set the time in milliseconds a break sent with ~b lasts
+    #[clap(long, default_value = "250", value_name = "MS")]
+    pub break_duration: u64,
+
+    /// Set the DTR line right after opening the port
+    #[clap(long, value_name = "LEVEL", possible_values = &["on", "off"])]
+    pub dtr: Option<String>,
+    /// Set the RTS line right after opening the port
+    #[clap(long, value_name = "LEVEL", possible_values = &["on", "off"])]
+    pub rts: Option<String>,
+    /// Keep the board from being reset by closing and opening the port (Linux only)
+    #[clap(
+        long,
+        conflicts_with = "reset",
+        long_help = r"Keep the board from being reset by closing and opening the port (Linux only)
+
+Boards like the Arduino reset when DTR is asserted. Linux asserts DTR and RTS whenever the port is opened and drops them when it is closed, unless the HUPCL flag is cleared. This clears it, so after the first session the lines stay asserted and the next open doesn't change them. --dtr and --rts are applied right after opening, before anything is read.
+"
+    )]
+    pub no_reset: bool,
+    /// Drop DTR and RTS before closing the port on exit
+    #[clap(long, conflicts_with = "no-reset")]
+    pub hangup_on_exit: bool,
+    /// Reset the board after connecting by toggling DTR and RTS, ~R does it again
+    #[clap(
+        long,
+        value_name = "PROFILE",
+        long_help = r"Reset the board after connecting by toggling DTR and RTS, ~R does it again
+
+Possible values:
+    - arduino     => pulse DTR low for 250 ms, like avrdude
+    - esp32       => hold EN low with RTS while IO0 is pulled low with DTR, like esptool
+    - custom:SPEC => steps like dtr=0,rts=1,sleep=100,dtr=1 where 1 asserts the line
+
+The lines are left as --dtr and --rts set them, or released.
+"
+    )]
+    pub reset: Option<String>,
+
+    /// Send bytes when nothing was written to the port for this many seconds
+    #[clap(
+        long,
+        value_name = "SECS[:BYTES]",
+        long_help = r"Send bytes when nothing was written to the port for this many seconds
+
+The bytes take the escapes of --send and are a single NUL if left out, e.g. 60 or 30:\r. They are not echoed, and not sent during file transfers. ~k turns this off and on.
+"
+    )]
+    pub keepalive: Option<String>,
+    /// Set how long in milliseconds to retry a write the device does not accept
+    #[clap(long, default_value = "5000", value_name = "MS")]
+    pub write_timeout: u64,
+    /// Set how many bytes are read from the port and the terminal at once
+    #[clap(
+        long,
+        default_value = "512",
+        value_name = "BYTES",
+        long_help = r"Set how many bytes are read from the port and the terminal at once
+
+At high baud rates like 3000000 a larger buffer, e.g. 16384, takes up what arrived in fewer reads and keeps the device's data from piling up in the driver.
+"
+    )]
+    pub read_buffer: usize,
+    /// Set how long in milliseconds a single write to the port waits before it is retried
+    #[clap(
+        long,
+        default_value = "10",
+        value_name = "MS",
+        long_help = r"Set how long in milliseconds a single write to the port waits before it is retried
+
+This is the timeout of the port itself, and of the socket reads while a tcp:// or rfc2217:// connection is set up. --write-timeout is how long the retries go on.
+"
+    )]
+    pub timeout_ms: u64,
+
+    /// Prefix every received line with a timestamp
+    #[clap(
+        long,
+        value_name = "CLOCK",
+        min_values = 0,
+        max_values = 1,
+        default_missing_value = "wall",
+        possible_values = &["wall", "relative", "delta", "both"],
+        long_help = r"Prefix every received line with a timestamp
+
+Possible values:
+    - wall     => time of day as HH:MM:SS.mmm (used when no value is given)
+    - relative => seconds since connecting
+    - delta    => time since the previous line started, like +   3.2ms
+    - both     => time of day and time since the previous line
+"
+    )]
+    pub timestamp: Option<String>,
+    /// Show a line like --- 38 ms --- where the port was silent for longer than MS
+    #[clap(
+        long,
+        value_name = "MS",
+        long_help = r"Show a line like --- 38 ms --- where the port was silent for longer than MS
+
+The time is taken when the data is read from the port, so gaps much shorter than the 10 ms or so a USB adapter buffers its data for aren't seen. A marker in the middle of a line ends it first, and a UTF-8 character split by the gap is shown in front of the marker. Only the screen shows the markers, with --log-gaps the log file gets them too.
+"
+    )]
+    pub gap_marker: Option<u64>,
+    /// Write the gaps of --gap-marker to the log file
+    #[clap(long, requires_all = &["gap-marker", "log"])]
+    pub log_gaps: bool,
+    /// Show received control characters instead of letting the terminal carry them out
+    #[clap(
+        long,
+        value_name = "WHICH",
+        min_values = 0,
+        max_values = 1,
+        default_missing_value = "other",
+        possible_values = &["other", "all"],
+        long_help = r"Show received control characters instead of letting the terminal carry them out
+
+Possible values:
+    - other => all but newline, carriage return and tab (used when no value is given)
+    - all   => newline, carriage return and tab too, a newline still ends the line
+
+Only the screen shows them, the log, the capture and the clients get the bytes as received.
+"
+    )]
+    pub show_control: Option<String>,
+    /// Set how --show-control shows a control character
+    #[clap(
+        long,
+        value_name = "STYLE",
+        default_value = "caret",
+        possible_values = &["caret", "hex"],
+        requires = "show-control",
+        long_help = r"Set how --show-control shows a control character
+
+Possible values:
+    - caret => in caret notation like ^M, ^[ and ^@
+    - hex   => in hex like <1B>, in reverse video
+"
+    )]
+    pub control_style: String,
+    /// Drop the ANSI escape sequences the device sends before showing the data
+    #[clap(long)]
+    pub strip_ansi: bool,
+    /// Show received data as UTF-8 text, with broken sequences in the --invalid-utf8 style
+    #[clap(
+        long,
+        long_help = r"Show received data as UTF-8 text, with broken sequences in the --invalid-utf8 style
+
+A character split across reads is put together again, only its bytes are held back until the rest arrives. Bytes that aren't UTF-8 and the C1 controls U+0080 to U+009F are shown in the --invalid-utf8 style instead of reaching the terminal. Only the screen shows this, the log, the capture and the clients get the bytes as received.
+"
+    )]
+    pub utf8: bool,
+    /// Set how --utf8 shows what isn't UTF-8
+    #[clap(
+        long,
+        value_name = "STYLE",
+        default_value = "replacement",
+        possible_values = &["replacement", "hex"],
+        requires = "utf8",
+        long_help = r"Set how --utf8 shows what isn't UTF-8
+
+Possible values:
+    - replacement => a U+FFFD replacement character for each broken sequence
+    - hex         => each byte in hex like <C3>, in reverse video
+"
+    )]
+    pub invalid_utf8: String,
+    /// Check the checksums of the NMEA 0183 sentences a GPS receiver sends
+    #[clap(
+        long,
+        long_help = r"Check the checksums of the NMEA 0183 sentences a GPS receiver sends
+
+A sentence whose *hh checksum doesn't match the one of its data, or that has none, is shown in red with the expected checksum after it. A sentence split across reads is checked once its line ends. Anything else, like the binary UBX messages of a u-blox receiver, is shown as it came. ~n sends a sentence with its checksum added. Only the screen shows this, the log, the capture and the clients get the bytes as received.
+"
+    )]
+    pub nmea: bool,
+    /// Show only these NMEA sentence types, like GGA,RMC
+    #[clap(
+        long,
+        value_name = "TYPES",
+        requires = "nmea",
+        long_help = r"Show only these NMEA sentence types, like GGA,RMC
+
+A type without the talker, like GGA, matches it from any talker, $GPGGA and $GNGGA alike. A whole address like GPGSV or PUBX matches only itself. The other sentences aren't shown, the rest of the data is.
+"
+    )]
+    pub nmea_filter: Option<String>,
+    /// Show the received data as the frames of a protocol
+    #[clap(
+        long,
+        value_name = "PROTOCOL",
+        possible_values = &["modbus-rtu"],
+        long_help = r"Show the received data as the frames of a protocol
+
+Possible values:
+    - modbus-rtu => Modbus RTU, a frame ends after 3.5 character times of silence, 1.75 ms above 19200 baud
+
+Every frame is shown on a line of its own, its bytes in hex followed by the address, the function and what it asks for or answers, an exception, and whether the CRC is right. Of a function that isn't decoded only the bytes are shown. A request and its response that arrive in one read, as some USB adapters deliver them, are told apart by their CRCs. Only the screen shows the frames, the log, the capture and the clients get the bytes as received.
+"
+    )]
+    pub decode: Option<String>,
+    /// Show every chunk sent and received as a line of hex with its direction
+    #[clap(
+        long,
+        conflicts_with_all = &["decode", "nmea", "write-only"],
+        long_help = r"Show every chunk sent and received as a line of hex with its direction
+
+In place of the received data, each read from the port and each write to it gets a line with the time, -> for sent or <- for received and the bytes in hex, like
+    12:00:01.250 -> 01 03 00 10 00 02 c5 ce
+    12:00:01.262 <- 01 03 04 00 2a 00 07 9b f1
+Longer chunks go on over several lines of 16 bytes. Sent lines are yellow and received ones green, local echo is left out. A pretty log gets the same lines with its own timestamps in place of its RX and TX records, the raw log, the capture and the clients get the bytes as received.
+"
+    )]
+    pub debug_view: bool,
+    /// Add the printable characters after the hex of --debug-view
+    #[clap(long, requires = "debug-view")]
+    pub debug_ascii: bool,
+
+    /// Translate the line endings sent to the device
+    #[clap(
+        long,
+        value_name = "MODE",
+        default_value = "raw",
+        possible_values = &["raw", "cr", "lf", "crlf"],
+        long_help = r"Translate the line endings sent to the device
+
+Every CR, LF or CR+LF typed or pasted is sent as the given line ending.
+Possible values:
+    - raw  => send line endings unchanged
+    - cr   => \r
+    - lf   => \n
+    - crlf => \r\n
+"
+    )]
+    pub newline: String,
+    /// Translate the line endings received from the device
+    #[clap(
+        long,
+        value_name = "MODE",
+        default_value = "raw",
+        possible_values = &["raw", "cr", "lf", "crlf"],
+        long_help = r"Translate the line endings received from the device
+
+Use crlf to display a lone CR or LF from the device as a proper new line.
+Possible values are the same as for --newline.
+"
+    )]
+    pub rx_newline: String,
+
+    /// Show typed characters locally for devices that do not echo
+    #[clap(long, env = "SCIPIO_ECHO")]
+    pub echo: bool,
+
+    /// Edit lines locally and send them on Enter, with a history on the arrow keys
+    #[clap(
+        long,
+        env = "SCIPIO_LINE_MODE",
+        long_help = r"Edit lines locally and send them on Enter, with a history on the arrow keys
+
+The line is shown on the bottom row while the output scrolls above it. Ctrl-C clears it, or is sent to the device when the line is empty. Escape commands work from an empty line, ~l turns line mode on and off.
+"
+    )]
+    pub line_mode: bool,
+    /// Keep the history of line mode and the ~h, ~n and ~B prompts for this session only
+    #[clap(
+        long,
+        long_help = r"Keep the history of line mode and the ~h, ~n and ~B prompts for this session only
+
+The lines sent in line mode are saved to $XDG_STATE_HOME/scipio/history (~/.local/state/scipio/history by default), the answers to ~h, ~n and ~B to history-hex, history-nmea and history-baud beside it, and loaded the next time. Ctrl-R in line mode searches the history.
+
+The last lines typed, which ~A sends again, are only ever kept in memory. With this option they are not kept at all.
+"
+    )]
+    pub no_history: bool,
+    /// Keep this many entries of each history
+    #[clap(long, default_value = "1000", value_name = "COUNT")]
+    pub history_size: usize,
+
+    /// Set when to color the output
+    #[clap(
+        long,
+        value_name = "WHEN",
+        default_value = "auto",
+        possible_values = &["auto", "always", "never"],
+        long_help = r"Set when to color the output
+
+Possible values:
+    - auto   => when stdout is a terminal and NO_COLOR is not set
+    - always
+    - never
+"
+    )]
+    pub color: String,
+    /// Color the matches of this regular expression in the output
+    #[clap(
+        long,
+        value_name = "REGEX[:COLOR]",
+        multiple_occurrences = true,
+        number_of_values = 1,
+        long_help = r"Color the matches of this regular expression in the output
+
+Can be given several times. COLOR is one of black, red, green, yellow, blue, magenta, cyan, white and dim, red if it is left out. Only the screen is colored, not the log file, and nothing is highlighted when --color turns colors off.
+"
+    )]
+    pub highlight: Vec<String>,
+    /// Color, hide or ring the bell for received lines matching a regular expression
+    #[clap(
+        long,
+        value_name = "ACTION:REGEX",
+        multiple_occurrences = true,
+        number_of_values = 1,
+        long_help = r"Color, hide or ring the bell for received lines matching a regular expression
+
+ACTION is one of
+    - color=COLOR => color the matches like --highlight
+    - hide        => show neither the line on the screen nor write it to the log file
+    - log-only    => write the line to the log file but don't show it
+    - bell        => ring the bell after the line
+
+Can be given several times, the [[rules]] of the config file come first. The rules apply in order: the first hide or log-only rule matching a line decides whether it is logged, and of overlapping colored matches the one that starts first wins, then the earlier rule. A line is only matched once it is complete or has waited for its end for 30 ms. With hide rules a raw log is written line by line.
+"
+    )]
+    pub rule: Vec<String>,
+    /// Ignore --rule and the rules of the config file
+    #[clap(long)]
+    pub no_rules: bool,
+    /// Color whole received lines by the severity of the log format they are in
+    #[clap(
+        long,
+        value_name = "PRESET",
+        long_help = r"Color whole received lines by the severity of the log format they are in
+
+PRESET is one of
+    - esp-idf => E (123) tag: lines red, W yellow, I green, D cyan and V dim
+    - kernel  => the printk levels <0> to <7>, or error and warning messages of [    1.234567] lines
+    - none    => nothing
+
+The config file adds presets, or entries in front of those of a built-in one, with [log_colors.NAME] tables of REGEX = COLOR. Where several entries match a line, the match starting first decides. Only the screen is colored, neither the log file nor captures, and nothing is colored when --color turns colors off.
+"
+    )]
+    pub log_colors: Option<String>,
+    /// An entry of a [log_colors.NAME] table of the config file, as NAME:COLOR:REGEX
+    #[clap(long, hide = true, multiple_occurrences = true, number_of_values = 1)]
+    pub log_color_entry: Vec<String>,
+    /// Act when a received line matches this regular expression
+    #[clap(
+        long,
+        value_name = "REGEX:ACTION",
+        multiple_occurrences = true,
+        number_of_values = 1,
+        long_help = r"Act when a received line matches this regular expression
+
+Can be given several times, all of them are active. ACTION is one of:
+    - bell          => ring the terminal bell
+    - exit          => close the port and exit with status 6
+    - log-mark      => write a marker line to the --log file
+    - exec:COMMAND  => run COMMAND with the shell, without waiting for it. It gets the line on stdin and in $SCIPIO_MATCH
+"
+    )]
+    pub on_match: Vec<String>,
+    /// Send REPLY when a received line matches REGEX
+    #[clap(
+        long,
+        value_name = "REGEX=REPLY",
+        multiple_occurrences = true,
+        number_of_values = 1,
+        long_help = r"Send REPLY when a received line matches REGEX
+
+For devices asking the same questions every time, like Press y to continue. The regex ends at the first =, an = in it is written \=: 'mode\=1\?=y' answers mode=1? with y. REPLY takes the escapes of --send, like y\r for y and Enter. A prompt is answered without waiting for its line to end, once per line. Each rule answers up to --auto-respond-max times, ~y turns them off and on. The [autorespond] table of the config file has rules too, as regex = reply.
+"
+    )]
+    pub auto_respond: Vec<String>,
+    /// Wait MS before sending an --auto-respond reply
+    #[clap(
+        long,
+        value_name = "MS",
+        default_value = "0",
+        requires = "auto-respond"
+    )]
+    pub auto_respond_delay: u64,
+    /// Send each --auto-respond reply at most N times
+    #[clap(long, value_name = "N", default_value = "5", requires = "auto-respond")]
+    pub auto_respond_max: u32,
+    /// Set the character that starts escape commands, like ^] for Ctrl-]
+    #[clap(long, value_name = "CHAR", default_value = "~")]
+    pub escape_char: String,
+    /// Send every typed byte to the port, there are no escape commands
+    #[clap(
+        long,
+        long_help = r"Send every typed byte to the port, there are no escape commands
+
+Nothing typed ends the session then, send the program SIGINT or SIGTERM instead, e.g. with kill -INT. It exits with status 130.
+"
+    )]
+    pub no_escape: bool,
+    /// Quit with a key chord like minicom's ctrl-a q, commands follow the first key directly
+    #[clap(
+        long,
+        value_name = "KEYS",
+        long_help = r"Quit with a key chord like minicom's ctrl-a q, commands follow the first key directly
+
+For keyboards where ~ is a dead key. The first key is a control key, written ctrl-a or ^A. It is followed by the key that quits, or by the key of any escape command, like ctrl-a b for a break, without an Enter before. Pressing it twice sends it. Given alone, like ctrl-], it quits by itself.
+
+The escape character keeps working beside it, unless --no-escape turns it off.
+"
+    )]
+    pub quit_key: Option<String>,
+    /// Let Ctrl-C end the session instead of sending it to the device
+    #[clap(
+        long,
+        value_name = "SWITCH",
+        default_value = "off",
+        possible_values = &["on", "off"],
+        long_help = r"Let Ctrl-C end the session instead of sending it to the device
+
+off sends Ctrl-C to the device like any other key. on ends the session with status 130, like SIGINT does; ~K followed by Ctrl-C still sends it then.
+"
+    )]
+    pub intercept_sigint: String,
+    /// Stay on the normal screen so the session remains in the scrollback after exit
+    #[clap(long)]
+    pub no_alt_screen: bool,
+    /// Keep this many lines of the output for ~[ to page through and search
+    #[clap(
+        long,
+        default_value = "5000",
+        value_name = "LINES",
+        long_help = r"Keep this many lines of the output for ~[ to page through and search
+
+The alternate screen has no scrollback of its own. ~[ or PageUp shows the kept lines instead of the live output: the arrows, PageUp, PageDown, Home and End move through them, / searches forward and ? backward for a regular expression, n and N search again, q or Esc go back. Nothing typed meanwhile is sent, what arrives is kept and shown after going back.
+
+The lines are kept as text without colors, at most 1024 bytes each, in memory only. 0 keeps none and sends PageUp to the device.
+"
+    )]
+    pub scrollback: usize,
+    /// Pass bytes through without raw mode, escape commands or status lines
+    #[clap(
+        long,
+        long_help = r"Pass bytes through without raw mode, escape commands or status lines
+
+Received data goes to stdout unchanged and stdin is sent to the port, ~. included. This is also the mode when stdin or stdout is not a terminal. Status messages go to stderr.
+"
+    )]
+    pub pipe: bool,
+    /// Set what goes to stdout, json is an object per line for each event, for programs
+    #[clap(
+        long,
+        value_name = "FORMAT",
+        default_value = "raw",
+        possible_values = &["raw", "json"],
+        conflicts_with = "daemon",
+        long_help = r#"Set what goes to stdout, json is an object per line for each event, for programs
+
+With json the session runs as with --pipe, and stdout gets one object per line instead of the data:
+    {"t":"connect","ts":"2024-05-01T12:00:00.000Z","device":"/dev/ttyUSB0"}
+    {"t":"tx","ts":"...","data_b64":"QVQN"}
+    {"t":"rx","ts":"...","data_b64":"T0sNCg=="}
+    {"t":"match","ts":"...","rule":"PANIC","line":"PANIC at boot"}
+    {"t":"disconnect","ts":"...","reason":"device disconnected"}
+    {"t":"exit","ts":"...","code":0,"message":null,"stats":{"tx_bytes":4,"rx_bytes":4,...}}
+The data is as it went over the port, see --json-data for text instead of base64. A match is an --on-match or --auto-respond rule, and the exit object comes last with the exit status. Arguments the session can't start with only get the exit status and the message on stderr.
+"#
+    )]
+    pub output: String,
+    /// Set how --output json writes the data
+    #[clap(
+        long,
+        value_name = "ENCODING",
+        default_value = "base64",
+        possible_values = &["base64", "text"],
+        long_help = r"Set how --output json writes the data
+
+Possible values:
+    - base64 => the bytes in data_b64, as they are
+    - text   => lossy UTF-8 in data, a broken or split character becomes U+FFFD
+"
+    )]
+    pub json_data: String,
+    /// Exit when stdin reaches its end instead of only stopping to send
+    #[clap(
+        long,
+        long_help = r"Exit when stdin reaches its end instead of only stopping to send
+
+For --pipe and the other ways of passing bytes, e.g. with stdin from a file or /dev/null under a supervisor. The exit status is 0. A terminal going away only stops the sending of a session on it, as it ends with the terminal anyway.
+"
+    )]
+    pub exit_on_eof: bool,
+    /// Only log the port, without a terminal or stdin, for a service
+    #[clap(
+        long,
+        requires = "log",
+        conflicts_with_all = &["command", "exit-on-eof"],
+        long_help = r"Only log the port, without a terminal or stdin, for a service
+
+For a logger left attached to a device. Nothing is read from stdin and nothing is shown on stdout, the status messages and a line when starting and stopping go to stderr. The session reconnects when the device goes away, as with --reconnect. SIGHUP opens the log file again, so logrotate can move it away, and SIGTERM ends the session with the log file written and closed, exit status 0.
+
+It stays in the foreground, as systemd wants it, unless --detach is given.
+"
+    )]
+    pub daemon: bool,
+    /// Go to the background once the port and the log file are open (Unix only)
+    #[clap(long, requires = "daemon")]
+    pub detach: bool,
+    /// Write the PID of the --daemon to this file, it is removed on exit
+    #[clap(long, value_name = "PATH", requires = "daemon")]
+    pub pid_file: Option<PathBuf>,
+
+    /// Strip the trailing padding from the last block of files received with XMODEM
+    #[clap(long)]
+    pub xmodem_strip_eof: bool,
+    /// Ask for YMODEM-G when receiving with ~gy, streaming the blocks without an ACK each
+    #[clap(
+        long,
+        long_help = r"Ask for YMODEM-G when receiving with ~gy, streaming the blocks without an ACK each
+
+Much faster over USB adapters and other error free links, but a garbled block can't be sent again and fails the transfer.
+"
+    )]
+    pub ymodem_g: bool,
+    /// Show ZMODEM transfers as data instead of receiving the files
+    #[clap(
+        long,
+        long_help = r"Show ZMODEM transfers as data instead of receiving the files
+
+Otherwise running sz on the device is noticed and the files it sends are received into --download-dir.
+"
+    )]
+    pub no_zmodem: bool,
+    /// Set the directory files received with ZMODEM or YMODEM are written to
+    #[clap(long, default_value = ".", value_name = "DIR")]
+    pub download_dir: String,
+    /// Replace existing files when receiving with ZMODEM or YMODEM instead of adding a suffix like .1
+    #[clap(long)]
+    pub overwrite: bool,
+
+    /// Send this string right after connecting and after every reconnect, like ATE0\r
+    #[clap(
+        long,
+        value_name = "STRING",
+        long_help = r"Send this string right after connecting and after every reconnect, like ATE0\r
+
+The string takes the escapes of --send, and \~MS pauses for MS milliseconds, like \~1000+++\~1000 for the guard times a modem needs around +++. The bytes go out as given without the pauses of --char-delay, a pause starts once the bytes before it left the port, and nothing else is sent meanwhile. They are shown dimmed and logged as sent. It comes after --settle and before the other startup actions, Ctrl-C stops it.
+"
+    )]
+    pub init: Option<String>,
+    /// Send --init only after connecting, not again after a reconnect
+    #[clap(long, requires = "init")]
+    pub init_once: bool,
+    /// Send these bytes given in hex right after connecting, before the chat script
+    #[clap(long, value_name = "BYTES")]
+    pub send_hex: Option<String>,
+    /// Send a file right after connecting
+    #[clap(long, value_name = "PATH")]
+    pub send_file: Option<String>,
+    /// Send a file right after connecting, then check its SHA-256 on the device
+    #[clap(
+        long,
+        value_name = "PATH",
+        long_help = r"Send a file right after connecting, then check its SHA-256 on the device
+
+Once the file is sent like --send-file, --verify-command goes to the device and its answer is searched for a SHA-256 in hex, past the echo of the command and the prompts. The file is read a block at a time for its own digest. When the device doesn't answer with the same one within --expect-timeout, the session ends with status 5, and with --script-only it ends after a match too. ~V sends and checks a file the same way and only shows the result.
+"
+    )]
+    pub send_file_verify: Option<String>,
+    /// Set the path of the file of --send-file-verify on the device [default: the file's name]
+    #[clap(long, value_name = "PATH", requires = "send-file-verify")]
+    pub remote_path: Option<String>,
+    /// Set the command that has the device print the SHA-256 of a file from --send-file-verify and ~V
+    #[clap(
+        long,
+        value_name = "TEMPLATE",
+        default_value = r"sha256sum {remote_path}\n",
+        long_help = r"Set the command that has the device print the SHA-256 of a file from --send-file-verify and ~V
+
+The command takes the escapes of --send, {remote_path} is replaced by --remote-path or the name of the file. A file received with cat > PATH needs the end of input first, like \x04sha256sum {remote_path}\n.
+"
+    )]
+    pub verify_command: String,
+    /// Send a text file line by line right after connecting, as if typed
+    #[clap(
+        long,
+        value_name = "PATH",
+        long_help = r"Send a text file line by line right after connecting, as if typed
+
+Every line ends with --text-newline, whatever ending it has in the file. Lines left blank after removing the matches of --text-skip-regex are not sent. With --text-wait-prompt the next line is only sent once the device answered with the prompt. ~t sends a file the same way.
+"
+    )]
+    pub send_text: Option<String>,
+    /// Set what ends every line of --send-text and ~t
+    #[clap(long, value_name = "ENDING", default_value = "cr", possible_values = &["cr", "lf", "crlf"])]
+    pub text_newline: String,
+    /// Remove the matches of this regular expression from the lines of --send-text and ~t
+    #[clap(long, value_name = "REGEX")]
+    pub text_skip_regex: Option<String>,
+    /// Wait for the device to send this text after each line of --send-text and ~t
+    #[clap(
+        long,
+        value_name = "TEXT",
+        long_help = r"Wait for the device to send this text after each line of --send-text and ~t
+
+The text takes the escapes of --expect. When it doesn't show up within --text-wait-timeout, the upload stops on the question whether to send the line again, skip it or abort. Without a terminal it is aborted.
+"
+    )]
+    pub text_wait_prompt: Option<String>,
+    /// Set how many seconds to wait for the prompt of --text-wait-prompt
+    #[clap(
+        long,
+        default_value = "10",
+        value_name = "SECS",
+        requires = "text-wait-prompt"
+    )]
+    pub text_wait_timeout: u64,
+    /// Check an Intel HEX file and send it record by record right after connecting
+    #[clap(
+        long,
+        value_name = "PATH",
+        conflicts_with = "send-srec",
+        long_help = r"Check an Intel HEX file and send it record by record right after connecting
+
+The whole file is read first, a record with a bad checksum or length keeps anything from being sent. The records are then sent like the lines of --send-text, waiting for --text-wait-prompt after each one if given, like the . or ok ROM monitors answer with. A record whose prompt doesn't come is sent again up to --record-retries times, after that the session ends with exit status 3. The address range and count of the records are shown before, the time it took after.
+"
+    )]
+    pub send_ihex: Option<String>,
+    /// Check a Motorola S-record file and send it record by record right after connecting
+    #[clap(
+        long,
+        value_name = "PATH",
+        long_help = r"Check a Motorola S-record file and send it record by record right after connecting
+
+It is checked and sent like the file of --send-ihex.
+"
+    )]
+    pub send_srec: Option<String>,
+    /// Send a record of --send-ihex or --send-srec again this many times before giving up
+    #[clap(long, default_value = "3", value_name = "COUNT")]
+    pub record_retries: u32,
+    /// Set the pause in milliseconds after every character sent, typed or from a file
+    #[clap(
+        long,
+        default_value = "0",
+        value_name = "MS",
+        long_help = r"Set the pause in milliseconds after every character sent, typed or from a file
+
+Typed and pasted data waits in a queue while the pauses are taken, received data keeps showing meanwhile. ~a drops what is still queued.
+"
+    )]
+    pub char_delay: u64,
+    /// Set the pause in milliseconds after every line sent, typed or from a file
+    #[clap(long, default_value = "0", value_name = "MS")]
+    pub line_delay: u64,
+    /// Keep what is typed while a transfer or a script sends, to send it after
+    #[clap(
+        long,
+        value_name = "SWITCH",
+        default_value = "on",
+        possible_values = &["on", "off"],
+        long_help = r"Keep what is typed while a transfer or a script sends, to send it after
+
+While a file, a chat script or the output of ~! goes to the port, typed keys would land in the middle of it. on keeps them, up to 64 KiB, and sends them as if typed once it is over. off drops them with a note. Either way Ctrl-C stops what is sending, and ~., ~p and ~a work meanwhile without pressing Enter first, the other escape commands are refused.
+"
+    )]
+    pub tx_queue: String,
+
+    /// Share the session with TCP clients connecting to this address
+    #[clap(
+        long,
+        value_name = "ADDR:PORT",
+        long_help = r"Share the session with TCP clients connecting to this address
+
+All received data is mirrored to the clients and what they send is written to the port, e.g. --listen 0.0.0.0:7000 to let others watch with nc or telnet.
+"
+    )]
+    pub listen: Option<String>,
+    /// Ignore what the clients of --listen send
+    #[clap(long, requires = "listen")]
+    pub listen_read_only: bool,
+    /// Require clients of --listen to send this token as their first line
+    #[clap(long, value_name = "TOKEN", requires = "listen")]
+    pub listen_token: Option<String>,
+    /// Set how many clients of --listen may be connected at once
+    #[clap(long, default_value = "4", value_name = "COUNT")]
+    pub listen_max_clients: usize,
+    /// Bridge the port to a pseudo-terminal linked at this path, for another program
+    #[cfg(all(unix, feature = "pty"))]
+    #[clap(
+        long,
+        value_name = "LINK",
+        long_help = r"Bridge the port to a pseudo-terminal linked at this path, for another program
+
+For a flasher, pppd or any program that opens a serial port to use the device while the session goes on. What the port receives is shown and written to the pseudo-terminal, what the program writes to it is sent to the port and shown dimmed. The program may open and close it as often as it likes. While it doesn't read, up to a few KB are kept for it and the rest is dropped. The link is removed when scip exits, one left behind by an earlier session is replaced.
+"
+    )]
+    pub pty: Option<PathBuf>,
+    /// Log what the second port sends and receives to this file, like --log
+    #[clap(long, value_name = "PATH")]
+    pub split_log: Option<String>,
+
+    /// Wait for the device to send this text, part of a chat script run after connecting
+    #[clap(
+        long,
+        value_name = "PATTERN",
+        multiple_occurrences = true,
+        number_of_values = 1,
+        long_help = r"Wait for the device to send this text, part of a chat script run after connecting
+
+--expect, --expect-regex and --send steps are run in the order they are given, before the session turns interactive. If a pattern does not show up within --expect-timeout, the program exits with status 5.
+"
+    )]
+    pub expect: Vec<String>,
+    /// Wait for the device to send text matching this regular expression
+    #[clap(
+        long,
+        value_name = "REGEX",
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    pub expect_regex: Vec<String>,
+    /// Send this string as a step of the chat script
+    #[clap(
+        long,
+        value_name = "STRING",
+        multiple_occurrences = true,
+        number_of_values = 1,
+        long_help = r"Send this string as a step of the chat script
+
+Escapes: \r, \n, \t, \\ and \xNN for a byte in hex; \p pauses for a second.
+"
+    )]
+    pub send: Vec<String>,
+    /// Set how many seconds a step of the chat script waits for its pattern
+    #[clap(
+        long,
+        visible_alias = "timeout",
+        default_value = "10",
+        value_name = "SECS"
+    )]
+    pub expect_timeout: u64,
+    /// Exit once the chat script completed instead of going interactive
+    #[clap(long)]
+    pub script_only: bool,
+    /// A step of the startup = [...] list of the config file, run after connecting
+    #[clap(long, hide = true, multiple_occurrences = true, number_of_values = 1)]
+    pub startup_step: Vec<String>,
+    /// Leave out the startup actions of the config file
+    #[clap(long)]
+    pub skip_startup: bool,
+
+    /// Send this line, print the reply to stdout and exit
+    #[clap(
+        long,
+        value_name = "STRING",
+        multiple_occurrences = true,
+        number_of_values = 1,
+        long_help = r"Send this line, print the reply to stdout and exit
+
+The string is sent with Enter, translated by --newline, and understands the escapes of --send. The reply is printed until the --until pattern given after it shows up, or until --timeout passes when there is none. There is no raw mode, alternate screen or banner, and the program exits with status 0, or 5 if a pattern did not show up in time. --command and --until can be repeated and run in the order given, together with the steps of a chat script.
+"
+    )]
+    pub command: Vec<String>,
+    /// Wait for the device to send this text in reply to --command
+    #[clap(
+        long,
+        value_name = "PATTERN",
+        multiple_occurrences = true,
+        number_of_values = 1,
+        requires = "command"
+    )]
+    pub until: Vec<String>,
+}
+
+impl SessionConfig {
+    /// The options of `matches`, which were parsed with [`app`]. Unlike the `FromArgMatches`
+    /// of clap this keeps the order of the chat script and which port settings were given.
+    pub fn from_matches(matches: &ArgMatches) -> Result<SessionConfig, clap::Error> {
+        let mut config = SessionConfig::from_arg_matches(matches)?;
+        config.script_order = chat::script_args(matches);
+        config.given_settings = PORT_SETTINGS
+            .into_iter()
+            .filter(|name| {
+                matches
+                    .value_source(name)
+                    .is_some_and(|source| source != ValueSource::DefaultValue)
+            })
+            .collect();
+        if config.baud.is_some() && !config.given_settings.contains(&"baud rate") {
+            config.given_settings.insert(0, "baud rate");
+        }
+        Ok(config)
+    }
+}
+
+// The arguments of the port settings, which a socket or FIFOs don't have
+const PORT_SETTINGS: [&str; 5] = [
+    "baud rate",
+    "data bits",
+    "parity",
+    "stop bits",
+    "flow control",
+];
+
+/// The second positional argument, the baud rate or the device of a second port
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RateOrDevice {
+    Rate(u32),
+    /// The device of the second pane of a split view
+    Device(String),
+}
+
+impl FromStr for RateOrDevice {
+    type Err = String;
+
+    // A baud rate starts with a digit, a device path, COM3 or usb:VID:PID doesn't
+    fn from_str(value: &str) -> Result<RateOrDevice, String> {
+        if !value.starts_with(|c: char| c.is_ascii_digit()) {
+            return Ok(RateOrDevice::Device(value.to_string()));
+        }
+        value
+            .parse()
+            .map(RateOrDevice::Rate)
+            .map_err(|err| format!("{} isn't a baud rate: {}", value, err))
+    }
+}
+
+// Instead of possible values, so that a third device gets a clearer error than a wrong number
+// of data bits
+fn data_bits_value(value: &str) -> Result<(), String> {
+    match value {
+        "5" | "6" | "7" | "8" => Ok(()),
+        _ if value.starts_with(|c: char| c.is_ascii_digit()) => {
+            Err("the data bits are 5, 6, 7 or 8".to_string())
+        }
+        _ => Err(
+            "a third device can't be given, two ports at most are shown at once, \
+              or else the data bits are 5, 6, 7 or 8"
+                .to_string(),
+        ),
+    }
+}
+
+#[derive(Clone, Debug, Subcommand)]
+pub enum Command {
+    /// Print a completion script for a shell
+    #[clap(long_about = r"Print a completion script for a shell
+
+For bash add `source <(scip completions bash)` to ~/.bashrc, for zsh write the script to a file named _scip in a directory of $fpath, for fish to ~/.config/fish/completions/scip.fish. The device is completed with the serial ports present at that moment in bash, zsh and fish.")]
+    Completions {
+        #[clap(arg_enum)]
+        shell: Shell,
+    },
+    /// Show the received data of a --record recording with its original timing
+    #[clap(
+        long_about = r"Show the received data of a --record recording with its original timing
+
+No serial port is opened, the data is written to stdout as it came from the device. Ctrl-C stops the replay. Recordings in the asciicast format play with asciinema instead."
+    )]
+    Replay {
+        #[clap(value_name = "PATH")]
+        path: PathBuf,
+        /// Play this many times as fast, 0.5 is half the speed
+        #[clap(long, value_name = "FACTOR", default_value = "1.0")]
+        speed: f64,
+    },
+}
+
+// What to do with a line read by a prompt
+enum PromptAction {
+    SendFile,
+    SendFileVerify,
+    SendText,
+    Transfer(Transfer),
+    BaudRate,
+    Capture,
+    SendHex,
+    SendNmea,
+    Reset,
+    PipeCommand,
+    XonXoff,
+    Marker,
+    Repeat,
+    Log,
+}
+
+// The baud rate without one given
+const DEFAULT_BAUD_RATE: u32 = 9600;
+
+// The common values listed in the help of the baud rate, stepped through by ~B+ and ~B-
+const COMMON_BAUD_RATES: &[u32] = &[
+    300, 1200, 2400, 4800, 9600, 19200, 38400, 57600, 115200, 230400, 460800, 500000, 576000,
+    921600, 1000000, 1152000, 1500000, 2000000, 2500000, 3000000, 3500000, 4000000,
+];
+
+// Within this fraction of a common rate an unusual rate is taken for a typo
+const BAUD_TYPO_BAND: f64 = 0.05;
+
+// Rates devices use on purpose that are close to a common one, or to ten times or a tenth of
+// one: MIDI, the ESP8266 boot messages, SBUS, Marlin and DMX, the standard rates of modems,
+// Windows and UARTs that aren't in the list above, and those of fast USB adapters
+const DELIBERATE_BAUD_RATES: &[u32] = &[
+    31250, 74880, 100000, 250000, 14400, 28800, 56000, 76800, 128000, 153600, 200000, 256000,
+    307200, 400000, 5000000, 6000000, 12000000,
+];
+// The common rate an unusual `rate` was probably meant to be: one it is within 5% of, or
+// one it is ten times or a tenth of, for a zero typed too few or too many
+fn likely_meant_rate(rate: u32) -> Option<u32> {
+    if COMMON_BAUD_RATES.contains(&rate) || DELIBERATE_BAUD_RATES.contains(&rate) {
+        return None;
+    }
+    // the closest match decides, 96000 is 9600 rather than 4% short of 1000000
+    [1.0, 10.0, 0.1]
+        .into_iter()
+        .flat_map(|factor| {
+            let meant = f64::from(rate) * factor;
+            COMMON_BAUD_RATES.iter().map(move |&common| {
+                (
+                    common,
+                    (meant - f64::from(common)).abs() / f64::from(common),
+                )
+            })
+        })
+        .filter(|&(_, off)| off < BAUD_TYPO_BAND)
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(common, _)| common)
+}
+
+/// A session with a device over a [`Transport`]: what is received is shown, what is typed is
+/// sent, with the escape commands, logging, file transfers and reconnecting of scip. Built
+/// by [`Session::new`] from a [`SessionConfig`].
+pub struct Session {
+    serial_port: Box<dyn Transport>,
+    // --log, or the log started with ~o
+    log: Option<LogFile>,
+    log_format: LogFormat,
+    // the hide rules, for a log ~o starts too
+    log_filter: Option<LogFilter>,
+    log_dir: Option<PathBuf>,
+    log_rotation: Option<Rotation>,
+    record: Option<Recorder>,
+    // raw copy of the received data, toggled with ~w and ~W
+    capture: Option<Capture>,
+    triggers: Triggers,
+    // the --auto-respond replies to send, in the order they are due
+    auto_replies: Vec<PendingReply>,
+    auto_respond_delay: Duration,
+    timestamps: Option<LineStamper>,
+    gap_marker: Option<GapMarker>,
+    log_gaps: bool,
+    // --no-raw-marks
+    no_raw_marks: bool,
+    // when the data being shown was read from the port
+    read_at: Instant,
+    // --show-control and --strip-ansi, for the screen only
+    control: Option<ControlFilter>,
+    // --utf8, for the screen only
+    utf8: Option<Utf8Filter>,
+    // --nmea, for the screen only
+    nmea: Option<NmeaDecoder>,
+    // --decode, the frames are shown instead of the data
+    decoder: Option<Box<dyn Decoder>>,
+    // --debug-view, the hex lines are shown instead of the data
+    debug_view: Option<DebugView>,
+    tx_newline: NewlineTranslator,
+    rx_newline: NewlineTranslator,
+    write_timeout: Duration,
+    // --read-buffer, the size of a read from the port or the terminal
+    read_buffer: usize,
+    // serialport has no getter for the output lines, so remember what was set
+    dtr: bool,
+    rts: bool,
+    // when to clear a break sent with ~b
+    break_until: Option<Instant>,
+    // None with --no-escape
+    escape_char: Option<u8>,
+    quit_key: Option<QuitKey>,
+    keepalive: Option<Keepalive>,
+    // when data was last written to the port, for the keep-alive
+    last_write: Instant,
+    // the --reset profile run by ~R, and the run in progress
+    reset_sequence: Option<ResetSequence>,
+    reset: Option<ResetRun>,
+    // the DTR and RTS levels a reset leaves behind
+    reset_levels: (bool, bool),
+    verbose: bool,
+    // watches the input lines for --show-lines and --log-lines
+    line_monitor: Option<LineMonitor>,
+    show_lines: bool,
+    log_lines: bool,
+    echo: bool,
+    // Ctrl-C ends the session, --intercept-sigint on
+    intercept_ctrl_c: bool,
+    // ~K sends the next key as it is
+    send_next_key: bool,
+    // typed lines are edited locally and sent on Enter
+    line_mode: bool,
+    line_editor: LineEditor,
+    color: bool,
+    break_duration: Duration,
+    xmodem_strip_eof: bool,
+    ymodem_g: bool,
+    // notices sz starting, None with --no-zmodem
+    zmodem: Option<StartDetector>,
+    // what sz sent so far once it was noticed, the main loop starts receiving
+    zmodem_start: Option<Vec<u8>>,
+    download_dir: PathBuf,
+    overwrite: bool,
+    char_delay: Duration,
+    line_delay: Duration,
+    // typed data waiting for the pauses of --char-delay and --line-delay
+    tx_queue: TxQueue,
+    // typed data waiting for a transfer or a script to stop sending
+    tx_hold: TxHold,
+    // set while ~z runs a shell, which then reads the terminal. The stdin thread holds the
+    // lock from looking at it until its read is done, so setting it waits for that read.
+    stdin_paused: Arc<Mutex<bool>>,
+    // what arrived while in the shell, shown with ~v
+    held_back: Vec<u8>,
+    // an escape command reading a line of input
+    prompt: Option<(PromptAction, Prompt)>,
+    hex_history: History,
+    nmea_history: History,
+    // what ~A sends again
+    sent_lines: SentLines,
+    baud_history: History,
+    // TCP clients sharing the session
+    listener: Option<Listener>,
+    #[cfg(all(unix, feature = "pty"))]
+    pty: Option<pty::PtyBridge>,
+    // received data collected while a chat script runs
+    chat_input: Option<Vec<u8>>,
+    device: Device,
+    // what the port is opened with again when reconnecting
+    settings: PortSettings,
+    open_lines: OpenLines,
+    stats: Stats,
+    // the throughput of --stats-interval shown in the status row
+    rate_row: Option<String>,
+    // ~T, and the time it shows on the status row
+    stopwatch: Option<Stopwatch>,
+    stopwatch_row: Option<String>,
+    // the share of received bytes in error, warned about in the status row when it is high
+    rx_errors: ErrorMonitor,
+    error_row: Option<String>,
+    // how far --send-text got, shown in the status row
+    progress_row: Option<String>,
+    // how much ~p holds back, shown in the status row while the view is paused
+    pause_row: Option<String>,
+    // the lines shown, and the view of them ~[ shows
+    scrollback: Scrollback,
+    scroll_view: Option<ScrollView>,
+    // ~O, the port settings panel
+    port_panel: Option<PortPanel>,
+    text_send: TextSend,
+    file_verify: FileVerify,
+    // the port's current reader thread
+    reader_id: u64,
+    notifier: Option<Notifier>,
+    // --read-only, and whether it was told that typed keys are dropped
+    read_only: bool,
+    keys_dropped_shown: bool,
+    write_only: bool,
+    // --status-bar, on a terminal
+    status_bar: Option<StatusBar>,
+    // the device sent XOFF and no XON since, with software flow control
+    held_off: bool,
+    answerback: Option<Answerback>,
+    // --flush-on-connect or --settle
+    flush_on_connect: bool,
+    json: Option<JsonOutput>,
+    config: SessionConfig,
+    // what run() does once the port is open, set up from the options
+    start: Start,
+    events: Sender<Event>,
+    // taken by run()
+    events_rx: Option<Receiver<Event>>,
+    // the stdin thread reads the terminal, the session was started by run()
+    read_stdin: bool,
+    // the output goes to stdout as it is, without a terminal
+    pipe: bool,
+    // --detach, told once the session is set up
+    detached: Option<daemon::Detached>,
+    // the debug output goes nowhere, which the session tells once it shows its messages
+    debug_unseen: bool,
+}
+
+impl Session {
+    // The session with the options that need no checking taken from `sc_args`
+    fn build(
+        serial_port: Box<dyn Transport>,
+        device: Device,
+        log: Option<LogFile>,
+        triggers: Triggers,
+        listener: Option<Listener>,
+        (events, events_rx): (Sender<Event>, Receiver<Event>),
+        sc_args: &SessionConfig,
+    ) -> Session {
+        let open_lines = open_lines(sc_args);
+        let timestamps = sc_args.timestamp.as_deref().map(|clock| {
+            LineStamper::new(match clock {
+                "relative" => TimestampMode::Relative,
+                "delta" => TimestampMode::Delta,
+                "both" => TimestampMode::Both,
+                _ => TimestampMode::Wall,
+            })
+        });
+        let show_control = sc_args.show_control.as_deref().map(|which| {
+            let style = match sc_args.control_style.as_str() {
+                "hex" => ControlStyle::Hex,
+                _ => ControlStyle::Caret,
+            };
+            (style, which == "all")
+        });
+        let control = (show_control.is_some() || sc_args.strip_ansi)
+            .then(|| ControlFilter::new(show_control, sc_args.strip_ansi));
+        Session {
+            serial_port,
+            log,
+            log_format: log_format(sc_args),
+            log_filter: None,
+            log_dir: sc_args.log_dir.clone(),
+            log_rotation: log_rotation(sc_args),
+            record: None,
+            capture: None,
+            triggers,
+            auto_replies: Vec::new(),
+            auto_respond_delay: Duration::from_millis(sc_args.auto_respond_delay),
+            timestamps,
+            gap_marker: sc_args
+                .gap_marker
+                .map(|ms| GapMarker::new(Duration::from_millis(ms))),
+            log_gaps: sc_args.log_gaps,
+            no_raw_marks: sc_args.no_raw_marks,
+            read_at: Instant::now(),
+            control,
+            nmea: None,
+            decoder: None,
+            debug_view: sc_args
+                .debug_view
+                .then(|| DebugView::new(sc_args.debug_ascii, use_color(&sc_args.color))),
+            utf8: sc_args.utf8.then(|| {
+                Utf8Filter::new(match sc_args.invalid_utf8.as_str() {
+                    "hex" => InvalidStyle::Hex,
+                    _ => InvalidStyle::Replacement,
+                })
+            }),
+            tx_newline: NewlineTranslator::new(NewlineMode::from_arg(&sc_args.newline)),
+            rx_newline: NewlineTranslator::new(NewlineMode::from_arg(&sc_args.rx_newline)),
+            write_timeout: Duration::from_millis(sc_args.write_timeout),
+            read_buffer: sc_args.read_buffer,
+            // the port asserts both lines on open
+            dtr: open_lines.dtr.unwrap_or(true),
+            rts: open_lines.rts.unwrap_or(true),
+            break_until: None,
+            escape_char: None,
+            quit_key: None,
+            keepalive: None,
+            last_write: Instant::now(),
+            reset_sequence: None,
+            reset: None,
+            reset_levels: reset_levels(sc_args),
+            verbose: sc_args.verbose > 0,
+            line_monitor: (sc_args.show_lines || sc_args.log_lines || sc_args.status_bar)
+                .then(LineMonitor::new),
+            show_lines: sc_args.show_lines,
+            log_lines: sc_args.log_lines,
+            echo: sc_args.echo,
+            intercept_ctrl_c: sc_args.intercept_sigint == "on",
+            send_next_key: false,
+            line_mode: sc_args.line_mode,
+            line_editor: LineEditor::with_history(load_history(sc_args, "history")),
+            color: use_color(&sc_args.color),
+            break_duration: Duration::from_millis(sc_args.break_duration),
+            xmodem_strip_eof: sc_args.xmodem_strip_eof,
+            ymodem_g: sc_args.ymodem_g,
+            // the receiver would answer the sender
+            zmodem: (!sc_args.no_zmodem && !sc_args.read_only).then(StartDetector::new),
+            zmodem_start: None,
+            download_dir: PathBuf::from(&sc_args.download_dir),
+            overwrite: sc_args.overwrite,
+            char_delay: Duration::from_millis(sc_args.char_delay),
+            line_delay: Duration::from_millis(sc_args.line_delay),
+            tx_queue: TxQueue::new(
+                Duration::from_millis(sc_args.char_delay),
+                Duration::from_millis(sc_args.line_delay),
+            ),
+            tx_hold: TxHold::new(sc_args.tx_queue == "on"),
+            stdin_paused: Arc::new(Mutex::new(false)),
+            held_back: Vec::new(),
+            prompt: None,
+            hex_history: load_history(sc_args, "history-hex"),
+            nmea_history: load_history(sc_args, "history-nmea"),
+            sent_lines: SentLines::new(if sc_args.no_history { 0 } else { repeat::SIZE }),
+            baud_history: load_history(sc_args, "history-baud"),
+            listener,
+            #[cfg(all(unix, feature = "pty"))]
+            pty: None,
+            chat_input: None,
+            device,
+            settings: parse_arguments_into_settings(sc_args),
+            open_lines,
+            stats: Stats::new(sc_args.stats_interval.map(Duration::from_secs)),
+            rate_row: None,
+            stopwatch: None,
+            stopwatch_row: None,
+            rx_errors: ErrorMonitor::new(),
+            error_row: None,
+            progress_row: None,
+            pause_row: None,
+            scrollback: Scrollback::new(sc_args.scrollback),
+            scroll_view: None,
+            port_panel: None,
+            text_send: TextSend::default(),
+            file_verify: FileVerify::default(),
+            reader_id: 0,
+            notifier: sc_args
+                .notify
+                .as_deref()
+                .map(|mode| Notifier::new(mode, sc_args.profile.clone())),
+            read_only: sc_args.read_only,
+            keys_dropped_shown: false,
+            write_only: sc_args.write_only || sc_args.daemon,
+            status_bar: None,
+            held_off: false,
+            answerback: None,
+            flush_on_connect: sc_args.flush_on_connect || sc_args.settle.is_some(),
+            json: (sc_args.output == "json").then(|| {
+                JsonOutput::new(match sc_args.json_data.as_str() {
+                    "text" => DataEncoding::Text,
+                    _ => DataEncoding::Base64,
+                })
+            }),
+            config: sc_args.clone(),
+            start: Start::default(),
+            events,
+            events_rx: Some(events_rx),
+            read_stdin: false,
+            pipe: pipe_given(sc_args),
+            detached: None,
+            debug_unseen: false,
+        }
+    }
+
+    /// A session over `serial_port`, which is connected to `device`, with the options of
+    /// `config`. The port is used as it is, the port settings of `config` are what `device`
+    /// is opened with again when reconnecting. Nothing is read or sent before
+    /// [`Session::run`]. Fails on invalid options and on the log, the recording or the
+    /// listener of `config` that can't be set up.
+    pub fn new(
+        config: &SessionConfig,
+        device: Device,
+        serial_port: Box<dyn Transport>,
+    ) -> Result<Session, ScipioError> {
+        let options = parse_options(config)?;
+        Session::with_options(config, device, serial_port, options, channel())
+    }
+
+    // The session of run(), whose options were checked before opening the port
+    fn with_options(
+        sc_args: &SessionConfig,
+        device: Device,
+        serial_port: Box<dyn Transport>,
+        options: Options,
+        events: (Sender<Event>, Receiver<Event>),
+    ) -> Result<Session, ScipioError> {
+        let log: Option<LogFile> = match &sc_args.log {
+            Some(template) => {
+                match LogFile::open(template, sc_args.log_truncate, log_format(sc_args)) {
+                    Ok(log) => Some(log),
+                    Err(err) => {
+                        return Err(setup_error(format!("opening log file {}", template), err))
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let record: Option<Recorder> = match &sc_args.record {
+            Some(template) => {
+                let format = match sc_args.record_format.as_str() {
+                    "asciicast" => RecordFormat::Asciicast,
+                    _ => RecordFormat::Binary,
+                };
+                let size = if stdout().is_terminal() {
+                    term::backend().size().unwrap_or((80, 24))
+                } else {
+                    (80, 24)
+                };
+                match Recorder::create(template, format, size) {
+                    Ok(record) => Some(record),
+                    Err(err) => {
+                        return Err(setup_error(format!("creating recording {}", template), err))
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let listener: Option<Listener> = match &sc_args.listen {
+            Some(addr) => match Listener::bind(
+                addr,
+                sc_args.listen_max_clients,
+                sc_args.listen_token.clone(),
+                sc_args.listen_read_only,
+                events.0.clone(),
+            ) {
+                Ok(listener) => Some(listener),
+                Err(err) => return Err(setup_error(format!("listening on {}", addr), err)),
+            },
+            None => None,
+        };
+
+        let Options {
+            highlights,
+            rules,
+            log_colors,
+            triggers,
+            send_hex,
+            escape_char,
+            quit_key,
+            keepalive,
+            reset_sequence,
+            startup,
+            script,
+            init,
+            text_send,
+            file_verify,
+            firmware,
+            auto_baud_rates,
+            auto_baud_probe,
+            term_size,
+            nmea_types,
+        } = options;
+        let mut session = Session::build(
+            serial_port,
+            device,
+            log,
+            triggers,
+            listener,
+            events,
+            sc_args,
+        );
+        session.reset_sequence = reset_sequence;
+        session.text_send = text_send;
+        session.file_verify = file_verify;
+        session.record = record;
+        session.escape_char = escape_char;
+        session.quit_key = quit_key;
+        session.keepalive = keepalive;
+        if sc_args.nmea {
+            session.nmea = Some(NmeaDecoder::new(nmea_types, session.color));
+        }
+        if sc_args.answerback {
+            session.answerback = Some(Answerback::new(term_size));
+        }
+        session.start = Start {
+            highlights,
+            rules,
+            log_colors,
+            startup,
+            script,
+            init,
+            send_hex,
+            firmware,
+            auto_baud_rates,
+            auto_baud_probe,
+        };
+        Ok(session)
+    }
+
+    /// Where the input of the session goes: [`Event::Stdin`] for what is typed and
+    /// [`Event::Interrupted`] to end it. [`Session::run`] reads the terminal itself only when
+    /// the session was started by [`run`].
+    pub fn events(&self) -> Sender<Event> {
+        self.events.clone()
+    }
+
+    /// Run the session until it ends and return the exit status, one of the `EXIT_*`
+    /// constants or 0. `open_terminal` is called once with whether to switch to the alternate
+    /// screen, unless the options make it a pipe session.
+    pub fn run<F>(self, open_terminal: F) -> u8
+    where
+        F: FnOnce(bool) -> io::Result<Box<dyn OutputSink>>,
+    {
+        let mut session = self;
+        let sc_args = &session.config.clone();
+        let start = std::mem::take(&mut session.start);
+        let rx = match session.events_rx.take() {
+            Some(rx) => rx,
+            None => return EXIT_ERROR,
+        };
+        let events = (session.events.clone(), rx);
+        // before the reader takes anything
+        let flushed = session
+            .flush_on_connect
+            .then(|| session.serial_port.discard_input());
+        if let Err(err) = transport::spawn_reader(
+            session.serial_port.as_ref(),
+            session.reader_id,
+            session.read_buffer,
+            events.0.clone(),
+        ) {
+            return fail(setup_error("setting up port".to_string(), err));
+        }
+        let auto_baud_msg = if sc_args.auto_baud {
+            match auto_baud(
+                &mut session,
+                &events,
+                &start.auto_baud_rates,
+                &start.auto_baud_probe,
+            ) {
+                Ok(msg) => Some(msg),
+                Err(err) => return fail(setup_error("finding the baud rate".to_string(), err)),
+            }
+        } else {
+            None
+        };
+        // at the baud rate auto-baud found
+        session.decoder = sc_args
+            .decode
+            .as_deref()
+            .and_then(|protocol| decode::new(protocol, &session.settings, session.color));
+
+        let command_mode = !sc_args.command.is_empty();
+        let mut screen = if session.pipe {
+            Screen::pipe()
+        } else {
+            let alternate = !sc_args.no_alt_screen;
+            match open_terminal(alternate)
+                .and_then(|terminal| Screen::terminal(terminal, alternate))
+            {
+                Ok(screen) => screen,
+                Err(err) => return fail(setup_error("setting up the terminal".to_string(), err)),
+            }
+        };
+
+        if sc_args.status_bar && screen.is_terminal() {
+            let hex = sc_args.show_control.is_some() && sc_args.control_style == "hex";
+            session.status_bar = Some(StatusBar::new(hex));
+        }
+
+        // the rules come before --highlight, so they win overlapping matches
+        let mut rules = start.rules;
+        if session.color {
+            rules.extend(start.highlights);
+            rules.extend(start.log_colors);
+        }
+        session.log_filter = LogFilter::new(&rules);
+        if let Some(log) = &mut session.log {
+            log.set_filter(session.log_filter.clone());
+            log.set_rotation(session.log_rotation);
+        }
+        if !rules.is_empty() {
+            screen.set_highlighter(Highlighter::new(rules, session.color));
+        }
+        if screen.is_terminal() {
+            if let Err(err) = write_start_screen_msg(&mut screen, &session) {
+                screen.fail(err);
+            }
+        }
+        update_input_line(&session, &mut screen);
+        if let Some(msg) = &auto_baud_msg {
+            write_status(&mut screen, msg);
+        }
+        if let Device::Usb(_) = session.device {
+            let msg = format!("connected to {}", session.port_description());
+            write_status(&mut screen, &msg);
+        }
+        if let Some(flushed) = flushed {
+            write_status(&mut screen, &flushed_msg(flushed));
+        }
+        if let Some(msg) = ignored_settings(&session.device, &sc_args.given_settings) {
+            write_status(&mut screen, &msg);
+        }
+        for msg in baud_rate_notes(&session) {
+            write_status(&mut screen, &msg);
+        }
+        if session.debug_unseen {
+            write_status(
+                &mut screen,
+                "stderr is the terminal, give --debug-log for the debug output",
+            );
+        }
+        notify(&session, &mut screen, "device connected");
+        let device_name = session.port_description();
+        json_event(&mut session, &mut screen, |json| json.connect(&device_name));
+        if let Some(listener) = &session.listener {
+            let msg = format!("listening on {}", listener.local_addr());
+            write_status(&mut screen, &msg);
+        }
+        #[cfg(all(unix, feature = "pty"))]
+        if let Some(link) = &sc_args.pty {
+            match pty::PtyBridge::open(link, events.0.clone()) {
+                Ok(bridge) => {
+                    let msg = format!(
+                        "bridged to {} at {}",
+                        bridge.slave_path().display(),
+                        bridge.link().display()
+                    );
+                    session.pty = Some(bridge);
+                    write_status(&mut screen, &msg);
+                }
+                Err(err) => {
+                    return fail(setup_error(
+                        format!("creating the pty at {}", link.display()),
+                        err,
+                    ))
+                }
+            }
+        }
+        if let (true, Some(log)) = (sc_args.daemon, &session.log) {
+            eprintln!(
+                "[daemon: logging {} to {}, PID {}]",
+                session.port_description(),
+                log.path().display(),
+                std::process::id()
+            );
+        }
+        if let Some(detached) = session.detached.take() {
+            if let Err(err) = detached.ready() {
+                return fail(setup_error("going to the background".to_string(), err));
+            }
+        }
+
+        if session.read_stdin {
+            spawn_stdin_thread(
+                events.0.clone(),
+                session.stdin_paused.clone(),
+                session.read_buffer,
+            );
+        }
+
+        let mut escape_state: EscapeState = EscapeState::WaitForEnter;
+        let mut next_step: NextStep = NextStep::None;
+        if let Some(ms) = sc_args.settle {
+            next_step = settle(
+                Duration::from_millis(ms),
+                &mut session,
+                &mut screen,
+                &events.1,
+                &mut escape_state,
+            );
+        }
+        if let NextStep::None = next_step {
+            next_step = init::send(&start.init, &mut session, &mut screen, &events.1);
+        }
+        if let (NextStep::None, true) = (&next_step, session.reset_sequence.is_some()) {
+            start_reset(&mut session, &mut screen);
+            next_step = finish_reset(&mut session, &mut screen, &events.1);
+        }
+        if let (NextStep::None, false) = (&next_step, start.startup.is_empty()) {
+            let timeout = Duration::from_secs(sc_args.expect_timeout);
+            next_step = run_startup(
+                &sc_args.startup_step,
+                &start.startup,
+                timeout,
+                &mut session,
+                &mut screen,
+                &events.1,
+            );
+        }
+        if let (NextStep::None, Some(bytes)) = (&next_step, &start.send_hex) {
+            next_step = send_hex_bytes(&mut session, &mut screen, bytes);
+        }
+        if let (NextStep::None, false) = (&next_step, start.script.is_empty()) {
+            let timeout = Duration::from_secs(sc_args.expect_timeout);
+            let script = &start.script;
+            let failure = match chat::run(script, timeout, &mut session, &mut screen, &events.1) {
+                Ok(()) if sc_args.script_only || command_mode => {
+                    next_step = NextStep::LoopBreak;
+                    None
+                }
+                Ok(()) => None,
+                Err((_, ChatError::Timeout(pattern))) => {
+                    Some(format!("Timed out waiting for {}", pattern))
+                }
+                Err((_, ChatError::Failed(reason))) => Some(reason),
+                Err((_, ChatError::Cancelled)) => Some("Chat script cancelled".to_string()),
+                Err((_, ChatError::Disconnected)) => {
+                    Some("Device disconnected during the chat script".to_string())
+                }
+                Err((_, ChatError::Stopped(step))) => {
+                    next_step = step;
+                    None
+                }
+            };
+            if let Some(msg) = failure {
+                next_step = NextStep::Exit(ScipioError::Script(msg).into());
+            }
+        }
+        if let (NextStep::None, Some(file)) = (&next_step, &sc_args.send_file) {
+            next_step = transfer::send_paced(file, &mut session, &mut screen, &events.1);
+        }
+        if let (NextStep::None, Some(file)) = (&next_step, &sc_args.send_file_verify) {
+            let remote_path = sc_args.remote_path.as_deref();
+            let verified =
+                verify::send_verified(file, remote_path, &mut session, &mut screen, &events.1);
+            let failure = match verified {
+                Ok(()) if sc_args.script_only => {
+                    next_step = NextStep::LoopBreak;
+                    None
+                }
+                Ok(()) => None,
+                Err(ChatError::Timeout(reason)) | Err(ChatError::Failed(reason)) => Some(reason),
+                Err(ChatError::Cancelled) => Some(format!("Verifying {} cancelled", file)),
+                Err(ChatError::Disconnected) => {
+                    Some(format!("Device disconnected while verifying {}", file))
+                }
+                Err(ChatError::Stopped(step)) => {
+                    next_step = step;
+                    None
+                }
+            };
+            if let Some(msg) = failure {
+                next_step = NextStep::Exit(ScipioError::Script(msg).into());
+            }
+        }
+        if let (NextStep::None, Some(file)) = (&next_step, &sc_args.send_text) {
+            next_step = textsend::send_text(file, &mut session, &mut screen, &events.1);
+        }
+        if let (NextStep::None, Some((path, firmware))) = (&next_step, &start.firmware) {
+            next_step = textsend::send_firmware(
+                path,
+                firmware,
+                sc_args.record_retries,
+                &mut session,
+                &mut screen,
+                &events.1,
+            );
+        }
+        let exit = loop {
+            if let Some(err) = screen.take_failure() {
+                next_step = screen_error(err);
+            }
+            match next_step {
+                NextStep::LoopBreak => break Exit::default(),
+                NextStep::Exit(exit) => break exit,
+                NextStep::Disconnected => {
+                    match handle_disconnect(
+                        sc_args,
+                        &mut session,
+                        &events,
+                        &mut escape_state,
+                        &mut screen,
+                    ) {
+                        Ok(sp) => {
+                            restore_session(&mut session, sp, &events.0, &mut screen);
+                            if !sc_args.init_once {
+                                let init = &start.init;
+                                next_step = init::send(init, &mut session, &mut screen, &events.1);
+                                continue;
+                            }
+                        }
+                        Err(exit) => break exit,
+                    }
+                }
+                _ => {}
+            }
+
+            if let Some(until) = session.break_until {
+                if Instant::now() >= until {
+                    session.break_until = None;
+                    match session.serial_port.clear_break() {
+                        Ok(()) => write_status(&mut screen, "break sent"),
+                        Err(err) => {
+                            write_status(&mut screen, &format!("clearing break failed: {}", err))
+                        }
+                    }
+                }
+            }
+
+            if let Some(monitor) = &session.line_monitor {
+                if Instant::now() >= monitor.next_poll() {
+                    poll_modem_lines(&mut session, &mut screen);
+                }
+            }
+
+            show_rate(&mut session, &mut screen);
+            if let Some(stopwatch) = &mut session.stopwatch {
+                if Instant::now() >= stopwatch.due_at() {
+                    session.stopwatch_row = Some(stopwatch.show());
+                    show_status_row(&session, &mut screen);
+                }
+            }
+            if statusbar::update(&mut session) {
+                show_status_row(&session, &mut screen);
+            }
+            if let Some(decoder) = &mut session.decoder {
+                if decoder.due_at().is_some_and(|at| Instant::now() >= at) {
+                    let frame = decoder.flush();
+                    next_step = show_received(&mut session, &mut screen, &frame);
+                    continue;
+                }
+            }
+            advance_reset(&mut session, &mut screen);
+            let keepalive_at = session
+                .keepalive
+                .as_ref()
+                .and_then(|keepalive| keepalive.due_at(session.last_write));
+            if keepalive_at.is_some_and(|at| Instant::now() >= at) {
+                next_step = send_keepalive(&mut session, &mut screen);
+                continue;
+            }
+            if let Some(held) = session.tx_hold.release(&mut screen) {
+                next_step = send_typed(&mut session, &mut screen, &held);
+                continue;
+            }
+            if let Some(chunk) = session.tx_queue.take_due() {
+                next_step = write_to_serial_port(&mut session, &mut screen, &chunk);
+                continue;
+            }
+            let auto_reply_at = session.auto_replies.first().map(|reply| reply.due_at);
+            if auto_reply_at.is_some_and(|at| Instant::now() >= at) {
+                next_step = send_auto_reply(&mut session, &mut screen);
+                continue;
+            }
+
+            // wake up in time to clear a break, take a reset step, send the keep-alive, the
+            // queued input or an auto reply, read the input lines or show the throughput
+            let wake = [
+                session.break_until,
+                session.reset.as_ref().and_then(ResetRun::wake_at),
+                keepalive_at,
+                session.tx_queue.due_at(),
+                auto_reply_at,
+                session.line_monitor.as_ref().map(LineMonitor::next_poll),
+                session.stats.rate_due_at(),
+                session.status_bar.as_ref().map(StatusBar::due_at),
+                session.stopwatch.as_ref().map(Stopwatch::due_at),
+                session
+                    .decoder
+                    .as_ref()
+                    .and_then(|decoder| decoder.due_at()),
+            ]
+            .into_iter()
+            .flatten()
+            .min();
+            next_step = match next_event(&mut session, &mut screen, &events.1, wake) {
+                Some(Event::Serial(_, data, _)) => {
+                    match display_received(&mut session, &mut screen, &data) {
+                        NextStep::None => match session.zmodem_start.take() {
+                            Some(pending) => transfer::receive_zmodem(
+                                &pending,
+                                &mut session,
+                                &mut screen,
+                                &events.1,
+                            ),
+                            None => NextStep::None,
+                        },
+                        next_step => next_step,
+                    }
+                }
+                Some(Event::SerialError(_, err)) => port_error(err),
+                Some(Event::StdinError(err)) => stdin_error(err),
+                // stop sending, but keep showing what the device sends. A terminal only ends when
+                // it went away, that is left to the writes failing.
+                Some(Event::StdinClosed) if sc_args.exit_on_eof && !screen.is_terminal() => {
+                    NextStep::LoopBreak
+                }
+                Some(Event::Stdin(data)) => {
+                    let next_step = process_stdin(
+                        &mut session,
+                        &mut screen,
+                        &events.1,
+                        &mut escape_state,
+                        &data,
+                    );
+                    update_input_line(&session, &mut screen);
+                    next_step
+                }
+                // what the TCP clients sent goes to the port unchanged
+                Some(Event::Client(ClientEvent::Data(_, input))) => {
+                    send_input(&mut session, &mut screen, &input)
+                }
+                Some(Event::Pty(input)) => send_pty_input(&mut session, &mut screen, &input),
+                Some(Event::Interrupted) => interrupted(),
+                Some(Event::ReopenLog) => {
+                    reopen_log(&mut session, &mut screen);
+                    NextStep::None
+                }
+                // a leftover notification or a timeout
+                _ => NextStep::None,
+            };
+        };
+
+        if sc_args.hangup_on_exit {
+            // a port that failed has nothing left to hang up
+            let _ = session.serial_port.write_data_terminal_ready(false);
+            let _ = session.serial_port.write_request_to_send(false);
+        }
+
+        // leave the alternate screen first so the messages stay visible
+        let interactive = screen.is_terminal();
+        drop(screen);
+        if let Some(msg) = &exit.message {
+            eprintln!("{}", msg);
+        }
+        if interactive || sc_args.daemon {
+            eprintln!("{}", session.stats.summary());
+        }
+        if let Some(log) = session.log {
+            let log_path = log.path().to_path_buf();
+            if let Err(err) = log.close() {
+                eprintln!("Error writing log file {}: {}", log_path.display(), err);
+            }
+        }
+        if let Some(record) = session.record {
+            let record_path = record.path().to_path_buf();
+            if let Err(err) = record.close() {
+                eprintln!("Error writing recording {}: {}", record_path.display(), err);
+            }
+        }
+        if let Some(capture) = session.capture {
+            let capture_path = capture.path().to_path_buf();
+            if let Err(err) = capture.close() {
+                eprintln!("Error writing capture {}: {}", capture_path.display(), err);
+            }
+        }
+        if sc_args.daemon {
+            eprintln!("[daemon: stopped logging {}]", session.device);
+            // SIGTERM is how a service is stopped
+            if exit.code == EXIT_INTERRUPTED {
+                return 0;
+            }
+        }
+        if let Some(json) = &mut session.json {
+            if let Err(err) = json.exit(exit.code, exit.message.as_deref(), &session.stats) {
+                eprintln!("Error writing the JSON output: {}", err);
+            }
+        }
+        info!("session ended with status {}", exit.code);
+        exit.code
+    }
+
+    // Swap in a reopened port, restoring the output lines set during this session
+    fn reconnected(&mut self, serial_port: Box<dyn Transport>) -> io::Result<()> {
+        self.serial_port = serial_port;
+        self.break_until = None;
+        self.reset = None;
+        // only lines changed during the session differ from the state after open
+        if self.dtr != self.open_lines.dtr.unwrap_or(true) {
+            self.serial_port.write_data_terminal_ready(self.dtr)?;
+        }
+        if self.rts != self.open_lines.rts.unwrap_or(true) {
+            self.serial_port.write_request_to_send(self.rts)?;
+        }
+        Ok(())
+    }
+
+    // The device as given, with the path it was found at for usb: and serial:
+    fn port_description(&self) -> String {
+        match (&self.device, self.serial_port.local_path()) {
+            (Device::Usb(_), Some(path)) => format!("{} ({})", self.device, path.display()),
+            _ => self.device.to_string(),
+        }
+    }
+
+    fn toggle_dtr(&mut self) -> io::Result<bool> {
+        self.serial_port.write_data_terminal_ready(!self.dtr)?;
+        self.dtr = !self.dtr;
+        Ok(self.dtr)
+    }
+
+    fn toggle_rts(&mut self) -> io::Result<bool> {
+        self.serial_port.write_request_to_send(!self.rts)?;
+        self.rts = !self.rts;
+        Ok(self.rts)
+    }
+}
+
+/// Input for the main thread from the helper threads
+pub enum Event {
+    Stdin(Vec<u8>),
+    /// Reading the terminal failed, the stdin thread stopped
+    StdinError(io::Error),
+    /// Stdin reached its end, the stdin thread stopped
+    StdinClosed,
+    /// SIGINT or SIGTERM was received
+    Interrupted,
+    /// SIGHUP in --daemon mode, logrotate moved the log file away
+    ReopenLog,
+    /// What the program using the --pty wrote
+    Pty(Vec<u8>),
+    /// The device node may have appeared
+    DeviceReady,
+    /// Data read from the port, tagged with the reader it came from and when it was read
+    Serial(u64, Vec<u8>, Instant),
+    /// How many bytes the port marked as received with a framing or parity error, sent ahead
+    /// of the Serial event with them
+    ReceiveErrors(u64, u64),
+    /// Reading the port failed, its reader thread stopped
+    SerialError(u64, io::Error),
+    Client(ClientEvent),
+}
+
+/// What the main loop does after handling an event
+pub enum NextStep {
+    LoopContinue,
+    LoopBreak,
+    /// The device went away
+    Disconnected,
+    /// Write these bytes to the port instead of the typed ones
+    Send(Vec<u8>),
+    /// Run a local escape command
+    Command(EscapeCommand),
+    /// End the session for another reason than the user quitting
+    Exit(Exit),
+    None,
+}
+
+/// Why the session ended
+#[derive(Default)]
+pub struct Exit {
+    code: u8,
+    // printed once the terminal is restored
+    message: Option<String>,
+}
+
+impl From<ScipioError> for Exit {
+    fn from(err: ScipioError) -> Exit {
+        Exit::with_message(err.exit_code(), err.to_string())
+    }
+}
+
+impl Exit {
+    fn interrupted() -> Exit {
+        Exit {
+            code: EXIT_INTERRUPTED,
+            message: None,
+        }
+    }
+
+    fn with_message(code: u8, message: String) -> Exit {
+        Exit {
+            code,
+            message: Some(message),
+        }
+    }
+}
+
+/// The name of the binary, for the help and the banner
+pub const PROGRAM_NAME: &str = "scip";
+
+// Exit statuses, 0 is the user quitting. Keep the list in the README in sync.
+
+/// Invalid arguments or config, or the port could not be opened
+pub const EXIT_ERROR: u8 = 1;
+/// The device went away and --reconnect was not given or gave up
+pub const EXIT_DISCONNECTED: u8 = 2;
+/// Reading or writing the port or the terminal failed
+pub const EXIT_IO_ERROR: u8 = 3;
+/// The device did not come back within --wait-timeout
+pub const EXIT_WAIT_TIMEOUT: u8 = 4;
+/// The chat script did not complete
+pub const EXIT_SCRIPT_FAILED: u8 = 5;
+/// An --on-match pattern with the exit action showed up
+pub const EXIT_MATCHED: u8 = 6;
+/// SIGINT or SIGTERM, like a shell reports a process killed by SIGINT
+pub const EXIT_INTERRUPTED: u8 = 130;
+
+const CTRL_C: u8 = 0x03;
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+
+// The name ~o suggests for a new log file
+const DEFAULT_LOG_NAME: &str = "scip-%Y%m%d-%H%M%S.log";
+
+// How long the stdin thread waits for input before looking whether it was paused
+const STDIN_POLL: Duration = Duration::from_millis(100);
+
+// What the options are turned into before the port is opened
+struct Options {
+    highlights: Vec<Rule>,
+    rules: Vec<Rule>,
+    log_colors: Vec<Rule>,
+    triggers: Triggers,
+    send_hex: Option<Vec<u8>>,
+    escape_char: Option<u8>,
+    quit_key: Option<QuitKey>,
+    keepalive: Option<Keepalive>,
+    reset_sequence: Option<ResetSequence>,
+    // the startup actions of the config file
+    startup: Vec<ChatStep>,
+    script: Vec<ChatStep>,
+    // --init, sent after connecting
+    init: Vec<InitPart>,
+    text_send: TextSend,
+    file_verify: FileVerify,
+    // the path and records of --send-ihex or --send-srec
+    firmware: Option<(String, Firmware)>,
+    auto_baud_rates: Vec<u32>,
+    auto_baud_probe: Vec<u8>,
+    // --term-size, rows and columns
+    term_size: Option<(u16, u16)>,
+    // the types of --nmea-filter
+    nmea_types: Vec<String>,
+}
+
+// The part of the options Session::run() takes care of once the port is open
+#[derive(Default)]
+struct Start {
+    highlights: Vec<Rule>,
+    rules: Vec<Rule>,
+    log_colors: Vec<Rule>,
+    startup: Vec<ChatStep>,
+    script: Vec<ChatStep>,
+    init: Vec<InitPart>,
+    send_hex: Option<Vec<u8>>,
+    firmware: Option<(String, Firmware)>,
+    auto_baud_rates: Vec<u32>,
+    auto_baud_probe: Vec<u8>,
+}
+
+fn parse_options(sc_args: &SessionConfig) -> Result<Options, ScipioError> {
+    for baud_rate in baud_rates(sc_args).map_err(ScipioError::InvalidArgument)? {
+        if let (Some(common), false) = (likely_meant_rate(baud_rate), sc_args.force_baud) {
+            return Err(ScipioError::InvalidArgument(format!(
+                "in the baud rate: {} is unusual, did you mean {}? Give --force-baud to connect at {} anyway",
+                baud_rate, common, baud_rate
+            )));
+        }
+    }
+    let highlights: Vec<Rule> = sc_args
+        .highlight
+        .iter()
+        .map(|spec| Rule::parse(spec))
+        .collect::<Result<Vec<Rule>, String>>()
+        .map_err(ScipioError::InvalidArgument)?;
+    let rules: Vec<Rule> = if sc_args.no_rules {
+        Vec::new()
+    } else {
+        sc_args
+            .rule
+            .iter()
+            .map(|spec| Rule::parse_rule(spec))
+            .collect::<Result<Vec<Rule>, String>>()
+            .map_err(ScipioError::InvalidArgument)?
+    };
+    let log_colors = match &sc_args.log_colors {
+        Some(preset) => logcolors::preset_rules(preset, &sc_args.log_color_entry)
+            .map_err(ScipioError::InvalidArgument)?,
+        None => Vec::new(),
+    };
+    let triggers = sc_args
+        .on_match
+        .iter()
+        .map(|spec| Trigger::parse(spec))
+        .collect::<Result<Vec<Trigger>, String>>()
+        .map_err(ScipioError::InvalidArgument)?;
+    let replies = sc_args
+        .auto_respond
+        .iter()
+        .map(|spec| Trigger::reply(spec))
+        .collect::<Result<Vec<Trigger>, String>>()
+        .map_err(ScipioError::InvalidArgument)?;
+    let triggers = Triggers::new(
+        triggers.into_iter().chain(replies).collect(),
+        sc_args.auto_respond_max,
+    );
+    if triggers.needs_log() && sc_args.log.is_none() {
+        return Err(ScipioError::InvalidArgument(
+            "the log-mark action of --on-match needs --log".to_string(),
+        ));
+    }
+    let send_hex = match sc_args.send_hex.as_deref() {
+        Some(text) => Some(
+            hex::parse_hex(text)
+                .map_err(|err| ScipioError::InvalidArgument(format!("in --send-hex: {}", err)))?,
+        ),
+        None => None,
+    };
+    let escape_char = if sc_args.no_escape {
+        None
+    } else {
+        Some(
+            escape::parse_escape_char(&sc_args.escape_char)
+                .map_err(|err| ScipioError::InvalidArgument(format!("in --escape-char {}", err)))?,
+        )
+    };
+    let quit_key = match &sc_args.quit_key {
+        Some(spec) => Some(
+            QuitKey::parse(spec, escape_char)
+                .map_err(|err| ScipioError::InvalidArgument(format!("in --quit-key {}", err)))?,
+        ),
+        None => None,
+    };
+    let keepalive = match sc_args.keepalive.as_deref() {
+        Some(spec) => Some(Keepalive::parse(spec).map_err(ScipioError::InvalidArgument)?),
+        None => None,
+    };
+    let (reset_dtr, reset_rts) = reset_levels(sc_args);
+    let reset_sequence = match sc_args.reset.as_deref() {
+        Some(profile) => Some(
+            ResetSequence::parse(profile, reset_dtr, reset_rts)
+                .map_err(|err| ScipioError::InvalidArgument(format!("in --reset {}", err)))?,
+        ),
+        None => None,
+    };
+    let startup = if sc_args.skip_startup {
+        Vec::new()
+    } else {
+        chat::startup_script(&sc_args.startup_step, reset_dtr, reset_rts)
+            .map_err(ScipioError::InvalidArgument)?
+    };
+    let script_given = [
+        &sc_args.expect,
+        &sc_args.expect_regex,
+        &sc_args.send,
+        &sc_args.command,
+        &sc_args.until,
+    ]
+    .iter()
+    .map(|values| values.len())
+    .sum::<usize>();
+    if script_given != sc_args.script_order.len() {
+        let msg =
+            "the order of the chat script is unknown, use SessionConfig::from_matches".to_string();
+        return Err(ScipioError::InvalidArgument(msg));
+    }
+    let script = chat::script(&sc_args.script_order).map_err(ScipioError::InvalidArgument)?;
+    let init = match &sc_args.init {
+        Some(init) => init::parse(init).map_err(ScipioError::InvalidArgument)?,
+        None => Vec::new(),
+    };
+    let text_send = TextSend::new(
+        &sc_args.text_newline,
+        sc_args.text_skip_regex.as_deref(),
+        sc_args.text_wait_prompt.as_deref(),
+        Duration::from_secs(sc_args.text_wait_timeout),
+    )
+    .map_err(ScipioError::InvalidArgument)?;
+    let file_verify = FileVerify::new(
+        &sc_args.verify_command,
+        Duration::from_secs(sc_args.expect_timeout),
+    )
+    .map_err(ScipioError::InvalidArgument)?;
+    let firmware = match (&sc_args.send_ihex, &sc_args.send_srec) {
+        (Some(path), _) => Some((path.clone(), read_firmware(path, Format::IntelHex)?)),
+        (None, Some(path)) => Some((path.clone(), read_firmware(path, Format::Srec)?)),
+        (None, None) => None,
+    };
+    let auto_baud_rates = match sc_args.auto_baud_rates.as_deref() {
+        Some(list) => list
+            .split(',')
+            .map(|rate| {
+                rate.trim()
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|&rate| rate > 0)
+                    .ok_or_else(|| {
+                        ScipioError::InvalidArgument(format!(
+                            "invalid baud rate {:?} in --auto-baud-rates",
+                            rate
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<u32>, ScipioError>>()?,
+        None => COMMON_BAUD_RATES.to_vec(),
+    };
+    let auto_baud_probe = match sc_args.auto_baud_probe.as_deref() {
+        Some(probe) => {
+            chat::parse_escapes(probe, "--auto-baud-probe").map_err(ScipioError::InvalidArgument)?
+        }
+        None => Vec::new(),
+    };
+    if sc_args.read_buffer == 0 {
+        let msg = "--read-buffer has to be at least 1 byte".to_string();
+        return Err(ScipioError::InvalidArgument(msg));
+    }
+    if sc_args.log_rotate_size == Some(0) {
+        let msg = "--log-rotate-size has to be at least 1 byte".to_string();
+        return Err(ScipioError::InvalidArgument(msg));
+    }
+    if sc_args.log_rotate_keep == 0 {
+        let msg = "--log-rotate-keep has to be at least 1".to_string();
+        return Err(ScipioError::InvalidArgument(msg));
+    }
+    if sc_args.timeout_ms == 0 {
+        let msg = "--timeout-ms has to be at least 1 ms".to_string();
+        return Err(ScipioError::InvalidArgument(msg));
+    }
+    let term_size = match sc_args.term_size.as_deref() {
+        Some(size) => Some(parse_term_size(size).ok_or_else(|| {
+            ScipioError::InvalidArgument(format!(
+                "invalid --term-size {:?}, expected ROWS,COLS like 24,80",
+                size
+            ))
+        })?),
+        None => None,
+    };
+    let nmea_types: Vec<String> = match &sc_args.nmea_filter {
+        Some(types) => types
+            .split(',')
+            .map(|kind| kind.trim().to_ascii_uppercase())
+            .map(|kind| {
+                if (1..=6).contains(&kind.len()) && kind.bytes().all(|b| b.is_ascii_alphanumeric())
+                {
+                    Ok(kind)
+                } else {
+                    Err(ScipioError::InvalidArgument(format!(
+                        "invalid sentence type {:?} in --nmea-filter, expected types like GGA,RMC",
+                        kind
+                    )))
+                }
+            })
+            .collect::<Result<Vec<String>, ScipioError>>()?,
+        None => Vec::new(),
+    };
+    Ok(Options {
+        highlights,
+        rules,
+        log_colors,
+        triggers,
+        send_hex,
+        escape_char,
+        quit_key,
+        keepalive,
+        reset_sequence,
+        startup,
+        script,
+        init,
+        text_send,
+        file_verify,
+        firmware,
+        auto_baud_rates,
+        auto_baud_probe,
+        term_size,
+        nmea_types,
+    })
+}
+
+fn parse_term_size(size: &str) -> Option<(u16, u16)> {
+    let (rows, cols) = size.split_once(',')?;
+    let rows = rows.trim().parse::<u16>().ok().filter(|&rows| rows > 0)?;
+    let cols = cols.trim().parse::<u16>().ok().filter(|&cols| cols > 0)?;
+    Some((rows, cols))
+}
+
+// The records of --send-ihex or --send-srec, failing on the first one that is broken
+fn read_firmware(path: &str, format: Format) -> Result<Firmware, ScipioError> {
+    let data = fs::read(path).map_err(|err| setup_error(format!("reading {}", path), err))?;
+    firmware::parse(&data, format)
+        .map_err(|err| ScipioError::InvalidArgument(format!("in {}: {}", path, err)))
+}
+
+// Listen to the port at each rate of --auto-baud-rates and stay at the one that got the most
+// readable data, or else at the baud rate given. Returns what to tell about it.
+fn auto_baud(
+    session: &mut Session,
+    events: &(Sender<Event>, Receiver<Event>),
+    rates: &[u32],
+    probe: &[u8],
+) -> io::Result<String> {
+    eprint!("[auto-baud: listening at {} baud rates]\n\r", rates.len());
+    let detected = autobaud::detect(
+        session.serial_port.as_mut(),
+        (&events.0, &events.1),
+        session.reader_id,
+        rates,
+        probe,
+    )?;
+    let (baud_rate, msg) = match detected {
+        Some(detected) => (
+            detected.baud_rate,
+            format!(
+                "auto-baud: {} baud, {:.0}% readable",
+                detected.baud_rate,
+                detected.score * 100.0
+            ),
+        ),
+        None => (
+            session.settings.baud_rate,
+            format!(
+                "auto-baud: nothing readable at any rate, staying at {} baud",
+                session.settings.baud_rate
+            ),
+        ),
+    };
+    session.serial_port.set_baud_rate(baud_rate)?;
+    session.settings.baud_rate = baud_rate;
+    Ok(msg)
+}
+
+// Report an error before the session started, the terminal is still as it was
+fn fail(err: ScipioError) -> u8 {
+    eprint!("{}\n\r", err);
+    err.exit_code()
+}
+
+// fail() for an Exit, a Ctrl-C ends the program without a message
+fn exit_with(exit: Exit) -> u8 {
+    if let Some(msg) = &exit.message {
+        eprint!("{}\n\r", msg);
+    }
+    exit.code
+}
+
+fn setup_error(what: String, source: io::Error) -> ScipioError {
+    ScipioError::Setup { what, source }
+}
+
+// scip replay, without a port or raw mode
+fn replay(path: &Path, speed: f64) -> u8 {
+    if speed.is_nan() || speed <= 0.0 {
+        let msg = format!("--speed {} is not above 0", speed);
+        return fail(ScipioError::InvalidArgument(msg));
+    }
+    match record::replay(path, speed) {
+        Ok(()) => 0,
+        Err(err) => fail(setup_error(format!("replaying {}", path.display()), err)),
+    }
+}
+
+/// Opens the terminal for an interactive session, it is given whether to switch to the
+/// alternate screen and has to leave raw mode again when the sink is dropped
+pub type OpenTerminal = fn(alternate: bool) -> io::Result<Box<dyn OutputSink>>;
+
+/// The command line parser of scip, which [`SessionConfig::from_matches`] takes the
+/// matches of. The completion scripts are made from it too.
+pub fn app() -> App<'static> {
+    // clap keeps a reference to the help text for the whole run
+    let after_help: &'static str = Box::leak(escape::after_help().into_boxed_str());
+    SessionConfig::into_app().after_help(after_help)
+}
+
+/// Run scip with the options of `sc_args` until it ends, returning the exit status: list the
+/// ports, pick a device, open it and run a [`Session`] on the terminal `open_terminal` sets
+/// up. This is [`Session::new`] and [`Session::run`] with the port opened, locked and
+/// reading the terminal as the options say.
+pub fn run(sc_args: &SessionConfig, open_terminal: OpenTerminal) -> u8 {
+    let options = match parse_options(sc_args) {
+        Ok(options) => options,
+        Err(err) => return fail(err),
+    };
+    let debug_level = debuglog::level(sc_args.verbose, sc_args.debug_log.is_some());
+    // the debug output mustn't end up between the data on the screen
+    let stderr_free = is_pipe(sc_args) || !io::stderr().is_terminal();
+    let debug_unseen = match debuglog::init(debug_level, sc_args.debug_log.as_deref(), stderr_free)
+    {
+        Ok(placed) => !placed && debug_level > LevelFilter::Info,
+        Err(err) => {
+            let path = sc_args
+                .debug_log
+                .as_deref()
+                .unwrap_or(Path::new(""))
+                .display();
+            return fail(setup_error(format!("opening debug log {}", path), err));
+        }
+    };
+    debug!(
+        "{} {}, arguments {:?}",
+        PROGRAM_NAME,
+        env!("CARGO_PKG_VERSION"),
+        std::env::args().skip(1).collect::<Vec<_>>()
+    );
+
+    if let Some(Command::Completions { shell }) = sc_args.subcommand {
+        return completion::write_script(shell, app());
+    }
+    if let Some(Command::Replay { path, speed }) = &sc_args.subcommand {
+        return replay(path, *speed);
+    }
+    if sc_args.complete_devices {
+        return completion::list_devices();
+    }
+    if sc_args.list {
+        return list_ports();
+    }
+    if second_device(sc_args).is_some() {
+        if is_pipe(sc_args) {
+            let msg = "the split view of two devices needs a terminal".to_string();
+            return fail(ScipioError::InvalidArgument(msg));
+        }
+        return split::run(
+            sc_args,
+            options.escape_char,
+            options.quit_key,
+            open_terminal,
+        );
+    }
+
+    if sc_args.split_log.is_some() {
+        let msg = "--split-log needs a second device in place of the baud rate".to_string();
+        return fail(ScipioError::InvalidArgument(msg));
+    }
+
+    #[cfg(unix)]
+    let fifo = sc_args.fifo.as_deref();
+    #[cfg(not(unix))]
+    let fifo: Option<&str> = None;
+    let parsed = match (fifo, &sc_args.device) {
+        #[cfg(unix)]
+        (Some(pair), _) => Device::parse_fifo(pair),
+        (_, Some(device_path)) => Device::parse(device_path),
+        (_, None) if is_pipe(sc_args) => {
+            let msg = "no device given, picking one needs a terminal".to_string();
+            return fail(ScipioError::InvalidArgument(msg));
+        }
+        (_, None) => match picker::pick_device(sc_args.auto, sc_args.wait) {
+            Ok(device_path) => Device::parse(&device_path),
+            Err(err) => return fail(err),
+        },
+    };
+    let device: Device = match parsed {
+        Ok(device) => device,
+        Err(err) => return fail(ScipioError::InvalidDevice(err)),
+    };
+    let settings: PortSettings = parse_arguments_into_settings(sc_args);
+    // the lock holds the PID, so it is taken in the background
+    let detached = if sc_args.detach {
+        match daemon::detach() {
+            Ok(detached) => Some(detached),
+            Err(err) => return fail(setup_error("going to the background".to_string(), err)),
+        }
+    } else {
+        None
+    };
+    let _pid_file = match &sc_args.pid_file {
+        Some(path) => match PidFile::create(path) {
+            Ok(pid_file) => Some(pid_file),
+            Err(err) => return fail(setup_error(format!("writing {}", path.display()), err)),
+        },
+        None => None,
+    };
+    // after detaching, the thread doesn't survive the fork
+    let events = channel::<Event>();
+    if let Err(err) = spawn_signal_thread(events.0.clone(), sc_args.daemon) {
+        return fail(setup_error("setting up signal handling".to_string(), err));
+    }
+    let retry_until = sc_args
+        .retry_open
+        .map(|secs| Instant::now() + Duration::from_secs(secs));
+    let lines = open_lines(sc_args);
+    let opened = open_retrying(&device, &settings, &lines, retry_until, sc_args, &events.1);
+    let (serial_port, _lock) = match opened {
+        Ok(opened) => opened,
+        Err(exit) => return exit_with(exit),
+    };
+    let mut session = match Session::with_options(sc_args, device, serial_port, options, events) {
+        Ok(session) => session,
+        Err(err) => return fail(err),
+    };
+    // a daemon leaves stdin alone
+    session.read_stdin = !sc_args.daemon;
+    session.pipe = is_pipe(sc_args);
+    session.detached = detached;
+    session.debug_unseen = debug_unseen;
+    session.run(open_terminal)
+}
+
+// Open the log file again where logrotate moved it away, the next data goes to a new file
+fn reopen_log(session: &mut Session, screen: &mut Screen) {
+    let log_file = match &mut session.log {
+        Some(log_file) => log_file,
+        None => return,
+    };
+    let msg = match log_file.reopen() {
+        Ok(()) => format!("reopened log file {}", log_file.path().display()),
+        Err(err) => {
+            let msg = format!("stopped logging to {}: {}", log_file.path().display(), err);
+            session.log = None;
+            msg
+        }
+    };
+    write_status(screen, &msg);
+}
+
+// Read the input lines, updating the status row and logging what changed
+fn poll_modem_lines(session: &mut Session, screen: &mut Screen) {
+    let monitor = match &mut session.line_monitor {
+        Some(monitor) => monitor,
+        None => return,
+    };
+    let changes = monitor.poll(session.serial_port.modem_lines());
+    if let (true, Some(log_file)) = (session.log_lines, &mut session.log) {
+        for change in &changes {
+            if let Err(err) = log_file.write_event(change) {
+                let msg = format!("stopped logging to {}: {}", log_file.path().display(), err);
+                session.log = None;
+                write_status(screen, &msg);
+                break;
+            }
+        }
+    }
+    if !session.show_lines {
+        return;
+    }
+    if screen.is_terminal() {
+        if monitor.render().is_some() {
+            show_status_row(session, screen);
+        }
+    } else {
+        for change in &changes {
+            write_status(screen, change);
+        }
+    }
+}
+
+// Show the throughput of --stats-interval once the interval is over
+fn show_rate(session: &mut Session, screen: &mut Screen) {
+    let rate = match session.stats.take_rate() {
+        Some(rate) => rate,
+        None => return,
+    };
+    if screen.is_terminal() {
+        session.rate_row = Some(rate);
+        show_status_row(session, screen);
+    } else {
+        write_status(screen, &rate);
+    }
+}
+
+// Warn about a high share of received bytes in error, and take the warning back once the data
+// is clean again
+fn check_rx_errors(session: &mut Session, screen: &mut Screen, data: &[u8]) {
+    let marked = session.serial_port.marks_errors();
+    session.stats.suspect_bytes += session.rx_errors.feed(data, marked);
+    let warning = match session.rx_errors.update(marked) {
+        Some(warning) => warning,
+        None => return,
+    };
+    if screen.is_terminal() {
+        session.error_row = warning;
+        show_status_row(session, screen);
+    } else if let Some(warning) = warning {
+        write_status(screen, &warning);
+    }
+}
+
+// The lines of --show-lines, the throughput of --stats-interval and the progress of
+// --send-text side by side, the row is removed when none of them is shown
+fn show_status_row(session: &Session, screen: &mut Screen) {
+    let lines = session
+        .line_monitor
+        .as_ref()
+        .filter(|_| session.show_lines)
+        .and_then(LineMonitor::shown);
+    let parts: Vec<&str> = [
+        session.status_bar.as_ref().and_then(StatusBar::shown),
+        session.error_row.as_deref(),
+        session.pause_row.as_deref(),
+        lines,
+        session.rate_row.as_deref(),
+        session.stopwatch_row.as_deref(),
+        session.progress_row.as_deref(),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    let row = (!parts.is_empty()).then(|| parts.join("    "));
+    // a failing terminal shows up again with the next write
+    let _ = screen.set_status_row(row);
+}
+
+// Update the status row part of a paused view when the amount held back changed
+fn show_paused(session: &mut Session, screen: &mut Screen) {
+    let row = screen
+        .paused_bytes()
+        .map(|bytes| format!("PAUSED, {} KB buffered", bytes.div_ceil(1024)));
+    if row != session.pause_row {
+        session.pause_row = row;
+        show_status_row(session, screen);
+    }
+}
+
+// SIGINT and SIGTERM are taken by a thread of their own and sent as Event::Interrupted, so
+// the session ends with the terminal restored and the log written, SIGWINCH gets the size
+// for --answerback read again. With `hangup`, for --daemon, SIGHUP is sent as
+// Event::ReopenLog. This has to run before any other thread is started, they inherit the
+// blocked signals.
+#[cfg(unix)]
+fn spawn_signal_thread(tx: Sender<Event>, hangup: bool) -> io::Result<()> {
+    let mut signals: libc::sigset_t = unsafe { std::mem::zeroed() };
+    unsafe {
+        libc::sigemptyset(&mut signals);
+        libc::sigaddset(&mut signals, libc::SIGINT);
+        libc::sigaddset(&mut signals, libc::SIGTERM);
+        libc::sigaddset(&mut signals, libc::SIGWINCH);
+        if hangup {
+            libc::sigaddset(&mut signals, libc::SIGHUP);
+        }
+    }
+    let err = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &signals, std::ptr::null_mut()) };
+    if err != 0 {
+        return Err(io::Error::from_raw_os_error(err));
+    }
+    thread::spawn(move || loop {
+        let mut signal = 0;
+        if unsafe { libc::sigwait(&signals, &mut signal) } != 0 {
+            return;
+        }
+        if signal == libc::SIGWINCH {
+            answerback::terminal_resized();
+            continue;
+        }
+        let event = match signal {
+            libc::SIGHUP => Event::ReopenLog,
+            _ => Event::Interrupted,
+        };
+        if tx.send(event).is_err() {
+            return;
+        }
+    });
+    Ok(())
+}
+
+// A Windows console reads Ctrl-C as a key in raw mode, in a pipe it ends scip right away
+#[cfg(not(unix))]
+fn spawn_signal_thread(_tx: Sender<Event>, _hangup: bool) -> io::Result<()> {
+    Ok(())
+}
+
+// Read from terminal stdin in chunks of up to `chunk_size`, looking every STDIN_POLL whether ~z
+// paused it
+fn spawn_stdin_thread(tx: Sender<Event>, paused: Arc<Mutex<bool>>, chunk_size: usize) {
+    let mut stdin = stdin();
+    thread::spawn(move || loop {
+        if !stdin_ready(STDIN_POLL) {
+            continue;
+        }
+        // a pause set after this waits for the read, which has data and doesn't block
+        let pause = paused.lock().unwrap_or_else(|err| err.into_inner());
+        if *pause {
+            drop(pause);
+            thread::sleep(STDIN_POLL);
+            continue;
+        }
+        let mut data = vec![0; chunk_size];
+        let read = stdin.read(&mut data);
+        drop(pause);
+        let event = match read {
+            Ok(0) => {
+                let _ = tx.send(Event::StdinClosed);
+                return;
+            }
+            Ok(n) => {
+                data.truncate(n);
+                Event::Stdin(data)
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                let _ = tx.send(Event::StdinError(err));
+                return;
+            }
+        };
+        if tx.send(event).is_err() {
+            return;
+        }
+    });
+}
+
+// Wait up to `timeout` for stdin to have data or reach its end. A failing poll counts as
+// ready so the read reports the error.
+#[cfg(unix)]
+fn stdin_ready(timeout: Duration) -> bool {
+    let mut fds = libc::pollfd {
+        fd: libc::STDIN_FILENO,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut fds, 1, timeout.as_millis() as libc::c_int) != 0 }
+}
+
+// Without ~z nothing pauses stdin, the read just blocks
+#[cfg(not(unix))]
+fn stdin_ready(_timeout: Duration) -> bool {
+    true
+}
+
+fn write_status(screen: &mut Screen, msg: &str) {
+    // stdout only carries the received data when piped
+    if !screen.is_terminal() {
+        eprintln!("[{}]", msg);
+        return;
+    }
+    let written = write!(screen, "\r\n[{}]\r\n", msg).and_then(|()| screen.flush());
+    if let Err(err) = written {
+        screen.fail(err);
+    }
+}
+
+// Ctrl-C cancels what is running, piped input is only data
+fn ctrl_c_pressed(screen: &Screen, data: &[u8]) -> bool {
+    screen.is_terminal() && data.contains(&CTRL_C)
+}
+
+// Wait for the next event until `deadline`, None on timeout. Client connections are
+// looked after here, only the input to forward is returned.
+fn next_event(
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+    deadline: Option<Instant>,
+) -> Option<Event> {
+    loop {
+        let release_at = screen.release_at();
+        let wake_at = match (deadline, release_at) {
+            (Some(deadline), Some(release_at)) => Some(deadline.min(release_at)),
+            (deadline, release_at) => deadline.or(release_at),
+        };
+        // the channel can't disconnect, the main thread holds a sender
+        let event = match wake_at {
+            Some(wake_at) => {
+                match rx.recv_timeout(wake_at.saturating_duration_since(Instant::now())) {
+                    Ok(event) => event,
+                    Err(_) if release_at.is_some() => {
+                        // a failing terminal shows up again with the next write
+                        let _ = screen.release_held();
+                        continue;
+                    }
+                    Err(_) => return None,
+                }
+            }
+            None => rx.recv().ok()?,
+        };
+        match (event, &mut session.listener) {
+            (Event::Client(client_event), Some(listener)) => {
+                if let Some(input) = listener.handle(client_event, screen) {
+                    return Some(Event::Client(input));
+                }
+            }
+            (
+                Event::Serial(id, ..) | Event::ReceiveErrors(id, _) | Event::SerialError(id, _),
+                _,
+            ) if id != session.reader_id => {}
+            (Event::ReceiveErrors(_, count), _) => {
+                session.stats.rx_errors += count;
+                session.rx_errors.add_errors(count);
+            }
+            (Event::Serial(id, data, at), _) => {
+                session.read_at = at;
+                return Some(Event::Serial(id, data, at));
+            }
+            (Event::SerialError(id, err), _) => {
+                session.stats.read_errors += 1;
+                return Some(Event::SerialError(id, err));
+            }
+            (event, _) => return Some(event),
+        }
+    }
+}
+
+// Show the prompt, what is typed goes to it until it is answered or cancelled
+fn start_prompt(
+    session: &mut Session,
+    screen: &mut Screen,
+    action: PromptAction,
+    prompt: Prompt,
+) -> NextStep {
+    if let Err(err) = prompt.start(screen) {
+        return screen_error(err);
+    }
+    session.prompt = Some((action, prompt));
+    NextStep::None
+}
+
+fn process_stdin(
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+    escape_state: &mut EscapeState,
+    data: &[u8],
+) -> NextStep {
+    if let Some((action, mut prompt)) = session.prompt.take() {
+        match prompt.handle(data, screen) {
+            Ok(PromptStep::Pending) => session.prompt = Some((action, prompt)),
+            Ok(PromptStep::Done(line)) => {
+                return run_prompt_action(action, &line, session, screen, rx)
+            }
+            Ok(PromptStep::Cancelled) => write_status(screen, "cancelled"),
+            Err(err) => return screen_error(err),
+        }
+        return NextStep::None;
+    }
+    if session.scroll_view.is_some() {
+        return scrollback::handle_keys(session, screen, data);
+    }
+    if session.port_panel.is_some() {
+        return portpanel::handle_keys(session, screen, data);
+    }
+    if session.send_next_key {
+        session.send_next_key = false;
+        return send_key(session, screen, data);
+    }
+    if screen.is_terminal() && data == b"\x1b[5~" && session.scrollback.is_kept() {
+        return scrollback::enter(session, screen, true);
+    }
+
+    if session.line_mode && screen.is_terminal() {
+        return process_line_input(session, screen, rx, escape_state, data);
+    }
+
+    let (escape_char, quit_key) = (session.escape_char, session.quit_key);
+    if !screen.is_terminal() || (escape_char.is_none() && quit_key.is_none()) {
+        return send_typed(session, screen, data);
+    }
+    // a read can hold several keys, like a paste or what was typed while the session was
+    // busy, so every byte goes through the escape detection
+    let mut typed: Vec<u8> = Vec::with_capacity(data.len());
+    for (i, character) in data.iter().enumerate() {
+        match key_state_machine(character, escape_char, quit_key, escape_state) {
+            NextStep::None => typed.push(*character),
+            NextStep::Send(bytes) => typed.extend_from_slice(&bytes),
+            // held back until it is clear whether an escape command follows
+            NextStep::LoopContinue => {}
+            next_step => {
+                // what was typed before the command goes out first
+                if !typed.is_empty() {
+                    match send_typed(session, screen, &typed) {
+                        NextStep::None => {}
+                        next_step => return next_step,
+                    }
+                }
+                return run_escape_step(
+                    next_step,
+                    session,
+                    screen,
+                    rx,
+                    escape_state,
+                    &data[i + 1..],
+                );
+            }
+        }
+    }
+    if typed.is_empty() {
+        return NextStep::None;
+    }
+    send_typed(session, screen, &typed)
+}
+
+// Run what the escape detection returned for a key, then handle the keys read with it.
+// After a command that opened a prompt these go to the prompt.
+fn run_escape_step(
+    next_step: NextStep,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+    escape_state: &mut EscapeState,
+    rest: &[u8],
+) -> NextStep {
+    let next_step = match next_step {
+        NextStep::Command(command) => {
+            debug!("escape command {:?}", command);
+            run_escape_command(command, session, screen, rx)
+        }
+        next_step => next_step,
+    };
+    match next_step {
+        NextStep::None | NextStep::LoopContinue if !rest.is_empty() => {
+            process_stdin(session, screen, rx, escape_state, rest)
+        }
+        next_step => next_step,
+    }
+}
+
+// Send the key read after ~K without looking at it, a function key's whole sequence
+fn send_key(session: &mut Session, screen: &mut Screen, key: &[u8]) -> NextStep {
+    let next_step = send_input(session, screen, key);
+    if let NextStep::None = next_step {
+        let mut shown = Vec::new();
+        for &byte in key {
+            if control::is_control(byte, true) {
+                control::show_control(&mut shown, byte, ControlStyle::Caret);
+            } else {
+                shown.push(byte);
+            }
+        }
+        let hex: Vec<String> = key.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let msg = format!(
+            "sent {} ({})",
+            String::from_utf8_lossy(&shown),
+            hex.join(" ")
+        );
+        write_status(screen, &msg);
+    }
+    next_step
+}
+
+// Send keys as typed outside of line mode, echoing them with --echo
+fn send_typed(session: &mut Session, screen: &mut Screen, typed: &[u8]) -> NextStep {
+    // what came before the Ctrl-C goes out, --intercept-sigint on ends the session there
+    let intercepted = session.intercept_ctrl_c && screen.is_terminal();
+    let (typed, ctrl_c) = match typed.iter().position(|&byte| byte == CTRL_C) {
+        Some(i) if intercepted => (&typed[..i], true),
+        _ => (typed, false),
+    };
+    if session.read_only {
+        drop_typed(session, screen);
+        return if ctrl_c {
+            interrupted()
+        } else {
+            NextStep::None
+        };
+    }
+    let to_send: Vec<u8> = session.tx_newline.translate(typed);
+    session.sent_lines.add(&to_send);
+    match send_echoed(session, screen, &to_send) {
+        NextStep::None if ctrl_c => interrupted(),
+        next_step => next_step,
+    }
+}
+
+// Send what was typed, or ~A repeats, showing it as well with local echo
+fn send_echoed(session: &mut Session, screen: &mut Screen, to_send: &[u8]) -> NextStep {
+    let next_step = send_input(session, screen, to_send);
+    // --debug-view shows what went out already
+    let echo = session.echo && session.debug_view.is_none();
+    if let (NextStep::None, true) = (&next_step, echo) {
+        if let Err(err) = echo_locally(session, screen, to_send) {
+            return screen_error(err);
+        }
+    }
+    next_step
+}
+
+// Tell once that the keys typed in a --read-only session go nowhere
+fn drop_typed(session: &mut Session, screen: &mut Screen) {
+    if !session.keys_dropped_shown {
+        session.keys_dropped_shown = true;
+        write_status(screen, "read-only, typed keys are not sent");
+    }
+}
+
+fn run_escape_command(
+    command: EscapeCommand,
+    session: &mut Session,
+    screen: &mut Screen,
+    // ~z and ~gy take events themselves
+    rx: &Receiver<Event>,
+) -> NextStep {
+    if session.read_only && command.transmits() {
+        write_status(screen, "read-only, that escape command would use the port");
+        return NextStep::None;
+    }
+    match command {
+        EscapeCommand::SendBreak => {
+            // the break is cleared at the top of the main loop so RX keeps flowing
+            match session.serial_port.set_break() {
+                Ok(()) => session.break_until = Some(Instant::now() + session.break_duration),
+                Err(err) => write_status(screen, &format!("sending break failed: {}", err)),
+            }
+        }
+        EscapeCommand::ToggleDtr => match session.toggle_dtr() {
+            Ok(level) => write_status(screen, &format!("DTR: {}", line_level(level))),
+            Err(err) => write_status(screen, &format!("setting DTR failed: {}", err)),
+        },
+        EscapeCommand::ToggleEcho => {
+            session.echo = !session.echo;
+            write_status(screen, &format!("local echo: {}", on_off(session.echo)));
+        }
+        EscapeCommand::ToggleRts => match session.toggle_rts() {
+            Ok(level) => write_status(screen, &format!("RTS: {}", line_level(level))),
+            Err(err) => write_status(screen, &format!("setting RTS failed: {}", err)),
+        },
+        EscapeCommand::SendFile => {
+            let prompt = Prompt::new("send file: ");
+            return start_prompt(session, screen, PromptAction::SendFile, prompt);
+        }
+        EscapeCommand::SendFileVerify => {
+            let prompt = Prompt::new("send and verify file: ");
+            return start_prompt(session, screen, PromptAction::SendFileVerify, prompt);
+        }
+        EscapeCommand::SendText => {
+            let prompt = Prompt::new("send text file: ");
+            return start_prompt(session, screen, PromptAction::SendText, prompt);
+        }
+        EscapeCommand::StartTransfer(transfer) => {
+            let prompt = Prompt::new(transfer.prompt());
+            return start_prompt(session, screen, PromptAction::Transfer(transfer), prompt);
+        }
+        EscapeCommand::ReceiveYmodem => return transfer::receive_ymodem(session, screen, rx),
+        EscapeCommand::ChangeBaudRate => {
+            let mut prompt = Prompt::with_shortcuts("baud rate (+/- to step): ", b"+-");
+            prompt.set_history(session.baud_history.entries());
+            return start_prompt(session, screen, PromptAction::BaudRate, prompt);
+        }
+        EscapeCommand::StartCapture => match &session.capture {
+            Some(capture) => {
+                let path = capture.path().display();
+                write_status(screen, &format!("already capturing to {}", path));
+            }
+            None => {
+                let prompt = Prompt::new("capture to: ");
+                return start_prompt(session, screen, PromptAction::Capture, prompt);
+            }
+        },
+        EscapeCommand::InsertMarker => {
+            let prompt = Prompt::new("marker note: ");
+            return start_prompt(session, screen, PromptAction::Marker, prompt);
+        }
+        EscapeCommand::RepeatLine => {
+            if session.sent_lines.is_empty() {
+                let reason = if session.sent_lines.is_kept() {
+                    "no line sent yet"
+                } else {
+                    "no lines are kept with --no-history"
+                };
+                write_status(screen, reason);
+                return NextStep::None;
+            }
+            let shown = session.sent_lines.shown();
+            let mut prompt = Prompt::new("send again: ");
+            prompt.set_history(&shown);
+            prompt.set_input(&shown[shown.len() - 1]);
+            return start_prompt(session, screen, PromptAction::Repeat, prompt);
+        }
+        EscapeCommand::ToggleLog => match session.log.take() {
+            Some(log_file) => {
+                let path = log_file.path().to_path_buf();
+                match log_file.close() {
+                    Ok(bytes) => write_status(
+                        screen,
+                        &format!("stopped logging, {} bytes in {}", bytes, path.display()),
+                    ),
+                    Err(err) => write_status(
+                        screen,
+                        &format!("writing {} failed: {}", path.display(), err),
+                    ),
+                }
+            }
+            None => {
+                let mut prompt = Prompt::new("log to: ");
+                if let Ok(name) = logfile::expand_path_template(DEFAULT_LOG_NAME) {
+                    let path = match &session.log_dir {
+                        Some(dir) => dir.join(name),
+                        None => name,
+                    };
+                    prompt.set_input(&path.to_string_lossy());
+                }
+                return start_prompt(session, screen, PromptAction::Log, prompt);
+            }
+        },
+        EscapeCommand::StopCapture => match session.capture.take() {
+            Some(capture) => {
+                let path = capture.path().to_path_buf();
+                match capture.close() {
+                    Ok(bytes) => write_status(
+                        screen,
+                        &format!("captured {} bytes to {}", bytes, path.display()),
+                    ),
+                    Err(err) => write_status(
+                        screen,
+                        &format!("writing {} failed: {}", path.display(), err),
+                    ),
+                }
+            }
+            None => write_status(screen, "not capturing"),
+        },
+        EscapeCommand::SendHex => {
+            let mut prompt = Prompt::new("send hex: ");
+            prompt.set_history(session.hex_history.entries());
+            return start_prompt(session, screen, PromptAction::SendHex, prompt);
+        }
+        EscapeCommand::SendNmea => {
+            let mut prompt = Prompt::new("send NMEA sentence: ");
+            prompt.set_history(session.nmea_history.entries());
+            return start_prompt(session, screen, PromptAction::SendNmea, prompt);
+        }
+        EscapeCommand::ResetBoard => {
+            if session.reset_sequence.is_some() {
+                start_reset(session, screen);
+            } else {
+                let prompt = Prompt::new("reset profile (arduino, esp32, custom:SPEC): ");
+                return start_prompt(session, screen, PromptAction::Reset, prompt);
+            }
+        }
+        EscapeCommand::ToggleKeepalive => match &mut session.keepalive {
+            Some(keepalive) => {
+                keepalive.enabled = !keepalive.enabled;
+                // counting starts over rather than sending right away
+                session.last_write = Instant::now();
+                write_status(
+                    screen,
+                    &format!("keep-alive: {}", on_off(keepalive.enabled)),
+                );
+            }
+            None => write_status(screen, "no keep-alive, give one with --keepalive"),
+        },
+        EscapeCommand::ToggleAutoRespond => {
+            if session.triggers.has_replies() {
+                let replying = !session.triggers.is_replying();
+                session.triggers.set_replying(replying);
+                // answering by hand now
+                session.auto_replies.clear();
+                write_status(screen, &format!("auto-respond: {}", on_off(replying)));
+            } else {
+                write_status(screen, "no rules, give them with --auto-respond");
+            }
+        }
+        EscapeCommand::AbortQueue => match session.tx_queue.clear() {
+            0 => write_status(screen, "nothing queued"),
+            dropped => write_status(screen, &format!("dropped {} queued bytes", dropped)),
+        },
+        EscapeCommand::ToggleLineMode => {
+            session.line_mode = !session.line_mode;
+            write_status(screen, &format!("line mode: {}", on_off(session.line_mode)));
+        }
+        EscapeCommand::SendXonXoff => {
+            let prompt = Prompt::with_shortcuts("send XON (q) or XOFF (s): ", b"qs");
+            return start_prompt(session, screen, PromptAction::XonXoff, prompt);
+        }
+        EscapeCommand::CycleFlowControl => cycle_flow_control(session, screen),
+        EscapeCommand::PortSettings => return portpanel::enter(session, screen),
+        EscapeCommand::PipeCommand => {
+            let prompt = Prompt::new("command to send the output of: ");
+            return start_prompt(session, screen, PromptAction::PipeCommand, prompt);
+        }
+        #[cfg(unix)]
+        EscapeCommand::RunShell => return shell::run_shell(session, screen, rx),
+        // a console read blocking in the stdin thread would take the shell's input
+        #[cfg(not(unix))]
+        EscapeCommand::RunShell => write_status(screen, "~z is only available on Unix"),
+        EscapeCommand::ShowHeldBack => {
+            if session.held_back.is_empty() {
+                write_status(screen, "nothing was received while in the shell");
+            } else {
+                let held_back = std::mem::take(&mut session.held_back);
+                if let Err(err) = screen
+                    .write_received(&held_back)
+                    .and_then(|()| screen.flush())
+                {
+                    return screen_error(err);
+                }
+            }
+        }
+        EscapeCommand::TogglePause => {
+            if screen.paused_bytes().is_some() {
+                if let Err(err) = screen.unpause() {
+                    return screen_error(err);
+                }
+            } else {
+                screen.pause();
+            }
+            show_paused(session, screen);
+        }
+        EscapeCommand::ToggleStopwatch => match session.stopwatch.take() {
+            Some(stopwatch) => {
+                session.stopwatch_row = None;
+                show_status_row(session, screen);
+                write_status(
+                    screen,
+                    &format!("stopwatch stopped at {}", stopwatch.stop()),
+                );
+            }
+            None => {
+                session.stopwatch = Some(Stopwatch::start());
+                write_status(screen, "stopwatch started");
+            }
+        },
+        EscapeCommand::SendKey => {
+            session.send_next_key = true;
+            write_status(screen, "press the key to send");
+        }
+        EscapeCommand::ScrollBack => {
+            if !session.scrollback.is_kept() {
+                write_status(screen, "no lines are kept with --scrollback 0");
+                return NextStep::None;
+            }
+            return scrollback::enter(session, screen, false);
+        }
+        EscapeCommand::ResetTerminal => {
+            // nothing is sent, the device doesn't know about it
+            if let Err(err) = screen.reset_terminal() {
+                return screen_error(err);
+            }
+            if let Err(err) = write_banner(screen, session) {
+                return screen_error(err);
+            }
+        }
+        EscapeCommand::SwitchPane => {
+            write_status(screen, "there is one pane without a second device")
+        }
+        EscapeCommand::ShowHelp => {
+            if let Err(err) = escape::write_help(session, screen) {
+                return screen_error(err);
+            }
+        }
+        EscapeCommand::ShowInfo => {
+            if let Err(err) = info::write_info(session, screen) {
+                return screen_error(err);
+            }
+        }
+    }
+    NextStep::None
+}
+
+// Keystrokes in line mode go to the line editor, unless they are an escape command
+fn process_line_input(
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+    escape_state: &mut EscapeState,
+    data: &[u8],
+) -> NextStep {
+    let (escape_char, quit_key) = (session.escape_char, session.quit_key);
+    let prefix = quit_key.map(|quit_key| quit_key.prefix());
+    let mut rest = data;
+    while let Some(&first) = rest.first() {
+        let escaping = matches!(
+            escape_state,
+            EscapeState::ProcessCMD(_) | EscapeState::ProcessSubCMD(..)
+        );
+        // there is no Enter before an escape command, an empty line stands in for it. The
+        // --quit-key prefix needs neither.
+        let starting = escape_char == Some(first) && session.line_editor.is_empty();
+        let (typed, end) = if escaping || starting || prefix == Some(first) {
+            if starting && !escaping {
+                *escape_state = EscapeState::WaitForEC;
+            }
+            match key_state_machine(&first, escape_char, quit_key, escape_state) {
+                NextStep::None => (vec![first], 1),
+                NextStep::Send(bytes) => (bytes, 1),
+                NextStep::LoopContinue => (Vec::new(), 1),
+                next_step => {
+                    return run_escape_step(
+                        next_step,
+                        session,
+                        screen,
+                        rx,
+                        escape_state,
+                        &rest[1..],
+                    )
+                }
+            }
+        } else {
+            // up to the next escape character or prefix, the escape character starts a
+            // command if the keys before leave the line empty
+            let end = rest[1..]
+                .iter()
+                .position(|&character| [escape_char, prefix].contains(&Some(character)))
+                .map_or(rest.len(), |i| i + 1);
+            (rest[..end].to_vec(), end)
+        };
+        rest = &rest[end..];
+        match edit_line(session, screen, &typed) {
+            NextStep::None => {}
+            next_step => return next_step,
+        }
+    }
+    if let Some(err) = session.line_editor.take_history_error() {
+        write_status(screen, &err);
+    }
+    NextStep::None
+}
+
+// Hand keys to the line editor, sending the lines finished with Enter
+fn edit_line(session: &mut Session, screen: &mut Screen, typed: &[u8]) -> NextStep {
+    for input in session.line_editor.handle(typed) {
+        if session.read_only {
+            drop_typed(session, screen);
+            continue;
+        }
+        let to_send = match input {
+            LineInput::Line(line) => {
+                let mut line = line.into_bytes();
+                line.push(b'\r');
+                session.tx_newline.translate(&line)
+            }
+            LineInput::Interrupt if session.intercept_ctrl_c => return interrupted(),
+            LineInput::Interrupt => vec![CTRL_C],
+        };
+        session.sent_lines.add(&to_send);
+        match send_echoed(session, screen, &to_send) {
+            NextStep::None => {}
+            next_step => return next_step,
+        }
+    }
+    NextStep::None
+}
+
+fn load_history(sc_args: &SessionConfig, name: &str) -> History {
+    if sc_args.no_history {
+        History::new(sc_args.history_size)
+    } else {
+        History::load(name, sc_args.history_size)
+    }
+}
+
+fn add_to_history(history: &mut History, line: &str, screen: &mut Screen) {
+    history.add(line);
+    if let Some(err) = history.take_error() {
+        write_status(screen, &err);
+    }
+}
+
+// Draw the line mode input line, or give its row back while a prompt needs it
+fn update_input_line(session: &Session, screen: &mut Screen) {
+    let result = if session.line_mode && session.prompt.is_none() && screen.is_terminal() {
+        let editor = &session.line_editor;
+        screen.show_input_line(&editor.prompt(), &editor.text(), editor.cursor())
+    } else {
+        screen.hide_input_line()
+    };
+    // a failing terminal shows up again with the next write
+    let _ = result;
+}
+
+fn run_prompt_action(
+    action: PromptAction,
+    line: &str,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> NextStep {
+    match action {
+        PromptAction::BaudRate => {
+            // stepping with + and - isn't worth remembering
+            if !matches!(line, "+" | "-") {
+                add_to_history(&mut session.baud_history, line.trim(), screen);
+            }
+            change_baud_rate(line.trim(), session, screen);
+            return NextStep::None;
+        }
+        PromptAction::SendHex => {
+            return match hex::parse_hex(line) {
+                Ok(bytes) => {
+                    add_to_history(&mut session.hex_history, line.trim(), screen);
+                    send_hex_bytes(session, screen, &bytes)
+                }
+                // asked again with the input kept, so a typo is fixed rather than retyped
+                Err(err) => {
+                    write_status(screen, &format!("invalid hex: {}", err));
+                    let mut prompt = Prompt::new("send hex: ");
+                    prompt.set_input(line);
+                    prompt.set_history(session.hex_history.entries());
+                    start_prompt(session, screen, PromptAction::SendHex, prompt)
+                }
+            };
+        }
+        PromptAction::SendNmea => {
+            if line.trim().is_empty() {
+                write_status(screen, "no sentence given");
+                return NextStep::None;
+            }
+            add_to_history(&mut session.nmea_history, line.trim(), screen);
+            let sentence = nmea::sentence(line);
+            let next_step = write_to_serial_port(session, screen, &sentence);
+            if let NextStep::None = next_step {
+                let shown = String::from_utf8_lossy(&sentence);
+                write_status(screen, &format!("sent {}", shown.trim_end()));
+            }
+            return next_step;
+        }
+        PromptAction::XonXoff => {
+            let (byte, name) = match line.trim().to_ascii_lowercase().as_str() {
+                "q" | "xon" => (XON, "XON"),
+                "s" | "xoff" => (XOFF, "XOFF"),
+                answer => {
+                    write_status(screen, &format!("not XON or XOFF: {}", answer));
+                    return NextStep::None;
+                }
+            };
+            // past the --char-delay queue, which the device may be holding up
+            let next_step = write_to_serial_port(session, screen, &[byte]);
+            if let NextStep::None = next_step {
+                write_status(screen, &format!("sent {}", name));
+            }
+            return next_step;
+        }
+        PromptAction::Marker => {
+            if let Err(err) = insert_marker(session, screen, line.trim()) {
+                return screen_error(err);
+            }
+            return NextStep::None;
+        }
+        PromptAction::Repeat => return send_again(line, session, screen),
+        PromptAction::PipeCommand => {
+            return match line.trim() {
+                "" => {
+                    write_status(screen, "no command given");
+                    NextStep::None
+                }
+                command => pipecommand::run_command(command, session, screen, rx),
+            };
+        }
+        PromptAction::Reset => {
+            let (dtr, rts) = session.reset_levels;
+            match ResetSequence::parse(line.trim(), dtr, rts) {
+                Ok(sequence) => {
+                    session.reset_sequence = Some(sequence);
+                    start_reset(session, screen);
+                }
+                Err(err) => {
+                    write_status(screen, &format!("invalid reset profile {}", err));
+                    let mut prompt = Prompt::new("reset profile (arduino, esp32, custom:SPEC): ");
+                    prompt.set_input(line);
+                    return start_prompt(session, screen, PromptAction::Reset, prompt);
+                }
+            }
+            return NextStep::None;
+        }
+        _ => {}
+    }
+    let file = line.trim();
+    if file.is_empty() {
+        write_status(screen, "no file given");
+        return NextStep::None;
+    }
+    match action {
+        PromptAction::SendFile => transfer::send_paced(file, session, screen, rx),
+        PromptAction::SendFileVerify => verify::send_and_verify(file, session, screen, rx),
+        PromptAction::SendText => textsend::send_text(file, session, screen, rx),
+        PromptAction::Transfer(transfer) => {
+            transfer::run_transfer(transfer, file, session, screen, rx)
+        }
+        PromptAction::BaudRate
+        | PromptAction::SendHex
+        | PromptAction::SendNmea
+        | PromptAction::Reset
+        | PromptAction::PipeCommand
+        | PromptAction::XonXoff
+        | PromptAction::Marker
+        | PromptAction::Repeat => NextStep::None,
+        PromptAction::Log => {
+            match LogFile::open(file, false, session.log_format) {
+                Ok(mut log_file) => {
+                    log_file.set_filter(session.log_filter.clone());
+                    log_file.set_rotation(session.log_rotation);
+                    let path = log_file.path().display().to_string();
+                    write_status(screen, &format!("logging to {}", path));
+                    session.log = Some(log_file);
+                }
+                Err(err) => write_status(screen, &format!("opening {} failed: {}", file, err)),
+            }
+            NextStep::None
+        }
+        PromptAction::Capture => {
+            match Capture::create(file) {
+                Ok(capture) => {
+                    write_status(screen, &format!("capturing to {}", file));
+                    session.capture = Some(capture);
+                }
+                Err(err) => write_status(screen, &format!("opening {} failed: {}", file, err)),
+            }
+            NextStep::None
+        }
+    }
+}
+
+// Write `bytes` to the port as they are and show them as a hex dump
+fn send_hex_bytes(session: &mut Session, screen: &mut Screen, bytes: &[u8]) -> NextStep {
+    let next_step = write_to_serial_port(session, screen, bytes);
+    if let NextStep::None = next_step {
+        write_status(screen, &format!("sent {} bytes", bytes.len()));
+        if let Err(err) = write_hex_dump(screen, bytes) {
+            return screen_error(err);
+        }
+    }
+    next_step
+}
+
+fn write_hex_dump(screen: &mut Screen, bytes: &[u8]) -> io::Result<()> {
+    for row in hex::hex_dump(bytes) {
+        if screen.is_terminal() {
+            write!(screen, "    {}\r\n", row)?;
+        } else {
+            eprintln!("    {}", row);
+        }
+    }
+    screen.flush()
+}
+
+// Switch to the typed rate, or to the next common one for + and -
+fn change_baud_rate(answer: &str, session: &mut Session, screen: &mut Screen) {
+    let current = session.settings.baud_rate;
+    let baud_rate = match answer {
+        "+" => COMMON_BAUD_RATES
+            .iter()
+            .copied()
+            .find(|&rate| rate > current),
+        "-" => COMMON_BAUD_RATES
+            .iter()
+            .copied()
+            .rev()
+            .find(|&rate| rate < current),
+        answer => match answer.parse::<u32>() {
+            Ok(rate) if rate > 0 => Some(rate),
+            _ => {
+                write_status(screen, &format!("invalid baud rate: {}", answer));
+                return;
+            }
+        },
+    };
+    let baud_rate = match baud_rate {
+        Some(baud_rate) => baud_rate,
+        None => {
+            write_status(screen, &format!("no common baud rate beyond {}", current));
+            return;
+        }
+    };
+    if let Err(err) = session.serial_port.set_baud_rate(baud_rate) {
+        write_status(screen, &format!("setting baud rate failed: {}", err));
+        return;
+    }
+    session.settings.baud_rate = baud_rate;
+    if let Some(decoder) = &mut session.decoder {
+        decoder.set_baud_rate(baud_rate);
+    }
+    // the driver may have picked the closest rate it can do
+    match session.serial_port.settings() {
+        Ok(settings) => write_status(screen, &format!("baud rate: {}", settings.baud_rate)),
+        Err(_) => write_status(screen, &format!("baud rate: {}", baud_rate)),
+    }
+}
+
+// ~m: a line like ---- 12:03:44 MARK: started test 7 ---- on the screen, in the log and in the
+// capture, to find the spot again later. Nothing of it goes to the port.
+// Send the line picked at the ~A prompt. One left as shown goes out as it was sent before,
+// an edited one like a line typed in line mode.
+fn send_again(line: &str, session: &mut Session, screen: &mut Screen) -> NextStep {
+    if line.is_empty() {
+        write_status(screen, "nothing sent");
+        return NextStep::None;
+    }
+    let to_send = match session.sent_lines.find(line) {
+        Some(sent) => sent.to_vec(),
+        None => {
+            let mut typed = line.as_bytes().to_vec();
+            typed.push(b'\r');
+            session.tx_newline.translate(&typed)
+        }
+    };
+    session.sent_lines.add(&to_send);
+    let next_step = send_echoed(session, screen, &to_send);
+    if let NextStep::None = next_step {
+        write_status(screen, &format!("sent again: {}", line));
+    }
+    next_step
+}
+
+fn insert_marker(session: &mut Session, screen: &mut Screen, note: &str) -> io::Result<()> {
+    let time = Local::now().format("%H:%M:%S");
+    let line = match note {
+        "" => format!("---- {} MARK ----", time),
+        note => format!("---- {} MARK: {} ----", time, note),
+    };
+    if screen.is_terminal() {
+        write!(
+            screen,
+            "\r\n{}{}{}\r\n",
+            ansi::INVERT,
+            line,
+            ansi::NO_INVERT
+        )?;
+        screen.flush()?;
+    } else {
+        eprintln!("{}", line);
+    }
+    if let Some(log_file) = &mut session.log {
+        let skipped = session.no_raw_marks && log_file.format() == LogFormat::Raw;
+        if !skipped {
+            if let Err(err) = log_file.write_marker(&line, note) {
+                let msg = format!("stopped logging to {}: {}", log_file.path().display(), err);
+                session.log = None;
+                write_status(screen, &msg);
+            }
+        }
+    }
+    if let (Some(capture), false) = (&mut session.capture, session.no_raw_marks) {
+        if let Err(err) = capture.write_marker(&line) {
+            let msg = format!("stopped capturing to {}: {}", capture.path().display(), err);
+            session.capture = None;
+            write_status(screen, &msg);
+        }
+    }
+    Ok(())
+}
+
+// ~F: none, then XON/XOFF, then RTS/CTS
+fn cycle_flow_control(session: &mut Session, screen: &mut Screen) {
+    let flow_control = match session.settings.flow_control {
+        FlowControl::None => FlowControl::Software,
+        FlowControl::Software => FlowControl::Hardware,
+        FlowControl::Hardware => FlowControl::None,
+    };
+    if let Err(err) = session.serial_port.set_flow_control(flow_control) {
+        write_status(screen, &format!("setting flow control failed: {}", err));
+        return;
+    }
+    session.settings.flow_control = flow_control;
+    session.held_off = false;
+    write_status(
+        screen,
+        &format!("flow control: {}", info::flow_control(flow_control)),
+    );
+}
+
+// Follow the XON and XOFF the device sends with software flow control, the last one of
+// `data` tells whether it is holding off what is sent to it. They are passed on like any
+// other byte, and the ones ~S sends aren't counted.
+fn track_flow(session: &mut Session, screen: &mut Screen, data: &[u8]) {
+    if session.settings.flow_control != FlowControl::Software {
+        return;
+    }
+    let held_off = match data.iter().rev().find(|&&byte| byte == XON || byte == XOFF) {
+        Some(&byte) => byte == XOFF,
+        None => return,
+    };
+    if held_off == session.held_off {
+        return;
+    }
+    session.held_off = held_off;
+    // the status bar shows it as XOFF
+    if session.status_bar.is_some() {
+        return;
+    }
+    if held_off {
+        write_status(
+            screen,
+            "the device sent XOFF, it is holding off what is sent",
+        );
+    } else {
+        write_status(screen, "the device sent XON");
+    }
+}
+
+// A reset leaves the lines as --dtr and --rts set them, or released
+fn reset_levels(sc_args: &SessionConfig) -> (bool, bool) {
+    (
+        sc_args.dtr.as_deref() == Some("on"),
+        sc_args.rts.as_deref() == Some("on"),
+    )
+}
+
+// Begin the sequence of the --reset profile, the main loop takes its steps
+fn start_reset(session: &mut Session, screen: &mut Screen) {
+    let sequence = match &session.reset_sequence {
+        Some(sequence) => sequence,
+        None => return,
+    };
+    write_status(screen, &format!("resetting with {}", sequence.name()));
+    session.reset = Some(sequence.start());
+    advance_reset(session, screen);
+}
+
+/// A reset of the startup actions, done before going on like the one of --reset
+pub fn run_reset(
+    sequence: &ResetSequence,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> NextStep {
+    write_status(screen, &format!("resetting with {}", sequence.name()));
+    session.reset = Some(sequence.start());
+    finish_reset(session, screen, rx)
+}
+
+// Run the startup actions of the config file, naming the one that failed
+fn run_startup(
+    actions: &[String],
+    steps: &[ChatStep],
+    timeout: Duration,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> NextStep {
+    let (i, err) = match chat::run(steps, timeout, session, screen, rx) {
+        Ok(()) => return NextStep::None,
+        Err(failed) => failed,
+    };
+    let step = format!("step {} ({})", i + 1, actions[i]);
+    let msg = match err {
+        ChatError::Timeout(pattern) => {
+            format!("Startup {} failed: timed out waiting for {}", step, pattern)
+        }
+        ChatError::Failed(reason) => format!("Startup {} failed: {}", step, reason),
+        ChatError::Cancelled => format!("Startup cancelled at {}", step),
+        ChatError::Disconnected => format!("Device disconnected during startup {}", step),
+        ChatError::Stopped(next_step) => return next_step,
+    };
+    NextStep::Exit(ScipioError::Script(msg).into())
+}
+
+// Take the steps of the reset in progress that are due
+fn advance_reset(session: &mut Session, screen: &mut Screen) {
+    while let Some(run) = &mut session.reset {
+        let step = match run.next_step() {
+            Some(step) => step,
+            None if run.is_done() => {
+                session.reset = None;
+                write_status(screen, "reset done");
+                return;
+            }
+            None => return,
+        };
+        if session.verbose {
+            write_status(screen, &format!("reset: {}", step));
+        }
+        let result = match step {
+            ResetStep::Dtr(level) => session
+                .serial_port
+                .write_data_terminal_ready(level)
+                .map(|()| session.dtr = level),
+            ResetStep::Rts(level) => session
+                .serial_port
+                .write_request_to_send(level)
+                .map(|()| session.rts = level),
+            ResetStep::Sleep(_) => Ok(()),
+        };
+        if let Err(err) = result {
+            session.reset = None;
+            write_status(screen, &format!("reset failed: {}", err));
+        }
+    }
+}
+
+// Carry out the reset started on connecting before the rest of the startup actions,
+// showing what the device sends meanwhile
+fn finish_reset(session: &mut Session, screen: &mut Screen, rx: &Receiver<Event>) -> NextStep {
+    loop {
+        advance_reset(session, screen);
+        let wake = match &session.reset {
+            Some(run) => run.wake_at(),
+            None => return NextStep::None,
+        };
+        match next_event(session, screen, rx, wake) {
+            Some(Event::Serial(_, data, _)) => match display_received(session, screen, &data) {
+                NextStep::None => {}
+                next_step => return next_step,
+            },
+            Some(Event::Stdin(data)) => match txhold::handle_keys(session, screen, rx, &data) {
+                HeldKeys::Kept => {}
+                HeldKeys::Abort => {
+                    session.reset = None;
+                    write_status(screen, "reset cancelled");
+                    return NextStep::None;
+                }
+                HeldKeys::Stop(next_step) => return next_step,
+            },
+            Some(Event::SerialError(_, err)) => return port_error(err),
+            Some(Event::StdinError(err)) => return stdin_error(err),
+            Some(Event::Interrupted) => return interrupted(),
+            _ => {}
+        }
+    }
+}
+
+fn use_color(when: &str) -> bool {
+    match when {
+        "always" => true,
+        "never" => false,
+        _ => stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+    }
+}
+
+// What the program on the --pty wrote goes to the port unchanged, and is shown dimmed with
+// the control characters in hex so a binary protocol doesn't garble the terminal
+fn send_pty_input(session: &mut Session, screen: &mut Screen, data: &[u8]) -> NextStep {
+    if screen.is_terminal() && !session.read_only {
+        let shown = ControlFilter::new(Some((ControlStyle::Hex, false)), false).filter(data);
+        let result = write!(screen, "{}", ansi::FAINT)
+            .and_then(|()| screen.write_all(&shown))
+            .and_then(|()| write!(screen, "{}", ansi::RESET))
+            .and_then(|()| screen.flush());
+        if let Err(err) = result {
+            return screen_error(err);
+        }
+    }
+    send_input(session, screen, data)
+}
+
+fn echo_locally(session: &Session, screen: &mut Screen, data: &[u8]) -> io::Result<()> {
+    // the cursor needs a line feed as well for Enter
+    let mut echoed = Vec::with_capacity(data.len() + 8);
+    for (i, &byte) in data.iter().enumerate() {
+        match byte {
+            b'\r' => echoed.extend_from_slice(b"\r\n"),
+            b'\n' if i > 0 && data[i - 1] == b'\r' => {}
+            b'\n' => echoed.extend_from_slice(b"\r\n"),
+            _ => echoed.push(byte),
+        }
+    }
+    if session.color {
+        write!(screen, "{}", ansi::FAINT)?;
+        screen.write_all(&echoed)?;
+        write!(screen, "{}", ansi::RESET)?;
+    } else {
+        screen.write_all(&echoed)?;
+    }
+    screen.flush()
+}
+
+fn line_level(level: bool) -> &'static str {
+    if level {
+        "high"
+    } else {
+        "low"
+    }
+}
+
+fn restore_session(
+    session: &mut Session,
+    serial_port: Box<dyn Transport>,
+    tx: &Sender<Event>,
+    screen: &mut Screen,
+) {
+    // events still queued from the old port's reader are dropped
+    session.reader_id += 1;
+    let mut serial_port = serial_port;
+    let flushed = session
+        .flush_on_connect
+        .then(|| serial_port.discard_input());
+    if let Err(err) = transport::spawn_reader(
+        serial_port.as_ref(),
+        session.reader_id,
+        session.read_buffer,
+        tx.clone(),
+    ) {
+        write_status(screen, &format!("reading from the port failed: {}", err));
+    }
+    let restored = session.reconnected(serial_port);
+    session.stats.reconnected();
+    let msg = format!("reconnected to {}", session.port_description());
+    write_status(screen, &msg);
+    if let Some(flushed) = flushed {
+        write_status(screen, &flushed_msg(flushed));
+    }
+    notify(session, screen, "device reconnected");
+    let device_name = session.port_description();
+    json_event(session, screen, |json| json.connect(&device_name));
+    if let Err(err) = restored {
+        write_status(screen, &format!("restoring DTR/RTS failed: {}", err));
+    }
+}
+
+fn flushed_msg(flushed: io::Result<u32>) -> String {
+    match flushed {
+        Ok(bytes) => format!("flushed {} bytes the port held", bytes),
+        Err(err) => format!("flushing the port failed: {}", err),
+    }
+}
+
+// --settle: drop what arrives during the first `duration`. The keys are handled as usual, so
+// ~. gets out of a setting that was too long.
+fn settle(
+    duration: Duration,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+    escape_state: &mut EscapeState,
+) -> NextStep {
+    let deadline = Instant::now() + duration;
+    let mut discarded: usize = 0;
+    let next_step = loop {
+        let next_step = match next_event(session, screen, rx, Some(deadline)) {
+            Some(Event::Serial(_, data, _)) => {
+                discarded += data.len();
+                NextStep::None
+            }
+            Some(Event::Stdin(data)) => process_stdin(session, screen, rx, escape_state, &data),
+            Some(Event::SerialError(_, err)) => port_error(err),
+            Some(Event::StdinError(err)) => stdin_error(err),
+            Some(Event::Interrupted) => interrupted(),
+            Some(_) => NextStep::None,
+            None => break NextStep::None,
+        };
+        if let NextStep::LoopBreak | NextStep::Exit(_) | NextStep::Disconnected = next_step {
+            break next_step;
+        }
+    };
+    let msg = format!(
+        "dropped {} bytes received in the first {} ms",
+        discarded,
+        duration.as_millis()
+    );
+    write_status(screen, &msg);
+    next_step
+}
+
+// Tell about `event` as --notify asks for
+fn notify(session: &Session, screen: &mut Screen, event: &str) {
+    let notifier = match &session.notifier {
+        Some(notifier) => notifier,
+        None => return,
+    };
+    // the bell would end up in the output of a pipe
+    let terminal: Option<&mut dyn Write> = if screen.is_terminal() {
+        Some(screen)
+    } else {
+        None
+    };
+    // a failing terminal shows up again with the next write
+    let _ = notifier.notify(terminal, event, &session.port_description());
+}
+
+fn handle_disconnect(
+    sc_args: &SessionConfig,
+    session: &mut Session,
+    events: &(Sender<Event>, Receiver<Event>),
+    escape_state: &mut EscapeState,
+    screen: &mut Screen,
+) -> Result<Box<dyn Transport>, Exit> {
+    json_event(session, screen, |json| {
+        json.disconnect("device disconnected")
+    });
+    if !sc_args.reconnect && !sc_args.daemon {
+        return Err(ScipioError::Disconnected.into());
+    }
+    // what was captured so far is on disk while waiting
+    if let Some(capture) = &mut session.capture {
+        if let Err(err) = capture.flush() {
+            let msg = format!("stopped capturing to {}: {}", capture.path().display(), err);
+            session.capture = None;
+            write_status(screen, &msg);
+        }
+    }
+    let device = session.device.clone();
+    write_status(
+        screen,
+        &format!("device disconnected, waiting for {}", device),
+    );
+    notify(session, screen, "device disconnected");
+    wait_for_device(sc_args, session, events, escape_state, screen)
+}
+
+// Open the port, retrying until `retry_until` while it is held by another program. The
+// signal thread is already running, so Ctrl-C and SIGTERM end the waits between attempts with
+// the lock removed. The lock of a local
+// port is taken once its path exists, so a /dev/serial/by-id link appearing meanwhile locks
+// the device node it points to. It is held until the program ends, reconnecting keeps it.
+fn open_retrying(
+    device: &Device,
+    settings: &PortSettings,
+    lines: &OpenLines,
+    retry_until: Option<Instant>,
+    sc_args: &SessionConfig,
+    rx: &Receiver<Event>,
+) -> Result<(Box<dyn Transport>, Option<lock::PortLock>), Exit> {
+    let mut backoff = Duration::from_millis(100);
+    let mut attempt = 1;
+    let status_line = io::stderr().is_terminal();
+    let mut port_lock = None;
+    loop {
+        if let (Some(path), None, false) = (device.local_path(), &port_lock, sc_args.no_lock) {
+            if path.exists() {
+                port_lock = lock::lock_port(path, sc_args.lock_dir.as_deref())?;
+            }
+        }
+        let err = match transport::open(device, settings, lines) {
+            Ok(port) => {
+                if attempt > 1 && status_line {
+                    eprintln!();
+                }
+                return Ok((port, port_lock));
+            }
+            Err(err) => err,
+        };
+        let retryable = matches!(
+            err.kind(),
+            // EBUSY and EIO end up as Other
+            io::ErrorKind::PermissionDenied
+                | io::ErrorKind::Other
+                | io::ErrorKind::TimedOut
+                | io::ErrorKind::WouldBlock
+                | io::ErrorKind::Interrupted
+        );
+        let now = Instant::now();
+        match retry_until {
+            Some(retry_until) if retryable && now < retry_until => {
+                attempt += 1;
+                // one line, rewritten with every attempt
+                if status_line {
+                    eprint!("\r[{}: {}, attempt {}]\x1b[K", device, err, attempt);
+                } else if attempt == 2 {
+                    eprintln!("[{}: {}, retrying]", device, err);
+                }
+                let wake = now + backoff.min(retry_until - now);
+                // SIGHUP has no log file to reopen yet
+                while let Some(wait) = wake.checked_duration_since(Instant::now()) {
+                    if let Ok(Event::Interrupted) = rx.recv_timeout(wait) {
+                        if status_line {
+                            eprintln!();
+                        }
+                        return Err(Exit::interrupted());
+                    }
+                }
+                backoff = (backoff * 2).min(Duration::from_secs(2));
+            }
+            _ => {
+                if attempt > 1 && status_line {
+                    eprintln!();
+                }
+                return Err(ScipioError::open_failed(device, err).into());
+            }
+        }
+    }
+}
+
+fn wait_for_device(
+    sc_args: &SessionConfig,
+    session: &mut Session,
+    (tx, rx): &(Sender<Event>, Receiver<Event>),
+    escape_state: &mut EscapeState,
+    screen: &mut Screen,
+) -> Result<Box<dyn Transport>, Exit> {
+    let device = &session.device.clone();
+    let settings = &session.settings.clone();
+    let open_lines = &session.open_lines.clone();
+    let deadline: Option<Instant> = sc_args
+        .wait_timeout
+        .map(|secs| Instant::now() + Duration::from_secs(secs));
+    let reconnect_delay = Duration::from_millis(sc_args.reconnect_delay);
+    let mut watching = false;
+    let mut notified = false;
+    let mut failed_attempts: u32 = 0;
+    // remote and usb: devices can't be watched, so they are tried every reconnect delay
+    let mut next_attempt = Instant::now();
+    loop {
+        let now = Instant::now();
+        let ready = match device.local_path() {
+            Some(path) => path.exists(),
+            None => now >= next_attempt,
+        };
+        if ready {
+            info!(
+                "reconnecting to {}, {} failed attempts",
+                device, failed_attempts
+            );
+            match transport::open(device, settings, open_lines) {
+                Ok(sp) => return Ok(sp),
+                // the adapter isn't plugged in yet, that is no failed attempt
+                Err(err)
+                    if err.kind() == io::ErrorKind::NotFound
+                        && matches!(device, Device::Usb(_)) =>
+                {
+                    next_attempt = Instant::now() + reconnect_delay;
+                }
+                Err(err) => {
+                    failed_attempts += 1;
+                    if let Some(retries) = sc_args.reconnect_retries {
+                        if failed_attempts >= retries {
+                            return Err(ScipioError::GaveUpReconnecting {
+                                device: device.to_string(),
+                                source: err,
+                            }
+                            .into());
+                        }
+                    }
+                    if device.local_path().is_some() {
+                        thread::sleep(reconnect_delay);
+                        continue;
+                    }
+                    next_attempt = Instant::now() + reconnect_delay;
+                }
+            }
+        }
+        if let (Some(path), false) = (device.local_path(), watching) {
+            watching = true;
+            notified = watch::spawn_device_watcher(path, tx.clone());
+        }
+
+        // block until the device shows up, polling where that can't be noticed
+        let now = Instant::now();
+        let mut timeout = if device.local_path().is_some() {
+            Duration::from_millis(100)
+        } else {
+            next_attempt.saturating_duration_since(now)
+        };
+        if let Some(deadline) = deadline {
+            if now >= deadline {
+                return Err(ScipioError::WaitTimeout(device.to_string()).into());
+            }
+            if notified {
+                timeout = deadline - now;
+            } else {
+                timeout = timeout.min(deadline - now);
+            }
+        }
+        let wake = if notified && deadline.is_none() {
+            None
+        } else {
+            Some(now + timeout)
+        };
+        match next_event(session, screen, rx, wake) {
+            // keystrokes are dropped while disconnected, but ~. and Ctrl-C still exit
+            Some(Event::Stdin(d)) => {
+                if !screen.is_terminal() {
+                    continue;
+                }
+                for character in &d {
+                    if *character == CTRL_C {
+                        return Err(Exit::default());
+                    }
+                    let (escape_char, quit_key) = (session.escape_char, session.quit_key);
+                    if let NextStep::LoopBreak =
+                        key_state_machine(character, escape_char, quit_key, escape_state)
+                    {
+                        return Err(Exit::default());
+                    }
+                }
+            }
+            Some(Event::StdinError(err)) => return Err(ScipioError::Stdin(err).into()),
+            Some(Event::DeviceReady) => watching = false,
+            Some(Event::Interrupted) => return Err(Exit::interrupted()),
+            Some(Event::ReopenLog) => reopen_log(session, screen),
+            // client input and the timeout
+            _ => {}
+        }
+    }
+}
+
+// Show data from the port's reader thread and pass it on to the log and clients
+fn display_received(session: &mut Session, screen: &mut Screen, data: &[u8]) -> NextStep {
+    session.stats.rx_bytes += data.len() as u64;
+    record(session, screen, Direction::Rx, data);
+    track_flow(session, screen, data);
+    #[cfg(all(unix, feature = "pty"))]
+    if let Some(bridge) = &mut session.pty {
+        if bridge.write(data) {
+            let msg = format!("{} isn't read, dropping data", bridge.link().display());
+            write_status(screen, &msg);
+        }
+    }
+    // from the start of a ZMODEM transfer on, the data is for the receiver
+    let mut data = data;
+    if let (Some(detector), true) = (&mut session.zmodem, screen.is_terminal()) {
+        if let Some((shown, pending)) = detector.find(data) {
+            data = &data[..shown];
+            session.zmodem_start = Some(pending);
+        }
+    }
+    check_rx_errors(session, screen, data);
+    if let Some(capture) = &mut session.capture {
+        if let Err(err) = capture.write_rx(data) {
+            let msg = format!("stopped capturing to {}: {}", capture.path().display(), err);
+            session.capture = None;
+            write_status(screen, &msg);
+        }
+    }
+    // the queries answered are left out of everything but the capture
+    let answered: Vec<u8>;
+    if let Some(answerback) = &mut session.answerback {
+        let replies;
+        (answered, replies) = answerback.feed(data, screen.footer_rows());
+        data = &answered;
+        if !replies.is_empty() {
+            match write_to_serial_port(session, screen, &replies) {
+                NextStep::None => {}
+                next_step => return next_step,
+            }
+        }
+    }
+    let mut received: Vec<u8> = session.rx_newline.translate(data);
+    // matched without the timestamps
+    let received_lines = received.clone();
+    if let Some(buffer) = &mut session.chat_input {
+        buffer.extend_from_slice(&received);
+    }
+    if let Some(stamper) = &mut session.timestamps {
+        received = stamper.stamp(&received);
+    }
+    let filtered: Vec<u8>;
+    let mut shown: &[u8] = match &mut session.control {
+        Some(control) => {
+            filtered = control.filter(&received);
+            &filtered
+        }
+        None => &received,
+    };
+    let decoded: Vec<u8>;
+    if let Some(utf8) = &mut session.utf8 {
+        decoded = utf8.filter(shown);
+        shown = &decoded;
+    }
+    let checked: Vec<u8>;
+    if let Some(nmea) = &mut session.nmea {
+        checked = nmea.filter(shown);
+        shown = &checked;
+    }
+    let frames: Vec<u8>;
+    if let Some(decoder) = &mut session.decoder {
+        frames = decoder.feed(data, session.read_at);
+        shown = &frames;
+    }
+    let viewed: Vec<u8>;
+    let mut view_lines = None;
+    if let Some(view) = &session.debug_view {
+        let lines = view.lines(Direction::Rx, data);
+        viewed = view.show(Direction::Rx, &lines);
+        shown = &viewed;
+        view_lines = Some(lines);
+    }
+    let marked: Vec<u8>;
+    if let Some(marker) = &mut session.gap_marker {
+        let gap = marker.gap(session.read_at);
+        if let (Some(gap), true, Some(log_file)) = (gap, session.log_gaps, &mut session.log) {
+            if let Err(err) = log_file.write_event(&format!("{} ms gap", gap.as_millis())) {
+                let msg = format!("stopped logging to {}: {}", log_file.path().display(), err);
+                session.log = None;
+                write_status(screen, &msg);
+            }
+        }
+        marked = marker.mark(gap, shown);
+        shown = &marked;
+    }
+    if let NextStep::Exit(exit) = show_received(session, screen, shown) {
+        return NextStep::Exit(exit);
+    }
+    if session.pause_row.is_some() {
+        show_paused(session, screen);
+    }
+    if let Some(listener) = &mut session.listener {
+        listener.broadcast(&received, screen);
+    }
+    if let Some(log_file) = &mut session.log {
+        // a pretty log has timestamps of its own and shows the bytes as they came
+        let result = match (log_file.format(), &view_lines) {
+            (LogFormat::Raw, _) => log_file.write_rx(&received),
+            (LogFormat::Pretty, Some(lines)) => log_file.write_view(lines),
+            (LogFormat::Pretty, None) => log_file.write_rx(data),
+        };
+        if let Err(err) = result {
+            let msg = format!("stopped logging to {}: {}", log_file.path().display(), err);
+            session.log = None;
+            write_status(screen, &msg);
+        }
+    }
+    let max_replies = session.triggers.max_replies();
+    for (trigger, line) in session.triggers.feed(&received_lines) {
+        if let Some(json) = &mut session.json {
+            if let Err(err) = json.matched(trigger.pattern(), &line) {
+                session.json = None;
+                write_status(screen, &format!("stopped the JSON output: {}", err));
+            }
+        }
+        match trigger.action() {
+            TriggerAction::Bell if session.json.is_some() => {}
+            TriggerAction::Bell => {
+                if let Err(err) = screen.write_all(b"\x07").and_then(|()| screen.flush()) {
+                    return screen_error(err);
+                }
+            }
+            TriggerAction::Exit => {
+                let msg = format!("Matched /{}/: {}", trigger.pattern(), line);
+                return NextStep::Exit(Exit::with_message(EXIT_MATCHED, msg));
+            }
+            TriggerAction::LogMark => {
+                if let Some(log_file) = &mut session.log {
+                    if let Err(err) = log_file.write_mark(trigger.pattern()) {
+                        let msg =
+                            format!("stopped logging to {}: {}", log_file.path().display(), err);
+                        session.log = None;
+                        write_status(screen, &msg);
+                    }
+                }
+            }
+            TriggerAction::Exec(command) => {
+                if let Err(err) = trigger::spawn_command(command, &line) {
+                    write_status(screen, &format!("running {} failed: {}", command, err));
+                }
+            }
+            TriggerAction::Reply(data) => {
+                let mut note = format!("auto: {} for /{}/", data.escape_ascii(), trigger.pattern());
+                if trigger.replies() == max_replies {
+                    note += &format!(", the last of {}", max_replies);
+                }
+                session.auto_replies.push(PendingReply {
+                    due_at: Instant::now() + session.auto_respond_delay,
+                    data: data.clone(),
+                    note,
+                });
+            }
+        }
+    }
+    NextStep::None
+}
+
+// Send the first --auto-respond reply that is due, marked on the screen and in the log
+fn send_auto_reply(session: &mut Session, screen: &mut Screen) -> NextStep {
+    let reply = session.auto_replies.remove(0);
+    write_status(screen, &reply.note);
+    if let Some(log_file) = &mut session.log {
+        if let Err(err) = log_file.write_event(&reply.note) {
+            let msg = format!("stopped logging to {}: {}", log_file.path().display(), err);
+            session.log = None;
+            write_status(screen, &msg);
+        }
+    }
+    write_to_serial_port(session, screen, &reply.data)
+}
+
+// What display_received() made of the data, or a frame a silence ended
+fn show_received(session: &mut Session, screen: &mut Screen, shown: &[u8]) -> NextStep {
+    // --output json has the data in the rx objects
+    if session.write_only || session.json.is_some() {
+        return NextStep::None;
+    }
+    if screen.is_terminal() {
+        session.scrollback.feed(shown);
+    }
+    if let Err(err) = screen.write_received(shown).and_then(|()| screen.flush()) {
+        return screen_error(err);
+    }
+    scrollback::show_new_lines(session, screen)
+}
+
+// What to do after the reader thread stopped with `err`
+fn port_error(err: io::Error) -> NextStep {
+    if is_disconnect(&err) {
+        return NextStep::Disconnected;
+    }
+    NextStep::Exit(ScipioError::Port(err).into())
+}
+
+fn interrupted() -> NextStep {
+    NextStep::Exit(Exit::interrupted())
+}
+
+fn stdin_error(err: io::Error) -> NextStep {
+    NextStep::Exit(ScipioError::Stdin(err).into())
+}
+
+fn screen_error(err: io::Error) -> NextStep {
+    NextStep::Exit(ScipioError::Terminal(err).into())
+}
+
+// The keep-alive goes straight to the port, it isn't echoed or translated like typed input
+fn send_keepalive(session: &mut Session, screen: &mut Screen) -> NextStep {
+    let bytes = match &session.keepalive {
+        Some(keepalive) => keepalive.bytes().to_vec(),
+        None => return NextStep::None,
+    };
+    let next_step = write_to_serial_port(session, screen, &bytes);
+    // a device that doesn't take it gets the next one an interval later, not right away
+    session.last_write = Instant::now();
+    next_step
+}
+
+// Send what was typed or what a client sent, through the queue when it is paced
+fn send_input(session: &mut Session, screen: &mut Screen, data: &[u8]) -> NextStep {
+    if session.tx_queue.is_paced() {
+        session.tx_queue.push(data);
+        return NextStep::None;
+    }
+    write_to_serial_port(session, screen, data)
+}
+
+// Record and log what a write took, with --debug-view it is shown as well
+fn log_sent(session: &mut Session, screen: &mut Screen, data: &[u8]) -> NextStep {
+    record(session, screen, Direction::Tx, data);
+    let view_lines = session
+        .debug_view
+        .as_ref()
+        .map(|view| view.lines(Direction::Tx, data));
+    if let Some(log_file) = &mut session.log {
+        let result = match (log_file.format(), &view_lines) {
+            (LogFormat::Pretty, Some(lines)) => log_file.write_view(lines),
+            _ => log_file.write_tx(data),
+        };
+        if let Err(err) = result {
+            let msg = format!("stopped logging to {}: {}", log_file.path().display(), err);
+            session.log = None;
+            write_status(screen, &msg);
+        }
+    }
+    match (&session.debug_view, view_lines) {
+        (Some(view), Some(lines)) => {
+            let shown = view.show(Direction::Tx, &lines);
+            show_received(session, screen, &shown)
+        }
+        _ => NextStep::None,
+    }
+}
+
+// Add data that went over the port to --record and --output json, stopping either when it
+// fails
+fn record(session: &mut Session, screen: &mut Screen, direction: Direction, data: &[u8]) {
+    json_event(session, screen, |json| json.data(direction, data));
+    if let Some(record) = &mut session.record {
+        if let Err(err) = record.write(direction, data) {
+            let msg = format!("stopped recording to {}: {}", record.path().display(), err);
+            session.record = None;
+            write_status(screen, &msg);
+        }
+    }
+}
+
+// Write an object of --output json, which stops when stdout fails
+fn json_event(
+    session: &mut Session,
+    screen: &mut Screen,
+    event: impl FnOnce(&mut JsonOutput) -> io::Result<()>,
+) {
+    if let Some(json) = &mut session.json {
+        if let Err(err) = event(json) {
+            session.json = None;
+            write_status(screen, &format!("stopped the JSON output: {}", err));
+        }
+    }
+}
+
+fn write_to_serial_port(session: &mut Session, screen: &mut Screen, data: &[u8]) -> NextStep {
+    // the commands that send are refused before getting here with --read-only
+    if session.read_only {
+        return NextStep::None;
+    }
+    // write all terminal input to the serial port, retrying while the device is busy
+    let deadline = Instant::now() + session.write_timeout;
+    let mut written: usize = 0;
+    let mut backoff = Duration::from_millis(1);
+    while written < data.len() {
+        match session.serial_port.write(&data[written..]) {
+            Ok(n) if n > 0 => {
+                trace!("wrote {} of {} bytes", n, data.len() - written);
+                let next_step = log_sent(session, screen, &data[written..written + n]);
+                written += n;
+                session.stats.tx_bytes += n as u64;
+                session.last_write = Instant::now();
+                backoff = Duration::from_millis(1);
+                if let NextStep::Exit(exit) = next_step {
+                    return NextStep::Exit(exit);
+                }
+                continue;
+            }
+            Ok(_) => {}
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => {}
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => {
+                debug!("writing failed: {} ({:?})", err, err.kind());
+                session.stats.write_errors += 1;
+                if is_disconnect(&err) {
+                    return NextStep::Disconnected;
+                }
+                return NextStep::Exit(ScipioError::Port(err).into());
+            }
+        }
+
+        if Instant::now() >= deadline {
+            debug!(
+                "writing timed out after {} of {} bytes",
+                written,
+                data.len()
+            );
+            session.stats.write_errors += 1;
+            write_status(
+                screen,
+                &format!(
+                    "write timed out, dropped {} of {} bytes",
+                    data.len() - written,
+                    data.len()
+                ),
+            );
+            return NextStep::None;
+        }
+        // the reader thread keeps received data queued meanwhile
+        thread::sleep(backoff);
+        backoff = (backoff * 2).min(Duration::from_millis(50));
+    }
+    NextStep::None
+}
+
+fn is_disconnect(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::NotFound
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+    )
+}
+
+// A rate that isn't a common one, and the rate the driver really set when it couldn't do the
+// one asked for
+fn baud_rate_notes(session: &Session) -> Vec<String> {
+    let mut notes = Vec::new();
+    if session.device.is_local_stream() {
+        return notes;
+    }
+    let baud_rate = session.settings.baud_rate;
+    if !COMMON_BAUD_RATES.contains(&baud_rate) {
+        notes.push(format!("{} isn't one of the common baud rates", baud_rate));
+    }
+    // a network port has no settings to read back
+    if let Ok(settings) = session.serial_port.settings() {
+        if settings.baud_rate != baud_rate {
+            notes.push(format!(
+                "the driver set {} baud instead of {}",
+                settings.baud_rate, baud_rate
+            ));
+        }
+    }
+    notes
+}
+
+// The baud rate of each device: those of --baud, one for every device or one each, else that
+// of the baud rate argument
+fn baud_rates(sc_args: &SessionConfig) -> Result<Vec<u32>, String> {
+    let devices = 1 + usize::from(second_device(sc_args).is_some());
+    let list = match (&sc_args.baud, &sc_args.baud_rate) {
+        (Some(list), _) => list,
+        (None, RateOrDevice::Rate(baud_rate)) => return Ok(vec![*baud_rate]),
+        (None, RateOrDevice::Device(_)) => return Ok(vec![DEFAULT_BAUD_RATE; devices]),
+    };
+    let rates = list
+        .split(',')
+        .map(|rate| {
+            rate.trim()
+                .parse()
+                .map_err(|_| format!("in --baud {}: {} isn't a baud rate", list, rate))
+        })
+        .collect::<Result<Vec<u32>, String>>()?;
+    match rates.len() {
+        1 => Ok(vec![rates[0]; devices]),
+        len if len == devices => Ok(rates),
+        len => Err(format!(
+            "--baud {} has {} rates for {} devices, give one for each or one for all",
+            list, len, devices
+        )),
+    }
+}
+
+// The device given in place of the baud rate, for a split view
+fn second_device(sc_args: &SessionConfig) -> Option<&str> {
+    match &sc_args.baud_rate {
+        RateOrDevice::Device(path) => Some(path),
+        RateOrDevice::Rate(_) => None,
+    }
+}
+
+// The port settings given for a socket or FIFOs, which have none
+fn ignored_settings(device: &Device, given: &[&str]) -> Option<String> {
+    if !device.is_local_stream() {
+        return None;
+    }
+    if given.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "warning: {} ignored, {} has no port settings",
+        given.join(", "),
+        device
+    ))
+}
+
+fn open_lines(sc_args: &SessionConfig) -> OpenLines {
+    OpenLines {
+        dtr: sc_args.dtr.as_deref().map(|level| level == "on"),
+        rts: sc_args.rts.as_deref().map(|level| level == "on"),
+        keep_on_close: sc_args.no_reset || sc_args.read_only,
+    }
+}
+
+fn log_rotation(sc_args: &SessionConfig) -> Option<Rotation> {
+    sc_args.log_rotate_size.map(|size| Rotation {
+        size,
+        keep: sc_args.log_rotate_keep,
+    })
+}
+
+fn log_format(sc_args: &SessionConfig) -> LogFormat {
+    match sc_args.log_format.as_str() {
+        "pretty" => LogFormat::Pretty,
+        _ => LogFormat::Raw,
+    }
+}
+
+fn parse_arguments_into_settings(sc_args: &SessionConfig) -> PortSettings {
+    fn match_data_bits(data_bits: u8) -> DataBits {
+        match data_bits {
+            8 => DataBits::Eight,
+            7 => DataBits::Seven,
+            6 => DataBits::Six,
+            5 => DataBits::Five,
+            _ => DataBits::Eight,
+        }
+    }
+    // clap checked the value, in any case
+    fn match_parity(parity: &str) -> PortParity {
+        match parity.to_ascii_lowercase().as_str() {
+            "odd" | "o" => PortParity::Odd,
+            "even" | "e" => PortParity::Even,
+            "mark" | "m" => PortParity::Mark,
+            "space" | "s" => PortParity::Space,
+            _ => PortParity::None,
+        }
+    }
+    fn match_stop_bits(stop_bits: u8) -> StopBits {
+        match stop_bits {
+            1 => StopBits::One,
+            2 => StopBits::Two,
+            _ => StopBits::One,
+        }
+    }
+    fn match_flow_control(flow_control: &str) -> FlowControl {
+        match flow_control.to_ascii_lowercase().as_str() {
+            "rtscts" | "hardware" | "h" => FlowControl::Hardware,
+            "xonxoff" | "software" | "s" => FlowControl::Software,
+            _ => FlowControl::None,
+        }
+    }
+    PortSettings {
+        // parse_options checked the rates
+        baud_rate: baud_rates(sc_args).map_or(DEFAULT_BAUD_RATE, |rates| rates[0]),
+        data_bits: match_data_bits(sc_args.data_bits),
+        parity: match_parity(sc_args.parity.as_str()),
+        stop_bits: match_stop_bits(sc_args.stop_bits),
+        flow_control: match_flow_control(sc_args.flow_control.as_str()),
+        timeout: Duration::from_millis(sc_args.timeout_ms),
+    }
+}
+
+// Whether the session just passes bytes, see --pipe
+fn is_pipe(sc_args: &SessionConfig) -> bool {
+    pipe_given(sc_args) || !stdin().is_terminal() || !stdout().is_terminal()
+}
+
+// Whether the options make it a pipe session wherever it runs
+fn pipe_given(sc_args: &SessionConfig) -> bool {
+    sc_args.pipe || sc_args.output == "json" || sc_args.daemon || !sc_args.command.is_empty()
+}
+
+// Print a clap error or the help, with our exit status instead of clap's
+fn list_ports() -> u8 {
+    let ports = match serialport::available_ports() {
+        Ok(ports) => ports,
+        Err(err) => {
+            eprintln!("Error listing serial ports: {}", err);
+            return EXIT_ERROR;
+        }
+    };
+    if ports.is_empty() {
+        eprintln!("No serial ports found");
+        return EXIT_ERROR;
+    }
+
+    // path, type, VID:PID, manufacturer, product, serial number
+    let rows: Vec<[String; 6]> = ports
+        .into_iter()
+        .map(|port| match port.port_type {
+            SerialPortType::UsbPort(usb) => [
+                port.port_name,
+                "usb".to_string(),
+                format!("{:04x}:{:04x}", usb.vid, usb.pid),
+                usb.manufacturer.unwrap_or_default(),
+                usb.product.unwrap_or_default(),
+                usb.serial_number.unwrap_or_default(),
+            ],
+            port_type => {
+                let kind = match port_type {
+                    SerialPortType::PciPort => "pci",
+                    SerialPortType::BluetoothPort => "bluetooth",
+                    _ => "unknown",
+                };
+                let mut row: [String; 6] = Default::default();
+                row[0] = port.port_name;
+                row[1] = kind.to_string();
+                row
+            }
+        })
+        .collect();
+
+    let mut out = stdout();
+    if out.is_terminal() {
+        // align every column but the last one to its widest entry
+        let mut widths = [0; 6];
+        for row in &rows {
+            for (width, field) in widths.iter_mut().zip(row.iter()) {
+                *width = (*width).max(field.chars().count());
+            }
+        }
+        for row in &rows {
+            let mut line = String::new();
+            for (i, field) in row.iter().enumerate() {
+                if i + 1 < row.len() {
+                    line.push_str(&format!("{:width$}  ", field, width = widths[i]));
+                } else {
+                    line.push_str(field);
+                }
+            }
+            writeln!(out, "{}", line.trim_end()).unwrap();
+        }
+    } else {
+        // one tab separated line per port for scripts
+        for row in &rows {
+            writeln!(out, "{}", row.join("\t")).unwrap();
+        }
+    }
+    0
+}
+
+fn write_start_screen_msg(screen: &mut Screen, session: &Session) -> io::Result<()> {
+    // the output above the session stays in place on the normal screen
+    if !screen.is_alternate() {
+        write!(
+            screen,
+            "{}\r\n{}\r\nor unplug the serial port.\r\n",
+            welcome(session),
+            how_to_exit(session.escape_char, session.quit_key)
+        )?;
+        return screen.flush();
+    }
+    write!(screen, "{}", ansi::CLEAR_ALL)?;
+    write_banner(screen, session)
+}
+
+// The first line of the banner, saying so when the session only goes one way
+fn welcome(session: &Session) -> String {
+    let mode = if session.read_only {
+        ", read-only"
+    } else if session.write_only {
+        ", write-only"
+    } else {
+        ""
+    };
+    format!("Welcome to {}{}.", PROGRAM_NAME, mode)
+}
+
+fn how_to_exit(escape_char: Option<u8>, quit_key: Option<QuitKey>) -> String {
+    match (escape_char, quit_key) {
+        (Some(escape_char), Some(quit_key)) => format!(
+            "To exit type <Enter> + {} + . or {}",
+            escape::escape_char_name(escape_char),
+            quit_key.name()
+        ),
+        (Some(escape_char), None) => format!(
+            "To exit type <Enter> + {} + .",
+            escape::escape_char_name(escape_char)
+        ),
+        (None, Some(quit_key)) => format!("To exit type {}", quit_key.name()),
+        (None, None) => format!("To exit run kill -INT {}", std::process::id()),
+    }
+}
+
+// The welcome in the top rows of a cleared screen, the output continues below it
+fn write_banner(screen: &mut Screen, session: &Session) -> io::Result<()> {
+    write!(
+        screen,
+        "{}{}{}{}\r\nor unplug the serial port.{}",
+        ansi::Goto(1, 1),
+        welcome(session),
+        ansi::Goto(1, 2),
+        how_to_exit(session.escape_char, session.quit_key),
+        ansi::Goto(1, 4)
+    )?;
+    screen.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn common_and_deliberate_rates_are_no_typos() {
+        for rate in [
+            115200, 1152000, 9600, 31250, 74880, 56000, 128000, 200000, 256000,
+        ] {
+            assert_eq!(likely_meant_rate(rate), None, "{}", rate);
+        }
+    }
+
+    #[test]
+    fn rates_off_by_a_zero_or_a_few_percent_are_typos() {
+        assert_eq!(likely_meant_rate(11520), Some(115200));
+        assert_eq!(likely_meant_rate(96000), Some(9600));
+        assert_eq!(likely_meant_rate(1152000 * 10), Some(1152000));
+        assert_eq!(likely_meant_rate(115000), Some(115200));
+    }
+
+    #[test]
+    fn unusual_rates_far_from_common_ones_pass() {
+        assert_eq!(likely_meant_rate(7000), None);
+        assert_eq!(likely_meant_rate(650000), None);
+    }
+
+    fn config(args: &[&str]) -> Result<SessionConfig, clap::Error> {
+        let args = ["scip", "--no-config", "/dev/ttyUSB0"].iter().chain(args);
+        SessionConfig::from_matches(&app().try_get_matches_from(args)?)
+    }
+
+    #[test]
+    fn a_second_device_takes_the_place_of_the_baud_rate() {
+        let sc_args = config(&["/dev/ttyUSB1", "--baud", "115200,921600"]).unwrap();
+        assert_eq!(second_device(&sc_args), Some("/dev/ttyUSB1"));
+        assert_eq!(baud_rates(&sc_args), Ok(vec![115200, 921600]));
+
+        let sc_args = config(&["115200"]).unwrap();
+        assert_eq!(sc_args.baud_rate, RateOrDevice::Rate(115200));
+        assert_eq!(second_device(&sc_args), None);
+        assert_eq!(baud_rates(&sc_args), Ok(vec![115200]));
+        assert!(config(&["115k"]).is_err());
+    }
+
+    #[test]
+    fn one_baud_rate_is_for_every_device() {
+        let sc_args = config(&["COM4", "--baud", "57600"]).unwrap();
+        assert_eq!(baud_rates(&sc_args), Ok(vec![57600, 57600]));
+        let sc_args = config(&["usb:0403:6001"]).unwrap();
+        assert_eq!(baud_rates(&sc_args), Ok(vec![9600, 9600]));
+        // --baud wins over the argument
+        let sc_args = config(&["115200", "--baud", "9600"]).unwrap();
+        assert_eq!(baud_rates(&sc_args), Ok(vec![9600]));
+        assert_eq!(sc_args.given_settings, ["baud rate"]);
+    }
+
+    #[test]
+    fn the_baud_rates_must_match_the_devices() {
+        let sc_args = config(&["/dev/ttyUSB1", "--baud", "115200,921600,9600"]).unwrap();
+        assert!(baud_rates(&sc_args)
+            .unwrap_err()
+            .contains("3 rates for 2 devices"));
+        let sc_args = config(&["--baud", "115200,921600"]).unwrap();
+        assert!(baud_rates(&sc_args)
+            .unwrap_err()
+            .contains("2 rates for 1 devices"));
+        let sc_args = config(&["/dev/ttyUSB1", "--baud", "115200,fast"]).unwrap();
+        assert!(baud_rates(&sc_args)
+            .unwrap_err()
+            .contains("fast isn't a baud rate"));
+        // the typo check covers the rate of each device
+        let sc_args = config(&["/dev/ttyUSB1", "--baud", "115200,11520"]).unwrap();
+        assert!(parse_options(&sc_args).is_err());
+    }
+
+    #[test]
+    fn a_third_device_is_refused_clearly() {
+        let err = config(&["/dev/ttyUSB1", "/dev/ttyUSB2"]).unwrap_err();
+        assert!(err.to_string().contains("a third device can't be given"));
+        let err = config(&["/dev/ttyUSB1", "9"]).unwrap_err();
+        assert!(err.to_string().contains("the data bits are 5, 6, 7 or 8"));
+        assert!(config(&["/dev/ttyUSB1", "7"]).is_ok());
+    }
+
+    fn option_error(args: &[&str]) -> String {
+        parse_options(&config(args).unwrap())
+            .err()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn the_read_buffer_and_the_port_timeout_have_to_be_above_zero() {
+        assert_eq!(
+            option_error(&["--read-buffer", "0"]),
+            "Error --read-buffer has to be at least 1 byte"
+        );
+        assert_eq!(
+            option_error(&["--timeout-ms", "0"]),
+            "Error --timeout-ms has to be at least 1 ms"
+        );
+        let sc_args = config(&["--read-buffer", "16384", "--timeout-ms", "1"]).unwrap();
+        assert!(parse_options(&sc_args).is_ok());
+        assert_eq!(sc_args.read_buffer, 16384);
+        assert_eq!(
+            parse_arguments_into_settings(&sc_args).timeout,
+            Duration::from_millis(1)
+        );
+        assert!(config(&["--read-buffer", "-1"]).is_err());
+        assert!(config(&["--timeout-ms", "10ms"]).is_err());
+    }
+
+    #[test]
+    fn a_daemon_needs_a_log_and_no_input() {
+        assert!(config(&["--daemon", "--log", "console.log"]).is_ok());
+        assert!(config(&["--daemon"]).is_err());
+        assert!(config(&["--daemon", "--log", "x.log", "--command", "ls"]).is_err());
+        assert!(config(&["--daemon", "--log", "x.log", "--exit-on-eof"]).is_err());
+        assert!(config(&["--detach"]).is_err());
+        assert!(config(&["--pid-file", "scip.pid"]).is_err());
+        let sc_args = config(&["--daemon", "--log", "x.log", "--detach"]).unwrap();
+        // nothing is read from stdin and the screen is a pipe
+        assert!(pipe_given(&sc_args));
+    }
+
+    #[test]
+    fn every_parity_alias_in_any_case() {
+        for (values, parity) in [
+            (["none", "n", "NONE", "N"], PortParity::None),
+            (["odd", "o", "Odd", "O"], PortParity::Odd),
+            (["even", "e", "EVEN", "E"], PortParity::Even),
+            (["mark", "m", "Mark", "M"], PortParity::Mark),
+            (["space", "s", "SPACE", "S"], PortParity::Space),
+        ] {
+            for value in values {
+                let sc_args = config(&["9600", "8", value]).unwrap();
+                let settings = parse_arguments_into_settings(&sc_args);
+                assert_eq!(settings.parity, parity, "{}", value);
+            }
+        }
+        assert!(config(&["9600", "8", "x"]).is_err());
+    }
+
+    #[test]
+    fn every_flow_control_alias_in_any_case() {
+        for (values, flow_control) in [
+            (&["none", "n", "N"][..], FlowControl::None),
+            (
+                &["rtscts", "hardware", "h", "RTSCTS", "Hardware", "H"][..],
+                FlowControl::Hardware,
+            ),
+            (
+                &["xonxoff", "software", "s", "XonXoff", "SOFTWARE", "S"][..],
+                FlowControl::Software,
+            ),
+        ] {
+            for value in values {
+                let sc_args = config(&["9600", "8", "none", "1", value]).unwrap();
+                let settings = parse_arguments_into_settings(&sc_args);
+                assert_eq!(settings.flow_control, flow_control, "{}", value);
+            }
+        }
+        assert!(config(&["9600", "8", "none", "1", "dtrdsr"]).is_err());
+    }
+}
@@ -0,0 +1,301 @@
+use crate::history::{self, History};
+
+pub enum LineInput {
+    // Enter was pressed on this line
+    Line(String),
+    // Ctrl-C on an empty line
+    Interrupt,
+}
+
+#[derive(Clone, Copy)]
+enum KeyState {
+    Text,
+    Escape,
+    // inside a CSI or SS3 sequence, collecting its parameters
+    Sequence,
+}
+
+// Ctrl-R going back through the history for lines containing what was typed since
+struct Search {
+    query: String,
+    // the entry shown, kept while a longer query finds nothing
+    found: Option<usize>,
+    failing: bool,
+    // the line that was being typed before, back on Ctrl-C
+    draft: Vec<char>,
+}
+
+// The line composed locally in line mode, sent to the port on Enter
+pub struct LineEditor {
+    text: Vec<char>,
+    cursor: usize,
+    history: History,
+    // the history entry shown, and the line that was being typed before
+    browsing: Option<(usize, Vec<char>)>,
+    search: Option<Search>,
+    key_state: KeyState,
+    params: String,
+    after_cr: bool,
+}
+
+impl Default for LineEditor {
+    fn default() -> LineEditor {
+        LineEditor::new()
+    }
+}
+
+impl LineEditor {
+    pub fn new() -> LineEditor {
+        LineEditor::with_history(History::new(history::DEFAULT_SIZE))
+    }
+
+    pub fn with_history(history: History) -> LineEditor {
+        LineEditor {
+            text: Vec::new(),
+            cursor: 0,
+            history,
+            browsing: None,
+            search: None,
+            key_state: KeyState::Text,
+            params: String::new(),
+            after_cr: false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty() && self.search.is_none()
+    }
+
+    // What the line starts with, which tells a search from the line being typed
+    pub fn prompt(&self) -> String {
+        match &self.search {
+            Some(search) if search.failing => format!("(failed search)`{}': ", search.query),
+            Some(search) => format!("(search)`{}': ", search.query),
+            None => "> ".to_string(),
+        }
+    }
+
+    // The line shown, the entry found while searching
+    pub fn text(&self) -> String {
+        match &self.search {
+            Some(search) => self.found_text(search).iter().collect(),
+            None => self.text.iter().collect(),
+        }
+    }
+
+    pub fn cursor(&self) -> usize {
+        match &self.search {
+            // at the start of the match
+            Some(search) => {
+                let text = self.text();
+                match text.find(&search.query) {
+                    Some(at) => text[..at].chars().count(),
+                    None => text.chars().count(),
+                }
+            }
+            None => self.cursor,
+        }
+    }
+
+    // Why the history couldn't be saved, once
+    pub fn take_history_error(&mut self) -> Option<String> {
+        self.history.take_error()
+    }
+
+    // Edit with a chunk read from stdin, returning the lines it completed
+    pub fn handle(&mut self, data: &[u8]) -> Vec<LineInput> {
+        let mut input = Vec::new();
+        for c in String::from_utf8_lossy(data).chars() {
+            let after_cr = self.after_cr;
+            self.after_cr = false;
+            match self.key_state {
+                KeyState::Escape => {
+                    self.key_state = match c {
+                        '[' | 'O' => KeyState::Sequence,
+                        _ => KeyState::Text,
+                    };
+                    self.params.clear();
+                    continue;
+                }
+                KeyState::Sequence => {
+                    if ('\x40'..='\x7e').contains(&c) {
+                        self.key_state = KeyState::Text;
+                        self.cursor_key(c);
+                    } else {
+                        self.params.push(c);
+                    }
+                    continue;
+                }
+                KeyState::Text => {}
+            }
+            if self.search_key(c) {
+                continue;
+            }
+            match c {
+                '\x1b' => self.key_state = KeyState::Escape,
+                '\r' | '\n' => {
+                    // a CR LF from a paste is a single Enter
+                    if c == '\n' && after_cr {
+                        continue;
+                    }
+                    self.after_cr = c == '\r';
+                    input.push(LineInput::Line(self.submit()));
+                }
+                // Ctrl-C
+                '\x03' if self.text.is_empty() => input.push(LineInput::Interrupt),
+                '\x03' => self.set_text(Vec::new()),
+                // Backspace and Delete
+                '\x08' | '\x7f' if self.cursor > 0 => {
+                    self.cursor -= 1;
+                    self.text.remove(self.cursor);
+                }
+                // Ctrl-U
+                '\x15' => {
+                    self.text.drain(..self.cursor);
+                    self.cursor = 0;
+                }
+                // Ctrl-R
+                '\x12' => {
+                    self.search = Some(Search {
+                        query: String::new(),
+                        found: None,
+                        failing: false,
+                        draft: self.text.clone(),
+                    })
+                }
+                // Ctrl-A and Ctrl-E
+                '\x01' => self.cursor = 0,
+                '\x05' => self.cursor = self.text.len(),
+                c if !c.is_control() => {
+                    self.text.insert(self.cursor, c);
+                    self.cursor += 1;
+                }
+                _ => {}
+            }
+        }
+        // cursor keys arrive in one read, so this was the Escape key itself
+        if let KeyState::Escape = self.key_state {
+            self.key_state = KeyState::Text;
+        }
+        input
+    }
+
+    // Handle a key while searching, false if there is no search or the key ends it with the
+    // entry found and is still to be handled as usual
+    fn search_key(&mut self, c: char) -> bool {
+        let search = match &mut self.search {
+            Some(search) => search,
+            None => return false,
+        };
+        let newest = self.history.len();
+        match c {
+            // Ctrl-R again, an older match
+            '\x12' => {
+                let before = search.found.unwrap_or(newest);
+                match self.history.search(&search.query, before) {
+                    Some(index) => search.found = Some(index),
+                    None => search.failing = true,
+                }
+            }
+            // Backspace and Delete
+            '\x08' | '\x7f' => {
+                search.query.pop();
+                search.failing = false;
+                search.found = if search.query.is_empty() {
+                    None
+                } else {
+                    self.history.search(&search.query, newest)
+                };
+            }
+            // Ctrl-C and Ctrl-G give up the search
+            '\x03' | '\x07' => {
+                if let Some(search) = self.search.take() {
+                    self.set_text(search.draft);
+                }
+            }
+            c if !c.is_control() => {
+                search.query.push(c);
+                // the entry shown stays if it still matches
+                let before = search.found.map_or(newest, |found| found + 1);
+                match self.history.search(&search.query, before) {
+                    Some(index) => search.found = Some(index),
+                    None => search.failing = true,
+                }
+            }
+            _ => {
+                if let Some(search) = self.search.take() {
+                    let text = self.found_text(&search);
+                    self.set_text(text);
+                }
+                return false;
+            }
+        }
+        true
+    }
+
+    fn found_text(&self, search: &Search) -> Vec<char> {
+        match search.found.and_then(|index| self.history.get(index)) {
+            Some(entry) => entry.chars().collect(),
+            None => search.draft.clone(),
+        }
+    }
+
+    fn cursor_key(&mut self, key: char) {
+        match (key, self.params.as_str()) {
+            ('A', _) => self.history_up(),
+            ('B', _) => self.history_down(),
+            ('C', _) => self.cursor = (self.cursor + 1).min(self.text.len()),
+            ('D', _) => self.cursor = self.cursor.saturating_sub(1),
+            ('H', _) | ('~', "1" | "7") => self.cursor = 0,
+            ('F', _) | ('~', "4" | "8") => self.cursor = self.text.len(),
+            ('~', "3") if self.cursor < self.text.len() => {
+                self.text.remove(self.cursor);
+            }
+            _ => {}
+        }
+    }
+
+    fn submit(&mut self) -> String {
+        let line: String = self.text.iter().collect();
+        self.history.add(&line);
+        self.browsing = None;
+        self.set_text(Vec::new());
+        line
+    }
+
+    fn history_up(&mut self) {
+        let index = match &self.browsing {
+            Some((0, _)) => return,
+            Some((index, _)) => index - 1,
+            None if self.history.is_empty() => return,
+            None => self.history.len() - 1,
+        };
+        let draft = match self.browsing.take() {
+            Some((_, draft)) => draft,
+            None => self.text.clone(),
+        };
+        self.browsing = Some((index, draft));
+        self.show_entry(index);
+    }
+
+    fn history_down(&mut self) {
+        match self.browsing.take() {
+            Some((index, draft)) if index + 1 == self.history.len() => self.set_text(draft),
+            Some((index, draft)) => {
+                self.browsing = Some((index + 1, draft));
+                self.show_entry(index + 1);
+            }
+            None => {}
+        }
+    }
+
+    fn show_entry(&mut self, index: usize) {
+        let entry = self.history.get(index).unwrap_or_default();
+        self.set_text(entry.chars().collect());
+    }
+
+    fn set_text(&mut self, text: Vec<char>) {
+        self.cursor = text.len();
+        self.text = text;
+    }
+}
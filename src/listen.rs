@@ -0,0 +1,236 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{sync_channel, Sender, SyncSender, TrySendError};
+use std::thread;
+
+use crate::{write_status, Event, Screen};
+
+// A client with this many chunks of mirrored data still unsent is dropped
+const MAX_QUEUED: usize = 256;
+
+pub enum ClientEvent {
+    Connected(TcpStream, SocketAddr),
+    // The client sent the right token
+    Authenticated(u64),
+    Data(u64, Vec<u8>),
+    Closed(u64, String),
+}
+
+struct Client {
+    id: u64,
+    addr: SocketAddr,
+    // kept to shut the connection down, which also ends the client's threads
+    stream: TcpStream,
+    outgoing: SyncSender<Vec<u8>>,
+    authenticated: bool,
+}
+
+// Shares the session with TCP clients: RX data is mirrored to every client
+// and what they send is forwarded to the port
+pub struct Listener {
+    addr: SocketAddr,
+    clients: Vec<Client>,
+    next_id: u64,
+    max_clients: usize,
+    token: Option<String>,
+    read_only: bool,
+    tx: Sender<Event>,
+}
+
+impl Listener {
+    pub fn bind(
+        addr: &str,
+        max_clients: usize,
+        token: Option<String>,
+        read_only: bool,
+        tx: Sender<Event>,
+    ) -> io::Result<Listener> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let accept_tx = tx.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let peer = match stream.peer_addr() {
+                    Ok(peer) => peer,
+                    Err(_) => continue,
+                };
+                let event = Event::Client(ClientEvent::Connected(stream, peer));
+                if accept_tx.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Listener {
+            addr,
+            clients: Vec::new(),
+            next_id: 0,
+            max_clients,
+            token,
+            read_only,
+            tx,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // Keep track of the clients, returning the input that should go to the port
+    pub fn handle(&mut self, event: ClientEvent, screen: &mut Screen) -> Option<ClientEvent> {
+        match event {
+            ClientEvent::Connected(stream, addr) => self.add(stream, addr, screen),
+            ClientEvent::Authenticated(id) => {
+                if let Some(client) = self.clients.iter_mut().find(|client| client.id == id) {
+                    client.authenticated = true;
+                    write_status(screen, &format!("client {} connected", client.addr));
+                }
+            }
+            ClientEvent::Data(id, data) => {
+                let known = self.clients.iter().any(|client| client.id == id);
+                if known && !self.read_only {
+                    return Some(ClientEvent::Data(id, data));
+                }
+            }
+            // a client that was dropped already may still report closing
+            ClientEvent::Closed(id, reason) => {
+                if let Some(i) = self.clients.iter().position(|client| client.id == id) {
+                    self.drop_client(i, &reason, screen);
+                }
+            }
+        }
+        None
+    }
+
+    // Queue data received from the port for every authenticated client
+    pub fn broadcast(&mut self, data: &[u8], screen: &mut Screen) {
+        let mut i = 0;
+        while i < self.clients.len() {
+            let client = &self.clients[i];
+            if !client.authenticated {
+                i += 1;
+                continue;
+            }
+            match client.outgoing.try_send(data.to_vec()) {
+                Ok(()) => i += 1,
+                Err(TrySendError::Full(_)) => {
+                    self.drop_client(i, "dropped, not keeping up", screen)
+                }
+                Err(TrySendError::Disconnected(_)) => self.drop_client(i, "disconnected", screen),
+            }
+        }
+    }
+
+    fn add(&mut self, mut stream: TcpStream, addr: SocketAddr, screen: &mut Screen) {
+        if self.clients.len() >= self.max_clients {
+            let _ = stream.write_all(b"too many clients\r\n");
+            write_status(screen, &format!("refused {}: too many clients", addr));
+            return;
+        }
+        let (reader, mut writer) = match (stream.try_clone(), stream.try_clone()) {
+            (Ok(reader), Ok(writer)) => (reader, writer),
+            (Err(err), _) | (_, Err(err)) => {
+                write_status(screen, &format!("refused {}: {}", addr, err));
+                return;
+            }
+        };
+        let _ = stream.set_nodelay(true);
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let (outgoing, queue) = sync_channel::<Vec<u8>>(MAX_QUEUED);
+        thread::spawn(move || {
+            for data in queue {
+                if writer.write_all(&data).is_err() {
+                    break;
+                }
+            }
+        });
+        let token = self.token.clone();
+        let tx = self.tx.clone();
+        thread::spawn(move || read_client(reader, id, token, tx));
+
+        let authenticated = self.token.is_none();
+        if authenticated {
+            write_status(screen, &format!("client {} connected", addr));
+        }
+        self.clients.push(Client {
+            id,
+            addr,
+            stream,
+            outgoing,
+            authenticated,
+        });
+    }
+
+    fn drop_client(&mut self, i: usize, reason: &str, screen: &mut Screen) {
+        let client = self.clients.remove(i);
+        let _ = client.stream.shutdown(Shutdown::Both);
+        write_status(screen, &format!("client {} {}", client.addr, reason));
+    }
+}
+
+fn closed(id: u64, reason: &str) -> Event {
+    Event::Client(ClientEvent::Closed(id, reason.to_string()))
+}
+
+// Forward what a client sends, after checking its token
+fn read_client(mut stream: TcpStream, id: u64, token: Option<String>, tx: Sender<Event>) {
+    // the first line has to be the token, nothing is shared before that
+    let mut token_line: Option<Vec<u8>> = token.as_ref().map(|_| Vec::new());
+    let mut buf = [0; 512];
+    loop {
+        let n = match stream.read(&mut buf) {
+            Ok(0) => {
+                let _ = tx.send(closed(id, "disconnected"));
+                return;
+            }
+            Ok(n) => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                let _ = tx.send(closed(id, &err.to_string()));
+                return;
+            }
+        };
+        let mut data = &buf[..n];
+        if let Some(line) = &mut token_line {
+            line.extend_from_slice(data);
+            let end = match line.iter().position(|&byte| byte == b'\n') {
+                Some(end) => end,
+                None if line.len() > 1024 => {
+                    let _ = tx.send(closed(id, "token too long"));
+                    return;
+                }
+                None => continue,
+            };
+            // the rest of the read after the token line is input
+            let consumed = n - (line.len() - end - 1);
+            let sent = String::from_utf8_lossy(&line[..end]);
+            if Some(sent.trim_end_matches('\r')) != token.as_deref() {
+                let _ = stream.write_all(b"invalid token\r\n");
+                let _ = tx.send(closed(id, "invalid token"));
+                return;
+            }
+            token_line = None;
+            data = &buf[consumed..n];
+            if tx
+                .send(Event::Client(ClientEvent::Authenticated(id)))
+                .is_err()
+            {
+                return;
+            }
+        }
+        if data.is_empty() {
+            continue;
+        }
+        if tx
+            .send(Event::Client(ClientEvent::Data(id, data.to_vec())))
+            .is_err()
+        {
+            return;
+        }
+    }
+}
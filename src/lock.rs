@@ -0,0 +1,281 @@
+use std::path::Path;
+
+use crate::error::ScipioError;
+
+// UUCP lock files like /var/lock/LCK..ttyUSB0, holding the PID of the program using the
+// port as ten digits and a newline. minicom, picocom and most flashing tools on Linux look
+// for them before opening a port.
+pub struct PortLock {
+    #[cfg(unix)]
+    path: std::path::PathBuf,
+}
+
+// Lock the serial port at `device` in `dir`, or the first of /var/lock and /run/lock there
+// is. A lock of a program that isn't running anymore is taken over, one of a running program
+// is an error. Without a lock directory to write to the port is used unlocked.
+#[cfg(unix)]
+pub fn lock_port(device: &Path, dir: Option<&Path>) -> Result<Option<PortLock>, ScipioError> {
+    unix::lock_port(device, dir)
+}
+
+// There is no such convention off Unix
+#[cfg(not(unix))]
+pub fn lock_port(device: &Path, dir: Option<&Path>) -> Result<Option<PortLock>, ScipioError> {
+    let _ = (device, dir);
+    Ok(None)
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::fs::{self, OpenOptions};
+    use std::io::{self, Write};
+    use std::panic;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Mutex, Once};
+
+    use super::PortLock;
+    use crate::error::ScipioError;
+
+    const LOCK_DIRS: &[&str] = &["/var/lock", "/run/lock"];
+
+    // The locks to remove when a panic ends the program, Drop doesn't run then. --baud a,b
+    // holds one for each port.
+    static HELD: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+    static PANIC_HOOK: Once = Once::new();
+
+    pub fn lock_port(device: &Path, dir: Option<&Path>) -> Result<Option<PortLock>, ScipioError> {
+        let dir = match dir {
+            Some(dir) => dir.to_path_buf(),
+            None => match LOCK_DIRS.iter().map(Path::new).find(|dir| dir.is_dir()) {
+                Some(dir) => dir.to_path_buf(),
+                None => return Ok(None),
+            },
+        };
+        // a /dev/serial/by-id link locks the device node it points to
+        let node = match fs::canonicalize(device) {
+            Ok(node) => node,
+            Err(err) => {
+                eprintln!("[not locking {}: {}]", device.display(), err);
+                return Ok(None);
+            }
+        };
+        let name = match node.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => return Ok(None),
+        };
+        let path = dir.join(format!("LCK..{}", name));
+
+        // once more after removing a stale lock, another program may take it meanwhile
+        for _ in 0..2 {
+            match create(&path) {
+                Ok(()) => {
+                    hold(&path);
+                    return Ok(Some(PortLock { path }));
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(err) => {
+                    eprintln!(
+                        "[not locking {}: {}: {}]",
+                        device.display(),
+                        path.display(),
+                        err
+                    );
+                    return Ok(None);
+                }
+            }
+            match owner(&path) {
+                Some(pid) if is_running(pid) => {
+                    return Err(ScipioError::Locked {
+                        device: device.display().to_string(),
+                        pid,
+                        program: program_name(pid),
+                    })
+                }
+                _ => {
+                    eprintln!("[removing the stale lock {}]", path.display());
+                    if let Err(err) = fs::remove_file(&path) {
+                        let what = format!("removing the stale lock {}", path.display());
+                        return Err(ScipioError::Setup { what, source: err });
+                    }
+                }
+            }
+        }
+        Err(ScipioError::Setup {
+            what: format!("creating the lock {}", path.display()),
+            source: io::Error::from(io::ErrorKind::AlreadyExists),
+        })
+    }
+
+    fn create(path: &Path) -> io::Result<()> {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+        // the HDB format, readable by every program using these locks
+        let written = writeln!(file, "{:>10}", std::process::id());
+        if written.is_err() {
+            let _ = fs::remove_file(path);
+        }
+        written
+    }
+
+    // The PID in a lock file, None if it can't be read
+    fn owner(path: &Path) -> Option<i32> {
+        fs::read_to_string(path).ok()?.trim().parse().ok()
+    }
+
+    fn is_running(pid: i32) -> bool {
+        if pid <= 0 {
+            return false;
+        }
+        // EPERM means it runs as another user
+        let alive = unsafe { libc::kill(pid, 0) } == 0;
+        alive || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+
+    fn program_name(pid: i32) -> Option<String> {
+        let comm = fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+        Some(comm.trim().to_string())
+    }
+
+    fn hold(path: &Path) {
+        HELD.lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .push(path.to_path_buf());
+        PANIC_HOOK.call_once(|| {
+            let default_hook = panic::take_hook();
+            panic::set_hook(Box::new(move |info| {
+                if let Ok(held) = HELD.lock() {
+                    for path in held.iter() {
+                        let _ = fs::remove_file(path);
+                    }
+                }
+                default_hook(info);
+            }));
+        });
+    }
+
+    impl Drop for PortLock {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.path);
+            HELD.lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .retain(|path| *path != self.path);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        // A lock directory of its own for each test
+        fn lock_dir(name: &str) -> PathBuf {
+            let dir =
+                std::env::temp_dir().join(format!("scip-lock-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            dir
+        }
+
+        fn held(path: &Path) -> bool {
+            HELD.lock().unwrap().iter().any(|held| held == path)
+        }
+
+        // A file standing in for the device node
+        fn node(dir: &Path, name: &str) -> PathBuf {
+            let node = dir.join(name);
+            fs::write(&node, "").unwrap();
+            node
+        }
+
+        #[test]
+        fn the_lock_holds_the_pid_in_the_lock_dir_until_dropped() {
+            let dir = lock_dir("pid");
+            let device = node(&dir, "ttyPID");
+            let lock = lock_port(&device, Some(&dir)).unwrap().unwrap();
+            let path = dir.join("LCK..ttyPID");
+            let text = fs::read_to_string(&path).unwrap();
+            assert_eq!(text, format!("{:>10}\n", std::process::id()));
+            assert_eq!(owner(&path), Some(std::process::id() as i32));
+            drop(lock);
+            assert!(!path.exists());
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        #[test]
+        fn the_pid_is_read_with_or_without_padding() {
+            let dir = lock_dir("owner");
+            let path = dir.join("LCK..ttyX");
+            for (text, pid) in [
+                ("      1234\n", Some(1234)),
+                ("1234", Some(1234)),
+                ("x\n", None),
+            ] {
+                fs::write(&path, text).unwrap();
+                assert_eq!(owner(&path), pid, "{:?}", text);
+            }
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        #[test]
+        fn a_lock_of_a_program_that_ended_is_taken_over() {
+            let dir = lock_dir("stale");
+            let device = node(&dir, "ttySTALE");
+            let path = dir.join("LCK..ttySTALE");
+            // above the highest PID Linux hands out
+            for stale in [format!("{:>10}\n", i32::MAX), "garbage\n".to_string()] {
+                fs::write(&path, stale).unwrap();
+                let lock = lock_port(&device, Some(&dir)).unwrap().unwrap();
+                assert_eq!(owner(&path), Some(std::process::id() as i32));
+                drop(lock);
+            }
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        #[test]
+        fn a_lock_of_a_running_program_is_refused() {
+            let dir = lock_dir("live");
+            let device = node(&dir, "ttyLIVE");
+            let path = dir.join("LCK..ttyLIVE");
+            // this process holds it
+            fs::write(&path, format!("{:>10}\n", std::process::id())).unwrap();
+            let err = lock_port(&device, Some(&dir)).err().unwrap();
+            let pid = std::process::id() as i32;
+            assert!(matches!(err, ScipioError::Locked { pid: held, .. } if held == pid));
+            assert!(path.exists());
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        #[test]
+        fn a_link_locks_the_node_it_points_to() {
+            let dir = lock_dir("link");
+            let device = node(&dir, "ttyACM0");
+            let link = dir.join("usb-Espressif_USB_JTAG-if00");
+            std::os::unix::fs::symlink(&device, &link).unwrap();
+            let lock = lock_port(&link, Some(&dir)).unwrap().unwrap();
+            assert!(dir.join("LCK..ttyACM0").exists());
+            drop(lock);
+            // a link that isn't there yet is no name to lock by
+            let missing = dir.join("usb-not-plugged-in");
+            assert!(lock_port(&missing, Some(&dir)).unwrap().is_none());
+            assert!(!dir.join("LCK..usb-not-plugged-in").exists());
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        #[test]
+        fn dropping_one_of_two_locks_keeps_the_other_for_the_panic_hook() {
+            let dir = lock_dir("two");
+            let first = lock_port(&node(&dir, "ttyFIRST"), Some(&dir))
+                .unwrap()
+                .unwrap();
+            let second = lock_port(&node(&dir, "ttySECOND"), Some(&dir))
+                .unwrap()
+                .unwrap();
+            let (first_path, second_path) = (dir.join("LCK..ttyFIRST"), dir.join("LCK..ttySECOND"));
+            assert!(held(&first_path) && held(&second_path));
+            drop(first);
+            assert!(!first_path.exists() && !held(&first_path));
+            assert!(second_path.exists() && held(&second_path));
+            drop(second);
+            assert!(!held(&second_path));
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
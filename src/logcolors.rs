@@ -0,0 +1,76 @@
+use crate::highlight::Rule;
+
+// The presets of --log-colors: regular expressions and the color of the lines they match.
+// Where several match a line, the match starting first wins, then the earlier entry.
+const PRESETS: &[(&str, &[(&str, &str)])] = &[
+    // `E (123) wifi: ...`, the clock may also be the time of day. A line can start after the
+    // color the firmware put there itself, or after junk like the boot ROM leaves behind.
+    (
+        "esp-idf",
+        &[
+            (r"(?:^|(?-u:\W)|\x1b\[[0-9;]*m)E \([0-9:.]+\) ", "red"),
+            (r"(?:^|(?-u:\W)|\x1b\[[0-9;]*m)W \([0-9:.]+\) ", "yellow"),
+            (r"(?:^|(?-u:\W)|\x1b\[[0-9;]*m)I \([0-9:.]+\) ", "green"),
+            (r"(?:^|(?-u:\W)|\x1b\[[0-9;]*m)D \([0-9:.]+\) ", "cyan"),
+            (r"(?:^|(?-u:\W)|\x1b\[[0-9;]*m)V \([0-9:.]+\) ", "dim"),
+        ],
+    ),
+    // The syslog levels of printk from emergency to debug, or for `[    1.234567] ...` lines
+    // without one what the message says
+    (
+        "kernel",
+        &[
+            (r"<[0-3]>", "red"),
+            (r"<4>", "yellow"),
+            (r"<5>", "cyan"),
+            (r"<7>", "dim"),
+            (
+                r"\[ *[0-9]+\.[0-9]+\] .*(?i:error|fail|fault|panic|oops|bug:|call trace)",
+                "red",
+            ),
+            (r"\[ *[0-9]+\.[0-9]+\] .*(?i:warn)", "yellow"),
+        ],
+    ),
+    ("none", &[]),
+];
+
+// The rules of the preset called `name`. `custom` holds the NAME:COLOR:REGEX entries of the
+// [log_colors.NAME] tables of the config file, which add presets or go before the entries of
+// the built-in one of that name.
+pub fn preset_rules(name: &str, custom: &[String]) -> Result<Vec<Rule>, String> {
+    let mut rules = Vec::new();
+    let mut names: Vec<&str> = PRESETS.iter().map(|(preset, _)| *preset).collect();
+    for entry in custom {
+        let mut fields = entry.splitn(3, ':');
+        let (preset, color, regex) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(preset), Some(color), Some(regex)) => (preset, color, regex),
+            _ => {
+                return Err(format!(
+                    "invalid log color {}, expected NAME:COLOR:REGEX",
+                    entry
+                ))
+            }
+        };
+        if !names.contains(&preset) {
+            names.push(preset);
+        }
+        if preset == name {
+            let rule = Rule::line_color(regex, color)
+                .map_err(|err| format!("in [log_colors.{}]: {}", name, err))?;
+            rules.push(rule);
+        }
+    }
+    if !names.contains(&name) {
+        return Err(format!(
+            "unknown --log-colors preset {}, expected one of {}",
+            name,
+            names.join(", ")
+        ));
+    }
+    if let Some((_, entries)) = PRESETS.iter().find(|(preset, _)| *preset == name) {
+        for (pattern, color) in entries.iter() {
+            rules.push(Rule::line_color(pattern, color)?);
+        }
+    }
+    Ok(rules)
+}
@@ -0,0 +1,455 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, Utc};
+
+use crate::highlight::LogFilter;
+
+// A record is written out when it grows this long even without a newline
+const MAX_RECORD: usize = 4096;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    // The received data as shown, byte for byte
+    Raw,
+    // A line per record with the time and direction, the data escaped
+    Pretty,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Direction {
+    Rx,
+    Tx,
+}
+
+// --log-rotate-size and --log-rotate-keep: a log growing past `size` bytes is moved to
+// name.1, the older ones to name.2 and so on up to name.`keep`, and a new file is started
+#[derive(Clone, Copy)]
+pub struct Rotation {
+    pub size: u64,
+    pub keep: u32,
+}
+
+// The data of one direction collected until the end of the line
+struct Record {
+    direction: Direction,
+    started: DateTime<Utc>,
+    data: Vec<u8>,
+}
+
+pub struct LogFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    format: LogFormat,
+    record: Option<Record>,
+    // the hide rules, a raw log then keeps the received data until the end of the line
+    filter: Option<LogFilter>,
+    line: Vec<u8>,
+    // the size of the file when it was opened, and what went to files logrotate moved away
+    start_len: u64,
+    rotated: u64,
+    rotation: Option<Rotation>,
+}
+
+impl LogFile {
+    // Open the log at `template` after expanding its strftime placeholders
+    pub fn open(template: &str, truncate: bool, format: LogFormat) -> io::Result<LogFile> {
+        let path = expand_path_template(template)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(!truncate)
+            .truncate(truncate)
+            .open(&path)?;
+        let start_len = file.metadata()?.len();
+        Ok(LogFile {
+            path,
+            writer: BufWriter::new(file),
+            format,
+            record: None,
+            filter: None,
+            line: Vec::new(),
+            start_len,
+            rotated: 0,
+            rotation: None,
+        })
+    }
+
+    // Leave out the received lines a hide rule matches
+    pub fn set_filter(&mut self, filter: Option<LogFilter>) {
+        self.filter = filter;
+    }
+
+    pub fn set_rotation(&mut self, rotation: Option<Rotation>) {
+        self.rotation = rotation;
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn format(&self) -> LogFormat {
+        self.format
+    }
+
+    // The bytes written to the log since it was opened, the data kept in a pretty record or a
+    // hidden line not counted yet
+    pub fn written(&self) -> u64 {
+        let len = self
+            .writer
+            .get_ref()
+            .metadata()
+            .map_or(0, |meta| meta.len());
+        self.rotated + len.saturating_sub(self.start_len)
+    }
+
+    // Log one chunk of received data, flushing so a crash loses at most this chunk.
+    // A pretty log keeps a line that isn't complete yet until it is.
+    pub fn write_rx(&mut self, data: &[u8]) -> io::Result<()> {
+        match self.format {
+            LogFormat::Raw if self.filter.is_some() => self.add_to_line(data)?,
+            LogFormat::Raw => self.writer.write_all(data)?,
+            LogFormat::Pretty => self.add_to_record(Direction::Rx, data)?,
+        }
+        self.flush_and_rotate()
+    }
+
+    // Log data written to the port, only a pretty log has it
+    pub fn write_tx(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.format == LogFormat::Raw {
+            return Ok(());
+        }
+        self.add_to_record(Direction::Tx, data)?;
+        self.flush_and_rotate()
+    }
+
+    // A line of its own noting that an --on-match pattern showed up
+    pub fn write_mark(&mut self, pattern: &str) -> io::Result<()> {
+        self.write_event(&format!("matched /{}/", pattern))
+    }
+
+    // A timestamped line of its own between the received data, like a change of CTS
+    pub fn write_event(&mut self, event: &str) -> io::Result<()> {
+        match self.format {
+            LogFormat::Raw => {
+                self.end_line()?;
+                let time = Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
+                write!(self.writer, "\n--- {} {} ---\n", time, event)?;
+            }
+            LogFormat::Pretty => {
+                self.end_record()?;
+                writeln!(self.writer, "{} -- {}", format_time(Utc::now()), event)?;
+            }
+        }
+        self.flush_and_rotate()
+    }
+
+    // The lines of --debug-view in place of a record, each with the time in front
+    pub fn write_view(&mut self, lines: &[String]) -> io::Result<()> {
+        self.end_record()?;
+        let time = format_time(Utc::now());
+        for line in lines {
+            writeln!(self.writer, "{} {}", time, line)?;
+        }
+        self.flush_and_rotate()
+    }
+
+    // A ~m marker, a MARK record in a pretty log and `line` as it was shown in a raw one
+    pub fn write_marker(&mut self, line: &str, note: &str) -> io::Result<()> {
+        match self.format {
+            LogFormat::Raw => {
+                self.end_line()?;
+                write!(self.writer, "\n{}\n", line)?;
+            }
+            LogFormat::Pretty => {
+                self.end_record()?;
+                let time = format_time(Utc::now());
+                match note {
+                    "" => writeln!(self.writer, "{} MARK", time)?,
+                    note => writeln!(
+                        self.writer,
+                        "{} MARK {}",
+                        time,
+                        escape_bytes(note.as_bytes())
+                    )?,
+                }
+            }
+        }
+        self.flush_and_rotate()
+    }
+
+    // Write out what is kept and open the path again, creating the file when it was moved away
+    pub fn reopen(&mut self) -> io::Result<()> {
+        self.end_line()?;
+        self.end_record()?;
+        self.writer.flush()?;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.rotated = self.written();
+        self.start_len = file.metadata()?.len();
+        self.writer = BufWriter::new(file);
+        Ok(())
+    }
+
+    // Flush what was written and start a new file when the log grew past the rotation size
+    fn flush_and_rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        let rotation = match self.rotation {
+            Some(rotation) => rotation,
+            None => return Ok(()),
+        };
+        let len = self.writer.get_ref().metadata()?.len();
+        if len < rotation.size {
+            return Ok(());
+        }
+        // every step is a rename, a crash in between leaves the data in one of the files
+        for number in (1..rotation.keep).rev() {
+            match fs::rename(
+                numbered(&self.path, number),
+                numbered(&self.path, number + 1),
+            ) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        fs::rename(&self.path, numbered(&self.path, 1))?;
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.rotated = self.written();
+        self.start_len = 0;
+        self.writer = BufWriter::new(file);
+        if self.format == LogFormat::Pretty {
+            let previous = numbered(&self.path, 1);
+            writeln!(
+                self.writer,
+                "{} -- rotated, continued from {}",
+                format_time(Utc::now()),
+                previous.display()
+            )?;
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    // Write out what is kept and close the log, returning the bytes written to it
+    pub fn close(mut self) -> io::Result<u64> {
+        self.end_line()?;
+        self.end_record()?;
+        self.writer.flush()?;
+        Ok(self.written())
+    }
+
+    // A new record starts when the direction changes, and one ends after every newline
+    fn add_to_record(&mut self, direction: Direction, data: &[u8]) -> io::Result<()> {
+        if self
+            .record
+            .as_ref()
+            .is_some_and(|r| r.direction != direction)
+        {
+            self.end_record()?;
+        }
+        for &byte in data {
+            let record = self.record.get_or_insert_with(|| Record {
+                direction,
+                started: Utc::now(),
+                data: Vec::new(),
+            });
+            record.data.push(byte);
+            if byte == b'\n' || record.data.len() >= MAX_RECORD {
+                self.end_record()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn add_to_line(&mut self, data: &[u8]) -> io::Result<()> {
+        for &byte in data {
+            self.line.push(byte);
+            if byte == b'\n' || self.line.len() >= MAX_RECORD {
+                self.end_line()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn end_line(&mut self) -> io::Result<()> {
+        let line = std::mem::take(&mut self.line);
+        if self
+            .filter
+            .as_ref()
+            .is_some_and(|filter| filter.hides(&line))
+        {
+            return Ok(());
+        }
+        self.writer.write_all(&line)
+    }
+
+    fn end_record(&mut self) -> io::Result<()> {
+        let record = match self.record.take() {
+            Some(record) => record,
+            None => return Ok(()),
+        };
+        let hidden = self
+            .filter
+            .as_ref()
+            .is_some_and(|filter| filter.hides(&record.data));
+        if record.direction == Direction::Rx && hidden {
+            return Ok(());
+        }
+        let direction = match record.direction {
+            Direction::Rx => "RX",
+            Direction::Tx => "TX",
+        };
+        writeln!(
+            self.writer,
+            "{} {} {}",
+            format_time(record.started),
+            direction,
+            escape_bytes(&record.data)
+        )
+    }
+}
+
+// name.1 for `number` 1
+fn numbered(path: &Path, number: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", number));
+    PathBuf::from(name)
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string()
+}
+
+// Printable ASCII as is, everything else as \xNN and a backslash doubled
+fn escape_bytes(data: &[u8]) -> String {
+    let mut escaped = String::with_capacity(data.len());
+    for &byte in data {
+        match byte {
+            b'\\' => escaped.push_str("\\\\"),
+            0x20..=0x7e => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    escaped
+}
+
+pub fn expand_path_template(template: &str) -> io::Result<PathBuf> {
+    let items: Vec<Item> = StrftimeItems::new(template).collect();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid placeholder in log path: {}", template),
+        ));
+    }
+    let expanded = Local::now()
+        .format_with_items(items.into_iter())
+        .to_string();
+    Ok(PathBuf::from(expanded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a directory of its own for each test, the tests run at the same time
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("scip-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        dir
+    }
+
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn a_log_past_the_rotation_size_moves_along_and_the_oldest_goes() {
+        let dir = temp_dir("rotate");
+        let path = dir.join("console.log");
+        let mut log = LogFile::open(path.to_str().unwrap(), false, LogFormat::Raw).unwrap();
+        log.set_rotation(Some(Rotation { size: 10, keep: 2 }));
+        for chunk in [
+            "first 1234\n",
+            "second 123\n",
+            "third 1234\n",
+            "fourth 123\n",
+        ] {
+            log.write_rx(chunk.as_bytes()).unwrap();
+        }
+        log.write_rx(b"rest\n").unwrap();
+        assert_eq!(log.close().unwrap(), 49);
+
+        assert_eq!(
+            names(&dir),
+            ["console.log", "console.log.1", "console.log.2"]
+        );
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("console.log"), "rest\n");
+        assert_eq!(read("console.log.1"), "fourth 123\n");
+        assert_eq!(read("console.log.2"), "third 1234\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_pretty_log_notes_where_it_continues_from() {
+        let dir = temp_dir("rot");
+        let path = dir.join("trace.log");
+        let mut log = LogFile::open(path.to_str().unwrap(), false, LogFormat::Pretty).unwrap();
+        // the records are about 50 bytes and the note below 100
+        log.set_rotation(Some(Rotation { size: 100, keep: 1 }));
+        log.write_rx(b"a line long enough\r\n").unwrap();
+        log.write_rx(b"and another\r\n").unwrap();
+        log.write_tx(b"ls\r").unwrap();
+        log.close().unwrap();
+
+        assert_eq!(names(&dir), ["trace.log", "trace.log.1"]);
+        let rotated = fs::read_to_string(dir.join("trace.log.1")).unwrap();
+        assert!(
+            rotated.contains(" RX a line long enough\\x0d\\x0a\n"),
+            "{}",
+            rotated
+        );
+        assert!(
+            rotated.ends_with(" RX and another\\x0d\\x0a\n"),
+            "{}",
+            rotated
+        );
+        let current = fs::read_to_string(&path).unwrap();
+        let mut lines = current.lines();
+        let note = format!(
+            " -- rotated, continued from {}",
+            dir.join("trace.log.1").display()
+        );
+        assert!(lines.next().unwrap().ends_with(&note), "{}", current);
+        assert!(
+            lines.next().unwrap().ends_with(" TX ls\\x0d"),
+            "{}",
+            current
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_path_template_with_a_broken_placeholder_is_refused() {
+        assert!(expand_path_template("log-%Y.txt").is_ok());
+        let err = expand_path_template("log-%Q.txt").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid placeholder in log path: log-%Q.txt"
+        );
+    }
+}
@@ -0,0 +1,338 @@
+use std::time::{Duration, Instant};
+
+use crate::ansi::{Fg, FG_RESET};
+use crate::decode::{Decoder, SilenceFramer};
+use crate::transport::PortSettings;
+
+// A frame ends after 3.5 character times of silence, above 19200 baud after 1.75 ms
+const FRAME_GAP_CHARS: f64 = 3.5;
+const MIN_FRAME_GAP: Duration = Duration::from_micros(1750);
+
+// The CRC-16 of Modbus, sent low byte first after the frame
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xffff;
+    for &byte in data {
+        crc ^= u16::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xa001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+// Whether `frame` ends in the CRC of what comes before
+pub fn crc_matches(frame: &[u8]) -> bool {
+    match frame.len() {
+        0..=2 => false,
+        len => crc16(&frame[..len - 2]).to_le_bytes() == frame[len - 2..],
+    }
+}
+
+// --decode modbus-rtu: a line for every frame with its bytes in hex, the address, the
+// function and what it asks or answers, and whether the CRC is right
+pub struct ModbusRtu {
+    framer: SilenceFramer,
+    color: bool,
+}
+
+impl ModbusRtu {
+    pub fn new(settings: &PortSettings, color: bool) -> ModbusRtu {
+        ModbusRtu {
+            framer: SilenceFramer::new(settings, FRAME_GAP_CHARS, MIN_FRAME_GAP),
+            color,
+        }
+    }
+
+    fn show(&self, frame: &[u8], out: &mut Vec<u8>) {
+        // an adapter holding the data back can deliver a request and its answer in one
+        // read, they are told apart where both halves have their CRC
+        if !crc_matches(frame) {
+            let split = (4..frame.len().saturating_sub(3))
+                .find(|&at| crc_matches(&frame[..at]) && crc_matches(&frame[at..]));
+            if let Some(at) = split {
+                self.show(&frame[..at], out);
+                self.show(&frame[at..], out);
+                return;
+            }
+        }
+        out.extend_from_slice(hex(frame).as_bytes());
+        out.extend_from_slice(b"  ");
+        if frame.len() < 4 {
+            out.extend_from_slice(b"too short for a frame\r\n");
+            return;
+        }
+        let body = &frame[..frame.len() - 2];
+        out.extend_from_slice(describe(body).as_bytes());
+        if crc_matches(frame) {
+            out.extend_from_slice(b", CRC ok");
+        } else {
+            let expected = hex(&crc16(body).to_le_bytes());
+            let bad = format!(", CRC bad, expected {}", expected);
+            if self.color {
+                out.extend_from_slice(Fg(1).to_string().as_bytes());
+            }
+            out.extend_from_slice(bad.as_bytes());
+            if self.color {
+                out.extend_from_slice(FG_RESET.as_bytes());
+            }
+        }
+        out.extend_from_slice(b"\r\n");
+    }
+}
+
+impl Decoder for ModbusRtu {
+    fn feed(&mut self, data: &[u8], at: Instant) -> Vec<u8> {
+        let mut out = Vec::new();
+        for frame in self.framer.feed(data, at) {
+            self.show(&frame, &mut out);
+        }
+        out
+    }
+
+    fn due_at(&self) -> Option<Instant> {
+        self.framer.due_at()
+    }
+
+    fn flush(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        if let Some(frame) = self.framer.flush() {
+            self.show(&frame, &mut out);
+        }
+        out
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) {
+        self.framer.set_baud_rate(baud_rate);
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    let bytes: Vec<String> = data.iter().map(|byte| format!("{:02X}", byte)).collect();
+    bytes.join(" ")
+}
+
+fn function_name(function: u8) -> Option<&'static str> {
+    Some(match function {
+        0x01 => "read coils",
+        0x02 => "read discrete inputs",
+        0x03 => "read holding registers",
+        0x04 => "read input registers",
+        0x05 => "write single coil",
+        0x06 => "write single register",
+        0x0f => "write multiple coils",
+        0x10 => "write multiple registers",
+        _ => return None,
+    })
+}
+
+fn exception_name(code: u8) -> &'static str {
+    match code {
+        0x01 => "illegal function",
+        0x02 => "illegal data address",
+        0x03 => "illegal data value",
+        0x04 => "server device failure",
+        0x05 => "acknowledge",
+        0x06 => "server device busy",
+        0x08 => "memory parity error",
+        0x0a => "gateway path unavailable",
+        0x0b => "gateway target device failed to respond",
+        _ => "unknown exception",
+    }
+}
+
+// 16 bit register values in decimal, an odd byte left over in hex
+fn registers(data: &[u8]) -> String {
+    let values: Vec<String> = data
+        .chunks(2)
+        .map(|pair| match pair {
+            [high, low] => u16::from_be_bytes([*high, *low]).to_string(),
+            _ => format!("{:02X}", pair[0]),
+        })
+        .collect();
+    values.join(" ")
+}
+
+fn is_response(function: u8, data: &[u8]) -> bool {
+    data.first().is_some_and(|&count| {
+        count as usize == data.len() - 1 && (function <= 0x02 || count % 2 == 0)
+    })
+}
+
+fn word(data: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([data[at], data[at + 1]])
+}
+
+// The address, the function and its fields of a frame without its CRC, which is at least
+// an address and a function code. A request and its response are told apart by their length.
+fn describe(body: &[u8]) -> String {
+    let address = match body[0] {
+        0 => "broadcast".to_string(),
+        address => format!("address {}", address),
+    };
+    let function = body[1];
+    let data = &body[2..];
+    if function & 0x80 != 0 {
+        if let (Some(name), [code]) = (function_name(function & 0x7f), data) {
+            return format!(
+                "{}, exception to {}: {} ({:02X})",
+                address,
+                name,
+                exception_name(*code),
+                code
+            );
+        }
+    }
+    let name = match function_name(function) {
+        Some(name) => name,
+        None => return format!("{}, function {:02X}", address, function),
+    };
+    let fields = match function {
+        // a response has the byte count first, registers take two each
+        0x01..=0x04 if is_response(function, data) => {
+            if function >= 0x03 {
+                format!("{} registers: {}", data[0] / 2, registers(&data[1..]))
+            } else {
+                format!("{} bytes of states: {}", data[0], hex(&data[1..]))
+            }
+        }
+        0x01..=0x04 if data.len() == 4 => {
+            format!("start {}, count {}", word(data, 0), word(data, 2))
+        }
+        0x05 if data.len() == 4 => {
+            let state = match word(data, 2) {
+                0xff00 => "on".to_string(),
+                0x0000 => "off".to_string(),
+                value => format!("invalid value {:04X}", value),
+            };
+            format!("coil {} {}", word(data, 0), state)
+        }
+        0x06 if data.len() == 4 => format!("register {} = {}", word(data, 0), word(data, 2)),
+        // the response repeats the start and the count
+        0x0f | 0x10 if data.len() == 4 => {
+            format!("start {}, count {}", word(data, 0), word(data, 2))
+        }
+        0x0f | 0x10 if data.len() >= 5 && data[4] as usize == data.len() - 5 => {
+            let values = if function == 0x10 {
+                registers(&data[5..])
+            } else {
+                hex(&data[5..])
+            };
+            format!(
+                "start {}, count {}: {}",
+                word(data, 0),
+                word(data, 2),
+                values
+            )
+        }
+        _ => format!("{} data bytes", data.len()),
+    };
+    format!("{}, {}, {}", address, name, fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::PortParity;
+    use serialport::{DataBits, FlowControl, StopBits};
+
+    fn decoder() -> ModbusRtu {
+        let settings = PortSettings {
+            baud_rate: 9600,
+            data_bits: DataBits::Eight,
+            parity: PortParity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            timeout: Duration::from_millis(100),
+        };
+        ModbusRtu::new(&settings, false)
+    }
+
+    fn shown(frame: &[u8]) -> String {
+        let mut out = Vec::new();
+        decoder().show(frame, &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    fn with_crc(body: &[u8]) -> Vec<u8> {
+        let mut frame = body.to_vec();
+        frame.extend_from_slice(&crc16(body).to_le_bytes());
+        frame
+    }
+
+    #[test]
+    fn the_crc_is_sent_low_byte_first() {
+        assert_eq!(
+            with_crc(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0a])[6..],
+            [0xc5, 0xcd]
+        );
+        assert!(crc_matches(&[
+            0x01, 0x03, 0x00, 0x00, 0x00, 0x0a, 0xc5, 0xcd
+        ]));
+        assert!(!crc_matches(&[0xc5, 0xcd]));
+    }
+
+    #[test]
+    fn a_valid_request_and_its_response() {
+        assert_eq!(
+            shown(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0a, 0xc5, 0xcd]),
+            "01 03 00 00 00 0A C5 CD  address 1, read holding registers, start 0, count 10, \
+             CRC ok\r\n"
+        );
+        let response = with_crc(&[0x01, 0x03, 0x04, 0x00, 0x2a, 0x01, 0x00]);
+        assert!(shown(&response)
+            .contains("address 1, read holding registers, 2 registers: 42 256, CRC ok"));
+    }
+
+    #[test]
+    fn a_bad_crc_tells_the_expected_one() {
+        assert_eq!(
+            shown(&[0x11, 0x06, 0x00, 0x01, 0x00, 0x03, 0x00, 0x00]),
+            "11 06 00 01 00 03 00 00  address 17, write single register, register 1 = 3, \
+             CRC bad, expected 9A 9B\r\n"
+        );
+    }
+
+    #[test]
+    fn an_exception_response() {
+        assert_eq!(
+            shown(&[0x01, 0x83, 0x02, 0xc0, 0xf1]),
+            "01 83 02 C0 F1  address 1, exception to read holding registers: illegal data \
+             address (02), CRC ok\r\n"
+        );
+    }
+
+    #[test]
+    fn a_request_and_its_response_in_one_read_are_told_apart() {
+        let mut both = with_crc(&[0x01, 0x06, 0x00, 0x01, 0x00, 0x03]);
+        both.extend(with_crc(&[0x01, 0x06, 0x00, 0x01, 0x00, 0x03]));
+        assert_eq!(shown(&both).matches(", CRC ok\r\n").count(), 2);
+        assert_eq!(shown(&[0x01, 0x03]), "01 03  too short for a frame\r\n");
+    }
+
+    #[test]
+    fn the_silence_gap_ends_a_frame() {
+        let request = [0x01, 0x03, 0x00, 0x00, 0x00, 0x0a, 0xc5, 0xcd];
+        let response = with_crc(&[0x01, 0x03, 0x02, 0x00, 0x07]);
+        let mut modbus = decoder();
+        let start = Instant::now();
+        // the second half of the request arrives right after its bytes were on the line
+        assert!(modbus.feed(&request[..3], start).is_empty());
+        let reply_at = start + Duration::from_millis(5);
+        assert!(modbus.feed(&request[3..], reply_at).is_empty());
+        let due = modbus.due_at().unwrap();
+        assert!(
+            due > reply_at + Duration::from_millis(3) && due < reply_at + Duration::from_millis(4)
+        );
+        // 3.5 characters at 9600 baud are about 3.6 ms
+        let shown = modbus.feed(&response, reply_at + Duration::from_millis(20));
+        let shown = String::from_utf8(shown).unwrap();
+        assert!(shown.starts_with("01 03 00 00 00 0A C5 CD  "), "{}", shown);
+        let rest = String::from_utf8(modbus.flush()).unwrap();
+        assert!(rest.contains("1 registers: 7, CRC ok"), "{}", rest);
+        assert_eq!(modbus.due_at(), None);
+    }
+}
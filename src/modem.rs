@@ -0,0 +1,129 @@
+use std::time::{Duration, Instant};
+
+use crate::ansi;
+
+// How often --show-lines and --log-lines read the modem status lines
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// A line that just changed is shown inverted for this long
+const CHANGE_HIGHLIGHT: Duration = Duration::from_secs(1);
+
+const NAMES: [&str; 4] = ["CTS", "DSR", "DCD", "RI"];
+
+// The status lines driven by the device, None where reading one failed
+#[derive(Clone, Copy, Default)]
+pub struct ModemLines {
+    pub cts: Option<bool>,
+    pub dsr: Option<bool>,
+    pub dcd: Option<bool>,
+    pub ri: Option<bool>,
+}
+
+impl ModemLines {
+    fn levels(&self) -> [Option<bool>; 4] {
+        [self.cts, self.dsr, self.dcd, self.ri]
+    }
+}
+
+// Follows the status lines between polls, for the status row and the log
+pub struct LineMonitor {
+    levels: [Option<bool>; 4],
+    // the last level that could be read, changes are reported against it
+    known: [Option<bool>; 4],
+    changed_at: [Option<Instant>; 4],
+    next_poll: Instant,
+    shown: Option<String>,
+}
+
+impl Default for LineMonitor {
+    fn default() -> LineMonitor {
+        LineMonitor::new()
+    }
+}
+
+impl LineMonitor {
+    pub fn new() -> LineMonitor {
+        LineMonitor {
+            levels: [None; 4],
+            known: [None; 4],
+            changed_at: [None; 4],
+            next_poll: Instant::now(),
+            shown: None,
+        }
+    }
+
+    pub fn next_poll(&self) -> Instant {
+        self.next_poll
+    }
+
+    // Take in what was read, returning the changes like "CTS on".
+    // A line that can't be read shows as ? but isn't reported, so a failing port doesn't
+    // fill the log.
+    pub fn poll(&mut self, lines: ModemLines) -> Vec<String> {
+        let now = Instant::now();
+        self.next_poll = now + POLL_INTERVAL;
+        let mut changes = Vec::new();
+        for (i, level) in lines.levels().into_iter().enumerate() {
+            self.levels[i] = level;
+            let level = match level {
+                Some(level) => level,
+                None => continue,
+            };
+            match self.known[i] {
+                Some(known) if known != level => {
+                    changes.push(format!("{} {}", NAMES[i], if level { "on" } else { "off" }));
+                    self.changed_at[i] = Some(now);
+                }
+                _ => {}
+            }
+            self.known[i] = Some(level);
+        }
+        changes
+    }
+
+    // The names of the lines that are on
+    pub fn active(&self) -> impl Iterator<Item = &'static str> + '_ {
+        NAMES
+            .iter()
+            .zip(self.levels)
+            .filter(|(_, level)| *level == Some(true))
+            .map(|(name, _)| *name)
+    }
+
+    // The status row as it was rendered last
+    pub fn shown(&self) -> Option<&str> {
+        self.shown.as_deref()
+    }
+
+    // The status row, only when it looks different from the one shown last
+    pub fn render(&mut self) -> Option<String> {
+        let now = Instant::now();
+        let mut row = String::new();
+        for (i, level) in self.levels.iter().enumerate() {
+            let value = match level {
+                Some(true) => "on",
+                Some(false) => "off",
+                None => "?",
+            };
+            if i > 0 {
+                row.push_str("  ");
+            }
+            let recent = self.changed_at[i].is_some_and(|at| now < at + CHANGE_HIGHLIGHT);
+            if recent {
+                row.push_str(&format!(
+                    "{}{}:{}{}",
+                    ansi::INVERT,
+                    NAMES[i],
+                    value,
+                    ansi::RESET
+                ));
+            } else {
+                row.push_str(&format!("{}:{}", NAMES[i], value));
+            }
+        }
+        if self.shown.as_ref() == Some(&row) {
+            return None;
+        }
+        self.shown = Some(row.clone());
+        Some(row)
+    }
+}
@@ -0,0 +1,64 @@
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum NewlineMode {
+    // Pass line endings through untouched
+    Raw,
+    Cr,
+    Lf,
+    CrLf,
+}
+
+impl NewlineMode {
+    pub fn from_arg(arg: &str) -> NewlineMode {
+        match arg {
+            "cr" => NewlineMode::Cr,
+            "lf" => NewlineMode::Lf,
+            "crlf" => NewlineMode::CrLf,
+            _ => NewlineMode::Raw,
+        }
+    }
+
+    fn ending(self) -> &'static [u8] {
+        match self {
+            NewlineMode::Raw => b"",
+            NewlineMode::Cr => b"\r",
+            NewlineMode::Lf => b"\n",
+            NewlineMode::CrLf => b"\r\n",
+        }
+    }
+}
+
+// Rewrites every "\r", "\n" and "\r\n" to the configured line ending
+pub struct NewlineTranslator {
+    mode: NewlineMode,
+    // a "\r\n" may be split across two chunks
+    after_cr: bool,
+}
+
+impl NewlineTranslator {
+    pub fn new(mode: NewlineMode) -> NewlineTranslator {
+        NewlineTranslator {
+            mode,
+            after_cr: false,
+        }
+    }
+
+    pub fn is_raw(&self) -> bool {
+        self.mode == NewlineMode::Raw
+    }
+
+    pub fn translate(&mut self, data: &[u8]) -> Vec<u8> {
+        if self.is_raw() {
+            return data.to_vec();
+        }
+        let mut translated = Vec::with_capacity(data.len() + 8);
+        for &byte in data {
+            match byte {
+                b'\n' if self.after_cr => {}
+                b'\r' | b'\n' => translated.extend_from_slice(self.mode.ending()),
+                _ => translated.push(byte),
+            }
+            self.after_cr = byte == b'\r';
+        }
+        translated
+    }
+}
@@ -0,0 +1,273 @@
+use crate::ansi::{Fg, FG_RESET};
+
+// NMEA 0183 allows 82 characters, some receivers send longer proprietary sentences
+const MAX_SENTENCE: usize = 128;
+// What may come before a sentence on its line and leave with it, like the prefix of
+// --timestamp. The stamper puts that in the same chunk as the first byte of the line.
+const MAX_HEAD: usize = 32;
+
+// --nmea: checks the *hh checksum of every sentence received, shows the ones that don't
+// match in red with the expected checksum, and with --nmea-filter leaves out the sentence
+// types not asked for. A sentence is held until its line ends, so one split across reads is
+// checked whole. Everything else, like the binary UBX messages of a u-blox receiver, goes
+// through as it came.
+pub struct NmeaDecoder {
+    // the sentence types to show, all of them when empty
+    types: Vec<String>,
+    color: bool,
+    // the sentence read so far, after the head of its line
+    held: Vec<u8>,
+    head_len: usize,
+    in_sentence: bool,
+    at_line_start: bool,
+    // a left out sentence ended with \r, the \n after it goes too
+    dropped_cr: bool,
+}
+
+impl NmeaDecoder {
+    pub fn new(types: Vec<String>, color: bool) -> NmeaDecoder {
+        NmeaDecoder {
+            types,
+            color,
+            held: Vec::new(),
+            head_len: 0,
+            in_sentence: false,
+            at_line_start: true,
+            dropped_cr: false,
+        }
+    }
+
+    // What to show of `data`, the start of a sentence still to be finished is kept
+    pub fn filter(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len() + 32);
+        // where the current line started in `out`, while it is short printable text
+        let mut head_start = self.at_line_start.then_some(0);
+        for &byte in data {
+            if std::mem::take(&mut self.dropped_cr) && byte == b'\n' {
+                continue;
+            }
+            if self.in_sentence {
+                if byte == b'\r' || byte == b'\n' {
+                    self.finish(byte, &mut out);
+                    head_start = Some(out.len());
+                } else if is_printable(byte) && self.held.len() - self.head_len < MAX_SENTENCE {
+                    self.held.push(byte);
+                } else {
+                    // binary or far too long, no sentence after all
+                    self.in_sentence = false;
+                    out.append(&mut self.held);
+                    out.push(byte);
+                    head_start = None;
+                }
+                continue;
+            }
+            if byte == b'$' || byte == b'!' {
+                let head = match head_start {
+                    Some(start) if out.len() - start <= MAX_HEAD => out.split_off(start),
+                    _ => Vec::new(),
+                };
+                self.head_len = head.len();
+                self.held = head;
+                self.held.push(byte);
+                self.in_sentence = true;
+                continue;
+            }
+            out.push(byte);
+            if byte == b'\r' || byte == b'\n' {
+                head_start = Some(out.len());
+            } else if !is_printable(byte) {
+                head_start = None;
+            }
+        }
+        self.at_line_start = head_start == Some(out.len());
+        out
+    }
+
+    // The held sentence ended with `ending`
+    fn finish(&mut self, ending: u8, out: &mut Vec<u8>) {
+        self.in_sentence = false;
+        let held = std::mem::take(&mut self.held);
+        let (head, sentence) = held.split_at(self.head_len);
+        let parsed = match parse(sentence) {
+            Some(parsed) => parsed,
+            None => {
+                out.extend_from_slice(&held);
+                out.push(ending);
+                return;
+            }
+        };
+        if !self.wanted(parsed.address) {
+            self.dropped_cr = ending == b'\r';
+            return;
+        }
+        out.extend_from_slice(head);
+        if parsed.is_valid() {
+            out.extend_from_slice(sentence);
+        } else {
+            let problem = match parsed.given {
+                Some(given) => format!("checksum {}, expected {:02X}", given, parsed.computed),
+                None => format!("no checksum, expected {:02X}", parsed.computed),
+            };
+            if self.color {
+                out.extend_from_slice(Fg(1).to_string().as_bytes());
+            }
+            out.extend_from_slice(sentence);
+            out.extend_from_slice(format!("  ({})", problem).as_bytes());
+            if self.color {
+                out.extend_from_slice(FG_RESET.as_bytes());
+            }
+        }
+        out.push(ending);
+    }
+
+    // GGA is wanted for $GPGGA and $GNGGA alike, a proprietary PUBX only as itself
+    fn wanted(&self, address: &str) -> bool {
+        self.types.is_empty()
+            || self.types.iter().any(|kind| {
+                address == kind
+                    || (address.len() == kind.len() + 2 && address.ends_with(kind.as_str()))
+            })
+    }
+}
+
+struct Sentence<'a> {
+    // the talker and the sentence type, like GPGGA
+    address: &'a str,
+    // what came after the *
+    given: Option<&'a str>,
+    computed: u8,
+}
+
+impl Sentence<'_> {
+    fn is_valid(&self) -> bool {
+        self.given.is_some_and(|given| {
+            given.len() == 2 && u8::from_str_radix(given, 16) == Ok(self.computed)
+        })
+    }
+}
+
+// A sentence like $GPGGA,...*47 without its line ending, None for something else starting
+// with $ or !, like a shell prompt
+fn parse(sentence: &[u8]) -> Option<Sentence<'_>> {
+    let text = std::str::from_utf8(sentence).ok()?;
+    let body = &text[1..];
+    let (data, given) = match body.rsplit_once('*') {
+        Some((data, given)) => (data, Some(given)),
+        None => (body, None),
+    };
+    let address = data.split(',').next()?;
+    let valid_address = (2..=6).contains(&address.len())
+        && address
+            .bytes()
+            .all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit());
+    if !valid_address || !data.contains(',') {
+        return None;
+    }
+    Some(Sentence {
+        address,
+        given,
+        computed: checksum(data.as_bytes()),
+    })
+}
+
+// The XOR of the bytes between the $ and the *
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum ^ byte)
+}
+
+// A sentence typed for ~n, with the $ in front if it was left out, the checksum and CR LF
+pub fn sentence(body: &str) -> Vec<u8> {
+    let body = body.trim();
+    let (start, data) = match body.strip_prefix('$').or_else(|| body.strip_prefix('!')) {
+        Some(data) => (&body[..1], data),
+        None => ("$", body),
+    };
+    // a checksum typed along is replaced
+    let data = match data.rsplit_once('*') {
+        Some((data, hex)) if hex.len() == 2 && u8::from_str_radix(hex, 16).is_ok() => data,
+        _ => data,
+    };
+    format!("{}{}*{:02X}\r\n", start, data, checksum(data.as_bytes())).into_bytes()
+}
+
+fn is_printable(byte: u8) -> bool {
+    (0x20..0x7f).contains(&byte)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GGA: &str = "$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47\r\n";
+    const RMC: &str = "$GPRMC,123519,A,4807.038,N,01131.000,E,022.4,084.4,230394,003.1,W*6A\r\n";
+
+    fn filtered(types: &[&str], reads: &[&[u8]]) -> String {
+        let types = types.iter().map(|kind| kind.to_string()).collect();
+        let mut nmea = NmeaDecoder::new(types, false);
+        let out: Vec<u8> = reads.iter().flat_map(|data| nmea.filter(data)).collect();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn good_sentences_go_through_as_they_came() {
+        let both = format!("{}{}", GGA, RMC);
+        assert_eq!(filtered(&[], &[both.as_bytes()]), both);
+    }
+
+    #[test]
+    fn a_bad_checksum_is_shown_with_the_expected_one() {
+        let bad = GGA.replace("*47", "*48");
+        assert_eq!(
+            filtered(&[], &[bad.as_bytes()]),
+            bad.replace("\r\n", "  (checksum 48, expected 47)\r\n")
+        );
+        let none = "$GPGLL,4916.45,N,12311.12,W,225444,A\r\n";
+        assert!(filtered(&[], &[none.as_bytes()]).contains("(no checksum, expected "));
+    }
+
+    #[test]
+    fn a_sentence_split_across_reads_is_checked_whole() {
+        let (start, end) = GGA.as_bytes().split_at(20);
+        assert_eq!(filtered(&[], &[start, end]), GGA);
+        assert_eq!(
+            filtered(&[], &[b"12:00:01 ", GGA.as_bytes()]),
+            format!("12:00:01 {}", GGA)
+        );
+    }
+
+    #[test]
+    fn the_filter_takes_any_talker() {
+        let gn = GGA.replace("$GP", "$GN").replace("*47", "*59");
+        let all = format!("{}{}{}", GGA, RMC, gn);
+        assert_eq!(
+            filtered(&["GGA"], &[all.as_bytes()]),
+            format!("{}{}", GGA, gn)
+        );
+        let pubx = "$PUBX,00,081350.00*3E\r\n";
+        assert_eq!(
+            filtered(&["PUBX"], &[GGA.as_bytes(), pubx.as_bytes()]),
+            pubx
+        );
+        // the \n of a sentence left out may come in the next read
+        let (rmc, lf) = RMC.as_bytes().split_at(RMC.len() - 1);
+        assert_eq!(filtered(&["GGA"], &[rmc, lf, GGA.as_bytes()]), GGA);
+    }
+
+    #[test]
+    fn text_and_binary_data_are_left_alone() {
+        assert_eq!(filtered(&["GGA"], &[b"$ ls\r\n"]), "$ ls\r\n");
+        let ubx = [0xb5, 0x62, b'$', 0x01, 0x07, b'\n'];
+        let mut nmea = NmeaDecoder::new(Vec::new(), false);
+        assert_eq!(nmea.filter(&ubx), ubx);
+    }
+
+    #[test]
+    fn a_typed_sentence_gets_its_checksum() {
+        assert_eq!(sentence("PMTK220,1000"), b"$PMTK220,1000*1F\r\n");
+        assert_eq!(sentence(" $PMTK220,1000*00 "), b"$PMTK220,1000*1F\r\n");
+        assert_eq!(
+            sentence("!AIVDM,1"),
+            format!("!AIVDM,1*{:02X}\r\n", checksum(b"AIVDM,1")).as_bytes()
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+const BEL: u8 = 0x07;
+
+// How --notify tells about the device coming and going: the terminal bell, a desktop
+// notification through notify-send, or both
+pub struct Notifier {
+    bell: bool,
+    desktop: bool,
+    // the --profile, named in the desktop notifications
+    profile: Option<String>,
+}
+
+impl Notifier {
+    pub fn new(mode: &str, profile: Option<String>) -> Notifier {
+        Notifier {
+            bell: mode == "bell" || mode == "both",
+            desktop: mode == "desktop" || mode == "both",
+            profile,
+        }
+    }
+
+    // Ring the bell on `terminal` if there is one and send the desktop notification
+    // "scip: `event`" about `device`
+    pub fn notify(
+        &self,
+        terminal: Option<&mut dyn Write>,
+        event: &str,
+        device: &str,
+    ) -> io::Result<()> {
+        if self.desktop {
+            let body = match &self.profile {
+                Some(profile) => format!("{} (profile {})", device, profile),
+                None => device.to_string(),
+            };
+            // a desktop without notifications or a D-Bus hiccup isn't worth telling about
+            let _ = send_desktop(&format!("scip: {}", event), &body);
+        }
+        match (self.bell, terminal) {
+            (true, Some(terminal)) => {
+                terminal.write_all(&[BEL])?;
+                terminal.flush()
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn send_desktop(summary: &str, body: &str) -> io::Result<()> {
+    let mut child = Command::new("notify-send")
+        .args(["--app-name", "scip", summary, body])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    // reaped here so the session doesn't wait for it
+    thread::spawn(move || child.wait());
+    Ok(())
+}
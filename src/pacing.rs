@@ -0,0 +1,82 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Typed and pasted data waiting to go out with the --char-delay and --line-delay pauses.
+// The main loop takes what is due between events, so RX keeps showing while it drains.
+pub struct TxQueue {
+    queue: VecDeque<u8>,
+    char_delay: Duration,
+    line_delay: Duration,
+    next_at: Instant,
+}
+
+impl TxQueue {
+    pub fn new(char_delay: Duration, line_delay: Duration) -> TxQueue {
+        TxQueue {
+            queue: VecDeque::new(),
+            char_delay,
+            line_delay,
+            next_at: Instant::now(),
+        }
+    }
+
+    // Without any delay the data is written right away instead of queued
+    pub fn is_paced(&self) -> bool {
+        !self.char_delay.is_zero() || !self.line_delay.is_zero()
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        if self.queue.is_empty() {
+            // the pause after the last chunk is over when nothing else was waiting
+            self.next_at = self.next_at.max(Instant::now());
+        }
+        self.queue.extend(data);
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+
+    // Drop everything waiting, returning how many bytes that was
+    pub fn clear(&mut self) -> usize {
+        let dropped = self.queue.len();
+        self.queue.clear();
+        dropped
+    }
+
+    // When the next chunk may be written, None while nothing is waiting
+    pub fn due_at(&self) -> Option<Instant> {
+        (!self.queue.is_empty()).then_some(self.next_at)
+    }
+
+    // The chunk to write now: a byte with --char-delay, else a line. A line ends with LF,
+    // or with a CR not followed by LF, as Enter and a paste into the terminal send.
+    pub fn take_due(&mut self) -> Option<Vec<u8>> {
+        let now = Instant::now();
+        if self.queue.is_empty() || now < self.next_at {
+            return None;
+        }
+        let ends_line = |queue: &VecDeque<u8>, i: usize| match queue[i] {
+            b'\n' => true,
+            b'\r' => queue.get(i + 1) != Some(&b'\n'),
+            _ => false,
+        };
+        let len = if !self.char_delay.is_zero() {
+            1
+        } else {
+            let end = (0..self.queue.len()).find(|&i| ends_line(&self.queue, i));
+            end.map_or(self.queue.len(), |end| end + 1)
+        };
+        let mut delay = self.char_delay;
+        if ends_line(&self.queue, len - 1) {
+            delay += self.line_delay;
+        }
+        let chunk: Vec<u8> = self.queue.drain(..len).collect();
+        self.next_at = now + delay;
+        Some(chunk)
+    }
+}
@@ -0,0 +1,97 @@
+use std::io::{self, stdin, Write};
+use std::thread;
+use std::time::Duration;
+
+use serialport::{SerialPortInfo, SerialPortType};
+
+use crate::error::ScipioError;
+
+// How often --wait looks for a port while none is there
+const WAIT_POLL: Duration = Duration::from_millis(500);
+
+// Ask on the terminal which port to open when no device was given, returning its path.
+// With `auto` the only port there is taken without asking, with `wait` a port being
+// plugged in is waited for.
+pub fn pick_device(auto: bool, wait: bool) -> Result<String, ScipioError> {
+    let ports = available_ports(wait)?;
+    if let (true, [port]) = (auto, ports.as_slice()) {
+        eprintln!("[using {}]", port.port_name);
+        return Ok(port.port_name.clone());
+    }
+
+    eprintln!("Serial ports:");
+    let width = ports
+        .iter()
+        .map(|port| port.port_name.len())
+        .max()
+        .unwrap_or(0);
+    for (i, port) in ports.iter().enumerate() {
+        let line = format!(
+            "{:>3}) {:width$}  {}",
+            i + 1,
+            port.port_name,
+            describe(port),
+            width = width
+        );
+        eprintln!("{}", line.trim_end());
+    }
+    loop {
+        eprint!("Pick a port [1-{}], Enter quits: ", ports.len());
+        let _ = io::stderr().flush();
+        let mut answer = String::new();
+        let read = stdin().read_line(&mut answer).map_err(ScipioError::Stdin)?;
+        let answer = answer.trim();
+        if read == 0 || answer.is_empty() {
+            // the prompt line still has to end
+            if read == 0 {
+                eprintln!();
+            }
+            return Err(ScipioError::InvalidArgument("no port picked".to_string()));
+        }
+        match answer.parse::<usize>() {
+            Ok(number) if (1..=ports.len()).contains(&number) => {
+                return Ok(ports[number - 1].port_name.clone());
+            }
+            _ => eprintln!("{} is none of the numbers", answer),
+        }
+    }
+}
+
+// The ports sorted by path, waiting for one to show up with `wait`
+fn available_ports(wait: bool) -> Result<Vec<SerialPortInfo>, ScipioError> {
+    let mut waiting = false;
+    loop {
+        let mut ports = serialport::available_ports().map_err(|err| ScipioError::Setup {
+            what: "listing serial ports".to_string(),
+            source: err.into(),
+        })?;
+        if !ports.is_empty() {
+            ports.sort_by(|a, b| a.port_name.cmp(&b.port_name));
+            return Ok(ports);
+        }
+        if !wait {
+            return Err(ScipioError::NoPorts);
+        }
+        if !waiting {
+            eprintln!("[waiting for a serial port to show up, Ctrl-C quits]");
+            waiting = true;
+        }
+        thread::sleep(WAIT_POLL);
+    }
+}
+
+// The product and IDs of a USB adapter, else the kind of port
+fn describe(port: &SerialPortInfo) -> String {
+    match &port.port_type {
+        SerialPortType::UsbPort(usb) => {
+            let ids = format!("{:04x}:{:04x}", usb.vid, usb.pid);
+            match usb.product.as_deref().or(usb.manufacturer.as_deref()) {
+                Some(product) => format!("{} ({})", product, ids),
+                None => format!("USB {}", ids),
+            }
+        }
+        SerialPortType::PciPort => "PCI".to_string(),
+        SerialPortType::BluetoothPort => "Bluetooth".to_string(),
+        SerialPortType::Unknown => String::new(),
+    }
+}
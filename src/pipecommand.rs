@@ -0,0 +1,189 @@
+use std::env;
+use std::io::{self, Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::screen::Screen;
+use crate::txhold::{self, HeldKeys};
+use crate::{
+    display_received, interrupted, next_event, port_error, screen_error, send_input, stdin_error,
+    write_status, Event, NextStep, Session,
+};
+
+// How long to wait for the port or a key before looking at the command's output again
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+// What the threads reading the command's output hand over
+enum Output {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    // a pipe reached its end or failed
+    Closed,
+}
+
+// Run `command` with the shell for ~!, sending its stdout to the port like typed input and
+// showing its stderr. The keys are the session's meanwhile, Ctrl-C kills the command.
+pub fn run_command(
+    command: &str,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> NextStep {
+    let mut child = match shell(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => {
+            write_status(screen, &format!("running {} failed: {}", command, err));
+            return NextStep::None;
+        }
+    };
+    let (tx, output) = channel();
+    if let Some(stdout) = child.stdout.take() {
+        spawn_reader(stdout, tx.clone(), Output::Stdout);
+    }
+    if let Some(stderr) = child.stderr.take() {
+        spawn_reader(stderr, tx, Output::Stderr);
+    }
+    write_status(screen, &format!("running {}, Ctrl-C kills it", command));
+
+    let mut sent: usize = 0;
+    let mut killed = false;
+    let mut open = 2;
+    while open > 0 {
+        while let Ok(chunk) = output.try_recv() {
+            match chunk {
+                Output::Stdout(data) => {
+                    match send_input(session, screen, &data) {
+                        NextStep::None => {}
+                        next_step => return stop(&mut child, next_step),
+                    }
+                    sent += data.len();
+                }
+                Output::Stderr(data) => {
+                    if let Err(err) = show_stderr(screen, &data) {
+                        return stop(&mut child, screen_error(err));
+                    }
+                }
+                Output::Closed => open -= 1,
+            }
+        }
+        if open == 0 {
+            break;
+        }
+        match next_event(session, screen, rx, Some(Instant::now() + POLL_INTERVAL)) {
+            Some(Event::Serial(_, data, _)) => match display_received(session, screen, &data) {
+                NextStep::None => {}
+                next_step => return stop(&mut child, next_step),
+            },
+            Some(Event::Stdin(data)) => match txhold::handle_keys(session, screen, rx, &data) {
+                HeldKeys::Kept => {}
+                HeldKeys::Abort => {
+                    kill(&mut child);
+                    killed = true;
+                    break;
+                }
+                HeldKeys::Stop(next_step) => return stop(&mut child, next_step),
+            },
+            Some(Event::StdinError(err)) => return stop(&mut child, stdin_error(err)),
+            Some(Event::Interrupted) => return stop(&mut child, interrupted()),
+            Some(Event::SerialError(_, err)) => return stop(&mut child, port_error(err)),
+            _ => {}
+        }
+    }
+
+    let status = match child.wait() {
+        Ok(_) if killed => "killed".to_string(),
+        Ok(status) => exit_text(status),
+        Err(err) => format!("unknown exit status: {}", err),
+    };
+    write_status(
+        screen,
+        &format!("{}: sent {} bytes, {}", command, sent, status),
+    );
+    NextStep::None
+}
+
+// $SHELL -c on Unix, cmd /C elsewhere
+fn shell(command: &str) -> Command {
+    #[cfg(unix)]
+    {
+        let shell = env::var_os("SHELL")
+            .filter(|shell| !shell.is_empty())
+            .unwrap_or_else(|| "/bin/sh".into());
+        let mut shell = Command::new(shell);
+        shell.arg("-c").arg(command);
+        // a group of its own, killed as a whole
+        std::os::unix::process::CommandExt::process_group(&mut shell, 0);
+        shell
+    }
+    #[cfg(not(unix))]
+    {
+        let shell = env::var_os("COMSPEC").unwrap_or_else(|| "cmd".into());
+        let mut shell = Command::new(shell);
+        shell.arg("/C").arg(command);
+        shell
+    }
+}
+
+fn spawn_reader(
+    mut pipe: impl Read + Send + 'static,
+    tx: Sender<Output>,
+    wrap: fn(Vec<u8>) -> Output,
+) {
+    thread::spawn(move || {
+        let mut buf = [0; 512];
+        loop {
+            match pipe.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if tx.send(wrap(buf[..n].to_vec())).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+        let _ = tx.send(Output::Closed);
+    });
+}
+
+// The terminal is in raw mode, so a newline needs its carriage return
+fn show_stderr(screen: &mut Screen, data: &[u8]) -> io::Result<()> {
+    let mut shown = Vec::with_capacity(data.len());
+    for &byte in data {
+        if byte == b'\n' && screen.is_terminal() {
+            shown.push(b'\r');
+        }
+        shown.push(byte);
+    }
+    screen.write_all(&shown)?;
+    screen.flush()
+}
+
+// End the session step with the command killed and reaped
+fn stop(child: &mut Child, next_step: NextStep) -> NextStep {
+    kill(child);
+    let _ = child.wait();
+    next_step
+}
+
+// The commands a shell started are in the process group of the shell, so they go too
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    if let Ok(pid) = libc::pid_t::try_from(child.id()) {
+        unsafe { libc::kill(-pid, libc::SIGKILL) };
+    }
+    let _ = child.kill();
+}
+
+fn exit_text(status: ExitStatus) -> String {
+    match status.code() {
+        Some(code) => format!("exit status {}", code),
+        None => format!("ended by {}", status),
+    }
+}
@@ -0,0 +1,300 @@
+use std::io::{self, Write};
+
+use serialport::{DataBits, FlowControl, StopBits};
+
+use crate::ansi::{self, Goto};
+use crate::info;
+use crate::screen::Screen;
+use crate::scrollback;
+use crate::transport::PortParity;
+use crate::{screen_error, NextStep, Session, COMMON_BAUD_RATES};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Row {
+    Device,
+    BaudRate,
+    DataBits,
+    Parity,
+    StopBits,
+    FlowControl,
+}
+
+const ROWS: [Row; 6] = [
+    Row::Device,
+    Row::BaudRate,
+    Row::DataBits,
+    Row::Parity,
+    Row::StopBits,
+    Row::FlowControl,
+];
+
+const DATA_BITS: [DataBits; 4] = [
+    DataBits::Five,
+    DataBits::Six,
+    DataBits::Seven,
+    DataBits::Eight,
+];
+const PARITIES: [PortParity; 5] = [
+    PortParity::None,
+    PortParity::Odd,
+    PortParity::Even,
+    PortParity::Mark,
+    PortParity::Space,
+];
+const STOP_BITS: [StopBits; 2] = [StopBits::One, StopBits::Two];
+const FLOW_CONTROLS: [FlowControl; 3] = [
+    FlowControl::None,
+    FlowControl::Software,
+    FlowControl::Hardware,
+];
+
+// The panel ~O shows over the output, like minicom's port setup. A change is made on the open
+// port right away, and one the driver refuses leaves the setting as it was with the error on
+// the panel. The received data is held back while it is shown.
+pub struct PortPanel {
+    row: usize,
+    // what the output showed when the panel opened, for drawing it again
+    lines_at_start: u64,
+    partial_at_start: Vec<u8>,
+    paused_before: bool,
+    // the last change or why it failed, until the next key
+    message: Option<String>,
+}
+
+impl PortPanel {
+    // Take the keys of one read, returning false when they close the panel
+    fn handle(&mut self, data: &[u8], session: &mut Session) -> bool {
+        // a lone ESC closes, longer sequences are cursor keys
+        if data.first() == Some(&0x1b) {
+            match &data[1..] {
+                [] => return false,
+                b"[A" | b"OA" => self.row = self.row.saturating_sub(1),
+                b"[B" | b"OB" => self.row = (self.row + 1).min(ROWS.len() - 1),
+                b"[C" | b"OC" => self.change(session, true),
+                b"[D" | b"OD" => self.change(session, false),
+                _ => {}
+            }
+            return true;
+        }
+        for &byte in data {
+            match byte {
+                b'q' => return false,
+                b'k' => self.row = self.row.saturating_sub(1),
+                b'j' => self.row = (self.row + 1).min(ROWS.len() - 1),
+                b'l' | b' ' | b'\r' | b'+' => self.change(session, true),
+                b'h' | b'-' => self.change(session, false),
+                _ => {}
+            }
+        }
+        true
+    }
+
+    // Step the setting of the current row to its next or previous value
+    fn change(&mut self, session: &mut Session, forward: bool) {
+        let settings = session.settings;
+        let result = match ROWS[self.row] {
+            Row::Device => {
+                self.message = Some("the device can't be changed in a session".to_string());
+                return;
+            }
+            Row::BaudRate => {
+                let current = settings.baud_rate;
+                let baud_rate = if forward {
+                    COMMON_BAUD_RATES
+                        .iter()
+                        .copied()
+                        .find(|&rate| rate > current)
+                } else {
+                    COMMON_BAUD_RATES
+                        .iter()
+                        .copied()
+                        .rev()
+                        .find(|&rate| rate < current)
+                };
+                let baud_rate = match baud_rate {
+                    Some(baud_rate) => baud_rate,
+                    None => {
+                        self.message = Some(format!("no common baud rate beyond {}", current));
+                        return;
+                    }
+                };
+                session.serial_port.set_baud_rate(baud_rate).map(|()| {
+                    session.settings.baud_rate = baud_rate;
+                    if let Some(decoder) = &mut session.decoder {
+                        decoder.set_baud_rate(baud_rate);
+                    }
+                    format!("baud rate: {}", baud_rate)
+                })
+            }
+            Row::DataBits => {
+                let data_bits = step(&DATA_BITS, settings.data_bits, forward);
+                session.serial_port.set_data_bits(data_bits).map(|()| {
+                    session.settings.data_bits = data_bits;
+                    format!("data bits: {}", info::data_bits(data_bits))
+                })
+            }
+            Row::Parity => {
+                let parity = step(&PARITIES, settings.parity, forward);
+                session.serial_port.set_parity(parity).map(|()| {
+                    session.settings.parity = parity;
+                    format!("parity: {}", info::parity(parity))
+                })
+            }
+            Row::StopBits => {
+                let stop_bits = step(&STOP_BITS, settings.stop_bits, forward);
+                session.serial_port.set_stop_bits(stop_bits).map(|()| {
+                    session.settings.stop_bits = stop_bits;
+                    format!("stop bits: {}", info::stop_bits(stop_bits))
+                })
+            }
+            Row::FlowControl => {
+                let flow_control = step(&FLOW_CONTROLS, settings.flow_control, forward);
+                session
+                    .serial_port
+                    .set_flow_control(flow_control)
+                    .map(|()| {
+                        session.settings.flow_control = flow_control;
+                        session.held_off = false;
+                        format!("flow control: {}", info::flow_control(flow_control))
+                    })
+            }
+        };
+        let change = match result {
+            Ok(change) => change,
+            Err(err) => {
+                self.message = Some(format!("not changed: {}", err));
+                return;
+            }
+        };
+        if let Some(log_file) = &mut session.log {
+            if let Err(err) = log_file.write_event(&change) {
+                let path = log_file.path().display().to_string();
+                session.log = None;
+                self.message = Some(format!("stopped logging to {}: {}", path, err));
+                return;
+            }
+        }
+        self.message = Some(change);
+    }
+
+    fn value(&self, row: Row, session: &Session) -> String {
+        let settings = &session.settings;
+        match row {
+            Row::Device => session.device.to_string(),
+            Row::BaudRate => settings.baud_rate.to_string(),
+            Row::DataBits => info::data_bits(settings.data_bits).to_string(),
+            Row::Parity => info::parity(settings.parity).to_string(),
+            Row::StopBits => info::stop_bits(settings.stop_bits).to_string(),
+            Row::FlowControl => info::flow_control(settings.flow_control).to_string(),
+        }
+    }
+}
+
+// The value after or before `current` in `values`, going round at the ends
+fn step<T: Copy + PartialEq>(values: &[T], current: T, forward: bool) -> T {
+    let at = values
+        .iter()
+        .position(|&value| value == current)
+        .unwrap_or(0);
+    let next = if forward {
+        (at + 1) % values.len()
+    } else {
+        (at + values.len() - 1) % values.len()
+    };
+    values[next]
+}
+
+fn row_name(row: Row) -> &'static str {
+    match row {
+        Row::Device => "device",
+        Row::BaudRate => "baud rate",
+        Row::DataBits => "data bits",
+        Row::Parity => "parity",
+        Row::StopBits => "stop bits",
+        Row::FlowControl => "flow control",
+    }
+}
+
+// Open the panel over the output, the baud rate row selected
+pub fn enter(session: &mut Session, screen: &mut Screen) -> NextStep {
+    if let Err(err) = screen.release_held() {
+        return screen_error(err);
+    }
+    let paused_before = screen.paused_bytes().is_some();
+    screen.pause();
+    let (lines_at_start, partial_at_start) = session.scrollback.snapshot();
+    session.port_panel = Some(PortPanel {
+        row: 1,
+        lines_at_start,
+        partial_at_start,
+        paused_before,
+        message: None,
+    });
+    redraw(session, screen)
+}
+
+// Take the keys read while the panel is open, none of them goes to the port
+pub fn handle_keys(session: &mut Session, screen: &mut Screen, data: &[u8]) -> NextStep {
+    let mut panel = match session.port_panel.take() {
+        Some(panel) => panel,
+        None => return NextStep::None,
+    };
+    panel.message = None;
+    if !panel.handle(data, session) {
+        return leave(panel, session, screen);
+    }
+    session.port_panel = Some(panel);
+    redraw(session, screen)
+}
+
+fn redraw(session: &Session, screen: &mut Screen) -> NextStep {
+    let panel = match &session.port_panel {
+        Some(panel) => panel,
+        None => return NextStep::None,
+    };
+    match draw(panel, session, screen) {
+        Ok(()) => NextStep::None,
+        Err(err) => screen_error(err),
+    }
+}
+
+fn draw(panel: &PortPanel, session: &Session, screen: &mut Screen) -> io::Result<()> {
+    screen.clear_output()?;
+    write!(screen, "{}PORT SETTINGS", Goto(3, 2))?;
+    for (i, &row) in ROWS.iter().enumerate() {
+        let line = format!(" {:<14}{} ", row_name(row), panel.value(row, session));
+        write!(screen, "{}", Goto(2, i as u16 + 4))?;
+        if i == panel.row {
+            write!(screen, "{}{}{}", ansi::INVERT, line, ansi::NO_INVERT)?;
+        } else {
+            write!(screen, "{}", line)?;
+        }
+    }
+    let bottom = ROWS.len() as u16 + 5;
+    if let Some(message) = &panel.message {
+        write!(screen, "{}[{}]", Goto(3, bottom), message)?;
+    }
+    write!(
+        screen,
+        "{}up and down pick a setting, left and right change it, Esc closes",
+        Goto(3, bottom + 2)
+    )?;
+    screen.flush()
+}
+
+// Back to the live output, then what arrived while the panel was open
+fn leave(panel: PortPanel, session: &mut Session, screen: &mut Screen) -> NextStep {
+    let mut result = scrollback::restore_output(
+        &session.scrollback,
+        screen,
+        panel.lines_at_start,
+        &panel.partial_at_start,
+    );
+    if !panel.paused_before {
+        result = result.and_then(|()| screen.unpause());
+    }
+    match result {
+        Ok(()) => NextStep::None,
+        Err(err) => screen_error(err),
+    }
+}
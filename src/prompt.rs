@@ -0,0 +1,138 @@
+use std::io::{self, Write};
+
+use crate::ansi;
+
+pub enum PromptStep {
+    Pending,
+    Done(String),
+    Cancelled,
+}
+
+// A single line of input read on the status row while the port is left alone
+pub struct Prompt {
+    label: String,
+    buffer: Vec<u8>,
+    // keys that are a whole answer when typed first, without Enter
+    shortcuts: &'static [u8],
+    // earlier answers for the up and down arrows, oldest first
+    history: Vec<String>,
+    // the answer shown, and what was typed before
+    browsing: Option<(usize, Vec<u8>)>,
+}
+
+impl Prompt {
+    pub fn new(label: &str) -> Prompt {
+        Prompt::with_shortcuts(label, &[])
+    }
+
+    pub fn with_shortcuts(label: &str, shortcuts: &'static [u8]) -> Prompt {
+        Prompt {
+            label: label.to_string(),
+            buffer: Vec::new(),
+            shortcuts,
+            history: Vec::new(),
+            browsing: None,
+        }
+    }
+
+    // Start out with `input` to edit, like an answer that has to be corrected
+    pub fn set_input(&mut self, input: &str) {
+        self.buffer = input.as_bytes().to_vec();
+    }
+
+    pub fn set_history(&mut self, history: &[String]) {
+        self.history = history.to_vec();
+    }
+
+    pub fn start(&self, screen: &mut impl Write) -> io::Result<()> {
+        write!(screen, "\r\n")?;
+        self.render(screen)
+    }
+
+    // Feed one chunk read from stdin
+    pub fn handle(&mut self, data: &[u8], screen: &mut impl Write) -> io::Result<PromptStep> {
+        // a lone ESC cancels, longer sequences are cursor keys
+        if data.first() == Some(&0x1b) {
+            match &data[1..] {
+                [] => return self.finish(screen, PromptStep::Cancelled),
+                b"[A" | b"OA" => self.history_up(),
+                b"[B" | b"OB" => self.history_down(),
+                _ => return Ok(PromptStep::Pending),
+            }
+            self.render(screen)?;
+            return Ok(PromptStep::Pending);
+        }
+        for &byte in data {
+            if self.buffer.is_empty() && self.shortcuts.contains(&byte) {
+                let line = (byte as char).to_string();
+                return self.finish(screen, PromptStep::Done(line));
+            }
+            match byte {
+                // Ctrl-C
+                0x03 => return self.finish(screen, PromptStep::Cancelled),
+                b'\r' | b'\n' => {
+                    let line = String::from_utf8_lossy(&self.buffer).into_owned();
+                    return self.finish(screen, PromptStep::Done(line));
+                }
+                // Backspace and Delete
+                0x08 | 0x7f => {
+                    // drop a whole UTF-8 character
+                    while let Some(last) = self.buffer.pop() {
+                        if last & 0xc0 != 0x80 {
+                            break;
+                        }
+                    }
+                }
+                // Ctrl-U
+                0x15 => self.buffer.clear(),
+                byte if byte >= 0x20 => self.buffer.push(byte),
+                _ => {}
+            }
+        }
+        self.render(screen)?;
+        Ok(PromptStep::Pending)
+    }
+
+    fn history_up(&mut self) {
+        let index = match &self.browsing {
+            Some((0, _)) => return,
+            Some((index, _)) => index - 1,
+            None if self.history.is_empty() => return,
+            None => self.history.len() - 1,
+        };
+        let draft = match self.browsing.take() {
+            Some((_, draft)) => draft,
+            None => self.buffer.clone(),
+        };
+        self.browsing = Some((index, draft));
+        self.buffer = self.history[index].as_bytes().to_vec();
+    }
+
+    fn history_down(&mut self) {
+        match self.browsing.take() {
+            Some((index, draft)) if index + 1 == self.history.len() => self.buffer = draft,
+            Some((index, draft)) => {
+                self.browsing = Some((index + 1, draft));
+                self.buffer = self.history[index + 1].as_bytes().to_vec();
+            }
+            None => {}
+        }
+    }
+
+    fn render(&self, screen: &mut impl Write) -> io::Result<()> {
+        write!(
+            screen,
+            "\r{}{}{}",
+            ansi::CLEAR_LINE,
+            self.label,
+            String::from_utf8_lossy(&self.buffer)
+        )?;
+        screen.flush()
+    }
+
+    fn finish(&self, screen: &mut impl Write, step: PromptStep) -> io::Result<PromptStep> {
+        write!(screen, "\r\n")?;
+        screen.flush()?;
+        Ok(step)
+    }
+}
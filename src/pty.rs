@@ -0,0 +1,164 @@
+use std::ffi::CStr;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{symlink, OpenOptionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use crate::Event;
+
+// --pty: a pseudo-terminal for another program, like a flasher or pppd, to use the port
+// through while the session goes on. Its slave is linked at the path given, what the port
+// receives is written to it and what the program writes comes as Event::Pty.
+pub struct PtyBridge {
+    master: File,
+    // kept open so the master doesn't hang up whenever the program closes the slave
+    _slave: File,
+    link: PathBuf,
+    slave_path: PathBuf,
+    // received data is dropped because the program doesn't read it
+    dropping: bool,
+}
+
+impl PtyBridge {
+    pub fn open(link: &Path, tx: Sender<Event>) -> io::Result<PtyBridge> {
+        let fd = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = unsafe { File::from_raw_fd(fd) };
+        if unsafe { libc::grantpt(fd) } != 0 || unsafe { libc::unlockpt(fd) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let slave_path = slave_name(&master)?;
+        let slave = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NOCTTY)
+            .open(&slave_path)?;
+        make_raw(&slave)?;
+        // the port isn't held up by a program that doesn't read
+        set_nonblocking(&master)?;
+
+        // a link left behind by an earlier session is replaced, anything else is not
+        if fs::symlink_metadata(link).is_ok_and(|meta| meta.file_type().is_symlink()) {
+            fs::remove_file(link)?;
+        }
+        symlink(&slave_path, link)?;
+
+        let mut reader = master.try_clone()?;
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            loop {
+                if !readable(&reader) {
+                    continue;
+                }
+                match reader.read(&mut buf) {
+                    Ok(0) => return,
+                    Ok(n) => {
+                        if tx.send(Event::Pty(buf[..n].to_vec())).is_err() {
+                            return;
+                        }
+                    }
+                    Err(err)
+                        if matches!(
+                            err.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+                        ) => {}
+                    // the slave is held open, so there is no hangup to wait out
+                    Err(_) => return,
+                }
+            }
+        });
+        Ok(PtyBridge {
+            master,
+            _slave: slave,
+            link: link.to_path_buf(),
+            slave_path,
+            dropping: false,
+        })
+    }
+
+    pub fn link(&self) -> &Path {
+        &self.link
+    }
+
+    pub fn slave_path(&self) -> &Path {
+        &self.slave_path
+    }
+
+    // Pass on what the port received, dropping what doesn't fit while the program isn't
+    // reading. Returns whether dropping just started.
+    pub fn write(&mut self, data: &[u8]) -> bool {
+        let written = self.master.write(data).unwrap_or_default();
+        let dropping = written < data.len();
+        let started = dropping && !self.dropping;
+        self.dropping = dropping;
+        started
+    }
+}
+
+impl Drop for PtyBridge {
+    fn drop(&mut self) {
+        // only the link this session made
+        if fs::read_link(&self.link).is_ok_and(|target| target == self.slave_path) {
+            let _ = fs::remove_file(&self.link);
+        }
+    }
+}
+
+fn slave_name(master: &File) -> io::Result<PathBuf> {
+    let mut name = [0 as libc::c_char; 128];
+    #[cfg(target_os = "linux")]
+    {
+        let err = unsafe { libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()) };
+        if err != 0 {
+            return Err(io::Error::from_raw_os_error(err));
+        }
+    }
+    // the others have no ptsname_r, the session's threads don't call ptsname
+    #[cfg(not(target_os = "linux"))]
+    unsafe {
+        let found = libc::ptsname(master.as_raw_fd());
+        if found.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        libc::strncpy(name.as_mut_ptr(), found, name.len() - 1);
+    }
+    let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+    Ok(PathBuf::from(name.to_string_lossy().into_owned()))
+}
+
+// Bytes pass unchanged until the program sets the modes it wants
+fn make_raw(slave: &File) -> io::Result<()> {
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(slave.as_raw_fd(), &mut termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    unsafe { libc::cfmakeraw(&mut termios) };
+    if unsafe { libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn set_nonblocking(file: &File) -> io::Result<()> {
+    let fd = file.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Wait for the master to have data, the reads don't block
+fn readable(file: &File) -> bool {
+    let mut fds = libc::pollfd {
+        fd: file.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut fds, 1, -1) > 0 }
+}
@@ -0,0 +1,418 @@
+use std::fs::File;
+use std::io::{self, stdout, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+
+use crate::ansi;
+use crate::logfile::expand_path_template;
+
+// The first bytes of a recording in the binary format, the version is the last one
+const MAGIC: &[u8; 8] = b"SCIPREC1";
+// The size of the frame header: direction, microseconds since the previous frame, length
+const HEADER_LEN: usize = 1 + 8 + 4;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RecordFormat {
+    // Frames of direction, time since the previous one and the bytes, played by scip replay
+    Binary,
+    // asciinema v2, the received data as output and the sent data as input events
+    Asciicast,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    Rx,
+    Tx,
+}
+
+// One chunk of data as it went over the port
+pub struct Frame {
+    pub direction: Direction,
+    // since the previous frame, or since the recording started for the first one
+    pub delay: Duration,
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.push(match self.direction {
+            Direction::Rx => 0,
+            Direction::Tx => 1,
+        });
+        let micros = u64::try_from(self.delay.as_micros()).unwrap_or(u64::MAX);
+        out.extend_from_slice(&micros.to_le_bytes());
+        out.extend_from_slice(&(self.data.len() as u32).to_le_bytes());
+        out.extend_from_slice(&self.data);
+    }
+}
+
+// Writes --record, flushing after every frame so a crash loses at most the last one
+pub struct Recorder {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    format: RecordFormat,
+    started: Instant,
+    last_frame: Instant,
+    // the start of a UTF-8 sequence the next chunk ends, per direction, for asciicast
+    pending: [Vec<u8>; 2],
+}
+
+impl Recorder {
+    // Create the recording at `template` after expanding its strftime placeholders.
+    // An asciicast recording has the terminal size, `size` as columns and rows.
+    pub fn create(template: &str, format: RecordFormat, size: (u16, u16)) -> io::Result<Recorder> {
+        let path = expand_path_template(template)?;
+        let mut writer = BufWriter::new(File::create(&path)?);
+        match format {
+            RecordFormat::Binary => writer.write_all(MAGIC)?,
+            RecordFormat::Asciicast => writeln!(
+                writer,
+                "{{\"version\": 2, \"width\": {}, \"height\": {}, \"timestamp\": {}}}",
+                size.0,
+                size.1,
+                Utc::now().timestamp()
+            )?,
+        }
+        writer.flush()?;
+        let now = Instant::now();
+        Ok(Recorder {
+            path,
+            writer,
+            format,
+            started: now,
+            last_frame: now,
+            pending: [Vec::new(), Vec::new()],
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn write(&mut self, direction: Direction, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let now = Instant::now();
+        match self.format {
+            RecordFormat::Binary => {
+                let frame = Frame {
+                    direction,
+                    delay: now.duration_since(self.last_frame),
+                    data: data.to_vec(),
+                };
+                let mut encoded = Vec::with_capacity(HEADER_LEN + data.len());
+                frame.encode(&mut encoded);
+                self.writer.write_all(&encoded)?;
+            }
+            RecordFormat::Asciicast => {
+                let (code, pending) = match direction {
+                    Direction::Rx => ("o", &mut self.pending[0]),
+                    Direction::Tx => ("i", &mut self.pending[1]),
+                };
+                pending.extend_from_slice(data);
+                let text = take_text(pending);
+                if !text.is_empty() {
+                    writeln!(
+                        self.writer,
+                        "[{:.6}, \"{}\", \"{}\"]",
+                        now.duration_since(self.started).as_secs_f64(),
+                        code,
+                        escape_json(&text)
+                    )?;
+                }
+            }
+        }
+        self.last_frame = now;
+        self.writer.flush()
+    }
+
+    pub fn close(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// Reads the frames of a recording in the binary format
+pub struct Player<R> {
+    reader: R,
+}
+
+impl<R: Read> Player<R> {
+    pub fn new(mut reader: R) -> io::Result<Player<R>> {
+        let mut magic = [0; MAGIC.len()];
+        if reader.read_exact(&mut magic).is_err() || &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a recording of scip --record, asciicast ones play with asciinema play",
+            ));
+        }
+        Ok(Player { reader })
+    }
+
+    // The next frame, None at the end of the recording
+    pub fn next_frame(&mut self) -> io::Result<Option<Frame>> {
+        let mut header = [0; HEADER_LEN];
+        let mut read = 0;
+        while read < HEADER_LEN {
+            match self.reader.read(&mut header[read..]) {
+                Ok(0) if read == 0 => return Ok(None),
+                Ok(0) => return Err(truncated()),
+                Ok(n) => read += n,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        let direction = match header[0] {
+            0 => Direction::Rx,
+            1 => Direction::Tx,
+            other => {
+                let msg = format!("unknown direction {} in the recording", other);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+            }
+        };
+        let micros = u64::from_le_bytes(header[1..9].try_into().unwrap());
+        let len = u32::from_le_bytes(header[9..13].try_into().unwrap());
+        let mut data = vec![0; len as usize];
+        self.reader
+            .read_exact(&mut data)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => truncated(),
+                _ => err,
+            })?;
+        Ok(Some(Frame {
+            direction,
+            delay: Duration::from_micros(micros),
+            data,
+        }))
+    }
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "the recording ends within a frame",
+    )
+}
+
+// Write the received data of a recording to stdout with the pauses it came with, `speed`
+// times as fast. The sent data was echoed by the device if it was shown at all.
+pub fn replay(path: &Path, speed: f64) -> io::Result<()> {
+    let mut player = Player::new(BufReader::new(File::open(path)?))?;
+    let mut out = stdout();
+    play(&mut player, &mut out, speed)?;
+    // whatever colors the device left set
+    if out.is_terminal() {
+        write!(out, "{}", ansi::RESET)?;
+    }
+    out.flush()
+}
+
+fn play<R: Read, W: Write>(player: &mut Player<R>, out: &mut W, speed: f64) -> io::Result<()> {
+    // the pauses add up from the start, so writing doesn't slow the replay down
+    let started = Instant::now();
+    let mut at = Duration::ZERO;
+    while let Some(frame) = player.next_frame()? {
+        at += frame.delay;
+        if frame.direction != Direction::Rx {
+            continue;
+        }
+        let due = started + at.div_f64(speed);
+        let now = Instant::now();
+        if due > now {
+            thread::sleep(due - now);
+        }
+        out.write_all(&frame.data)?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+// The valid UTF-8 from the start of `pending`, leaving a sequence that isn't complete yet.
+// Bytes that can't be UTF-8 become U+FFFD.
+fn take_text(pending: &mut Vec<u8>) -> String {
+    let mut text = String::new();
+    loop {
+        match std::str::from_utf8(pending) {
+            Ok(valid) => {
+                text.push_str(valid);
+                pending.clear();
+                return text;
+            }
+            Err(err) => {
+                let valid = err.valid_up_to();
+                text.push_str(&String::from_utf8_lossy(&pending[..valid]));
+                match err.error_len() {
+                    Some(invalid) => {
+                        text.push(char::REPLACEMENT_CHARACTER);
+                        pending.drain(..valid + invalid);
+                    }
+                    None => {
+                        pending.drain(..valid);
+                        return text;
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn escape_json(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 || c == '\u{7f}' => {
+                escaped.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(frames: &[Frame]) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        for frame in frames {
+            frame.encode(&mut data);
+        }
+        data
+    }
+
+    fn frame(direction: Direction, millis: u64, data: &[u8]) -> Frame {
+        Frame {
+            direction,
+            delay: Duration::from_millis(millis),
+            data: data.to_vec(),
+        }
+    }
+
+    fn error(data: &[u8]) -> String {
+        let mut player = match Player::new(data) {
+            Ok(player) => player,
+            Err(err) => return err.to_string(),
+        };
+        loop {
+            match player.next_frame() {
+                Ok(Some(_)) => {}
+                Ok(None) => panic!("the recording was read to its end"),
+                Err(err) => return err.to_string(),
+            }
+        }
+    }
+
+    #[test]
+    fn frames_read_back_as_they_were_written() {
+        let data = encoded(&[
+            frame(Direction::Rx, 0, b"login: "),
+            frame(Direction::Tx, 1500, b"root\r"),
+            frame(Direction::Rx, 20, b""),
+        ]);
+        assert_eq!(data.len(), MAGIC.len() + 3 * HEADER_LEN + 12);
+        let mut player = Player::new(&data[..]).unwrap();
+        let first = player.next_frame().unwrap().unwrap();
+        assert!(first.direction == Direction::Rx);
+        assert_eq!(
+            (first.delay, &first.data[..]),
+            (Duration::ZERO, &b"login: "[..])
+        );
+        let second = player.next_frame().unwrap().unwrap();
+        assert!(second.direction == Direction::Tx);
+        assert_eq!(second.delay, Duration::from_millis(1500));
+        assert_eq!(second.data, b"root\r");
+        let third = player.next_frame().unwrap().unwrap();
+        assert_eq!(
+            (third.delay, third.data.len()),
+            (Duration::from_millis(20), 0)
+        );
+        assert!(player.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn a_recording_written_to_a_file_plays_back() {
+        let path = std::env::temp_dir().join(format!("scip-record-{}", std::process::id()));
+        let template = path.to_str().unwrap();
+        let mut recorder = Recorder::create(template, RecordFormat::Binary, (80, 24)).unwrap();
+        recorder.write(Direction::Rx, b"$ ").unwrap();
+        recorder.write(Direction::Tx, b"").unwrap();
+        thread::sleep(Duration::from_millis(20));
+        recorder.write(Direction::Tx, b"ls\r").unwrap();
+        recorder.close().unwrap();
+        let file = File::open(&path).unwrap();
+        let mut player = Player::new(BufReader::new(file)).unwrap();
+        let mut frames = Vec::new();
+        while let Some(frame) = player.next_frame().unwrap() {
+            frames.push(frame);
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(
+            (&frames[0].data[..], &frames[1].data[..]),
+            (&b"$ "[..], &b"ls\r"[..])
+        );
+        assert!(frames[1].delay >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn the_received_data_plays_with_its_pauses_at_the_speed_given() {
+        let data = encoded(&[
+            frame(Direction::Rx, 0, b"a"),
+            frame(Direction::Tx, 100, b"typed"),
+            frame(Direction::Rx, 100, b"b"),
+        ]);
+        let mut out = Vec::new();
+        let started = Instant::now();
+        play(&mut Player::new(&data[..]).unwrap(), &mut out, 4.0).unwrap();
+        let took = started.elapsed();
+        assert_eq!(out, b"ab");
+        // the pause of the sent frame adds to the one after it
+        assert!(took >= Duration::from_millis(50), "{:?}", took);
+        assert!(took < Duration::from_millis(150), "{:?}", took);
+    }
+
+    #[test]
+    fn something_else_is_not_taken_for_a_recording() {
+        assert!(error(b"{\"version\": 2}").starts_with("not a recording of scip --record"));
+        assert!(error(b"SCIP").starts_with("not a recording of scip --record"));
+        let mut data = encoded(&[frame(Direction::Rx, 0, b"x")]);
+        data[MAGIC.len()] = 7;
+        assert_eq!(error(&data), "unknown direction 7 in the recording");
+    }
+
+    #[test]
+    fn a_truncated_recording_is_an_error() {
+        let data = encoded(&[frame(Direction::Rx, 5, b"hello")]);
+        // within the header, then within the data
+        assert_eq!(
+            error(&data[..MAGIC.len() + 4]),
+            "the recording ends within a frame"
+        );
+        assert_eq!(
+            error(&data[..data.len() - 1]),
+            "the recording ends within a frame"
+        );
+    }
+
+    #[test]
+    fn asciicast_text_waits_for_whole_characters() {
+        let mut pending = b"a\xc3".to_vec();
+        assert_eq!(take_text(&mut pending), "a");
+        pending.extend_from_slice(b"\xa9\xff");
+        assert_eq!(take_text(&mut pending), "é\u{fffd}");
+        assert!(pending.is_empty());
+        assert_eq!(
+            escape_json("\"\\\r\n\t\x1b\x7f"),
+            "\\\"\\\\\\r\\n\\t\\u001b\\u007f"
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use crate::control::{self, ControlStyle};
+
+// The lines kept for ~A
+pub const SIZE: usize = 20;
+
+// The last lines typed and sent, for ~A to send again. They are kept as they went to the
+// port, newline translation and all, and with the line ending, so that sending one again
+// repeats it to the byte. Only keys go in, not files, transfers or scripts. Lines are kept in
+// memory only and none at all with --no-history.
+pub struct SentLines {
+    // oldest first, without consecutive repeats
+    lines: Vec<Vec<u8>>,
+    // what was typed since the last line ending
+    current: Vec<u8>,
+    max: usize,
+}
+
+impl SentLines {
+    pub fn new(max: usize) -> SentLines {
+        SentLines {
+            lines: Vec::new(),
+            current: Vec::new(),
+            max,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    // Whether lines are kept at all, they aren't with --no-history
+    pub fn is_kept(&self) -> bool {
+        self.max > 0
+    }
+
+    // A line ends at CR or LF. The LF of a CR LF belongs to the line the CR ended, even when
+    // it was sent separately.
+    pub fn add(&mut self, sent: &[u8]) {
+        if self.max == 0 {
+            return;
+        }
+        for byte in sent {
+            if *byte == b'\n' && self.current.is_empty() {
+                if let Some(last) = self.lines.last_mut() {
+                    if last.ends_with(b"\r") {
+                        last.push(b'\n');
+                        self.drop_repeat();
+                        continue;
+                    }
+                }
+            }
+            self.current.push(*byte);
+            if matches!(byte, b'\r' | b'\n') {
+                self.finish_line();
+            }
+        }
+    }
+
+    fn finish_line(&mut self) {
+        let line = std::mem::take(&mut self.current);
+        // a bare Enter isn't worth repeating
+        if line.len() == 1 {
+            return;
+        }
+        self.lines.push(line);
+        self.drop_repeat();
+        if self.lines.len() > self.max {
+            self.lines.remove(0);
+        }
+    }
+
+    // The newest line goes if it is the one before it once more
+    fn drop_repeat(&mut self) {
+        let count = self.lines.len();
+        if count > 1 && self.lines[count - 1] == self.lines[count - 2] {
+            self.lines.pop();
+        }
+    }
+
+    // The lines as the ~A prompt shows them, oldest first
+    pub fn shown(&self) -> Vec<String> {
+        self.lines.iter().map(|line| show(line)).collect()
+    }
+
+    // The bytes of the line the prompt showed as `shown`, the newest one if several look alike
+    pub fn find(&self, shown: &str) -> Option<&[u8]> {
+        self.lines
+            .iter()
+            .rev()
+            .find(|line| show(line) == shown)
+            .map(|line| line.as_slice())
+    }
+}
+
+// A line without its ending, with the controls in it, like a backspace, in caret notation
+pub fn show(line: &[u8]) -> String {
+    let text = line.strip_suffix(b"\n").unwrap_or(line);
+    let text = text.strip_suffix(b"\r").unwrap_or(text);
+    let mut shown = Vec::with_capacity(text.len());
+    for byte in text {
+        if control::is_control(*byte, true) {
+            control::show_control(&mut shown, *byte, ControlStyle::Caret);
+        } else {
+            shown.push(*byte);
+        }
+    }
+    String::from_utf8_lossy(&shown).into_owned()
+}
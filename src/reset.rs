@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// What avrdude does to reset an Arduino through the capacitor on DTR
+const ARDUINO: &str = "dtr=0,sleep=250,dtr=1";
+// esptool's classic reset: RTS drives EN and DTR drives IO0, so the chip comes out of
+// reset with IO0 low and starts its ROM bootloader
+const ESP32: &str = "dtr=0,rts=1,sleep=100,dtr=1,rts=0,sleep=50,dtr=0";
+
+#[derive(Clone, Copy)]
+pub enum ResetStep {
+    Dtr(bool),
+    Rts(bool),
+    Sleep(Duration),
+}
+
+impl fmt::Display for ResetStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResetStep::Dtr(level) => write!(f, "dtr={}", *level as u8),
+            ResetStep::Rts(level) => write!(f, "rts={}", *level as u8),
+            ResetStep::Sleep(duration) => write!(f, "sleep={}", duration.as_millis()),
+        }
+    }
+}
+
+// The steps of a --reset profile, ending with the lines in the state they are left in
+#[derive(Clone)]
+pub struct ResetSequence {
+    name: String,
+    steps: Vec<ResetStep>,
+}
+
+impl ResetSequence {
+    // `arduino`, `esp32` or `custom:SPEC`, `dtr` and `rts` are the levels to leave the lines at
+    pub fn parse(profile: &str, dtr: bool, rts: bool) -> Result<ResetSequence, String> {
+        let spec = match profile {
+            "arduino" => ARDUINO,
+            "esp32" => ESP32,
+            _ => match profile.strip_prefix("custom:") {
+                Some(spec) => spec,
+                None => {
+                    return Err(format!(
+                        "{}: expected arduino, esp32 or custom:SPEC",
+                        profile
+                    ))
+                }
+            },
+        };
+        let mut steps = spec
+            .split(',')
+            .map(|step| parse_step(step.trim()))
+            .collect::<Result<Vec<ResetStep>, String>>()
+            .map_err(|err| format!("{}: {}", profile, err))?;
+        steps.push(ResetStep::Dtr(dtr));
+        steps.push(ResetStep::Rts(rts));
+        Ok(ResetSequence {
+            name: profile.to_string(),
+            steps,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn start(&self) -> ResetRun {
+        ResetRun {
+            steps: self.steps.iter().copied().collect(),
+            sleep_until: None,
+        }
+    }
+}
+
+// `dtr=0`, `rts=1` or `sleep=MS`
+fn parse_step(step: &str) -> Result<ResetStep, String> {
+    let level = |value: &str| match value {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(format!("in {}: the level has to be 0 or 1", step)),
+    };
+    match step.split_once('=') {
+        Some(("dtr", value)) => Ok(ResetStep::Dtr(level(value)?)),
+        Some(("rts", value)) => Ok(ResetStep::Rts(level(value)?)),
+        Some(("sleep", value)) => match value.parse::<u64>() {
+            Ok(ms) => Ok(ResetStep::Sleep(Duration::from_millis(ms))),
+            Err(_) => Err(format!("in {}: expected milliseconds", step)),
+        },
+        _ => Err(format!(
+            "unknown step {}, expected dtr=0|1, rts=0|1 or sleep=MS",
+            step
+        )),
+    }
+}
+
+// A reset sequence being carried out by the main loop between events
+pub struct ResetRun {
+    steps: VecDeque<ResetStep>,
+    sleep_until: Option<Instant>,
+}
+
+impl ResetRun {
+    // When the current sleep step is over
+    pub fn wake_at(&self) -> Option<Instant> {
+        self.sleep_until
+    }
+
+    // The step to take now, None while sleeping. A sleep step is taken by waiting for it.
+    pub fn next_step(&mut self) -> Option<ResetStep> {
+        if let Some(until) = self.sleep_until {
+            if Instant::now() < until {
+                return None;
+            }
+            self.sleep_until = None;
+        }
+        let step = self.steps.pop_front()?;
+        if let ResetStep::Sleep(duration) = step {
+            self.sleep_until = Some(Instant::now() + duration);
+        }
+        Some(step)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.steps.is_empty() && self.sleep_until.is_none()
+    }
+}
@@ -0,0 +1,329 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use serialport::{DataBits, FlowControl, StopBits};
+
+use crate::transport::{
+    blocking_reader, connect_tcp, read_tcp, PortParity, PortSettings, Transport,
+};
+
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+const BINARY: u8 = 0;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+const COM_PORT_OPTION: u8 = 44;
+
+// Client to server subnegotiation commands
+const SET_BAUDRATE: u8 = 1;
+const SET_DATASIZE: u8 = 2;
+const SET_PARITY: u8 = 3;
+const SET_STOPSIZE: u8 = 4;
+const SET_CONTROL: u8 = 5;
+
+const CONTROL_NO_FLOW: u8 = 1;
+const CONTROL_XON_XOFF: u8 = 2;
+const CONTROL_HARDWARE: u8 = 3;
+const CONTROL_BREAK_ON: u8 = 5;
+const CONTROL_BREAK_OFF: u8 = 6;
+const CONTROL_DTR_ON: u8 = 8;
+const CONTROL_DTR_OFF: u8 = 9;
+const CONTROL_RTS_ON: u8 = 11;
+const CONTROL_RTS_OFF: u8 = 12;
+
+// How long to wait for the server to accept the COM-PORT option
+const NEGOTIATION_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy)]
+enum ParseState {
+    Data,
+    Iac,
+    // WILL, WONT, DO or DONT, waiting for the option byte
+    Negotiate(u8),
+    Subnegotiation,
+    SubnegotiationIac,
+}
+
+// A Telnet connection with the RFC 2217 COM-PORT option, so the remote
+// serial port can be configured and its control lines driven
+pub struct Rfc2217 {
+    stream: TcpStream,
+    state: ParseState,
+    com_port_accepted: bool,
+    // data that arrived while negotiating, returned by the next read
+    pending: Vec<u8>,
+    // the server doesn't have to confirm them, so remember what was requested
+    settings: PortSettings,
+}
+
+impl Rfc2217 {
+    pub fn connect(address: &str, settings: &PortSettings) -> io::Result<Rfc2217> {
+        let mut port = Rfc2217 {
+            stream: connect_tcp(address, settings.timeout)?,
+            state: ParseState::Data,
+            com_port_accepted: false,
+            pending: Vec::new(),
+            settings: *settings,
+        };
+        port.stream.write_all(&[
+            IAC,
+            WILL,
+            COM_PORT_OPTION,
+            IAC,
+            WILL,
+            BINARY,
+            IAC,
+            DO,
+            BINARY,
+            IAC,
+            WILL,
+            SUPPRESS_GO_AHEAD,
+            IAC,
+            DO,
+            SUPPRESS_GO_AHEAD,
+        ])?;
+        port.wait_for_com_port()?;
+        port.configure(settings)?;
+        Ok(port)
+    }
+
+    fn wait_for_com_port(&mut self) -> io::Result<()> {
+        let deadline = Instant::now() + NEGOTIATION_TIMEOUT;
+        let mut buf = [0; 512];
+        while !self.com_port_accepted {
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "the server does not support RFC 2217",
+                ));
+            }
+            match self.read_telnet(&mut buf) {
+                Ok(n) => self.pending.extend_from_slice(&buf[..n]),
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    fn configure(&mut self, settings: &PortSettings) -> io::Result<()> {
+        self.subnegotiate(SET_BAUDRATE, &settings.baud_rate.to_be_bytes())?;
+        self.subnegotiate(SET_DATASIZE, &[data_size_value(settings.data_bits)])?;
+        self.subnegotiate(SET_PARITY, &[parity_value(settings.parity)])?;
+        self.subnegotiate(SET_STOPSIZE, &[stop_size_value(settings.stop_bits)])?;
+        self.subnegotiate(SET_CONTROL, &[flow_control_value(settings.flow_control)])
+    }
+
+    fn subnegotiate(&mut self, command: u8, value: &[u8]) -> io::Result<()> {
+        let mut message = vec![IAC, SB, COM_PORT_OPTION, command];
+        message.extend(escape_iac(value));
+        message.extend_from_slice(&[IAC, SE]);
+        self.stream.write_all(&message)
+    }
+
+    // Answer an option request, only the ones we offered are accepted
+    fn negotiate(&mut self, verb: u8, option: u8) -> io::Result<()> {
+        let supported = matches!(option, BINARY | SUPPRESS_GO_AHEAD | COM_PORT_OPTION);
+        match verb {
+            DO if option == COM_PORT_OPTION => {
+                self.com_port_accepted = true;
+                Ok(())
+            }
+            DONT if option == COM_PORT_OPTION => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the server refused the RFC 2217 COM-PORT option",
+            )),
+            // requests we made ourselves are acknowledged, not answered again
+            DO | WILL if supported => Ok(()),
+            DO => self.stream.write_all(&[IAC, WONT, option]),
+            WILL => self.stream.write_all(&[IAC, DONT, option]),
+            _ => Ok(()),
+        }
+    }
+
+    // Telnet commands are handled here and removed from the data
+    fn read_telnet(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut raw = vec![0; buf.len()];
+        let n = read_tcp(&mut self.stream, &mut raw)?;
+        let mut filled = 0;
+        for &byte in &raw[..n] {
+            self.state = match (self.state, byte) {
+                (ParseState::Data, IAC) => ParseState::Iac,
+                (ParseState::Data, byte) => {
+                    buf[filled] = byte;
+                    filled += 1;
+                    ParseState::Data
+                }
+                (ParseState::Iac, IAC) => {
+                    buf[filled] = IAC;
+                    filled += 1;
+                    ParseState::Data
+                }
+                (ParseState::Iac, verb @ (WILL | WONT | DO | DONT)) => ParseState::Negotiate(verb),
+                (ParseState::Iac, SB) => ParseState::Subnegotiation,
+                (ParseState::Iac, _) => ParseState::Data,
+                (ParseState::Negotiate(verb), option) => {
+                    self.negotiate(verb, option)?;
+                    ParseState::Data
+                }
+                // line and modem state notifications aren't used
+                (ParseState::Subnegotiation, IAC) => ParseState::SubnegotiationIac,
+                (ParseState::Subnegotiation, _) => ParseState::Subnegotiation,
+                (ParseState::SubnegotiationIac, SE) => ParseState::Data,
+                (ParseState::SubnegotiationIac, _) => ParseState::Subnegotiation,
+            };
+        }
+        if filled == 0 {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "no data"));
+        }
+        Ok(filled)
+    }
+}
+
+fn escape_iac(data: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &byte in data {
+        escaped.push(byte);
+        if byte == IAC {
+            escaped.push(IAC);
+        }
+    }
+    escaped
+}
+
+impl Read for Rfc2217 {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.pending.is_empty() {
+            let n = self.pending.len().min(buf.len());
+            buf[..n].copy_from_slice(&self.pending[..n]);
+            self.pending.drain(..n);
+            return Ok(n);
+        }
+        self.read_telnet(buf)
+    }
+}
+
+impl Write for Rfc2217 {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // escaped data is written whole so a short write can't split an IAC pair
+        self.stream.write_all(&escape_iac(buf))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+fn flow_control_value(flow_control: FlowControl) -> u8 {
+    match flow_control {
+        FlowControl::None => CONTROL_NO_FLOW,
+        FlowControl::Software => CONTROL_XON_XOFF,
+        FlowControl::Hardware => CONTROL_HARDWARE,
+    }
+}
+
+fn data_size_value(data_bits: DataBits) -> u8 {
+    match data_bits {
+        DataBits::Five => 5,
+        DataBits::Six => 6,
+        DataBits::Seven => 7,
+        DataBits::Eight => 8,
+    }
+}
+
+fn parity_value(parity: PortParity) -> u8 {
+    match parity {
+        PortParity::None => 1,
+        PortParity::Odd => 2,
+        PortParity::Even => 3,
+        PortParity::Mark => 4,
+        PortParity::Space => 5,
+    }
+}
+
+fn stop_size_value(stop_bits: StopBits) -> u8 {
+    match stop_bits {
+        StopBits::One => 1,
+        StopBits::Two => 2,
+    }
+}
+
+impl Transport for Rfc2217 {
+    fn set_break(&mut self) -> io::Result<()> {
+        self.subnegotiate(SET_CONTROL, &[CONTROL_BREAK_ON])
+    }
+
+    fn clear_break(&mut self) -> io::Result<()> {
+        self.subnegotiate(SET_CONTROL, &[CONTROL_BREAK_OFF])
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> io::Result<()> {
+        let control = if level {
+            CONTROL_DTR_ON
+        } else {
+            CONTROL_DTR_OFF
+        };
+        self.subnegotiate(SET_CONTROL, &[control])
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> io::Result<()> {
+        let control = if level {
+            CONTROL_RTS_ON
+        } else {
+            CONTROL_RTS_OFF
+        };
+        self.subnegotiate(SET_CONTROL, &[control])
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> io::Result<()> {
+        self.subnegotiate(SET_BAUDRATE, &baud_rate.to_be_bytes())?;
+        self.settings.baud_rate = baud_rate;
+        Ok(())
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> io::Result<()> {
+        self.subnegotiate(SET_CONTROL, &[flow_control_value(flow_control)])?;
+        self.settings.flow_control = flow_control;
+        Ok(())
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> io::Result<()> {
+        self.subnegotiate(SET_DATASIZE, &[data_size_value(data_bits)])?;
+        self.settings.data_bits = data_bits;
+        Ok(())
+    }
+
+    fn set_parity(&mut self, parity: PortParity) -> io::Result<()> {
+        self.subnegotiate(SET_PARITY, &[parity_value(parity)])?;
+        self.settings.parity = parity;
+        Ok(())
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> io::Result<()> {
+        self.subnegotiate(SET_STOPSIZE, &[stop_size_value(stop_bits)])?;
+        self.settings.stop_bits = stop_bits;
+        Ok(())
+    }
+
+    fn settings(&self) -> io::Result<PortSettings> {
+        Ok(self.settings)
+    }
+
+    // Takes over parsing, including the replies to the server's option requests
+    fn reader(&self) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(Rfc2217 {
+            stream: blocking_reader(&self.stream)?,
+            state: self.state,
+            com_port_accepted: self.com_port_accepted,
+            pending: self.pending.clone(),
+            settings: self.settings,
+        }))
+    }
+}
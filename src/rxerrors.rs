@@ -0,0 +1,168 @@
+use std::str;
+use std::time::{Duration, Instant};
+
+// How much data the share of errors is taken over
+const WINDOW: Duration = Duration::from_secs(1);
+// Fewer bytes in a window say little about the baud rate
+const MIN_WINDOW_BYTES: u64 = 16;
+// The shares of a window, in percent, when the warning goes up
+const MARKED_WARN_PERCENT: u64 = 10;
+const SUSPECT_WARN_PERCENT: u64 = 25;
+
+// The prefix of an error mark with PARMRK, a 0xFF received is doubled
+const MARK: u8 = 0xff;
+
+// Takes the PARMRK marks out of what a Linux port delivers: a byte received with a framing
+// or parity error, or a break, comes as FF 00 X, and a real FF as FF FF. The bytes in error
+// are passed on, so the data stays what it was.
+pub struct ErrorMarks {
+    // the bytes of a mark the last read ended in
+    pending: Vec<u8>,
+}
+
+impl Default for ErrorMarks {
+    fn default() -> ErrorMarks {
+        ErrorMarks::new()
+    }
+}
+
+impl ErrorMarks {
+    pub fn new() -> ErrorMarks {
+        ErrorMarks {
+            pending: Vec::new(),
+        }
+    }
+
+    // The data without the marks, and how many bytes had an error
+    pub fn decode(&mut self, data: &[u8]) -> (Vec<u8>, u64) {
+        let mut out = Vec::with_capacity(data.len());
+        let mut errors = 0;
+        for &byte in data {
+            match (self.pending.as_slice(), byte) {
+                ([], MARK) => self.pending.push(byte),
+                ([], _) => out.push(byte),
+                ([MARK], MARK) => {
+                    self.pending.clear();
+                    out.push(MARK);
+                }
+                ([MARK], 0) => self.pending.push(byte),
+                // not a mark the driver makes, passed on as it came
+                ([MARK], _) => {
+                    self.pending.clear();
+                    out.extend_from_slice(&[MARK, byte]);
+                }
+                _ => {
+                    self.pending.clear();
+                    out.push(byte);
+                    errors += 1;
+                }
+            }
+        }
+        (out, errors)
+    }
+}
+
+// Watches the share of received bytes in error for a warning on the status row. Without the
+// marks of the port, bytes that are 00 or not UTF-8 are counted as suspect instead, which is
+// what a wrong baud rate mostly makes of text.
+pub struct ErrorMonitor {
+    window_start: Instant,
+    window_bytes: u64,
+    window_errors: u64,
+    // the start of a UTF-8 sequence the last data ended in
+    utf8_pending: Vec<u8>,
+    warning: Option<String>,
+}
+
+impl Default for ErrorMonitor {
+    fn default() -> ErrorMonitor {
+        ErrorMonitor::new()
+    }
+}
+
+impl ErrorMonitor {
+    pub fn new() -> ErrorMonitor {
+        ErrorMonitor {
+            window_start: Instant::now(),
+            window_bytes: 0,
+            window_errors: 0,
+            utf8_pending: Vec::new(),
+            warning: None,
+        }
+    }
+
+    // Bytes the port marked as received with an error
+    pub fn add_errors(&mut self, count: u64) {
+        self.window_errors += count;
+    }
+
+    // Count in received `data`, returning how many of its bytes are suspect when the port
+    // doesn't mark errors, 0 when it does
+    pub fn feed(&mut self, data: &[u8], marked: bool) -> u64 {
+        self.window_bytes += data.len() as u64;
+        if marked {
+            self.utf8_pending.clear();
+            return 0;
+        }
+        let suspect = self.suspect_bytes(data);
+        self.window_errors += suspect;
+        suspect
+    }
+
+    // The warning once a window is over, Some when it changed: the text while too many bytes
+    // are in error, None when the data is clean again
+    pub fn update(&mut self, marked: bool) -> Option<Option<String>> {
+        if self.window_start.elapsed() < WINDOW {
+            return None;
+        }
+        let (bytes, errors) = (self.window_bytes, self.window_errors);
+        self.window_start = Instant::now();
+        self.window_bytes = 0;
+        self.window_errors = 0;
+        if bytes < MIN_WINDOW_BYTES {
+            return None;
+        }
+        let percent = (errors * 100 / bytes).min(100);
+        let warning = if marked && percent >= MARKED_WARN_PERCENT {
+            Some(format!(
+                "~{}% of bytes have framing or parity errors, wrong baud rate?",
+                percent
+            ))
+        } else if !marked && percent >= SUSPECT_WARN_PERCENT {
+            Some(format!(
+                "~{}% of bytes look like line noise, wrong baud rate?",
+                percent
+            ))
+        } else {
+            None
+        };
+        if warning == self.warning {
+            return None;
+        }
+        self.warning = warning.clone();
+        Some(warning)
+    }
+
+    // 00 bytes and the bytes of broken UTF-8 sequences
+    fn suspect_bytes(&mut self, data: &[u8]) -> u64 {
+        let mut suspect = data.iter().filter(|&&byte| byte == 0).count() as u64;
+        let mut bytes = std::mem::take(&mut self.utf8_pending);
+        bytes.extend_from_slice(data);
+        let mut rest = bytes.as_slice();
+        while let Err(err) = str::from_utf8(rest) {
+            let valid = err.valid_up_to();
+            match err.error_len() {
+                Some(len) => {
+                    suspect += len as u64;
+                    rest = &rest[valid + len..];
+                }
+                // it may still be completed by the next data
+                None => {
+                    self.utf8_pending = rest[valid..].to_vec();
+                    break;
+                }
+            }
+        }
+        suspect
+    }
+}
@@ -0,0 +1,519 @@
+//! Where the session is shown: the OutputSink a frontend gives it and the Screen on top.
+
+use std::io::{self, Stdout, Write};
+use std::time::Instant;
+
+use crate::ansi::{self, Goto, Up};
+use crate::highlight::Highlighter;
+use crate::term;
+
+/// Where an interactive session is shown, a terminal in raw mode for scip
+pub trait OutputSink: Write {
+    /// Give the terminal to a program run by an escape command, in the mode it was found in
+    fn suspend(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Take it back once the program is done
+    fn resume(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+enum Output {
+    // raw mode, on the alternate screen unless --no-alt-screen was given
+    Terminal(Box<dyn OutputSink>, bool),
+    // stdin or stdout isn't a terminal, or --pipe was given
+    Pipe(Stdout),
+}
+
+// What is kept of the output while the terminal is handed to another program or the view is
+// paused, the rest is dropped from the start
+const MAX_HELD_BACK: usize = 1 << 20;
+
+// Shown before the output of a paused view that didn't fit
+const TRUNCATED: &[u8] = b"[... output truncated ...]\r\n";
+
+// Save and restore the cursor, DECSC and DECRC
+const SAVE_CURSOR: &[u8] = b"\x1b7";
+const RESTORE_CURSOR: &[u8] = b"\x1b8";
+
+// The rows kept below the scrolling output: the --show-lines status row above the line
+// being composed in line mode
+struct Footer {
+    status: Option<String>,
+    // what the line starts with, the text and the cursor within it
+    input: Option<(String, Vec<char>, usize)>,
+    // the row count and footer height the scrolling region was set up for
+    region: (u16, u16),
+    status_drawn: bool,
+    // the cursor is on the input line, where the output continues is saved
+    input_drawn: bool,
+}
+
+impl Footer {
+    fn new() -> Footer {
+        Footer {
+            status: None,
+            input: None,
+            region: (0, 0),
+            status_drawn: false,
+            input_drawn: false,
+        }
+    }
+
+    fn height(&self) -> u16 {
+        self.status.is_some() as u16 + self.input.is_some() as u16
+    }
+}
+
+/// Where received data and status messages are shown
+pub struct Screen {
+    output: Output,
+    highlighter: Option<Highlighter>,
+    footer: Footer,
+    // the output collected while suspended
+    held_back: Option<Vec<u8>>,
+    // the received data collected while ~p paused the view, and whether some was dropped
+    paused: Option<(Vec<u8>, bool)>,
+    // the first failed write of a status line, which ends the session
+    failed: Option<io::Error>,
+}
+
+impl Screen {
+    pub fn terminal(mut terminal: Box<dyn OutputSink>, alternate: bool) -> io::Result<Screen> {
+        if alternate {
+            write!(terminal, "{}", ansi::TO_ALTERNATE_SCREEN)?;
+            terminal.flush()?;
+        }
+        Ok(Screen {
+            output: Output::Terminal(terminal, alternate),
+            highlighter: None,
+            footer: Footer::new(),
+            held_back: None,
+            paused: None,
+            failed: None,
+        })
+    }
+
+    /// Plain bytes to stdout, the terminal is left alone
+    pub fn pipe() -> Screen {
+        Screen {
+            output: Output::Pipe(io::stdout()),
+            highlighter: None,
+            footer: Footer::new(),
+            held_back: None,
+            paused: None,
+            failed: None,
+        }
+    }
+
+    /// Whether the session is interactive, with escape commands and status lines
+    pub fn is_terminal(&self) -> bool {
+        matches!(self.output, Output::Terminal(..))
+    }
+
+    pub fn is_alternate(&self) -> bool {
+        matches!(self.output, Output::Terminal(_, true))
+    }
+
+    /// The rows below the scrolling output, the status row and the input line
+    pub fn footer_rows(&self) -> u16 {
+        self.footer.height()
+    }
+
+    /// Keep a write error of a helper that can't return it, the session loop ends on it
+    pub fn fail(&mut self, err: io::Error) {
+        self.failed.get_or_insert(err);
+    }
+
+    pub fn take_failure(&mut self) -> Option<io::Error> {
+        self.failed.take()
+    }
+
+    pub fn set_highlighter(&mut self, highlighter: Highlighter) {
+        self.highlighter = Some(highlighter);
+    }
+
+    /// Show data from the port, colored by --highlight
+    pub fn write_received(&mut self, data: &[u8]) -> io::Result<()> {
+        if let Some(held_back) = &mut self.held_back {
+            hold_back(held_back, data);
+            return Ok(());
+        }
+        if let Some((paused, truncated)) = &mut self.paused {
+            *truncated |= hold_back(paused, data);
+            return Ok(());
+        }
+        self.leave_input_line()?;
+        match &mut self.highlighter {
+            Some(highlighter) => {
+                let lines = highlighter.feed(data);
+                self.output.write_all(&lines)
+            }
+            None => self.output.write_all(data),
+        }
+    }
+
+    /// Undo what received data did to the terminal, a selected line drawing set, a hidden
+    /// cursor or a scrolling region, and clear it with the cursor at the top. The footer
+    /// is drawn again on the next flush.
+    pub fn reset_terminal(&mut self) -> io::Result<()> {
+        if !self.is_terminal() {
+            return Ok(());
+        }
+        self.release_held()?;
+        self.leave_input_line()?;
+        write!(
+            self.output,
+            "{}{}{}{}{}{}",
+            ansi::SOFT_RESET,
+            ansi::ASCII_CHARSET,
+            ansi::RESET,
+            ansi::SHOW_CURSOR,
+            ansi::CLEAR_ALL,
+            Goto(1, 1)
+        )?;
+        self.footer.region = (0, 0);
+        self.footer.status_drawn = false;
+        self.flush()
+    }
+
+    /// Clear the rows above the footer with the cursor at the top, for a view drawn over the
+    /// output. The footer is drawn again on the next flush.
+    pub fn clear_output(&mut self) -> io::Result<()> {
+        self.leave_input_line()?;
+        write!(self.output, "{}{}", ansi::CLEAR_ALL, Goto(1, 1))?;
+        self.footer.region = (0, 0);
+        self.footer.status_drawn = false;
+        Ok(())
+    }
+
+    /// Stop showing received data until unpause(), status messages are still shown
+    pub fn pause(&mut self) {
+        self.paused.get_or_insert_with(|| (Vec::new(), false));
+    }
+
+    /// How much received data the paused view holds, None when it isn't paused
+    pub fn paused_bytes(&self) -> Option<usize> {
+        self.paused.as_ref().map(|(paused, _)| paused.len())
+    }
+
+    /// Show what was received while paused and go on with live output
+    pub fn unpause(&mut self) -> io::Result<()> {
+        let (paused, truncated) = match self.paused.take() {
+            Some(paused) => paused,
+            None => return Ok(()),
+        };
+        if truncated {
+            self.write_all(TRUNCATED)?;
+        }
+        self.write_received(&paused)?;
+        self.flush()
+    }
+
+    // Put the cursor back where the output continues
+    fn leave_input_line(&mut self) -> io::Result<()> {
+        if self.footer.input_drawn {
+            self.output.write_all(RESTORE_CURSOR)?;
+            self.footer.input_drawn = false;
+        }
+        Ok(())
+    }
+
+    /// When a partial line held back for highlighting is due to be shown
+    pub fn release_at(&self) -> Option<Instant> {
+        if self.held_back.is_some() {
+            return None;
+        }
+        self.highlighter.as_ref()?.release_at()
+    }
+
+    /// Show the held partial line, anything else written to the screen has to come after it
+    pub fn release_held(&mut self) -> io::Result<()> {
+        if let Some(highlighter) = &mut self.highlighter {
+            if highlighter.release_at().is_some() {
+                let held = highlighter.release();
+                self.leave_input_line()?;
+                self.output.write_all(&held)?;
+                self.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Screen {
+    /// Hand the terminal over on the main screen in its original mode. Until resume() the
+    /// output is collected instead of shown.
+    pub fn suspend(&mut self) -> io::Result<()> {
+        self.release_held()?;
+        self.leave_input_line()?;
+        if let Output::Terminal(terminal, alternate) = &mut self.output {
+            if self.footer.height() > 0 {
+                write!(terminal, "\x1b[r{}", Goto(1, self.footer.region.0))?;
+            }
+            if *alternate {
+                write!(terminal, "{}", ansi::TO_MAIN_SCREEN)?;
+            }
+            terminal.flush()?;
+            terminal.suspend()?;
+        }
+        self.held_back = Some(Vec::new());
+        Ok(())
+    }
+
+    /// Take the terminal back, returning the output collected meanwhile
+    pub fn resume(&mut self) -> io::Result<Vec<u8>> {
+        let held_back = self.held_back.take().unwrap_or_default();
+        if let Output::Terminal(terminal, alternate) = &mut self.output {
+            terminal.resume()?;
+            if *alternate {
+                write!(terminal, "{}", ansi::TO_ALTERNATE_SCREEN)?;
+            }
+        }
+        // the other program may have changed the scrolling region and the rows below it
+        self.footer.region = (0, 0);
+        self.footer.status_drawn = false;
+        self.flush()?;
+        Ok(held_back)
+    }
+
+    /// Show `text` with the cursor at character `cursor` on the bottom row, it is redrawn on flush
+    pub fn show_input_line(&mut self, prompt: &str, text: &str, cursor: usize) -> io::Result<()> {
+        self.leave_input_line()?;
+        let height = self.footer.height();
+        self.footer.input = Some((prompt.to_string(), text.chars().collect(), cursor));
+        self.resize_footer(height)
+    }
+
+    /// Give the bottom row back to the output
+    pub fn hide_input_line(&mut self) -> io::Result<()> {
+        if self.footer.input.is_none() {
+            return Ok(());
+        }
+        self.leave_input_line()?;
+        let height = self.footer.height();
+        self.footer.input = None;
+        self.resize_footer(height)
+    }
+
+    /// Show `row` on a line of its own below the output, or remove it with None
+    pub fn set_status_row(&mut self, row: Option<String>) -> io::Result<()> {
+        self.leave_input_line()?;
+        let height = self.footer.height();
+        self.footer.status = row;
+        self.footer.status_drawn = false;
+        self.resize_footer(height)
+    }
+
+    // Make the footer `height` rows high fit what it now shows
+    fn resize_footer(&mut self, height: u16) -> io::Result<()> {
+        let new_height = self.footer.height();
+        if new_height > height {
+            // make room at the bottom if the output ended there
+            let grow = new_height - height;
+            self.output.write_all(&b"\n".repeat(grow as usize))?;
+            write!(self.output, "{}", Up(grow))?;
+        } else if new_height < height {
+            let (_, rows) = term::backend().size()?;
+            self.output.write_all(SAVE_CURSOR)?;
+            // changing the scrolling region moves the cursor home
+            if new_height == 0 || rows <= new_height {
+                self.output.write_all(b"\x1b[r")?;
+            } else {
+                write!(self.output, "\x1b[1;{}r", rows.saturating_sub(new_height))?;
+            }
+            for row in rows.saturating_sub(height) + 1..=rows {
+                write!(self.output, "{}{}", Goto(1, row), ansi::CLEAR_LINE)?;
+            }
+            self.output.write_all(RESTORE_CURSOR)?;
+            self.footer.region = (rows, new_height);
+            self.footer.status_drawn = false;
+        }
+        self.flush()
+    }
+
+    fn draw_footer(&mut self) -> io::Result<()> {
+        let footer = &mut self.footer;
+        let height = footer.height();
+        if height == 0 {
+            return Ok(());
+        }
+        let (cols, rows) = term::backend().size()?;
+        // too small to split, the line is typed blind
+        if rows <= height || cols < 4 {
+            return Ok(());
+        }
+        let region_changed = footer.region != (rows, height);
+        let draw_status = footer.status.is_some() && (!footer.status_drawn || region_changed);
+        let draw_input = footer.input.is_some() && !footer.input_drawn;
+        if !draw_status && !draw_input && !region_changed {
+            return Ok(());
+        }
+        let output = &mut self.output;
+        output.write_all(SAVE_CURSOR)?;
+        if region_changed {
+            // the output scrolls above the footer, setting that up moves the cursor home
+            write!(output, "\x1b[1;{}r", rows - height)?;
+            output.write_all(RESTORE_CURSOR)?;
+            output.write_all(SAVE_CURSOR)?;
+            footer.region = (rows, height);
+        }
+        if let (Some(status), true) = (&footer.status, draw_status) {
+            // autowrap is off so a long row is cut instead of scrolling the screen
+            write!(
+                output,
+                "{}{}\x1b[?7l{}\x1b[?7h",
+                Goto(1, rows - height + 1),
+                ansi::CLEAR_LINE,
+                status
+            )?;
+            footer.status_drawn = true;
+        }
+        match &footer.input {
+            Some((prompt, text, cursor)) => {
+                // scroll sideways so the cursor stays visible after the prompt
+                let prompt: String = prompt.chars().take(cols as usize / 2).collect();
+                let prompt_width = prompt.chars().count();
+                let width = (cols as usize).saturating_sub(prompt_width + 1).max(1);
+                let start = cursor.saturating_sub(width);
+                let visible: String = text.iter().skip(start).take(width).collect();
+                write!(
+                    output,
+                    "{}{}{}{}{}",
+                    Goto(1, rows),
+                    ansi::CLEAR_LINE,
+                    prompt,
+                    visible,
+                    Goto((cursor - start + prompt_width + 1) as u16, rows)
+                )?;
+                footer.input_drawn = true;
+            }
+            None => output.write_all(RESTORE_CURSOR)?,
+        }
+        Ok(())
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Terminal(terminal, _) => terminal.write(buf),
+            Output::Pipe(stdout) => stdout.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Terminal(terminal, _) => terminal.flush(),
+            Output::Pipe(stdout) => stdout.flush(),
+        }
+    }
+}
+
+impl Write for Screen {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(held_back) = &mut self.held_back {
+            hold_back(held_back, buf);
+            return Ok(buf.len());
+        }
+        self.release_held()?;
+        self.leave_input_line()?;
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.held_back.is_some() {
+            return Ok(());
+        }
+        self.draw_footer()?;
+        self.output.flush()
+    }
+}
+
+// Returns whether the start had to be dropped
+fn hold_back(held_back: &mut Vec<u8>, data: &[u8]) -> bool {
+    held_back.extend_from_slice(data);
+    if held_back.len() <= MAX_HELD_BACK {
+        return false;
+    }
+    let excess = held_back.len() - MAX_HELD_BACK;
+    held_back.drain(..excess);
+    true
+}
+
+// Raw mode is left after this, when the terminal itself is dropped
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = self.release_held();
+        let _ = self.hide_input_line();
+        let _ = self.set_status_row(None);
+        if let Output::Terminal(terminal, true) = &mut self.output {
+            let _ = write!(terminal, "{}", ansi::TO_MAIN_SCREEN);
+        }
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // What was written to the terminal, with the hand-overs in between
+    #[derive(Clone, Default)]
+    struct Sink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl OutputSink for Sink {
+        fn suspend(&mut self) -> io::Result<()> {
+            self.write_all(b"<suspend>")
+        }
+
+        fn resume(&mut self) -> io::Result<()> {
+            self.write_all(b"<resume>")
+        }
+    }
+
+    #[test]
+    fn what_arrives_while_suspended_is_kept_instead_of_shown() {
+        let sink = Sink::default();
+        let shown = sink.0.clone();
+        let mut screen = Screen::terminal(Box::new(sink), true).unwrap();
+        screen.write_received(b"before").unwrap();
+        screen.suspend().unwrap();
+        screen.write_received(b"during ").unwrap();
+        write!(screen, "[note]").unwrap();
+        screen.flush().unwrap();
+        assert_eq!(screen.resume().unwrap(), b"during [note]");
+        screen.write_received(b"after").unwrap();
+        let expected = format!(
+            "{}before{}<suspend><resume>{}after",
+            ansi::TO_ALTERNATE_SCREEN,
+            ansi::TO_MAIN_SCREEN,
+            ansi::TO_ALTERNATE_SCREEN
+        );
+        assert_eq!(*shown.lock().unwrap(), expected.as_bytes());
+    }
+
+    #[test]
+    fn only_the_end_of_a_long_output_is_kept() {
+        let mut held_back = vec![b'a'; MAX_HELD_BACK - 2];
+        assert!(!hold_back(&mut held_back, b"bc"));
+        assert!(hold_back(&mut held_back, b"de"));
+        assert_eq!(held_back.len(), MAX_HELD_BACK);
+        assert!(held_back.starts_with(b"aa"));
+        assert!(held_back.ends_with(b"abcde"));
+    }
+}
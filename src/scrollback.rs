@@ -0,0 +1,454 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+use regex::{Regex, RegexBuilder};
+
+use crate::ansi::{self, Goto};
+use crate::screen::Screen;
+use crate::term;
+use crate::{screen_error, NextStep, Session};
+
+// A longer line is kept as several, so binary data without newlines stays bounded too
+const MAX_LINE: usize = 1024;
+
+// Where a terminal sequence in the received data is, it is left out of the kept lines
+#[derive(Clone, Copy, PartialEq)]
+enum Sequence {
+    None,
+    Escape,
+    // ESC [ up to the final byte
+    Csi,
+    // ESC ] up to BEL or ESC \
+    Osc,
+    OscEscape,
+}
+
+// The last --scrollback lines shown, as plain text without the colors and cursor movements,
+// for ~[ to page through and search. Kept only in memory, at most MAX_LINE bytes per line.
+pub struct Scrollback {
+    lines: VecDeque<Vec<u8>>,
+    // the line being received
+    current: Vec<u8>,
+    max: usize,
+    // the lines ever kept, the index of the next one
+    total: u64,
+    sequence: Sequence,
+    // a CR not followed by a LF lets the line be written over, like a progress bar does
+    after_cr: bool,
+}
+
+impl Scrollback {
+    pub fn new(max: usize) -> Scrollback {
+        Scrollback {
+            lines: VecDeque::new(),
+            current: Vec::new(),
+            max,
+            total: 0,
+            sequence: Sequence::None,
+            after_cr: false,
+        }
+    }
+
+    // Whether lines are kept at all, they aren't with --scrollback 0
+    pub fn is_kept(&self) -> bool {
+        self.max > 0
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        if self.max == 0 {
+            return;
+        }
+        for &byte in data {
+            self.sequence = match (self.sequence, byte) {
+                (Sequence::None, 0x1b) => Sequence::Escape,
+                (Sequence::None, _) => {
+                    self.add_byte(byte);
+                    Sequence::None
+                }
+                (Sequence::Escape, b'[') => Sequence::Csi,
+                (Sequence::Escape, b']') => Sequence::Osc,
+                (Sequence::Escape, _) => Sequence::None,
+                (Sequence::Csi, 0x40..=0x7e) => Sequence::None,
+                (Sequence::Csi, _) => Sequence::Csi,
+                (Sequence::Osc, 0x07) => Sequence::None,
+                (Sequence::Osc, 0x1b) => Sequence::OscEscape,
+                (Sequence::Osc, _) => Sequence::Osc,
+                (Sequence::OscEscape, _) => Sequence::None,
+            };
+        }
+    }
+
+    fn add_byte(&mut self, byte: u8) {
+        if self.after_cr && byte != b'\n' && byte != b'\r' {
+            self.current.clear();
+        }
+        self.after_cr = false;
+        match byte {
+            b'\n' => self.finish_line(),
+            b'\r' => self.after_cr = true,
+            // Backspace
+            0x08 => {
+                self.current.pop();
+            }
+            b'\t' => self.current.push(byte),
+            0x00..=0x1f | 0x7f => {}
+            _ => {
+                self.current.push(byte);
+                if self.current.len() >= MAX_LINE {
+                    self.finish_line();
+                }
+            }
+        }
+    }
+
+    fn finish_line(&mut self) {
+        self.lines.push_back(std::mem::take(&mut self.current));
+        self.total += 1;
+        if self.lines.len() > self.max {
+            self.lines.pop_front();
+        }
+    }
+
+    // The index of the oldest line still kept
+    fn first(&self) -> u64 {
+        self.total - self.lines.len() as u64
+    }
+
+    // The index after the last line, the one being received counted when it has anything
+    fn end(&self) -> u64 {
+        self.total + !self.current.is_empty() as u64
+    }
+
+    // The number of lines so far and the line being received, for restore_output()
+    pub fn snapshot(&self) -> (u64, Vec<u8>) {
+        (self.total, self.current.clone())
+    }
+
+    fn line(&self, index: u64) -> Option<&[u8]> {
+        if index == self.total && !self.current.is_empty() {
+            return Some(&self.current);
+        }
+        let offset = index.checked_sub(self.first())?;
+        self.lines.get(offset as usize).map(|line| line.as_slice())
+    }
+}
+
+// A line as shown, with its tabs expanded
+fn text(line: &[u8]) -> String {
+    let mut text = String::with_capacity(line.len());
+    let mut column = 0;
+    for c in String::from_utf8_lossy(line).chars() {
+        if c == '\t' {
+            let spaces = 8 - column % 8;
+            text.extend(std::iter::repeat_n(' ', spaces));
+            column += spaces;
+        } else {
+            text.push(c);
+            column += 1;
+        }
+    }
+    text
+}
+
+// The view ~[ shows over the output, while the received data is held back and kept
+pub struct ScrollView {
+    // the index of the line on the top row
+    top: u64,
+    // what the output showed when the view started, for drawing it again
+    lines_at_start: u64,
+    partial_at_start: Vec<u8>,
+    paused_before: bool,
+    search: Option<Regex>,
+    forward: bool,
+    // the search pattern being typed, after / or ?
+    typing: Option<(bool, String)>,
+    // shown on the bar until the next key
+    message: Option<String>,
+}
+
+impl ScrollView {
+    // Take the keys of one read, returning false when they leave the view
+    fn handle(&mut self, data: &[u8], scrollback: &Scrollback, page: u64) -> bool {
+        self.message = None;
+        // a lone ESC leaves, longer sequences are cursor keys
+        if data.first() == Some(&0x1b) && self.typing.is_none() {
+            match &data[1..] {
+                [] => return false,
+                b"[A" | b"OA" => self.top = self.top.saturating_sub(1),
+                b"[B" | b"OB" => self.top = self.top.saturating_add(1),
+                b"[5~" => self.top = self.top.saturating_sub(page),
+                b"[6~" => self.top = self.top.saturating_add(page),
+                b"[H" | b"OH" | b"[1~" => self.top = 0,
+                b"[F" | b"OF" | b"[4~" => self.top = u64::MAX,
+                _ => {}
+            }
+            return true;
+        }
+        for &byte in data {
+            if let Some((forward, pattern)) = &mut self.typing {
+                match byte {
+                    // Esc and Ctrl-C
+                    0x1b | 0x03 => self.typing = None,
+                    b'\r' | b'\n' => {
+                        let (forward, pattern) = (*forward, std::mem::take(pattern));
+                        self.typing = None;
+                        self.start_search(scrollback, &pattern, forward);
+                    }
+                    // Backspace and Delete
+                    0x08 | 0x7f => {
+                        pattern.pop();
+                    }
+                    byte if byte >= 0x20 => pattern.push(byte as char),
+                    _ => {}
+                }
+                continue;
+            }
+            match byte {
+                b'q' => return false,
+                b'k' => self.top = self.top.saturating_sub(1),
+                b'j' | b'\r' => self.top = self.top.saturating_add(1),
+                b'b' => self.top = self.top.saturating_sub(page),
+                b' ' | b'f' => self.top = self.top.saturating_add(page),
+                b'g' => self.top = 0,
+                b'G' => self.top = u64::MAX,
+                b'/' => self.typing = Some((true, String::new())),
+                b'?' => self.typing = Some((false, String::new())),
+                b'n' => self.search(scrollback, true),
+                b'N' => self.search(scrollback, false),
+                _ => {}
+            }
+        }
+        true
+    }
+
+    fn start_search(&mut self, scrollback: &Scrollback, pattern: &str, forward: bool) {
+        // an empty pattern searches for the last one again
+        if !pattern.is_empty() {
+            match RegexBuilder::new(pattern).case_insensitive(true).build() {
+                Ok(regex) => self.search = Some(regex),
+                Err(_) => {
+                    self.message = Some(format!("invalid pattern {}", pattern));
+                    return;
+                }
+            }
+        }
+        self.forward = forward;
+        self.search(scrollback, true);
+    }
+
+    // Move the next matching line to the top, in the direction of the search or against it
+    fn search(&mut self, scrollback: &Scrollback, same_direction: bool) {
+        let regex = match &self.search {
+            Some(regex) => regex,
+            None => {
+                self.message = Some("no search yet, / or ? starts one".to_string());
+                return;
+            }
+        };
+        let matches = |index: &u64| {
+            scrollback
+                .line(*index)
+                .is_some_and(|line| regex.is_match(&text(line)))
+        };
+        let (first, end) = (scrollback.first(), scrollback.end());
+        let top = self.top.clamp(first, end.saturating_sub(1).max(first));
+        let found = if self.forward == same_direction {
+            (top + 1..end).find(matches)
+        } else {
+            (first..top).rev().find(matches)
+        };
+        match found {
+            Some(index) => self.top = index,
+            None => self.message = Some(format!("not found: {}", regex.as_str())),
+        }
+    }
+}
+
+// Leave the live output for the scrollback, showing its last page or, for PageUp, the one
+// before
+pub fn enter(session: &mut Session, screen: &mut Screen, page_up: bool) -> NextStep {
+    if let Err(err) = screen.release_held() {
+        return screen_error(err);
+    }
+    let scrollback = &session.scrollback;
+    let pages = if page_up { 2 } else { 1 };
+    let paused_before = screen.paused_bytes().is_some();
+    screen.pause();
+    let (lines_at_start, partial_at_start) = scrollback.snapshot();
+    session.scroll_view = Some(ScrollView {
+        top: scrollback.end().saturating_sub(pages * page_height(screen)),
+        lines_at_start,
+        partial_at_start,
+        paused_before,
+        search: None,
+        forward: true,
+        typing: None,
+        message: None,
+    });
+    redraw(session, screen)
+}
+
+// Take the keys read while the view is shown, none of them goes to the port
+pub fn handle_keys(session: &mut Session, screen: &mut Screen, data: &[u8]) -> NextStep {
+    let mut view = match session.scroll_view.take() {
+        Some(view) => view,
+        None => return NextStep::None,
+    };
+    if !view.handle(data, &session.scrollback, page_height(screen)) {
+        return leave(view, session, screen);
+    }
+    session.scroll_view = Some(view);
+    redraw(session, screen)
+}
+
+// Show how many lines arrived since the view started, on its bar
+pub fn show_new_lines(session: &mut Session, screen: &mut Screen) -> NextStep {
+    let view = match &session.scroll_view {
+        Some(view) => view,
+        None => return NextStep::None,
+    };
+    let result = draw_bar(view, &session.scrollback, screen).and_then(|()| screen.flush());
+    match result {
+        Ok(()) => NextStep::None,
+        Err(err) => screen_error(err),
+    }
+}
+
+// The rows of the view without the bar, the footer stays below it
+fn page_height(screen: &Screen) -> u64 {
+    let rows = term::backend().size().map_or(24, |(_, rows)| rows);
+    rows.saturating_sub(screen.footer_rows() + 1).max(1) as u64
+}
+
+fn redraw(session: &mut Session, screen: &mut Screen) -> NextStep {
+    let (view, scrollback) = match &mut session.scroll_view {
+        Some(view) => (view, &session.scrollback),
+        None => return NextStep::None,
+    };
+    match draw(view, scrollback, screen) {
+        Ok(()) => NextStep::None,
+        Err(err) => screen_error(err),
+    }
+}
+
+fn draw(view: &mut ScrollView, scrollback: &Scrollback, screen: &mut Screen) -> io::Result<()> {
+    let height = page_height(screen);
+    let cols = term::backend().size().map_or(80, |(cols, _)| cols) as usize;
+    let last_top = scrollback
+        .end()
+        .saturating_sub(height)
+        .max(scrollback.first());
+    view.top = view.top.clamp(scrollback.first(), last_top);
+    screen.clear_output()?;
+    // autowrap is off so a long line is cut instead of taking a second row
+    write!(screen, "\x1b[?7l")?;
+    for row in 0..height {
+        write!(screen, "{}", Goto(1, row as u16 + 1))?;
+        if let Some(line) = scrollback.line(view.top + row) {
+            let shown: String = text(line).chars().take(cols).collect();
+            write_highlighted(screen, &shown, view.search.as_ref())?;
+        }
+    }
+    write!(screen, "\x1b[?7h")?;
+    draw_bar(view, scrollback, screen)?;
+    screen.flush()
+}
+
+// The text with the matches of the search in reverse video
+fn write_highlighted(screen: &mut Screen, text: &str, search: Option<&Regex>) -> io::Result<()> {
+    let regex = match search {
+        Some(regex) => regex,
+        None => return write!(screen, "{}", text),
+    };
+    let mut shown = 0;
+    for found in regex
+        .find_iter(text)
+        .filter(|found| found.start() < found.end())
+    {
+        write!(
+            screen,
+            "{}{}{}{}",
+            &text[shown..found.start()],
+            ansi::INVERT,
+            found.as_str(),
+            ansi::NO_INVERT
+        )?;
+        shown = found.end();
+    }
+    write!(screen, "{}", &text[shown..])
+}
+
+fn draw_bar(view: &ScrollView, scrollback: &Scrollback, screen: &mut Screen) -> io::Result<()> {
+    let height = page_height(screen);
+    let end = scrollback.end();
+    let mut bar = match &view.typing {
+        Some((true, pattern)) => format!("/{}", pattern),
+        Some((false, pattern)) => format!("?{}", pattern),
+        None => {
+            let last = (view.top + height).min(end);
+            let mut bar = format!(
+                "SCROLLBACK lines {}-{} of {}",
+                view.top.saturating_sub(scrollback.first()) + 1,
+                last.saturating_sub(scrollback.first()),
+                end - scrollback.first()
+            );
+            let new_lines = scrollback.total - view.lines_at_start;
+            if new_lines > 0 {
+                bar.push_str(&format!(", {} new lines", new_lines));
+            }
+            match &view.message {
+                Some(message) => bar.push_str(&format!("    [{}]", message)),
+                None => bar.push_str("    arrows PgUp PgDn, / ? n N search, q leaves"),
+            }
+            bar
+        }
+    };
+    let cols = term::backend().size().map_or(80, |(cols, _)| cols) as usize;
+    bar = bar.chars().take(cols).collect();
+    write!(
+        screen,
+        "{}{}\x1b[?7l{}{}{}\x1b[?7h",
+        Goto(1, height as u16 + 1),
+        ansi::CLEAR_LINE,
+        ansi::INVERT,
+        bar,
+        ansi::NO_INVERT
+    )
+}
+
+// Back to the live output: what it showed at the start, then what arrived meanwhile
+fn leave(view: ScrollView, session: &mut Session, screen: &mut Screen) -> NextStep {
+    let mut result = restore_output(
+        &session.scrollback,
+        screen,
+        view.lines_at_start,
+        &view.partial_at_start,
+    );
+    if !view.paused_before {
+        result = result.and_then(|()| screen.unpause());
+    }
+    match result {
+        Ok(()) => NextStep::None,
+        Err(err) => screen_error(err),
+    }
+}
+
+// Draw the output again as it was when a view was drawn over it, from the kept lines up to
+// `lines` and the `partial` line after them
+pub fn restore_output(
+    scrollback: &Scrollback,
+    screen: &mut Screen,
+    lines: u64,
+    partial: &[u8],
+) -> io::Result<()> {
+    let height = page_height(screen) + 1;
+    let start = lines.saturating_sub(height - 1).max(scrollback.first());
+    screen.clear_output()?;
+    for index in start..lines {
+        if let Some(line) = scrollback.line(index) {
+            write!(screen, "{}\r\n", String::from_utf8_lossy(line))?;
+        }
+    }
+    screen.write_all(partial)?;
+    screen.flush()
+}
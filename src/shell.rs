@@ -0,0 +1,123 @@
+use std::env;
+use std::ffi::OsString;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::listen::ClientEvent;
+use crate::screen::Screen;
+use crate::{
+    display_received, next_event, port_error, screen_error, send_input, write_status, Event,
+    NextStep, Session,
+};
+
+// How often the main thread looks whether the shell exited
+const WAIT_INTERVAL: Duration = Duration::from_millis(50);
+
+// Run $SHELL on the terminal in its original mode, the port staying open. What arrives
+// meanwhile goes to the log, the capture and the clients as usual, the screen part is kept
+// for ~v.
+pub fn run_shell(session: &mut Session, screen: &mut Screen, rx: &Receiver<Event>) -> NextStep {
+    let shell = env::var_os("SHELL")
+        .filter(|shell| !shell.is_empty())
+        .unwrap_or_else(|| OsString::from("/bin/sh"));
+    // the stdin thread leaves the terminal alone until the shell is done, a read it already
+    // started ends first
+    set_stdin_paused(session, true);
+    let waited = match screen.suspend() {
+        Ok(()) => wait_for_shell(&shell, session, screen, rx),
+        Err(err) => Ok(screen_error(err)),
+    };
+    set_stdin_paused(session, false);
+
+    match screen.resume() {
+        Ok(held_back) => session.held_back = held_back,
+        Err(err) => return screen_error(err),
+    }
+    let next_step = match waited {
+        Ok(next_step) => next_step,
+        Err(err) => {
+            let msg = format!("starting {} failed: {}", shell.to_string_lossy(), err);
+            write_status(screen, &msg);
+            NextStep::None
+        }
+    };
+    if !session.held_back.is_empty() {
+        let msg = format!(
+            "{} received while in the shell, ~v shows it",
+            format_size(session.held_back.len())
+        );
+        write_status(screen, &msg);
+    }
+    next_step
+}
+
+fn set_stdin_paused(session: &Session, paused: bool) {
+    *session
+        .stdin_paused
+        .lock()
+        .unwrap_or_else(|err| err.into_inner()) = paused;
+}
+
+// Err if the shell could not be started
+fn wait_for_shell(
+    shell: &OsString,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> io::Result<NextStep> {
+    eprintln!(
+        "[in {}, the session continues once it exits]",
+        shell.to_string_lossy()
+    );
+    let mut command = Command::new(shell);
+    // the signal thread of this process takes SIGINT, SIGTERM and SIGWINCH, the shell has to
+    // get them
+    unsafe {
+        command.pre_exec(|| {
+            unblock_signals();
+            Ok(())
+        });
+    }
+    let mut child = command.spawn()?;
+
+    let mut next_step = NextStep::None;
+    while let Ok(None) = child.try_wait() {
+        // once the port failed there is nothing left to receive
+        if !matches!(next_step, NextStep::None) {
+            thread::sleep(WAIT_INTERVAL);
+            continue;
+        }
+        next_step = match next_event(session, screen, rx, Some(Instant::now() + WAIT_INTERVAL)) {
+            Some(Event::Serial(_, data, _)) => display_received(session, screen, &data),
+            Some(Event::SerialError(_, err)) => port_error(err),
+            Some(Event::Client(ClientEvent::Data(_, input))) => send_input(session, screen, &input),
+            // Ctrl-C in the shell reaches this process too, it is meant for the shell
+            _ => NextStep::None,
+        };
+    }
+    Ok(next_step)
+}
+
+// Runs in the forked child, sigprocmask is async-signal-safe
+fn unblock_signals() {
+    unsafe {
+        let mut signals: libc::sigset_t = std::mem::zeroed();
+        libc::sigemptyset(&mut signals);
+        libc::sigaddset(&mut signals, libc::SIGINT);
+        libc::sigaddset(&mut signals, libc::SIGTERM);
+        libc::sigaddset(&mut signals, libc::SIGWINCH);
+        libc::sigprocmask(libc::SIG_UNBLOCK, &signals, std::ptr::null_mut());
+    }
+}
+
+fn format_size(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{} bytes", bytes)
+    } else {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    }
+}
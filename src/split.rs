@@ -0,0 +1,717 @@
+use std::io::{self, Write};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::ansi::{self, Goto};
+use crate::control::{ControlFilter, ControlStyle};
+use crate::error::ScipioError;
+use crate::escape::{self, key_state_machine, EscapeCommand, EscapeState, QuitKey};
+use crate::logfile::LogFile;
+use crate::newline::{NewlineMode, NewlineTranslator};
+use crate::screen::OutputSink;
+use crate::term;
+use crate::transport::{self, Device, PortSettings, Transport};
+use crate::utf8::{InvalidStyle, Utf8Filter};
+use crate::{
+    baud_rates, exit_with, fail, is_disconnect, log_format, log_rotation, open_lines,
+    open_retrying, parse_arguments_into_settings, second_device, setup_error, spawn_signal_thread,
+    spawn_stdin_thread, Event, NextStep, OpenTerminal, SessionConfig, EXIT_DISCONNECTED,
+    EXIT_INTERRUPTED, EXIT_IO_ERROR,
+};
+
+// How often the terminal size is looked at and a port of --reconnect is tried again
+const TICK: Duration = Duration::from_millis(250);
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+// What a paused pane keeps, the rest is dropped from the start
+const MAX_HELD_BACK: usize = 1 << 20;
+
+// One port of the split view with the rows it is shown in: a title row, then its output
+struct Pane {
+    device: Device,
+    settings: PortSettings,
+    port: Option<Box<dyn Transport>>,
+    reader_id: u64,
+    log: Option<LogFile>,
+    rx_newline: NewlineTranslator,
+    // the escape sequences are dropped and the other control characters shown, so they
+    // can't move the cursor out of the pane
+    control: ControlFilter,
+    utf8: Utf8Filter,
+    // the title row and how many rows of output follow it
+    title_row: u16,
+    rows: u16,
+    // row and column within the output, from 1, the column is one past the last while a wrap
+    // is due
+    cursor: (u16, u16),
+    // ~p, what came meanwhile and whether some was dropped
+    paused: Option<(Vec<u8>, bool)>,
+    // when a disconnected port of --reconnect is tried next
+    retry_at: Option<Instant>,
+    rx_bytes: u64,
+    tx_bytes: u64,
+}
+
+impl Pane {
+    fn new(
+        device: Device,
+        settings: PortSettings,
+        port: Box<dyn Transport>,
+        log: Option<LogFile>,
+        sc_args: &SessionConfig,
+    ) -> Pane {
+        let style = match sc_args.invalid_utf8.as_str() {
+            "hex" => InvalidStyle::Hex,
+            _ => InvalidStyle::Replacement,
+        };
+        Pane {
+            device,
+            settings,
+            port: Some(port),
+            reader_id: 0,
+            log,
+            rx_newline: NewlineTranslator::new(NewlineMode::from_arg(&sc_args.rx_newline)),
+            control: ControlFilter::new(Some((ControlStyle::Caret, false)), true),
+            utf8: Utf8Filter::new(style),
+            title_row: 1,
+            rows: 1,
+            cursor: (1, 1),
+            paused: None,
+            retry_at: None,
+            rx_bytes: 0,
+            tx_bytes: 0,
+        }
+    }
+
+    // Write `bytes`, which hold no escape sequences and no control characters but newline,
+    // carriage return and tab, in the rows of the pane
+    fn show(&mut self, out: &mut dyn Write, bytes: &[u8], cols: u16) -> io::Result<()> {
+        let (first, last) = (self.title_row + 1, self.title_row + self.rows);
+        // setting the scrolling region moves the cursor home
+        write!(out, "\x1b[{};{}r", first, last)?;
+        let (row, col) = self.cursor;
+        write!(out, "{}", Goto(col.min(cols), first + row - 1))?;
+        // the wrap that was due, the cursor was put on the last column
+        if col > cols {
+            out.write_all(b"\r\n")?;
+            self.cursor = ((row + 1).min(self.rows), 1);
+        }
+        out.write_all(bytes)?;
+        for &byte in bytes {
+            self.advance(byte, cols);
+        }
+        Ok(())
+    }
+
+    fn advance(&mut self, byte: u8, cols: u16) {
+        let (row, col) = &mut self.cursor;
+        match byte {
+            b'\r' => *col = 1,
+            // at the bottom the pane scrolls
+            b'\n' => *row = (*row + 1).min(self.rows),
+            b'\t' => *col = ((*col - 1) / 8 * 8 + 9).min(cols),
+            // UTF-8 continuation bytes take no column of their own
+            0x80..=0xbf => {}
+            _ => {
+                if *col > cols {
+                    *row = (*row + 1).min(self.rows);
+                    *col = 1;
+                }
+                *col += 1;
+            }
+        }
+    }
+
+    // A message on a line of its own, like write_status
+    fn status(&mut self, out: &mut dyn Write, msg: &str, cols: u16) -> io::Result<()> {
+        let line = format!("\r\n[{}]\r\n", msg);
+        self.show(out, line.as_bytes(), cols)
+    }
+
+    fn title(&self, focused: bool, cols: u16) -> String {
+        let state = match (&self.port, &self.paused) {
+            (None, _) => " disconnected",
+            (Some(_), Some(_)) => " paused",
+            (Some(_), None) => "",
+        };
+        let keyboard = if focused { " keyboard" } else { "" };
+        let text = format!(
+            "── {} {}{}{} ",
+            self.device, self.settings.baud_rate, state, keyboard
+        );
+        let width = text.chars().count();
+        let fill = "─".repeat((cols as usize).saturating_sub(width));
+        let text: String = text.chars().take(cols as usize).collect();
+        if focused {
+            format!("{}{}{}{}", ansi::INVERT, text, fill, ansi::NO_INVERT)
+        } else {
+            format!("{}{}", text, fill)
+        }
+    }
+
+    fn description(&self) -> String {
+        format!(
+            "{}: sent {} B, received {} B",
+            self.device, self.tx_bytes, self.rx_bytes
+        )
+    }
+}
+
+// The title row and the number of output rows of each pane on a terminal of `rows`, the
+// lower pane gets the odd row
+fn pane_rows(rows: u16) -> [(u16, u16); 2] {
+    let rows = rows.max(4);
+    let top = (rows - 2) / 2;
+    [(1, top), (top + 2, rows - top - 2)]
+}
+
+struct SplitView {
+    panes: [Pane; 2],
+    focus: usize,
+    terminal: Box<dyn OutputSink>,
+    // the size the panes were laid out for, columns and rows
+    size: (u16, u16),
+    next_reader_id: u64,
+    read_buffer: usize,
+    break_duration: Duration,
+    // the pane whose port is sending a break, until when
+    break_until: Option<(usize, Instant)>,
+}
+
+impl SplitView {
+    // Split the terminal in two, the output of both panes starts again at the top
+    fn layout(&mut self) -> io::Result<()> {
+        let (cols, rows) = term::backend().size()?;
+        self.size = (cols, rows);
+        for (pane, (title_row, rows)) in self.panes.iter_mut().zip(pane_rows(rows)) {
+            pane.title_row = title_row;
+            pane.rows = rows;
+            pane.cursor = (1, 1);
+        }
+        write!(self.terminal, "\x1b[r{}", ansi::CLEAR_ALL)?;
+        self.draw_titles()
+    }
+
+    fn draw_titles(&mut self) -> io::Result<()> {
+        let cols = self.size.0;
+        for (i, pane) in self.panes.iter().enumerate() {
+            // autowrap is off so the row is cut instead of scrolling the screen
+            write!(
+                self.terminal,
+                "\x1b[r{}\x1b[?7l{}\x1b[?7h",
+                Goto(1, pane.title_row),
+                pane.title(i == self.focus, cols)
+            )?;
+        }
+        // where the typing goes, the cursor is left
+        self.place_cursor()
+    }
+
+    fn place_cursor(&mut self) -> io::Result<()> {
+        let pane = &self.panes[self.focus];
+        let (row, col) = pane.cursor;
+        write!(
+            self.terminal,
+            "\x1b[{};{}r{}",
+            pane.title_row + 1,
+            pane.title_row + pane.rows,
+            Goto(col.min(self.size.0), pane.title_row + row)
+        )?;
+        self.terminal.flush()
+    }
+
+    fn status(&mut self, i: usize, msg: &str) -> io::Result<()> {
+        let cols = self.size.0;
+        self.panes[i].status(self.terminal.as_mut(), msg, cols)?;
+        self.place_cursor()
+    }
+
+    fn received(&mut self, i: usize, data: &[u8]) -> io::Result<()> {
+        let cols = self.size.0;
+        let pane = &mut self.panes[i];
+        pane.rx_bytes += data.len() as u64;
+        let mut log_error = None;
+        if let Some(log) = &mut pane.log {
+            if let Err(err) = log.write_rx(data) {
+                log_error = Some(format!(
+                    "stopped logging to {}: {}",
+                    log.path().display(),
+                    err
+                ));
+                pane.log = None;
+            }
+        }
+        let translated = pane.rx_newline.translate(data);
+        let filtered = pane.control.filter(&translated);
+        let shown = pane.utf8.filter(&filtered);
+        match &mut pane.paused {
+            Some((held, truncated)) => {
+                held.extend_from_slice(&shown);
+                if held.len() > MAX_HELD_BACK {
+                    let excess = held.len() - MAX_HELD_BACK;
+                    held.drain(..excess);
+                    *truncated = true;
+                }
+            }
+            None => pane.show(self.terminal.as_mut(), &shown, cols)?,
+        }
+        if let Some(msg) = log_error {
+            self.panes[i].status(self.terminal.as_mut(), &msg, cols)?;
+        }
+        self.place_cursor()
+    }
+
+    // Send typed bytes to the port of the focused pane, they are dropped while it is away
+    fn send(&mut self, data: &[u8]) -> io::Result<()> {
+        let i = self.focus;
+        let pane = &mut self.panes[i];
+        let port = match &mut pane.port {
+            Some(port) => port,
+            None => return Ok(()),
+        };
+        if let Err(err) = port.write_all(data).and_then(|()| port.flush()) {
+            let msg = format!("writing failed: {}", err);
+            return self.status(i, &msg);
+        }
+        pane.tx_bytes += data.len() as u64;
+        if let Some(log) = &mut pane.log {
+            if let Err(err) = log.write_tx(data) {
+                let msg = format!("stopped logging to {}: {}", log.path().display(), err);
+                pane.log = None;
+                return self.status(i, &msg);
+            }
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, command: EscapeCommand, escape_char: Option<u8>) -> io::Result<()> {
+        let i = self.focus;
+        match command {
+            EscapeCommand::SwitchPane => {
+                self.focus = 1 - self.focus;
+                self.draw_titles()
+            }
+            EscapeCommand::TogglePause => {
+                let cols = self.size.0;
+                let pane = &mut self.panes[i];
+                match pane.paused.take() {
+                    Some((held, truncated)) => {
+                        if truncated {
+                            pane.status(self.terminal.as_mut(), "output truncated", cols)?;
+                        }
+                        pane.show(self.terminal.as_mut(), &held, cols)?;
+                    }
+                    None => pane.paused = Some((Vec::new(), false)),
+                }
+                self.draw_titles()
+            }
+            EscapeCommand::SendBreak => match &mut self.panes[i].port {
+                Some(port) => match port.set_break() {
+                    Ok(()) => {
+                        self.break_until = Some((i, Instant::now() + self.break_duration));
+                        Ok(())
+                    }
+                    Err(err) => self.status(i, &format!("sending break failed: {}", err)),
+                },
+                None => Ok(()),
+            },
+            EscapeCommand::ShowHelp => {
+                let mut help = Vec::new();
+                escape::write_split_help(escape_char, &mut help)?;
+                let cols = self.size.0;
+                self.panes[i].show(self.terminal.as_mut(), &help, cols)?;
+                self.place_cursor()
+            }
+            _ => self.status(i, "not available in the split view"),
+        }
+    }
+
+    // Start reading the port of pane `i`
+    fn start_reader(&mut self, i: usize, tx: &Sender<Event>) -> io::Result<()> {
+        self.next_reader_id += 1;
+        let pane = &mut self.panes[i];
+        pane.reader_id = self.next_reader_id;
+        match &pane.port {
+            Some(port) => {
+                transport::spawn_reader(port.as_ref(), pane.reader_id, self.read_buffer, tx.clone())
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn pane_of(&self, reader_id: u64) -> Option<usize> {
+        self.panes
+            .iter()
+            .position(|pane| pane.port.is_some() && pane.reader_id == reader_id)
+    }
+}
+
+// Two devices: two ports at once, one above the other on the alternate screen. What a port
+// receives only goes to its pane and its log, the keyboard goes to the focused one, and a
+// port going away leaves the other running.
+pub fn run(
+    sc_args: &SessionConfig,
+    escape_char: Option<u8>,
+    quit_key: Option<QuitKey>,
+    open_terminal: OpenTerminal,
+) -> u8 {
+    let second_path = second_device(sc_args).unwrap_or_default();
+    let first_path = match &sc_args.device {
+        Some(path) => path.as_str(),
+        None => {
+            let msg = "a second device needs the first one given too".to_string();
+            return fail(ScipioError::InvalidArgument(msg));
+        }
+    };
+    if let Some(option) = single_port_option(sc_args) {
+        let msg = format!("{} doesn't work with two devices", option);
+        return fail(ScipioError::InvalidArgument(msg));
+    }
+    let first_settings = parse_arguments_into_settings(sc_args);
+    let mut second_settings = first_settings;
+    // parse_options checked the rates
+    if let Some(baud_rate) = baud_rates(sc_args)
+        .ok()
+        .and_then(|rates| rates.get(1).copied())
+    {
+        second_settings.baud_rate = baud_rate;
+    }
+
+    let retry_until = sc_args
+        .retry_open
+        .map(|secs| Instant::now() + Duration::from_secs(secs));
+    let log_format = log_format(sc_args);
+    let events = channel::<Event>();
+    if let Err(err) = spawn_signal_thread(events.0.clone(), false) {
+        return fail(setup_error("setting up signal handling".to_string(), err));
+    }
+    let mut panes = Vec::new();
+    let mut _locks = Vec::new();
+    for (path, settings, log) in [
+        (first_path, first_settings, &sc_args.log),
+        (second_path, second_settings, &sc_args.split_log),
+    ] {
+        let device = match Device::parse(path) {
+            Ok(device) => device,
+            Err(err) => return fail(ScipioError::InvalidDevice(err)),
+        };
+        let lines = open_lines(sc_args);
+        let opened = open_retrying(&device, &settings, &lines, retry_until, sc_args, &events.1);
+        let port = match opened {
+            Ok((port, lock)) => {
+                _locks.push(lock);
+                port
+            }
+            Err(exit) => return exit_with(exit),
+        };
+        let log = match log {
+            Some(template) => match LogFile::open(template, sc_args.log_truncate, log_format) {
+                Ok(mut log) => {
+                    log.set_rotation(log_rotation(sc_args));
+                    Some(log)
+                }
+                Err(err) => {
+                    let what = format!("opening log file {}", template);
+                    return fail(setup_error(what, err));
+                }
+            },
+            None => None,
+        };
+        panes.push(Pane::new(device, settings, port, log, sc_args));
+    }
+    let second = panes.pop().unwrap();
+    let first = panes.pop().unwrap();
+
+    let terminal = match open_terminal(true) {
+        Ok(terminal) => terminal,
+        Err(err) => return fail(setup_error("setting up the terminal".to_string(), err)),
+    };
+    let mut view = SplitView {
+        panes: [first, second],
+        focus: 0,
+        terminal,
+        size: (0, 0),
+        next_reader_id: 0,
+        read_buffer: sc_args.read_buffer,
+        break_duration: Duration::from_millis(sc_args.break_duration),
+        break_until: None,
+    };
+    let (code, message) = match run_view(&mut view, sc_args, escape_char, quit_key, &events) {
+        Ok(exit) => exit,
+        Err(err) => (EXIT_IO_ERROR, Some(ScipioError::Terminal(err).to_string())),
+    };
+
+    let _ = write!(view.terminal, "\x1b[r{}", ansi::TO_MAIN_SCREEN);
+    let _ = view.terminal.flush();
+    let SplitView {
+        panes, terminal, ..
+    } = view;
+    // raw mode is left with the terminal
+    drop(terminal);
+    if let Some(msg) = message {
+        eprintln!("{}", msg);
+    }
+    for pane in panes {
+        eprintln!("{}", pane.description());
+        if let Some(log) = pane.log {
+            let log_path = log.path().to_path_buf();
+            if let Err(err) = log.close() {
+                eprintln!("Error writing log file {}: {}", log_path.display(), err);
+            }
+        }
+    }
+    code
+}
+
+// The options of a session with one port that the split view has no place for
+fn single_port_option(sc_args: &SessionConfig) -> Option<&'static str> {
+    #[cfg(all(unix, feature = "pty"))]
+    if sc_args.pty.is_some() {
+        return Some("--pty");
+    }
+    [
+        (sc_args.daemon, "--daemon"),
+        (sc_args.detach, "--detach"),
+        (sc_args.pipe, "--pipe"),
+        (!sc_args.command.is_empty(), "--command"),
+        (sc_args.listen.is_some(), "--listen"),
+        (sc_args.auto_baud, "--auto-baud"),
+    ]
+    .into_iter()
+    .find_map(|(given, option)| given.then_some(option))
+}
+
+fn run_view(
+    view: &mut SplitView,
+    sc_args: &SessionConfig,
+    escape_char: Option<u8>,
+    quit_key: Option<QuitKey>,
+    events: &(Sender<Event>, Receiver<Event>),
+) -> io::Result<(u8, Option<String>)> {
+    write!(view.terminal, "{}", ansi::TO_ALTERNATE_SCREEN)?;
+    view.layout()?;
+    for i in 0..2 {
+        view.start_reader(i, &events.0)?;
+    }
+    spawn_stdin_thread(
+        events.0.clone(),
+        Arc::new(Mutex::new(false)),
+        sc_args.read_buffer,
+    );
+    let mut tx_newline = NewlineTranslator::new(NewlineMode::from_arg(&sc_args.newline));
+    let mut escape_state = EscapeState::WaitForEnter;
+    loop {
+        if term::backend().size()? != view.size {
+            view.layout()?;
+        }
+        if let Some((i, until)) = view.break_until {
+            if Instant::now() >= until {
+                view.break_until = None;
+                if let Some(port) = &mut view.panes[i].port {
+                    if let Err(err) = port.clear_break() {
+                        view.status(i, &format!("clearing break failed: {}", err))?;
+                    }
+                }
+            }
+        }
+        for i in 0..2 {
+            match view.panes[i].retry_at {
+                Some(at) if Instant::now() >= at => {}
+                _ => continue,
+            }
+            let pane = &mut view.panes[i];
+            match transport::open(&pane.device, &pane.settings, &open_lines(sc_args)) {
+                Ok(port) => {
+                    pane.port = Some(port);
+                    pane.retry_at = None;
+                    view.start_reader(i, &events.0)?;
+                    let msg = format!("reconnected to {}", view.panes[i].device);
+                    view.status(i, &msg)?;
+                    view.draw_titles()?;
+                }
+                Err(_) => pane.retry_at = Some(Instant::now() + RECONNECT_INTERVAL),
+            }
+        }
+
+        let event = match events.1.recv_timeout(TICK) {
+            Ok(event) => event,
+            Err(_) => continue,
+        };
+        match event {
+            Event::Serial(id, data, _) => {
+                if let Some(i) = view.pane_of(id) {
+                    view.received(i, &data)?;
+                }
+            }
+            Event::SerialError(id, err) => {
+                let i = match view.pane_of(id) {
+                    Some(i) => i,
+                    None => continue,
+                };
+                view.panes[i].port = None;
+                let msg = if is_disconnect(&err) {
+                    "device disconnected".to_string()
+                } else {
+                    format!("reading the port failed: {}", err)
+                };
+                if sc_args.reconnect {
+                    view.panes[i].retry_at = Some(Instant::now() + RECONNECT_INTERVAL);
+                    let msg = format!("{}, waiting for {}", msg, view.panes[i].device);
+                    view.status(i, &msg)?;
+                } else {
+                    view.status(i, &msg)?;
+                }
+                view.draw_titles()?;
+                if !sc_args.reconnect && view.panes.iter().all(|pane| pane.port.is_none()) {
+                    let msg = ScipioError::Disconnected.to_string();
+                    return Ok((EXIT_DISCONNECTED, Some(msg)));
+                }
+            }
+            Event::Stdin(data) => {
+                let mut typed = Vec::new();
+                for byte in &data {
+                    match key_state_machine(byte, escape_char, quit_key, &mut escape_state) {
+                        NextStep::None => typed.push(*byte),
+                        NextStep::Send(bytes) => typed.extend_from_slice(&bytes),
+                        NextStep::LoopBreak => {
+                            view.send(&tx_newline.translate(&typed))?;
+                            return Ok((0, None));
+                        }
+                        NextStep::Command(command) => {
+                            view.send(&tx_newline.translate(&typed))?;
+                            typed.clear();
+                            view.command(command, escape_char)?;
+                        }
+                        _ => {}
+                    }
+                }
+                view.send(&tx_newline.translate(&typed))?;
+            }
+            Event::StdinError(err) => return Err(err),
+            Event::Interrupted => return Ok((EXIT_INTERRUPTED, None)),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app;
+    use crate::transport::PortParity;
+    use serialport::{DataBits, FlowControl, StopBits};
+
+    fn pane(title_row: u16, rows: u16) -> Pane {
+        Pane {
+            device: Device::parse("/dev/ttyUSB1").unwrap(),
+            settings: PortSettings {
+                baud_rate: 921600,
+                data_bits: DataBits::Eight,
+                parity: PortParity::None,
+                stop_bits: StopBits::One,
+                flow_control: FlowControl::None,
+                timeout: Duration::from_millis(100),
+            },
+            port: None,
+            reader_id: 0,
+            log: None,
+            rx_newline: NewlineTranslator::new(NewlineMode::Raw),
+            control: ControlFilter::new(Some((ControlStyle::Caret, false)), true),
+            utf8: Utf8Filter::new(InvalidStyle::Replacement),
+            title_row,
+            rows,
+            cursor: (1, 1),
+            paused: None,
+            retry_at: None,
+            rx_bytes: 0,
+            tx_bytes: 0,
+        }
+    }
+
+    #[test]
+    fn the_panes_share_the_rows_below_their_titles() {
+        assert_eq!(pane_rows(24), [(1, 11), (13, 11)]);
+        assert_eq!(pane_rows(25), [(1, 11), (13, 12)]);
+        // a tiny terminal still gets a row for each
+        assert_eq!(pane_rows(2), [(1, 1), (3, 1)]);
+    }
+
+    #[test]
+    fn output_stays_in_the_rows_of_the_pane() {
+        let mut pane = pane(13, 11);
+        let mut out = Vec::new();
+        pane.show(&mut out, b"abc", 80).unwrap();
+        // the scrolling region is the pane's, the output starts below its title
+        let expected = format!("\x1b[14;24r{}abc", Goto(1, 14));
+        assert_eq!(out, expected.as_bytes());
+        assert_eq!(pane.cursor, (1, 4));
+
+        // the bottom row scrolls instead of leaving the pane
+        pane.show(&mut out, &[b'\n'; 20], 80).unwrap();
+        assert_eq!(pane.cursor, (11, 4));
+    }
+
+    #[test]
+    fn the_cursor_follows_the_text() {
+        let mut pane = pane(1, 5);
+        for &byte in b"ab\tc" {
+            pane.advance(byte, 20);
+        }
+        // the tab goes to column 9
+        assert_eq!(pane.cursor, (1, 10));
+        pane.advance(b'\r', 20);
+        assert_eq!(pane.cursor, (1, 1));
+        // the continuation bytes of é take no column
+        for &byte in "é".as_bytes() {
+            pane.advance(byte, 20);
+        }
+        assert_eq!(pane.cursor, (1, 2));
+    }
+
+    #[test]
+    fn a_full_row_wraps_with_the_next_character() {
+        let mut pane = pane(1, 5);
+        for &byte in b"abcd" {
+            pane.advance(byte, 4);
+        }
+        // the wrap is due, but waits for a character to need it
+        assert_eq!(pane.cursor, (1, 5));
+        pane.advance(b'e', 4);
+        assert_eq!(pane.cursor, (2, 2));
+    }
+
+    #[test]
+    fn the_title_fills_the_row_and_tells_the_state() {
+        let mut pane = pane(1, 5);
+        let title = pane.title(false, 50);
+        assert!(title.starts_with("── /dev/ttyUSB1 921600 disconnected ─"));
+        assert_eq!(title.chars().count(), 50);
+
+        pane.paused = Some((Vec::new(), false));
+        let focused = pane.title(true, 50);
+        assert!(focused.starts_with(ansi::INVERT));
+        assert!(focused.contains(" disconnected keyboard "));
+        // a narrow terminal cuts it
+        assert_eq!(pane.title(false, 10).chars().count(), 10);
+    }
+
+    #[test]
+    fn the_options_of_one_port_are_refused() {
+        let sc_args = |args: &[&str]| {
+            let args = ["scip", "--no-config", "/dev/ttyUSB0", "/dev/ttyUSB1"]
+                .iter()
+                .chain(args);
+            SessionConfig::from_matches(&app().try_get_matches_from(args).unwrap()).unwrap()
+        };
+        assert_eq!(
+            single_port_option(&sc_args(&["--listen", "0.0.0.0:7000"])),
+            Some("--listen")
+        );
+        assert_eq!(single_port_option(&sc_args(&["--pipe"])), Some("--pipe"));
+        assert_eq!(
+            single_port_option(&sc_args(&["--split-log", "trace.log"])),
+            None
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use std::time::{Duration, Instant};
+
+// What went over the port during the session, summed up on exit and, with
+// --stats-interval, shown as the throughput of the last interval
+pub struct Stats {
+    pub tx_bytes: u64,
+    pub rx_bytes: u64,
+    pub read_errors: u64,
+    pub write_errors: u64,
+    pub reconnects: u64,
+    // the bytes received with a framing or parity error, from a port that marks them
+    pub rx_errors: u64,
+    // the bytes that look like line noise, counted where the port doesn't mark errors
+    pub suspect_bytes: u64,
+    started: Instant,
+    interval: Option<Duration>,
+    // when the current interval began, and the byte counts then
+    interval_start: Instant,
+    interval_bytes: (u64, u64),
+}
+
+impl Stats {
+    pub fn new(interval: Option<Duration>) -> Stats {
+        let now = Instant::now();
+        Stats {
+            tx_bytes: 0,
+            rx_bytes: 0,
+            read_errors: 0,
+            write_errors: 0,
+            reconnects: 0,
+            rx_errors: 0,
+            suspect_bytes: 0,
+            started: now,
+            interval,
+            interval_start: now,
+            interval_bytes: (0, 0),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    // The time spent waiting for the device doesn't count into the next figure
+    pub fn reconnected(&mut self) {
+        self.reconnects += 1;
+        self.interval_start = Instant::now();
+        self.interval_bytes = (self.tx_bytes, self.rx_bytes);
+    }
+
+    // When the throughput of the current interval is due, None without --stats-interval
+    pub fn rate_due_at(&self) -> Option<Instant> {
+        self.interval.map(|interval| self.interval_start + interval)
+    }
+
+    // The throughput since the interval began like "TX 0 B/s  RX 1.2 KB/s" once it is
+    // due, starting the next interval
+    pub fn take_rate(&mut self) -> Option<String> {
+        self.take_rate_at(Instant::now())
+    }
+
+    fn take_rate_at(&mut self, now: Instant) -> Option<String> {
+        if self.rate_due_at().is_none_or(|due_at| now < due_at) {
+            return None;
+        }
+        let secs = now.duration_since(self.interval_start).as_secs_f64();
+        let (tx_start, rx_start) = self.interval_bytes;
+        let row = format!(
+            "TX {}/s  RX {}/s",
+            format_bytes(((self.tx_bytes - tx_start) as f64 / secs) as u64),
+            format_bytes(((self.rx_bytes - rx_start) as f64 / secs) as u64)
+        );
+        self.interval_start = now;
+        self.interval_bytes = (self.tx_bytes, self.rx_bytes);
+        Some(row)
+    }
+
+    // One line for the main screen after the session
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "Session {}: sent {}, received {}",
+            format_duration(self.elapsed()),
+            format_bytes(self.tx_bytes),
+            format_bytes(self.rx_bytes)
+        );
+        for (count, what) in [
+            (self.read_errors, "read error"),
+            (self.write_errors, "write error"),
+            (self.reconnects, "reconnect"),
+            (self.rx_errors, "framing or parity error"),
+            (self.suspect_bytes, "suspect byte"),
+        ] {
+            if count > 0 {
+                let plural = if count == 1 { "" } else { "s" };
+                summary.push_str(&format!(", {} {}{}", count, what, plural));
+            }
+        }
+        summary
+    }
+}
+
+// Like 00:01:02
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1048575 => format!("{:.1} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1048576.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_summary_has_the_counts_that_arent_zero() {
+        let mut stats = Stats::new(None);
+        assert_eq!(stats.summary(), "Session 00:00:00: sent 0 B, received 0 B");
+        stats.tx_bytes = 1536;
+        stats.rx_bytes = 3 * 1048576;
+        stats.read_errors = 1;
+        stats.reconnected();
+        stats.reconnected();
+        stats.suspect_bytes = 7;
+        assert_eq!(
+            stats.summary(),
+            "Session 00:00:00: sent 1.5 KB, received 3.0 MB, 1 read error, 2 reconnects, \
+             7 suspect bytes"
+        );
+    }
+
+    #[test]
+    fn sizes_and_durations() {
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(1024), "1.0 KB");
+        assert_eq!(format_bytes(1048575), "1024.0 KB");
+        assert_eq!(format_bytes(1048576), "1.0 MB");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "01:02:03");
+        assert_eq!(format_duration(Duration::from_millis(59_999)), "00:00:59");
+    }
+
+    #[test]
+    fn the_rate_is_of_the_last_interval() {
+        let mut stats = Stats::new(Some(Duration::from_secs(2)));
+        let start = stats.interval_start;
+        assert_eq!(stats.rate_due_at(), Some(start + Duration::from_secs(2)));
+        stats.rx_bytes = 2048;
+        assert_eq!(stats.take_rate_at(start + Duration::from_secs(1)), None);
+        let due = start + Duration::from_secs(2);
+        assert_eq!(stats.take_rate_at(due).unwrap(), "TX 0 B/s  RX 1.0 KB/s");
+        // the next interval counts from there
+        stats.tx_bytes = 100;
+        stats.rx_bytes += 10;
+        let next = due + Duration::from_secs(4);
+        assert_eq!(stats.take_rate_at(next).unwrap(), "TX 25 B/s  RX 2 B/s");
+        assert_eq!(stats.rate_due_at(), Some(next + Duration::from_secs(2)));
+        assert_eq!(Stats::new(None).take_rate(), None);
+    }
+}
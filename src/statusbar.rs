@@ -0,0 +1,125 @@
+use std::time::{Duration, Instant};
+
+use serialport::StopBits;
+
+use crate::info::data_bits;
+use crate::stats::{format_bytes, format_duration};
+use crate::transport::PortParity;
+use crate::Session;
+
+// How often the bar is brought up to date at most
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+// The bottom row of --status-bar, like
+// "/dev/ttyUSB0 @ 115200 8N1 | RX 12.3 KB | TX 0.4 KB | 00:12:41 | DTR RTS CTS | LOG"
+pub struct StatusBar {
+    next_draw: Instant,
+    // received control characters are shown as hex
+    hex: bool,
+    shown: Option<String>,
+}
+
+impl StatusBar {
+    pub fn new(hex: bool) -> StatusBar {
+        StatusBar {
+            next_draw: Instant::now(),
+            hex,
+            shown: None,
+        }
+    }
+
+    pub fn due_at(&self) -> Instant {
+        self.next_draw
+    }
+
+    // The bar as it was rendered last
+    pub fn shown(&self) -> Option<&str> {
+        self.shown.as_deref()
+    }
+}
+
+// Render the bar of `session` once it is due, returning whether it looks different now
+pub fn update(session: &mut Session) -> bool {
+    let due = session
+        .status_bar
+        .as_ref()
+        .is_some_and(|bar| Instant::now() >= bar.next_draw);
+    if !due {
+        return false;
+    }
+    let row = render(session);
+    match &mut session.status_bar {
+        Some(bar) => {
+            bar.next_draw = Instant::now() + REDRAW_INTERVAL;
+            if bar.shown.as_ref() == Some(&row) {
+                return false;
+            }
+            bar.shown = Some(row);
+            true
+        }
+        None => false,
+    }
+}
+
+fn render(session: &Session) -> String {
+    let settings = &session.settings;
+    let mut parts = vec![
+        format!(
+            "{} @ {} {}{}{}",
+            session.port_description(),
+            settings.baud_rate,
+            data_bits(settings.data_bits),
+            parity_letter(settings.parity),
+            match settings.stop_bits {
+                StopBits::One => 1,
+                StopBits::Two => 2,
+            }
+        ),
+        format!("RX {}", format_bytes(session.stats.rx_bytes)),
+        format!("TX {}", format_bytes(session.stats.tx_bytes)),
+        format_duration(session.stats.elapsed()),
+    ];
+
+    // the lines that are asserted
+    let mut lines: Vec<&str> = Vec::new();
+    if session.dtr {
+        lines.push("DTR");
+    }
+    if session.rts {
+        lines.push("RTS");
+    }
+    if let Some(monitor) = &session.line_monitor {
+        lines.extend(monitor.active());
+    }
+    if !lines.is_empty() {
+        parts.push(lines.join(" "));
+    }
+
+    let hex = session.status_bar.as_ref().is_some_and(|bar| bar.hex);
+    let indicators: Vec<&str> = [
+        (session.log.is_some(), "LOG"),
+        (session.capture.is_some(), "CAPTURE"),
+        (session.line_mode, "LINE"),
+        (hex, "HEX"),
+        (session.read_only, "READ-ONLY"),
+        (session.held_off, "XOFF"),
+    ]
+    .into_iter()
+    .filter(|(on, _)| *on)
+    .map(|(_, name)| name)
+    .collect();
+    if !indicators.is_empty() {
+        parts.push(indicators.join(" "));
+    }
+    parts.join(" | ")
+}
+
+fn parity_letter(parity: PortParity) -> char {
+    match parity {
+        PortParity::None => 'N',
+        PortParity::Odd => 'O',
+        PortParity::Even => 'E',
+        PortParity::Mark => 'M',
+        PortParity::Space => 'S',
+    }
+}
@@ -0,0 +1,171 @@
+use std::io::{self, stdout, Write};
+use std::panic;
+
+use crate::ansi;
+use crate::screen::OutputSink;
+
+// What scip needs from the terminal beyond the VT sequences of ansi.rs: raw mode and the
+// size. termion provides it on Unix, crossterm with the crossterm feature, which is the one
+// that builds on Windows.
+pub trait Backend: Sync {
+    // Stdout in raw mode, the mode it was found in is restored when it is dropped
+    fn raw_stdout(&self) -> io::Result<Box<dyn OutputSink>>;
+    // Columns and rows
+    fn size(&self) -> io::Result<(u16, u16)>;
+    // Called before raw_stdout, what a panic hook runs to restore the terminal
+    fn panic_restore(&self) -> Box<dyn Fn() + Send + Sync>;
+}
+
+#[cfg(not(any(feature = "crossterm", all(unix, feature = "termion"))))]
+compile_error!("scip needs a terminal backend, the termion feature on Unix or crossterm");
+
+#[cfg(feature = "crossterm")]
+pub fn backend() -> &'static dyn Backend {
+    &crossterm_backend::Crossterm
+}
+
+#[cfg(all(unix, feature = "termion", not(feature = "crossterm")))]
+pub fn backend() -> &'static dyn Backend {
+    &termion_backend::Termion
+}
+
+// The terminal of an interactive session, for run()
+pub fn open_terminal(alternate: bool) -> io::Result<Box<dyn OutputSink>> {
+    let backend = backend();
+    restore_terminal_on_panic(backend.panic_restore(), alternate);
+    backend.raw_stdout()
+}
+
+// The guards in run() only restore the terminal while unwinding, after the message
+// was already printed to the alternate screen. A panic in any thread ends the session.
+fn restore_terminal_on_panic(restore: Box<dyn Fn() + Send + Sync>, alternate: bool) {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let mut stdout = stdout();
+        if alternate {
+            let _ = write!(stdout, "{}", ansi::TO_MAIN_SCREEN);
+        }
+        let _ = stdout.flush();
+        restore();
+        default_hook(info);
+        std::process::exit(101);
+    }));
+}
+
+#[cfg(all(unix, feature = "termion", not(feature = "crossterm")))]
+mod termion_backend {
+    use std::io::{self, stdout, Stdout, Write};
+
+    use termion::raw::{IntoRawMode, RawTerminal};
+
+    use super::Backend;
+    use crate::screen::OutputSink;
+
+    pub struct Termion;
+
+    // Stdout in raw mode, left for the shell of ~z
+    struct RawStdout(RawTerminal<Stdout>);
+
+    impl Write for RawStdout {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    impl Backend for Termion {
+        fn raw_stdout(&self) -> io::Result<Box<dyn OutputSink>> {
+            Ok(Box::new(RawStdout(stdout().into_raw_mode()?)))
+        }
+
+        fn size(&self) -> io::Result<(u16, u16)> {
+            termion::terminal_size()
+        }
+
+        fn panic_restore(&self) -> Box<dyn Fn() + Send + Sync> {
+            let mut original: libc::termios = unsafe { std::mem::zeroed() };
+            if unsafe { libc::tcgetattr(libc::STDOUT_FILENO, &mut original) } != 0 {
+                return Box::new(|| {});
+            }
+            Box::new(move || unsafe {
+                libc::tcsetattr(libc::STDOUT_FILENO, libc::TCSANOW, &original);
+            })
+        }
+    }
+
+    impl OutputSink for RawStdout {
+        fn suspend(&mut self) -> io::Result<()> {
+            self.0.suspend_raw_mode()
+        }
+
+        fn resume(&mut self) -> io::Result<()> {
+            self.0.activate_raw_mode()
+        }
+    }
+}
+
+#[cfg(feature = "crossterm")]
+mod crossterm_backend {
+    use std::io::{self, stdout, Stdout, Write};
+
+    use crossterm::execute;
+    use crossterm::style::ResetColor;
+    use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+
+    use super::Backend;
+    use crate::screen::OutputSink;
+
+    pub struct Crossterm;
+
+    // Stdout, in raw mode until it is dropped or ~z runs a shell
+    struct RawStdout(Stdout);
+
+    impl Write for RawStdout {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.0.flush()
+        }
+    }
+
+    impl Backend for Crossterm {
+        fn raw_stdout(&self) -> io::Result<Box<dyn OutputSink>> {
+            let mut out = stdout();
+            // the first command switches a Windows console to VT processing
+            execute!(out, ResetColor)?;
+            enable_raw_mode()?;
+            Ok(Box::new(RawStdout(out)))
+        }
+
+        fn size(&self) -> io::Result<(u16, u16)> {
+            crossterm::terminal::size()
+        }
+
+        fn panic_restore(&self) -> Box<dyn Fn() + Send + Sync> {
+            Box::new(|| {
+                let _ = disable_raw_mode();
+            })
+        }
+    }
+
+    impl OutputSink for RawStdout {
+        fn suspend(&mut self) -> io::Result<()> {
+            disable_raw_mode()
+        }
+
+        fn resume(&mut self) -> io::Result<()> {
+            enable_raw_mode()
+        }
+    }
+
+    impl Drop for RawStdout {
+        fn drop(&mut self) {
+            let _ = disable_raw_mode();
+        }
+    }
+}
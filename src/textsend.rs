@@ -0,0 +1,343 @@
+use std::fs;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use regex::bytes::Regex;
+
+use crate::chat::{self, ChatError, Pattern};
+use crate::error::ScipioError;
+use crate::firmware::Firmware;
+use crate::stats::format_bytes;
+use crate::transfer::pause;
+use crate::{
+    ctrl_c_pressed, display_received, interrupted, next_event, port_error, show_status_row,
+    stdin_error, write_status, write_to_serial_port, Event, NextStep, Screen, Session,
+};
+
+// How --send-text and ~t turn a text file into typed lines
+pub struct TextSend {
+    // what ends every line sent
+    newline: Vec<u8>,
+    // removed from every line, a line left blank is not sent
+    strip: Option<Regex>,
+    // waited for after every line
+    prompt: Option<Pattern>,
+    prompt_timeout: Duration,
+}
+
+impl Default for TextSend {
+    fn default() -> TextSend {
+        TextSend {
+            newline: b"\r".to_vec(),
+            strip: None,
+            prompt: None,
+            prompt_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl TextSend {
+    // The values of --text-newline, --text-skip-regex and --text-wait-prompt
+    pub fn new(
+        newline: &str,
+        strip: Option<&str>,
+        prompt: Option<&str>,
+        prompt_timeout: Duration,
+    ) -> Result<TextSend, String> {
+        let newline = match newline {
+            "lf" => b"\n".to_vec(),
+            "crlf" => b"\r\n".to_vec(),
+            _ => b"\r".to_vec(),
+        };
+        let strip = match strip {
+            Some(pattern) => Some(
+                Regex::new(pattern)
+                    .map_err(|err| format!("in --text-skip-regex {}: {}", pattern, err))?,
+            ),
+            None => None,
+        };
+        let prompt = match prompt {
+            Some(prompt) => Some(Pattern::Substring(chat::parse_escapes(
+                prompt,
+                "--text-wait-prompt",
+            )?)),
+            None => None,
+        };
+        Ok(TextSend {
+            newline,
+            strip,
+            prompt,
+            prompt_timeout,
+        })
+    }
+
+    // The lines of `data` as they are sent, without their line endings
+    fn lines(&self, data: &[u8]) -> Vec<Vec<u8>> {
+        data.split(|&byte| byte == b'\n')
+            .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+            .map(|line| match &self.strip {
+                Some(strip) => strip.replace_all(line, &b""[..]).into_owned(),
+                None => line.to_vec(),
+            })
+            .filter(|line| !line.iter().all(u8::is_ascii_whitespace))
+            .collect()
+    }
+}
+
+// What to do after the prompt did not show up
+enum Answer {
+    Retry,
+    Skip,
+    Abort,
+}
+
+// Send the lines of a text file as if typed, waiting for the prompt after each one when
+// --text-wait-prompt is given. The progress is shown in the status row.
+pub fn send_text(
+    path: &str,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> NextStep {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) => {
+            let what = format!("sending {}", path);
+            let reason = err.to_string();
+            write_status(screen, &ScipioError::Transfer { what, reason }.to_string());
+            return NextStep::None;
+        }
+    };
+    let lines = session.text_send.lines(&data);
+    write_status(
+        screen,
+        &format!("sending {} ({} lines), Ctrl-C aborts", path, lines.len()),
+    );
+    let sent = send_lines(path, &lines, None, session, screen, rx);
+    session.chat_input = None;
+    session.progress_row = None;
+    show_status_row(session, screen);
+    match sent {
+        Ok(_) => {
+            write_status(screen, &format!("sent {} ({} lines)", path, lines.len()));
+            NextStep::None
+        }
+        Err(next_step) => next_step,
+    }
+}
+
+// Send the records of an Intel HEX or S-record file checked by firmware::parse like
+// --send-text sends lines. A record whose prompt doesn't show up is sent again up to
+// `retries` times, then the session ends with an error.
+pub fn send_firmware(
+    path: &str,
+    firmware: &Firmware,
+    retries: u32,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> NextStep {
+    write_status(
+        screen,
+        &format!(
+            "sending {} ({} records, {} at {}), Ctrl-C aborts",
+            path,
+            firmware.records.len(),
+            format_bytes(firmware.data_bytes),
+            firmware.range_text()
+        ),
+    );
+    let started = Instant::now();
+    let sent = send_lines(path, &firmware.records, Some(retries), session, screen, rx);
+    session.chat_input = None;
+    session.progress_row = None;
+    show_status_row(session, screen);
+    match sent {
+        Ok(retried) => {
+            write_status(
+                screen,
+                &format!(
+                    "sent {}: {} records, {} in {:.1} s, {} retries",
+                    path,
+                    firmware.records.len(),
+                    format_bytes(firmware.data_bytes),
+                    started.elapsed().as_secs_f64(),
+                    retried
+                ),
+            );
+            NextStep::None
+        }
+        Err(next_step) => next_step,
+    }
+}
+
+// Send `lines`, returning how many were sent again. A timed out prompt is asked about, or
+// with `retries` the line is sent again that many times.
+fn send_lines(
+    path: &str,
+    lines: &[Vec<u8>],
+    retries: Option<u32>,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> Result<u32, NextStep> {
+    let mut reported_tenths: usize = 0;
+    let mut retried: u32 = 0;
+    // of the current line
+    let mut attempts: u32 = 0;
+    let mut i = 0;
+    while i < lines.len() {
+        let progress = format!("sending {}: line {} of {}", path, i + 1, lines.len());
+        if screen.is_terminal() {
+            session.progress_row = Some(progress);
+            show_status_row(session, screen);
+        } else if i * 10 / lines.len() > reported_tenths {
+            reported_tenths = i * 10 / lines.len();
+            write_status(screen, &progress);
+        }
+
+        // only what the device answers to this line can be its prompt
+        if session.text_send.prompt.is_some() {
+            session.chat_input = Some(Vec::new());
+        }
+        let mut line = lines[i].clone();
+        line.extend_from_slice(&session.text_send.newline);
+        match type_line(&line, session, screen, rx) {
+            Ok(false) => {}
+            Ok(true) => return Err(aborted(path, i, lines.len(), screen)),
+            Err(next_step) => return Err(next_step),
+        }
+
+        let timeout = session.text_send.prompt_timeout;
+        let waited = match session.text_send.prompt.take() {
+            Some(prompt) => {
+                let waited = chat::expect(&prompt, timeout, session, screen, rx);
+                session.text_send.prompt = Some(prompt);
+                waited
+            }
+            None => Ok(()),
+        };
+        match waited {
+            Ok(()) => {}
+            Err(ChatError::Timeout(prompt)) => {
+                let msg = format!(
+                    "no {} within {} s after line {}",
+                    prompt,
+                    timeout.as_secs(),
+                    i + 1
+                );
+                let answer = match retries {
+                    Some(retries) if attempts < retries => {
+                        write_status(screen, &format!("{}, sending it again", msg));
+                        Ok(Answer::Retry)
+                    }
+                    Some(retries) => {
+                        let what = format!("sending {}", path);
+                        let reason = format!("{}, sent it {} times", msg, retries + 1);
+                        let err = ScipioError::Transfer { what, reason };
+                        return Err(NextStep::Exit(err.into()));
+                    }
+                    None => ask(&msg, session, screen, rx),
+                };
+                match answer {
+                    Ok(Answer::Retry) => {
+                        attempts += 1;
+                        retried += 1;
+                        continue;
+                    }
+                    Ok(Answer::Skip) => {}
+                    Ok(Answer::Abort) => return Err(aborted(path, i, lines.len(), screen)),
+                    Err(next_step) => return Err(next_step),
+                }
+            }
+            Err(ChatError::Cancelled) => return Err(aborted(path, i, lines.len(), screen)),
+            Err(ChatError::Failed(reason)) => {
+                let what = format!("sending {}", path);
+                let err = ScipioError::Transfer { what, reason };
+                return Err(NextStep::Exit(err.into()));
+            }
+            Err(ChatError::Disconnected) => return Err(NextStep::Disconnected),
+            Err(ChatError::Stopped(next_step)) => return Err(next_step),
+        }
+        attempts = 0;
+        i += 1;
+    }
+    Ok(retried)
+}
+
+// Write a line with the pauses of --char-delay and --line-delay, returning whether the
+// user pressed Ctrl-C
+fn type_line(
+    line: &[u8],
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> Result<bool, NextStep> {
+    let chunks: Vec<&[u8]> = if session.char_delay.is_zero() {
+        vec![line]
+    } else {
+        line.chunks(1).collect()
+    };
+    for chunk in chunks {
+        match write_to_serial_port(session, screen, chunk) {
+            NextStep::None => {}
+            next_step => return Err(next_step),
+        }
+        if pause(session, screen, rx, session.char_delay)? {
+            return Ok(true);
+        }
+    }
+    pause(session, screen, rx, session.line_delay)
+}
+
+// Ask whether to send the line again, go on with the next one or stop. Without a terminal
+// stdin is data, so the upload stops.
+fn ask(
+    msg: &str,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> Result<Answer, NextStep> {
+    if !screen.is_terminal() {
+        write_status(screen, msg);
+        return Ok(Answer::Abort);
+    }
+    write_status(
+        screen,
+        &format!("{}: r retries the line, s skips it, a aborts", msg),
+    );
+    loop {
+        match next_event(session, screen, rx, None) {
+            Some(Event::Serial(_, data, _)) => match display_received(session, screen, &data) {
+                NextStep::None => {}
+                next_step => return Err(next_step),
+            },
+            Some(Event::Stdin(data)) => {
+                let keys = &data[..];
+                if ctrl_c_pressed(screen, keys) {
+                    return Ok(Answer::Abort);
+                }
+                for key in keys {
+                    match key.to_ascii_lowercase() {
+                        b'r' => return Ok(Answer::Retry),
+                        b's' => return Ok(Answer::Skip),
+                        b'a' => return Ok(Answer::Abort),
+                        _ => {}
+                    }
+                }
+            }
+            Some(Event::StdinError(err)) => return Err(stdin_error(err)),
+            Some(Event::Interrupted) => return Err(interrupted()),
+            Some(Event::SerialError(_, err)) => return Err(port_error(err)),
+            _ => {}
+        }
+    }
+}
+
+fn aborted(path: &str, line: usize, lines: usize, screen: &mut Screen) -> NextStep {
+    write_status(
+        screen,
+        &format!("sending {} aborted at line {} of {}", path, line + 1, lines),
+    );
+    NextStep::None
+}
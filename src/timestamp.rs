@@ -0,0 +1,203 @@
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+
+use crate::stats::format_duration;
+
+// How often the stopwatch on the status row is brought up to date
+const STOPWATCH_INTERVAL: Duration = Duration::from_millis(100);
+
+pub enum TimestampMode {
+    // Wall clock time of day
+    Wall,
+    // Seconds since the session started
+    Relative,
+    // Time since the previous line started
+    Delta,
+    // Wall clock time and the time since the previous line
+    Both,
+}
+
+// Prefixes every received line with the time its first byte arrived
+pub struct LineStamper {
+    mode: TimestampMode,
+    start: Instant,
+    // when the previous line started, the first one counts from connecting
+    last_line: Instant,
+    at_line_start: bool,
+    after_cr: bool,
+}
+
+impl LineStamper {
+    pub fn new(mode: TimestampMode) -> LineStamper {
+        LineStamper {
+            mode,
+            start: Instant::now(),
+            last_line: Instant::now(),
+            at_line_start: true,
+            after_cr: false,
+        }
+    }
+
+    // Lines end with '\n' or a lone '\r', and may span several chunks
+    pub fn stamp(&mut self, data: &[u8]) -> Vec<u8> {
+        self.stamp_at(data, Instant::now())
+    }
+
+    // stamp() of data that arrived at `now`
+    fn stamp_at(&mut self, data: &[u8], now: Instant) -> Vec<u8> {
+        let mut stamped = Vec::with_capacity(data.len() + 16);
+        for &byte in data {
+            // the '\n' of "\r\n" belongs to the line that just ended
+            let continues_crlf = byte == b'\n' && self.after_cr;
+            if self.at_line_start && !continues_crlf {
+                let prefix = self.prefix(now);
+                stamped.extend_from_slice(prefix.as_bytes());
+                self.at_line_start = false;
+            }
+            stamped.push(byte);
+            if byte == b'\n' || byte == b'\r' {
+                self.at_line_start = true;
+            }
+            self.after_cr = byte == b'\r';
+        }
+        stamped
+    }
+
+    fn prefix(&mut self, now: Instant) -> String {
+        let delta = now.duration_since(self.last_line);
+        self.last_line = now;
+        match self.mode {
+            TimestampMode::Wall => format!("[{}] ", Local::now().format("%H:%M:%S%.3f")),
+            TimestampMode::Relative => {
+                format!("[{:10.3}] ", now.duration_since(self.start).as_secs_f64())
+            }
+            TimestampMode::Delta => format!("[{}] ", format_delta(delta)),
+            TimestampMode::Both => format!(
+                "[{} {}] ",
+                Local::now().format("%H:%M:%S%.3f"),
+                format_delta(delta)
+            ),
+        }
+    }
+}
+
+// Like "+   3.2ms", and "+  12.35s" from 10 seconds on, both the same width
+pub fn format_delta(delta: Duration) -> String {
+    if delta < Duration::from_secs(10) {
+        format!("+{:7.1}ms", delta.as_secs_f64() * 1000.0)
+    } else {
+        format!("+{:8.2}s", delta.as_secs_f64())
+    }
+}
+
+// The stopwatch of ~T, shown on the status row while it runs
+pub struct Stopwatch {
+    started: Instant,
+    next_draw: Instant,
+}
+
+impl Stopwatch {
+    pub fn start() -> Stopwatch {
+        Stopwatch::started_at(Instant::now())
+    }
+
+    fn started_at(now: Instant) -> Stopwatch {
+        Stopwatch {
+            started: now,
+            next_draw: now,
+        }
+    }
+
+    pub fn due_at(&self) -> Instant {
+        self.next_draw
+    }
+
+    // The time it has run as the status row shows it, putting off the next update
+    pub fn show(&mut self) -> String {
+        self.show_at(Instant::now())
+    }
+
+    fn show_at(&mut self, now: Instant) -> String {
+        self.next_draw = now + STOPWATCH_INTERVAL;
+        let elapsed = now.duration_since(self.started);
+        let tenths = elapsed.subsec_millis() / 100;
+        format!("STOPWATCH {}.{}", format_duration(elapsed), tenths)
+    }
+
+    // The time it ran, to the millisecond, like 00:01:23.456
+    pub fn stop(self) -> String {
+        self.stop_at(Instant::now())
+    }
+
+    fn stop_at(self, now: Instant) -> String {
+        let elapsed = now.duration_since(self.started);
+        format!(
+            "{}.{:03}",
+            format_duration(elapsed),
+            elapsed.subsec_millis()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamper(mode: TimestampMode, start: Instant) -> LineStamper {
+        LineStamper {
+            start,
+            last_line: start,
+            ..LineStamper::new(mode)
+        }
+    }
+
+    #[test]
+    fn deltas_keep_their_width() {
+        assert_eq!(format_delta(Duration::from_micros(3200)), "+    3.2ms");
+        assert_eq!(format_delta(Duration::from_millis(9999)), "+ 9999.0ms");
+        assert_eq!(format_delta(Duration::from_millis(12345)), "+   12.35s");
+        assert_eq!(
+            format_delta(Duration::ZERO).len(),
+            format_delta(Duration::from_secs(99)).len()
+        );
+    }
+
+    #[test]
+    fn a_delta_is_the_time_since_the_previous_line_started() {
+        let start = Instant::now();
+        let mut stamper = stamper(TimestampMode::Delta, start);
+        let at = |ms| start + Duration::from_millis(ms);
+        assert_eq!(stamper.stamp_at(b"one\r", at(5)), b"[+    5.0ms] one\r");
+        // the \n of \r\n ends the line that was stamped
+        assert_eq!(stamper.stamp_at(b"\ntw", at(20)), b"\n[+   15.0ms] tw");
+        assert_eq!(stamper.stamp_at(b"o\n", at(500)), b"o\n");
+        assert_eq!(stamper.stamp_at(b"x", at(12520)), b"[+   12.50s] x");
+    }
+
+    #[test]
+    fn relative_and_both_stamps() {
+        let start = Instant::now();
+        let mut relative = stamper(TimestampMode::Relative, start);
+        let stamped = relative.stamp_at(b"a\n", start + Duration::from_millis(1500));
+        assert_eq!(stamped, b"[     1.500] a\n");
+        let mut both = stamper(TimestampMode::Both, start);
+        let stamped = both.stamp_at(b"a", start + Duration::from_millis(42));
+        let stamped = String::from_utf8(stamped).unwrap();
+        // [HH:MM:SS.mmm +   42.0ms] a
+        assert_eq!(stamped.len(), 1 + 12 + 1 + 10 + 2 + 1, "{}", stamped);
+        assert!(stamped.ends_with(" +   42.0ms] a"), "{}", stamped);
+    }
+
+    #[test]
+    fn the_stopwatch_shows_tenths_and_stops_at_milliseconds() {
+        let start = Instant::now();
+        let mut stopwatch = Stopwatch::started_at(start);
+        assert_eq!(stopwatch.due_at(), start);
+        let now = start + Duration::from_millis(83_456);
+        assert_eq!(stopwatch.show_at(now), "STOPWATCH 00:01:23.4");
+        assert_eq!(stopwatch.due_at(), now + STOPWATCH_INTERVAL);
+        let now = start + Duration::from_millis(3_723_007);
+        assert_eq!(stopwatch.stop_at(now), "01:02:03.007");
+    }
+}
@@ -0,0 +1,434 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use crate::ansi;
+use crate::error::ScipioError;
+use crate::record::Direction;
+use crate::txhold::{self, HeldKeys};
+use crate::xmodem::{self, Link, Progress};
+use crate::ymodem::{self, Outgoing};
+use crate::zmodem;
+use crate::{
+    display_received, interrupted, next_event, port_error, record, screen_error, stdin_error,
+    write_status, write_to_serial_port, Event, NextStep, Screen, Session,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Transfer {
+    XmodemSend,
+    XmodemReceive,
+    YmodemSend,
+}
+
+impl Transfer {
+    pub fn prompt(self) -> &'static str {
+        match self {
+            Transfer::XmodemSend => "XMODEM send file: ",
+            Transfer::XmodemReceive => "XMODEM receive to file: ",
+            Transfer::YmodemSend => "YMODEM send files: ",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Transfer::XmodemSend => "xmodem send",
+            Transfer::XmodemReceive => "xmodem receive",
+            Transfer::YmodemSend => "ymodem send",
+        }
+    }
+}
+
+// The session's port while a transfer owns the main loop
+struct PortLink<'a> {
+    session: &'a mut Session,
+    screen: &'a mut Screen,
+    rx: &'a Receiver<Event>,
+    label: &'static str,
+    received: VecDeque<u8>,
+    cancelled: bool,
+    // a reader thread stopped, the main loop has to handle this after the transfer
+    stopped: Option<NextStep>,
+}
+
+impl PortLink<'_> {
+    // Give up on the transfer, the terminal can't cancel it anymore
+    fn stop(&mut self, next_step: NextStep) {
+        self.cancelled = true;
+        self.stopped = Some(next_step);
+    }
+
+    fn hold_keys(&mut self, data: &[u8]) {
+        match txhold::handle_keys(self.session, self.screen, self.rx, data) {
+            HeldKeys::Kept => {}
+            HeldKeys::Abort => self.cancelled = true,
+            HeldKeys::Stop(next_step) => self.stop(next_step),
+        }
+    }
+}
+
+impl Link for PortLink<'_> {
+    fn read_byte(&mut self, timeout: Duration) -> io::Result<Option<u8>> {
+        let deadline = Instant::now() + timeout;
+        while self.received.is_empty() && !self.cancelled {
+            match next_event(self.session, self.screen, self.rx, Some(deadline)) {
+                Some(Event::Serial(_, data, _)) => {
+                    self.session.stats.rx_bytes += data.len() as u64;
+                    record(self.session, self.screen, Direction::Rx, &data);
+                    self.received.extend(&data);
+                }
+                Some(Event::Stdin(data)) => self.hold_keys(&data),
+                Some(Event::StdinError(err)) => self.stop(stdin_error(err)),
+                Some(Event::Interrupted) => self.stop(interrupted()),
+                Some(Event::SerialError(_, err)) => {
+                    let copy = io::Error::new(err.kind(), err.to_string());
+                    self.stopped = Some(port_error(err));
+                    return Err(copy);
+                }
+                // client input is dropped like keystrokes
+                Some(_) => {}
+                None => break,
+            }
+        }
+        Ok(self.received.pop_front())
+    }
+
+    fn write_all(&mut self, mut data: &[u8]) -> io::Result<()> {
+        let deadline = Instant::now() + self.session.write_timeout;
+        while !data.is_empty() {
+            match self.session.serial_port.write(data) {
+                Ok(n) => {
+                    self.session.stats.tx_bytes += n as u64;
+                    record(self.session, self.screen, Direction::Tx, &data[..n]);
+                    self.session.last_write = Instant::now();
+                    data = &data[n..];
+                }
+                Err(err) if err.kind() == io::ErrorKind::TimedOut => {}
+                Err(err) => return Err(err),
+            }
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "write timed out"));
+            }
+        }
+        Ok(())
+    }
+
+    fn cancelled(&mut self) -> bool {
+        // only look at what is already queued, the transfer has to go on otherwise
+        while !self.cancelled {
+            match next_event(self.session, self.screen, self.rx, Some(Instant::now())) {
+                Some(Event::Serial(_, data, _)) => {
+                    self.session.stats.rx_bytes += data.len() as u64;
+                    record(self.session, self.screen, Direction::Rx, &data);
+                    self.received.extend(&data);
+                }
+                Some(Event::Stdin(data)) => self.hold_keys(&data),
+                Some(Event::StdinError(err)) => self.stop(stdin_error(err)),
+                Some(Event::Interrupted) => self.stop(interrupted()),
+                Some(Event::SerialError(_, err)) => {
+                    self.stopped = Some(port_error(err));
+                    break;
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+        self.cancelled
+    }
+
+    fn progress(&mut self, progress: &Progress) {
+        let shown = write!(
+            self.screen,
+            "\r{}[{}: {} blocks, {} bytes, {} retries]",
+            ansi::CLEAR_LINE,
+            self.label,
+            progress.blocks,
+            progress.bytes,
+            progress.retries
+        )
+        .and_then(|()| self.screen.flush());
+        if let Err(err) = shown {
+            self.stop(screen_error(err));
+        }
+    }
+
+    fn status(&mut self, msg: &str) {
+        write_status(self.screen, msg);
+        if let Some(err) = self.screen.take_failure() {
+            self.stop(screen_error(err));
+        }
+    }
+}
+
+// Run a file transfer to completion, reporting the outcome on the status line
+pub fn run_transfer(
+    transfer: Transfer,
+    path: &str,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> NextStep {
+    let label = transfer.label();
+    let mut stopped: Option<NextStep> = None;
+    let result = match transfer {
+        Transfer::XmodemSend => File::open(path)
+            .map_err(|err| err.to_string())
+            .and_then(|file| {
+                let mut link = port_link(session, screen, rx, label);
+                let result = xmodem::send(&mut link, &mut BufReader::new(file))
+                    .map_err(|err| err.to_string());
+                stopped = link.stopped.take();
+                result
+            }),
+        Transfer::XmodemReceive => {
+            File::create(path)
+                .map_err(|err| err.to_string())
+                .and_then(|file| {
+                    let strip_eof = session.xmodem_strip_eof;
+                    let mut link = port_link(session, screen, rx, label);
+                    let result = xmodem::receive(&mut link, &mut BufWriter::new(file), strip_eof)
+                        .map_err(|err| err.to_string());
+                    stopped = link.stopped.take();
+                    result
+                })
+        }
+        Transfer::YmodemSend => outgoing_files(path).and_then(|files| {
+            let mut link = port_link(session, screen, rx, label);
+            let result = ymodem::send(&mut link, files).map_err(|err| err.to_string());
+            stopped = link.stopped.take();
+            result
+        }),
+    };
+    match result {
+        Ok(progress) => write_status(
+            screen,
+            &format!("{} {}: {} bytes done", label, path, progress.bytes),
+        ),
+        Err(reason) => {
+            let what = format!("{} {}", label, path);
+            write_status(screen, &ScipioError::Transfer { what, reason }.to_string());
+        }
+    }
+    stopped.unwrap_or(NextStep::None)
+}
+
+// The files of a space separated list, opened up front so a missing one doesn't stop the
+// batch half way
+fn outgoing_files(paths: &str) -> Result<Vec<Outgoing<BufReader<File>>>, String> {
+    let mut files = Vec::new();
+    for path in paths.split_whitespace() {
+        let file = File::open(path).map_err(|err| format!("{}: {}", path, err))?;
+        let metadata = file
+            .metadata()
+            .map_err(|err| format!("{}: {}", path, err))?;
+        let name = std::path::Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| format!("{}: not a file", path))?;
+        files.push(Outgoing {
+            name,
+            size: metadata.len(),
+            mtime: metadata.modified().ok(),
+            data: BufReader::new(file),
+        });
+    }
+    Ok(files)
+}
+
+// Receive a YMODEM batch the device sends into --download-dir
+pub fn receive_ymodem(
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> NextStep {
+    write_status(screen, "ymodem receive started, Ctrl-C aborts");
+    let dir = session.download_dir.clone();
+    let overwrite = session.overwrite;
+    let streaming = session.ymodem_g;
+    let label = if streaming {
+        "ymodem-g receive"
+    } else {
+        "ymodem receive"
+    };
+    let mut link = port_link(session, screen, rx, label);
+    let result = ymodem::receive(&mut link, &dir, overwrite, streaming);
+    let stopped = link.stopped.take();
+    match result {
+        Ok(files) => {
+            let names: Vec<String> = files
+                .iter()
+                .map(|file| format!("{} ({} bytes)", file.path.display(), file.bytes))
+                .collect();
+            match names.is_empty() {
+                true => write_status(screen, "ymodem receive: no files"),
+                false => write_status(screen, &format!("ymodem received {}", names.join(", "))),
+            }
+        }
+        Err(err) => {
+            let what = "ymodem receive".to_string();
+            let reason = err.to_string();
+            write_status(screen, &ScipioError::Transfer { what, reason }.to_string());
+        }
+    }
+    stopped.unwrap_or(NextStep::None)
+}
+
+// Take over from `sz` on the other side, `pending` holds its first bytes
+pub fn receive_zmodem(
+    pending: &[u8],
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> NextStep {
+    write_status(screen, "zmodem receive started, Ctrl-C aborts");
+    let dir = session.download_dir.clone();
+    let overwrite = session.overwrite;
+    let mut link = port_link(session, screen, rx, "zmodem receive");
+    let result = zmodem::receive(&mut link, pending, &dir, overwrite);
+    let stopped = link.stopped.take();
+    match result {
+        Ok(files) => {
+            let names: Vec<String> = files
+                .iter()
+                .map(|file| format!("{} ({} bytes)", file.path.display(), file.bytes))
+                .collect();
+            match names.is_empty() {
+                true => write_status(screen, "zmodem receive: no files"),
+                false => write_status(screen, &format!("zmodem received {}", names.join(", "))),
+            }
+        }
+        Err(err) => {
+            let what = "zmodem receive".to_string();
+            let reason = err.to_string();
+            write_status(screen, &ScipioError::Transfer { what, reason }.to_string());
+        }
+    }
+    stopped.unwrap_or(NextStep::None)
+}
+
+fn port_link<'a>(
+    session: &'a mut Session,
+    screen: &'a mut Screen,
+    rx: &'a Receiver<Event>,
+    label: &'static str,
+) -> PortLink<'a> {
+    // a terminal that fails stops the transfer before it starts
+    let stopped = write!(screen, "\r\n").err().map(screen_error);
+    PortLink {
+        session,
+        screen,
+        rx,
+        label,
+        received: VecDeque::new(),
+        cancelled: stopped.is_some(),
+        stopped,
+    }
+}
+
+// Stream a file to the port with the configured pacing while still showing RX data
+pub fn send_paced(
+    path: &str,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> NextStep {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) => {
+            let what = format!("sending {}", path);
+            let reason = err.to_string();
+            write_status(screen, &ScipioError::Transfer { what, reason }.to_string());
+            return NextStep::None;
+        }
+    };
+    match send_data(path, &data, session, screen, rx) {
+        Ok(_) => NextStep::None,
+        Err(next_step) => next_step,
+    }
+}
+
+// Send the contents of `path` with the --char-delay and --line-delay pauses, returning
+// whether all of it went before Ctrl-C
+pub fn send_data(
+    path: &str,
+    data: &[u8],
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> Result<bool, NextStep> {
+    write_status(
+        screen,
+        &format!("sending {} ({} bytes), Ctrl-C aborts", path, data.len()),
+    );
+
+    let chunks: Vec<&[u8]> = if !session.char_delay.is_zero() {
+        data.chunks(1).collect()
+    } else if !session.line_delay.is_zero() {
+        data.split_inclusive(|&byte| byte == b'\n').collect()
+    } else {
+        data.chunks(512).collect()
+    };
+    let mut sent: usize = 0;
+    let mut reported_tenths: usize = 0;
+    for chunk in chunks {
+        match write_to_serial_port(session, screen, chunk) {
+            NextStep::None => {}
+            next_step => return Err(next_step),
+        }
+        sent += chunk.len();
+        let tenths = sent * 10 / data.len();
+        if tenths > reported_tenths && sent < data.len() {
+            reported_tenths = tenths;
+            write_status(screen, &format!("sending {}: {}%", path, tenths * 10));
+        }
+
+        let mut delay = session.char_delay;
+        if chunk.last() == Some(&b'\n') {
+            delay += session.line_delay;
+        }
+        match pause(session, screen, rx, delay) {
+            Ok(false) => {}
+            Ok(true) => {
+                write_status(
+                    screen,
+                    &format!("sending {} aborted after {} bytes", path, sent),
+                );
+                return Ok(false);
+            }
+            Err(next_step) => return Err(next_step),
+        }
+    }
+    write_status(screen, &format!("sent {}", path));
+    Ok(true)
+}
+
+// Wait `delay` while showing RX data, returning whether the user pressed Ctrl-C
+pub fn pause(
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+    delay: Duration,
+) -> Result<bool, NextStep> {
+    let deadline = Instant::now() + delay;
+    // keystrokes other than Ctrl-C are dropped
+    while let Some(event) = next_event(session, screen, rx, Some(deadline)) {
+        match event {
+            Event::Serial(_, data, _) => match display_received(session, screen, &data) {
+                NextStep::None => {}
+                next_step => return Err(next_step),
+            },
+            Event::Stdin(data) => match txhold::handle_keys(session, screen, rx, &data) {
+                HeldKeys::Kept => {}
+                HeldKeys::Abort => return Ok(true),
+                HeldKeys::Stop(next_step) => return Err(next_step),
+            },
+            Event::StdinError(err) => return Err(stdin_error(err)),
+            Event::Interrupted => return Err(interrupted()),
+            Event::SerialError(_, err) => return Err(port_error(err)),
+            _ => {}
+        }
+    }
+    Ok(false)
+}
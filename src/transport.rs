@@ -0,0 +1,1112 @@
+//! The connection to the device: the Transport trait and the serial ports, sockets and FIFOs
+//! that implement it.
+
+use std::fmt;
+#[cfg(unix)]
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::RawFd;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, trace};
+use serialport::{
+    ClearBuffer, DataBits, FlowControl, Parity, SerialPort, SerialPortType, StopBits, UsbPortInfo,
+};
+
+use crate::info;
+use crate::modem::ModemLines;
+use crate::rfc2217::Rfc2217;
+use crate::rxerrors::ErrorMarks;
+use crate::Event;
+
+// The reader thread just reads again after this
+const READER_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The connection to the device, either a local serial port or one reached over the network.
+/// Received data is read by a thread of its own from the handle returned by reader().
+pub trait Transport: Write + Send {
+    fn set_break(&mut self) -> io::Result<()>;
+    fn clear_break(&mut self) -> io::Result<()>;
+    fn write_data_terminal_ready(&mut self, level: bool) -> io::Result<()>;
+    fn write_request_to_send(&mut self, level: bool) -> io::Result<()>;
+    fn set_baud_rate(&mut self, baud_rate: u32) -> io::Result<()>;
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> io::Result<()>;
+    fn set_data_bits(&mut self, data_bits: DataBits) -> io::Result<()>;
+    fn set_parity(&mut self, parity: PortParity) -> io::Result<()>;
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> io::Result<()>;
+    /// The settings the port is actually using
+    fn settings(&self) -> io::Result<PortSettings>;
+    /// A second handle to the port that blocks in read until data arrives
+    fn reader(&self) -> io::Result<Box<dyn Read + Send>>;
+    /// The device node, for a local serial port
+    fn local_path(&self) -> Option<&Path> {
+        None
+    }
+    /// CTS, DSR, DCD and RI, unknown where the transport can't tell
+    fn modem_lines(&mut self) -> ModemLines {
+        ModemLines::default()
+    }
+    /// Whether the reader gets the bytes received with an error marked, see ErrorMarks
+    fn marks_errors(&self) -> bool {
+        false
+    }
+    /// Drop what was received but not read yet, returning how many bytes that was
+    fn discard_input(&mut self) -> io::Result<u32> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "only a local port can be flushed",
+        ))
+    }
+}
+
+/// Send everything read from the port as Event::Serial until the port fails, with reads of up
+/// to `buf_size` bytes. The bytes received with an error are counted in Event::ReceiveErrors.
+pub fn spawn_reader(
+    port: &dyn Transport,
+    id: u64,
+    buf_size: usize,
+    tx: Sender<Event>,
+) -> io::Result<()> {
+    let mut reader = port.reader()?;
+    let mut buf = vec![0; buf_size];
+    let mut marks = port.marks_errors().then(ErrorMarks::new);
+    thread::spawn(move || loop {
+        let event = match reader.read(&mut buf) {
+            Ok(0) => continue,
+            Ok(n) => {
+                let at = Instant::now();
+                trace!("reader {}: read {} bytes", id, n);
+                let data = match &mut marks {
+                    Some(marks) => {
+                        let (data, errors) = marks.decode(&buf[..n]);
+                        if errors > 0 && tx.send(Event::ReceiveErrors(id, errors)).is_err() {
+                            return;
+                        }
+                        data
+                    }
+                    None => buf[..n].to_vec(),
+                };
+                if data.is_empty() {
+                    continue;
+                }
+                Event::Serial(id, data, at)
+            }
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::TimedOut
+                        | io::ErrorKind::WouldBlock
+                        | io::ErrorKind::Interrupted
+                ) =>
+            {
+                continue
+            }
+            Err(err) => {
+                debug!("reader {}: reading failed: {} ({:?})", id, err, err.kind());
+                let _ = tx.send(Event::SerialError(id, err));
+                return;
+            }
+        };
+        if tx.send(event).is_err() {
+            return;
+        }
+    });
+    Ok(())
+}
+
+#[derive(Clone)]
+pub enum Device {
+    Serial(String),
+    /// a USB adapter, looked up again every time the port is opened
+    Usb(UsbSelector),
+    /// raw TCP, host:port
+    Tcp(String),
+    /// Telnet with the RFC 2217 COM-PORT option, host:port
+    Rfc2217(String),
+    /// a Unix domain socket, like the one of QEMU's -serial unix:PATH,server
+    #[cfg(unix)]
+    Unix(PathBuf),
+    /// a FIFO to read from and one to write to
+    #[cfg(unix)]
+    Fifo {
+        rx: PathBuf,
+        tx: PathBuf,
+    },
+}
+
+impl Device {
+    pub fn parse(device: &str) -> Result<Device, String> {
+        if let Some(address) = device.strip_prefix("tcp://") {
+            Ok(Device::Tcp(address.to_string()))
+        } else if let Some(address) = device.strip_prefix("rfc2217://") {
+            Ok(Device::Rfc2217(address.to_string()))
+        } else if let Some(ids) = device.strip_prefix("usb:") {
+            // from_str_radix alone would take a sign
+            let parse_id = |id: &str| {
+                if id.chars().all(|c| c.is_ascii_hexdigit()) {
+                    u16::from_str_radix(id, 16).ok()
+                } else {
+                    None
+                }
+            };
+            match ids.split_once(':') {
+                Some((vid, pid)) => match (parse_id(vid), parse_id(pid)) {
+                    (Some(vid), Some(pid)) => Ok(Device::Usb(UsbSelector::Ids(vid, pid))),
+                    _ => Err(format!("{}: the IDs have to be hexadecimal", device)),
+                },
+                None => Err(format!(
+                    "{}: expected usb:VID:PID, e.g. usb:303a:1001",
+                    device
+                )),
+            }
+        } else if let Some(serial_number) = device.strip_prefix("serial:") {
+            if serial_number.is_empty() {
+                return Err(format!("{}: the serial number is missing", device));
+            }
+            Ok(Device::Usb(UsbSelector::SerialNumber(
+                serial_number.to_string(),
+            )))
+        } else {
+            #[cfg(unix)]
+            if let Some(stream) = Device::local_stream(device) {
+                return Ok(stream);
+            }
+            Ok(Device::Serial(device.to_string()))
+        }
+    }
+
+    /// --fifo RX:TX
+    #[cfg(unix)]
+    pub fn parse_fifo(pair: &str) -> Result<Device, String> {
+        match pair.split_once(':') {
+            Some((rx, tx)) if !rx.is_empty() && !tx.is_empty() => Ok(Device::Fifo {
+                rx: PathBuf::from(rx),
+                tx: PathBuf::from(tx),
+            }),
+            _ => Err(format!(
+                "{}: expected --fifo RX:TX, the FIFO to read from and the one to write to",
+                pair
+            )),
+        }
+    }
+
+    // An existing socket, or PATH.in and PATH.out as made for QEMU's -serial pipe:PATH, which
+    // reads PATH.in and writes PATH.out
+    #[cfg(unix)]
+    fn local_stream(path: &str) -> Option<Device> {
+        let file_type = |path: &Path| fs::metadata(path).ok().map(|meta| meta.file_type());
+        if file_type(Path::new(path)).is_some_and(|kind| kind.is_socket()) {
+            return Some(Device::Unix(PathBuf::from(path)));
+        }
+        let rx = PathBuf::from(format!("{}.out", path));
+        let tx = PathBuf::from(format!("{}.in", path));
+        if [&rx, &tx]
+            .iter()
+            .all(|fifo| file_type(fifo).is_some_and(|kind| kind.is_fifo()))
+        {
+            return Some(Device::Fifo { rx, tx });
+        }
+        None
+    }
+
+    pub fn is_remote(&self) -> bool {
+        matches!(self, Device::Tcp(_) | Device::Rfc2217(_))
+    }
+
+    /// A socket or FIFOs on this machine, which pass the bytes on without any port settings
+    pub fn is_local_stream(&self) -> bool {
+        #[cfg(unix)]
+        if matches!(self, Device::Unix(_) | Device::Fifo { .. }) {
+            return true;
+        }
+        false
+    }
+
+    /// The device node to wait for, if there is one
+    #[cfg(unix)]
+    pub fn local_path(&self) -> Option<&Path> {
+        match self {
+            Device::Serial(path) => Some(Path::new(path)),
+            _ => None,
+        }
+    }
+
+    /// COM7 and \\.\COM12 are no files to wait for, they are tried every reconnect delay
+    #[cfg(not(unix))]
+    pub fn local_path(&self) -> Option<&Path> {
+        None
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Device::Serial(path) => write!(f, "{}", path),
+            Device::Usb(selector) => write!(f, "{}", selector),
+            Device::Tcp(address) => write!(f, "tcp://{}", address),
+            Device::Rfc2217(address) => write!(f, "rfc2217://{}", address),
+            #[cfg(unix)]
+            Device::Unix(path) => write!(f, "{}", path.display()),
+            #[cfg(unix)]
+            Device::Fifo { rx, tx } => write!(f, "{}:{}", rx.display(), tx.display()),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct PortSettings {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: PortParity,
+    pub stop_bits: StopBits,
+    pub flow_control: FlowControl,
+    /// --timeout-ms, how long a write or a read during setup waits before returning TimedOut
+    pub timeout: Duration,
+}
+
+/// What the output lines are set to right after opening, before the first read
+#[derive(Clone, Copy, Default)]
+pub struct OpenLines {
+    pub dtr: Option<bool>,
+    pub rts: Option<bool>,
+    /// clear HUPCL, so closing the port leaves DTR and RTS as they are (Linux only)
+    pub keep_on_close: bool,
+}
+
+/// serialport only knows None, Odd and Even, Mark and Space are set up through termios
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PortParity {
+    None,
+    Odd,
+    Even,
+    /// the parity bit is always 1
+    Mark,
+    /// the parity bit is always 0
+    Space,
+}
+
+#[derive(Clone)]
+pub enum UsbSelector {
+    Ids(u16, u16),
+    SerialNumber(String),
+}
+
+impl UsbSelector {
+    fn matches(&self, usb: &UsbPortInfo) -> bool {
+        match self {
+            UsbSelector::Ids(vid, pid) => usb.vid == *vid && usb.pid == *pid,
+            UsbSelector::SerialNumber(serial_number) => {
+                usb.serial_number.as_deref() == Some(serial_number.as_str())
+            }
+        }
+    }
+
+    // The port of the only adapter that matches
+    fn resolve(&self) -> io::Result<String> {
+        let mut found: Vec<String> = serialport::available_ports()?
+            .into_iter()
+            .filter(|port| match &port.port_type {
+                SerialPortType::UsbPort(usb) => self.matches(usb),
+                _ => false,
+            })
+            .map(|port| port.port_name)
+            .collect();
+        match found.len() {
+            0 => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no USB serial port matches {}", self),
+            )),
+            1 => Ok(found.remove(0)),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} matches several ports: {}", self, found.join(", ")),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for UsbSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UsbSelector::Ids(vid, pid) => write!(f, "usb:{:04x}:{:04x}", vid, pid),
+            UsbSelector::SerialNumber(serial_number) => write!(f, "serial:{}", serial_number),
+        }
+    }
+}
+
+/// The port is returned with `lines` applied. Its reader isn't started yet, so nothing is
+/// read before. The kernel still asserts DTR and RTS while opening a local port.
+pub fn open(
+    device: &Device,
+    settings: &PortSettings,
+    lines: &OpenLines,
+) -> io::Result<Box<dyn Transport>> {
+    debug!(
+        "opening {}: {} baud, {} data bits, parity {}, {} stop bits, flow control {}, timeout {:?}, DTR {:?}, RTS {:?}, keep lines on close {}",
+        device,
+        settings.baud_rate,
+        info::data_bits(settings.data_bits),
+        info::parity(settings.parity),
+        info::stop_bits(settings.stop_bits),
+        info::flow_control(settings.flow_control),
+        settings.timeout,
+        lines.dtr,
+        lines.rts,
+        lines.keep_on_close
+    );
+    let port = open_transport(device, settings, lines);
+    match &port {
+        Ok(_) => debug!("opened {}", device),
+        Err(err) => debug!("opening {} failed: {} ({:?})", device, err, err.kind()),
+    }
+    port
+}
+
+fn open_transport(
+    device: &Device,
+    settings: &PortSettings,
+    lines: &OpenLines,
+) -> io::Result<Box<dyn Transport>> {
+    let keep = lines.keep_on_close;
+    let mut port: Box<dyn Transport> = match device {
+        Device::Serial(path) => Box::new(SerialTransport::open(path, settings, keep)?),
+        Device::Usb(selector) => {
+            let path = selector.resolve()?;
+            debug!("{} is {}", selector, path);
+            Box::new(SerialTransport::open(&path, settings, keep)?)
+        }
+        Device::Tcp(address) => Box::new(TcpTransport::connect(address, settings.timeout)?),
+        Device::Rfc2217(address) => Box::new(Rfc2217::connect(address, settings)?),
+        #[cfg(unix)]
+        Device::Unix(path) => Box::new(UnixTransport::connect(path, settings.timeout)?),
+        #[cfg(unix)]
+        Device::Fifo { rx, tx } => Box::new(FifoTransport::open(rx, tx)?),
+    };
+    set_lines(port.as_mut(), lines)?;
+    Ok(port)
+}
+
+// --dtr and --rts, before the reader is started
+fn set_lines(port: &mut dyn Transport, lines: &OpenLines) -> io::Result<()> {
+    if let Some(level) = lines.dtr {
+        port.write_data_terminal_ready(level)?;
+    }
+    if let Some(level) = lines.rts {
+        port.write_request_to_send(level)?;
+    }
+    Ok(())
+}
+
+/// A local serial port
+pub struct SerialTransport {
+    port: Box<dyn SerialPort>,
+    path: PathBuf,
+    // for the parity modes serialport can't express
+    #[cfg(target_os = "linux")]
+    fd: RawFd,
+}
+
+impl SerialTransport {
+    fn open(
+        path: &str,
+        settings: &PortSettings,
+        keep_on_close: bool,
+    ) -> io::Result<SerialTransport> {
+        let parity = match settings.parity {
+            PortParity::Odd => Parity::Odd,
+            PortParity::Even => Parity::Even,
+            // Mark and Space are switched on after opening
+            PortParity::None | PortParity::Mark | PortParity::Space => Parity::None,
+        };
+        let builder = serialport::new(path, settings.baud_rate)
+            .data_bits(settings.data_bits)
+            .parity(parity)
+            .stop_bits(settings.stop_bits)
+            .flow_control(settings.flow_control)
+            .timeout(settings.timeout);
+
+        #[cfg(target_os = "linux")]
+        {
+            let port = builder.open_native()?;
+            let fd = port.as_raw_fd();
+            if let PortParity::Mark | PortParity::Space = settings.parity {
+                set_stick_parity(fd, settings.parity == PortParity::Mark)?;
+            }
+            if keep_on_close {
+                clear_hupcl(fd)?;
+            }
+            mark_errors(fd, settings.parity != PortParity::None)?;
+            Ok(SerialTransport {
+                port: Box::new(port),
+                path: PathBuf::from(path),
+                fd,
+            })
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            if let PortParity::Mark | PortParity::Space = settings.parity {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Mark and Space parity are not supported on this platform",
+                ));
+            }
+            // the lines drop on close where HUPCL can't be reached
+            let _ = keep_on_close;
+            Ok(SerialTransport {
+                port: builder.open()?,
+                path: PathBuf::from(path),
+            })
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn parity(&self) -> io::Result<PortParity> {
+        let termios = get_termios(self.fd)?;
+        let flags = termios.c_cflag;
+        Ok(
+            match (
+                flags & libc::PARENB != 0,
+                flags & libc::CMSPAR != 0,
+                flags & libc::PARODD != 0,
+            ) {
+                (false, _, _) => PortParity::None,
+                (true, true, true) => PortParity::Mark,
+                (true, true, false) => PortParity::Space,
+                (true, false, true) => PortParity::Odd,
+                (true, false, false) => PortParity::Even,
+            },
+        )
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn parity(&self) -> io::Result<PortParity> {
+        Ok(match self.port.parity()? {
+            Parity::None => PortParity::None,
+            Parity::Odd => PortParity::Odd,
+            Parity::Even => PortParity::Even,
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn get_termios(fd: RawFd) -> io::Result<libc::termios> {
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(termios)
+}
+
+// Mark and Space parity are "stick" parity: CMSPAR makes PARODD pick the bit's value
+#[cfg(target_os = "linux")]
+fn set_stick_parity(fd: RawFd, mark: bool) -> io::Result<()> {
+    let mut termios = get_termios(fd)?;
+    termios.c_cflag |= libc::PARENB | libc::CMSPAR;
+    if mark {
+        termios.c_cflag |= libc::PARODD;
+    } else {
+        termios.c_cflag &= !libc::PARODD;
+    }
+    termios.c_iflag |= libc::INPCK;
+    termios.c_iflag &= !libc::IGNPAR;
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Back to parity that is computed, before switching to none, odd or even
+#[cfg(target_os = "linux")]
+fn clear_stick_parity(fd: RawFd) -> io::Result<()> {
+    let mut termios = get_termios(fd)?;
+    if termios.c_cflag & libc::CMSPAR == 0 {
+        return Ok(());
+    }
+    termios.c_cflag &= !libc::CMSPAR;
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// The driver marks the bytes received with a framing or parity error, and breaks, with
+// FF 00 in front, and doubles a real FF. INPCK has it check the parity bit as well.
+#[cfg(target_os = "linux")]
+fn mark_errors(fd: RawFd, check_parity: bool) -> io::Result<()> {
+    let mut termios = get_termios(fd)?;
+    termios.c_iflag |= libc::PARMRK;
+    termios.c_iflag &= !(libc::IGNPAR | libc::ISTRIP);
+    if check_parity {
+        termios.c_iflag |= libc::INPCK;
+    }
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// Without HUPCL the last close of the port doesn't drop DTR and RTS, so a board resetting
+// on DTR isn't reset by the next open either
+#[cfg(target_os = "linux")]
+fn clear_hupcl(fd: RawFd) -> io::Result<()> {
+    let mut termios = get_termios(fd)?;
+    termios.c_cflag &= !libc::HUPCL;
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl Write for SerialTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl Transport for SerialTransport {
+    fn set_break(&mut self) -> io::Result<()> {
+        Ok(self.port.set_break()?)
+    }
+
+    fn clear_break(&mut self) -> io::Result<()> {
+        Ok(self.port.clear_break()?)
+    }
+
+    fn write_data_terminal_ready(&mut self, level: bool) -> io::Result<()> {
+        Ok(self.port.write_data_terminal_ready(level)?)
+    }
+
+    fn write_request_to_send(&mut self, level: bool) -> io::Result<()> {
+        Ok(self.port.write_request_to_send(level)?)
+    }
+
+    fn set_baud_rate(&mut self, baud_rate: u32) -> io::Result<()> {
+        Ok(self.port.set_baud_rate(baud_rate)?)
+    }
+
+    fn set_flow_control(&mut self, flow_control: FlowControl) -> io::Result<()> {
+        Ok(self.port.set_flow_control(flow_control)?)
+    }
+
+    fn set_data_bits(&mut self, data_bits: DataBits) -> io::Result<()> {
+        Ok(self.port.set_data_bits(data_bits)?)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn set_parity(&mut self, parity: PortParity) -> io::Result<()> {
+        match parity {
+            PortParity::Mark | PortParity::Space => {
+                set_stick_parity(self.fd, parity == PortParity::Mark)?
+            }
+            PortParity::None | PortParity::Odd | PortParity::Even => {
+                clear_stick_parity(self.fd)?;
+                self.port.set_parity(match parity {
+                    PortParity::Odd => Parity::Odd,
+                    PortParity::Even => Parity::Even,
+                    _ => Parity::None,
+                })?;
+            }
+        }
+        mark_errors(self.fd, parity != PortParity::None)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn set_parity(&mut self, parity: PortParity) -> io::Result<()> {
+        let parity = match parity {
+            PortParity::None => Parity::None,
+            PortParity::Odd => Parity::Odd,
+            PortParity::Even => Parity::Even,
+            PortParity::Mark | PortParity::Space => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "Mark and Space parity are not supported on this platform",
+                ))
+            }
+        };
+        Ok(self.port.set_parity(parity)?)
+    }
+
+    fn set_stop_bits(&mut self, stop_bits: StopBits) -> io::Result<()> {
+        Ok(self.port.set_stop_bits(stop_bits)?)
+    }
+
+    fn settings(&self) -> io::Result<PortSettings> {
+        Ok(PortSettings {
+            baud_rate: self.port.baud_rate()?,
+            data_bits: self.port.data_bits()?,
+            parity: self.parity()?,
+            stop_bits: self.port.stop_bits()?,
+            flow_control: self.port.flow_control()?,
+            timeout: self.port.timeout(),
+        })
+    }
+
+    fn reader(&self) -> io::Result<Box<dyn Read + Send>> {
+        // the timeout belongs to the handle, so writes keep theirs
+        let mut reader = self.port.try_clone()?;
+        reader.set_timeout(READER_TIMEOUT)?;
+        Ok(Box::new(reader))
+    }
+
+    fn local_path(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+
+    fn modem_lines(&mut self) -> ModemLines {
+        ModemLines {
+            cts: self.port.read_clear_to_send().ok(),
+            dsr: self.port.read_data_set_ready().ok(),
+            dcd: self.port.read_carrier_detect().ok(),
+            ri: self.port.read_ring_indicator().ok(),
+        }
+    }
+
+    fn marks_errors(&self) -> bool {
+        cfg!(target_os = "linux")
+    }
+
+    fn discard_input(&mut self) -> io::Result<u32> {
+        let waiting = self.port.bytes_to_read()?;
+        self.port.clear(ClearBuffer::Input)?;
+        Ok(waiting)
+    }
+}
+
+pub fn connect_tcp(address: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_nodelay(true)?;
+    Ok(stream)
+}
+
+/// Map socket reads onto the serial port conventions the session expects
+pub fn read_tcp(stream: &mut TcpStream, buf: &mut [u8]) -> io::Result<usize> {
+    read_stream(stream, buf, "connection closed by the remote end")
+}
+
+// The end of the stream is a disconnect, `closed` says by whom
+fn read_stream(stream: &mut impl Read, buf: &mut [u8], closed: &str) -> io::Result<usize> {
+    match stream.read(buf) {
+        Ok(0) => Err(io::Error::new(io::ErrorKind::BrokenPipe, closed)),
+        Ok(n) => Ok(n),
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+            Err(io::Error::new(io::ErrorKind::TimedOut, err))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// A clone of the socket for the reader thread. The read timeout is shared with the
+/// original, which isn't read from any more once the reader thread runs.
+pub fn blocking_reader(stream: &TcpStream) -> io::Result<TcpStream> {
+    let reader = stream.try_clone()?;
+    reader.set_read_timeout(None)?;
+    Ok(reader)
+}
+
+/// Bytes are passed through unchanged, so there are no control lines
+pub struct TcpTransport {
+    stream: TcpStream,
+}
+
+impl TcpTransport {
+    fn connect(address: &str, timeout: Duration) -> io::Result<TcpTransport> {
+        Ok(TcpTransport {
+            stream: connect_tcp(address, timeout)?,
+        })
+    }
+}
+
+fn unsupported_over_tcp() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "not supported over raw TCP")
+}
+
+impl Read for TcpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        read_tcp(&mut self.stream, buf)
+    }
+}
+
+impl Write for TcpTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl Transport for TcpTransport {
+    fn set_break(&mut self) -> io::Result<()> {
+        Err(unsupported_over_tcp())
+    }
+
+    fn clear_break(&mut self) -> io::Result<()> {
+        Err(unsupported_over_tcp())
+    }
+
+    fn write_data_terminal_ready(&mut self, _level: bool) -> io::Result<()> {
+        Err(unsupported_over_tcp())
+    }
+
+    fn write_request_to_send(&mut self, _level: bool) -> io::Result<()> {
+        Err(unsupported_over_tcp())
+    }
+
+    fn set_baud_rate(&mut self, _baud_rate: u32) -> io::Result<()> {
+        Err(unsupported_over_tcp())
+    }
+
+    fn set_flow_control(&mut self, _flow_control: FlowControl) -> io::Result<()> {
+        Err(unsupported_over_tcp())
+    }
+
+    fn set_data_bits(&mut self, _data_bits: DataBits) -> io::Result<()> {
+        Err(unsupported_over_tcp())
+    }
+
+    fn set_parity(&mut self, _parity: PortParity) -> io::Result<()> {
+        Err(unsupported_over_tcp())
+    }
+
+    fn set_stop_bits(&mut self, _stop_bits: StopBits) -> io::Result<()> {
+        Err(unsupported_over_tcp())
+    }
+
+    fn settings(&self) -> io::Result<PortSettings> {
+        Err(unsupported_over_tcp())
+    }
+
+    fn reader(&self) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(TcpTransport {
+            stream: blocking_reader(&self.stream)?,
+        }))
+    }
+}
+
+#[cfg(unix)]
+fn unsupported_over(what: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("not supported over {}", what),
+    )
+}
+
+/// Like TcpTransport, bytes only. QEMU closes the socket when the guest shuts down, and
+/// --reconnect connects again once it listens once more.
+#[cfg(unix)]
+pub struct UnixTransport {
+    stream: UnixStream,
+}
+
+#[cfg(unix)]
+impl UnixTransport {
+    fn connect(path: &Path, timeout: Duration) -> io::Result<UnixTransport> {
+        let stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(timeout))?;
+        Ok(UnixTransport { stream })
+    }
+}
+
+#[cfg(unix)]
+impl Read for UnixTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        read_stream(
+            &mut self.stream,
+            buf,
+            "the socket was closed by the other end",
+        )
+    }
+}
+
+#[cfg(unix)]
+impl Write for UnixTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixTransport {
+    fn set_break(&mut self) -> io::Result<()> {
+        Err(unsupported_over("a Unix socket"))
+    }
+
+    fn clear_break(&mut self) -> io::Result<()> {
+        Err(unsupported_over("a Unix socket"))
+    }
+
+    fn write_data_terminal_ready(&mut self, _level: bool) -> io::Result<()> {
+        Err(unsupported_over("a Unix socket"))
+    }
+
+    fn write_request_to_send(&mut self, _level: bool) -> io::Result<()> {
+        Err(unsupported_over("a Unix socket"))
+    }
+
+    fn set_baud_rate(&mut self, _baud_rate: u32) -> io::Result<()> {
+        Err(unsupported_over("a Unix socket"))
+    }
+
+    fn set_flow_control(&mut self, _flow_control: FlowControl) -> io::Result<()> {
+        Err(unsupported_over("a Unix socket"))
+    }
+
+    fn set_data_bits(&mut self, _data_bits: DataBits) -> io::Result<()> {
+        Err(unsupported_over("a Unix socket"))
+    }
+
+    fn set_parity(&mut self, _parity: PortParity) -> io::Result<()> {
+        Err(unsupported_over("a Unix socket"))
+    }
+
+    fn set_stop_bits(&mut self, _stop_bits: StopBits) -> io::Result<()> {
+        Err(unsupported_over("a Unix socket"))
+    }
+
+    fn settings(&self) -> io::Result<PortSettings> {
+        Err(unsupported_over("a Unix socket"))
+    }
+
+    fn reader(&self) -> io::Result<Box<dyn Read + Send>> {
+        let stream = self.stream.try_clone()?;
+        stream.set_read_timeout(None)?;
+        Ok(Box::new(UnixTransport { stream }))
+    }
+}
+
+/// Two FIFOs, read from `rx` and written to `tx`. The other end closing `rx` is a disconnect.
+#[cfg(unix)]
+pub struct FifoTransport {
+    rx: File,
+    tx: File,
+}
+
+#[cfg(unix)]
+impl FifoTransport {
+    // Opening a FIFO waits for its other end, so both are opened without waiting. Nobody
+    // reading `tx` yet is an error, and is tried again by --reconnect.
+    fn open(rx: &Path, tx: &Path) -> io::Result<FifoTransport> {
+        let writer = match OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(tx)
+        {
+            Ok(writer) => writer,
+            Err(err) if err.raw_os_error() == Some(libc::ENXIO) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    format!("nothing reads {}", tx.display()),
+                ))
+            }
+            Err(err) => return Err(err),
+        };
+        let reader = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(rx)?;
+        set_blocking(&writer)?;
+        set_blocking(&reader)?;
+        Ok(FifoTransport {
+            rx: reader,
+            tx: writer,
+        })
+    }
+}
+
+#[cfg(unix)]
+fn set_blocking(file: &File) -> io::Result<()> {
+    let fd = file.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// The reader thread's end of a FifoTransport
+#[cfg(unix)]
+struct FifoReader {
+    rx: File,
+}
+
+#[cfg(unix)]
+impl Read for FifoReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        read_stream(&mut self.rx, buf, "the FIFO was closed by the other end")
+    }
+}
+
+#[cfg(unix)]
+impl Write for FifoTransport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tx.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.tx.flush()
+    }
+}
+
+#[cfg(unix)]
+impl Transport for FifoTransport {
+    fn set_break(&mut self) -> io::Result<()> {
+        Err(unsupported_over("FIFOs"))
+    }
+
+    fn clear_break(&mut self) -> io::Result<()> {
+        Err(unsupported_over("FIFOs"))
+    }
+
+    fn write_data_terminal_ready(&mut self, _level: bool) -> io::Result<()> {
+        Err(unsupported_over("FIFOs"))
+    }
+
+    fn write_request_to_send(&mut self, _level: bool) -> io::Result<()> {
+        Err(unsupported_over("FIFOs"))
+    }
+
+    fn set_baud_rate(&mut self, _baud_rate: u32) -> io::Result<()> {
+        Err(unsupported_over("FIFOs"))
+    }
+
+    fn set_flow_control(&mut self, _flow_control: FlowControl) -> io::Result<()> {
+        Err(unsupported_over("FIFOs"))
+    }
+
+    fn set_data_bits(&mut self, _data_bits: DataBits) -> io::Result<()> {
+        Err(unsupported_over("FIFOs"))
+    }
+
+    fn set_parity(&mut self, _parity: PortParity) -> io::Result<()> {
+        Err(unsupported_over("FIFOs"))
+    }
+
+    fn set_stop_bits(&mut self, _stop_bits: StopBits) -> io::Result<()> {
+        Err(unsupported_over("FIFOs"))
+    }
+
+    fn settings(&self) -> io::Result<PortSettings> {
+        Err(unsupported_over("FIFOs"))
+    }
+
+    fn reader(&self) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(FifoReader {
+            rx: self.rx.try_clone()?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What was done to the port, in order
+    #[derive(Default)]
+    struct RecordingPort {
+        calls: Vec<String>,
+    }
+
+    impl Write for RecordingPort {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.calls.push(format!("write {:?}", buf));
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Transport for RecordingPort {
+        fn set_break(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+        fn clear_break(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+        fn write_data_terminal_ready(&mut self, level: bool) -> io::Result<()> {
+            self.calls.push(format!("DTR {}", level));
+            Ok(())
+        }
+        fn write_request_to_send(&mut self, level: bool) -> io::Result<()> {
+            self.calls.push(format!("RTS {}", level));
+            Ok(())
+        }
+        fn set_baud_rate(&mut self, _baud_rate: u32) -> io::Result<()> {
+            Ok(())
+        }
+        fn set_flow_control(&mut self, _flow_control: FlowControl) -> io::Result<()> {
+            Ok(())
+        }
+        fn set_data_bits(&mut self, _data_bits: DataBits) -> io::Result<()> {
+            Ok(())
+        }
+        fn set_parity(&mut self, _parity: PortParity) -> io::Result<()> {
+            Ok(())
+        }
+        fn set_stop_bits(&mut self, _stop_bits: StopBits) -> io::Result<()> {
+            Ok(())
+        }
+        fn settings(&self) -> io::Result<PortSettings> {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+        fn reader(&self) -> io::Result<Box<dyn Read + Send>> {
+            Err(io::ErrorKind::Unsupported.into())
+        }
+    }
+
+    #[test]
+    fn the_lines_given_are_set_dtr_first() {
+        let mut port = RecordingPort::default();
+        let lines = OpenLines {
+            dtr: Some(false),
+            rts: Some(true),
+            keep_on_close: true,
+        };
+        set_lines(&mut port, &lines).unwrap();
+        assert_eq!(port.calls, ["DTR false", "RTS true"]);
+    }
+
+    #[test]
+    fn lines_not_given_are_left_alone() {
+        let mut port = RecordingPort::default();
+        set_lines(&mut port, &OpenLines::default()).unwrap();
+        assert!(port.calls.is_empty());
+        let only_rts = OpenLines {
+            rts: Some(false),
+            ..OpenLines::default()
+        };
+        set_lines(&mut port, &only_rts).unwrap();
+        assert_eq!(port.calls, ["RTS false"]);
+    }
+
+    #[test]
+    fn windows_port_names_pass_through_unchanged() {
+        for name in ["COM7", r"\\.\COM12"] {
+            match Device::parse(name).ok().unwrap() {
+                Device::Serial(path) => assert_eq!(path, name),
+                _ => panic!("{} isn't a serial port", name),
+            }
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Instant;
+
+use regex::bytes::Regex;
+
+use crate::chat;
+
+// Longer lines are matched in pieces of this size
+const MAX_LINE: usize = 4096;
+
+#[derive(Clone)]
+pub enum TriggerAction {
+    Bell,
+    Exit,
+    LogMark,
+    Exec(String),
+    // --auto-respond, send this to the device
+    Reply(Vec<u8>),
+}
+
+pub struct Trigger {
+    regex: Regex,
+    action: TriggerAction,
+    // how often a reply was sent
+    replies: u32,
+}
+
+impl Trigger {
+    // `regex:action`, the regex may contain colons itself
+    pub fn parse(spec: &str) -> Result<Trigger, String> {
+        let (pattern, action) = if let Some(pattern) = spec.strip_suffix(":bell") {
+            (pattern, TriggerAction::Bell)
+        } else if let Some(pattern) = spec.strip_suffix(":exit") {
+            (pattern, TriggerAction::Exit)
+        } else if let Some(pattern) = spec.strip_suffix(":log-mark") {
+            (pattern, TriggerAction::LogMark)
+        } else if let Some((pattern, command)) = spec.split_once(":exec:") {
+            (pattern, TriggerAction::Exec(command.to_string()))
+        } else {
+            return Err(format!(
+                "in --on-match {}: expected REGEX:ACTION with ACTION one of bell, exit, log-mark or exec:COMMAND",
+                spec
+            ));
+        };
+        let regex =
+            Regex::new(pattern).map_err(|err| format!("in --on-match {}: {}", spec, err))?;
+        Ok(Trigger {
+            regex,
+            action,
+            replies: 0,
+        })
+    }
+
+    // `regex=reply` of --auto-respond, split at the first = that isn't written \=. The reply
+    // takes the escapes of --send.
+    pub fn reply(spec: &str) -> Result<Trigger, String> {
+        let (pattern, reply) = split_reply(spec)
+            .ok_or_else(|| format!("in --auto-respond {}: expected REGEX=REPLY", spec))?;
+        let regex =
+            Regex::new(&pattern).map_err(|err| format!("in --auto-respond {}: {}", spec, err))?;
+        let reply = chat::parse_escapes(reply, "--auto-respond")?;
+        Ok(Trigger {
+            regex,
+            action: TriggerAction::Reply(reply),
+            replies: 0,
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        self.regex.as_str()
+    }
+
+    pub fn action(&self) -> &TriggerAction {
+        &self.action
+    }
+
+    // The number of replies sent, counting the one about to go
+    pub fn replies(&self) -> u32 {
+        self.replies
+    }
+}
+
+// The regex of REGEX=REPLY with \= turned into =, and the reply. Other escapes are left to
+// the regex, so \\= is a backslash ending the regex.
+fn split_reply(spec: &str) -> Option<(String, &str)> {
+    let mut pattern = String::new();
+    let mut chars = spec.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '=' => return Some((pattern, &spec[i + 1..])),
+            '\\' => match chars.next() {
+                Some((_, '=')) => pattern.push('='),
+                Some((_, next)) => {
+                    pattern.push('\\');
+                    pattern.push(next);
+                }
+                None => pattern.push('\\'),
+            },
+            _ => pattern.push(c),
+        }
+    }
+    None
+}
+
+// Matches the --on-match triggers and the --auto-respond rules against whole received lines
+pub struct Triggers {
+    triggers: Vec<Trigger>,
+    // the line received so far, it may span several reads
+    line: Vec<u8>,
+    // the replies that answered the line received so far, before it ended
+    answered: Vec<usize>,
+    // ~y turns the replies off and on
+    replying: bool,
+    // --auto-respond-max, the replies of a rule in a session
+    max_replies: u32,
+}
+
+impl Triggers {
+    pub fn new(triggers: Vec<Trigger>, max_replies: u32) -> Triggers {
+        Triggers {
+            triggers,
+            line: Vec::new(),
+            answered: Vec::new(),
+            replying: true,
+            max_replies,
+        }
+    }
+
+    pub fn has_replies(&self) -> bool {
+        self.triggers
+            .iter()
+            .any(|trigger| matches!(trigger.action, TriggerAction::Reply(_)))
+    }
+
+    pub fn is_replying(&self) -> bool {
+        self.replying
+    }
+
+    pub fn set_replying(&mut self, replying: bool) {
+        self.replying = replying;
+    }
+
+    pub fn max_replies(&self) -> u32 {
+        self.max_replies
+    }
+
+    pub fn needs_log(&self) -> bool {
+        self.triggers
+            .iter()
+            .any(|trigger| matches!(trigger.action, TriggerAction::LogMark))
+    }
+
+    // Every trigger that fired on a line completed by `data`, with that line. A reply also
+    // answers the line received so far, as a prompt waits for it without ending its line.
+    pub fn feed(&mut self, data: &[u8]) -> Vec<(&Trigger, String)> {
+        let mut lines = Vec::new();
+        for &byte in data {
+            if byte == b'\n' || byte == b'\r' {
+                if !self.line.is_empty() {
+                    lines.push(std::mem::take(&mut self.line));
+                }
+                continue;
+            }
+            self.line.push(byte);
+            if self.line.len() >= MAX_LINE {
+                lines.push(std::mem::take(&mut self.line));
+            }
+        }
+        let mut fired = Vec::new();
+        for line in lines {
+            for i in self.matching(&line, false) {
+                fired.push((i, String::from_utf8_lossy(&line).into_owned()));
+            }
+            self.answered.clear();
+        }
+        if !self.line.is_empty() {
+            let line = self.line.clone();
+            for i in self.matching(&line, true) {
+                fired.push((i, String::from_utf8_lossy(&line).into_owned()));
+            }
+        }
+        fired
+            .into_iter()
+            .map(|(i, line)| (&self.triggers[i], line))
+            .collect()
+    }
+
+    // The indexes of the triggers firing on `line`, only replies for a `partial` one
+    fn matching(&mut self, line: &[u8], partial: bool) -> Vec<usize> {
+        let mut matching = Vec::new();
+        for (i, trigger) in self.triggers.iter_mut().enumerate() {
+            let is_reply = matches!(trigger.action, TriggerAction::Reply(_));
+            if (partial && !is_reply) || self.answered.contains(&i) {
+                continue;
+            }
+            if !trigger.regex.is_match(line) {
+                continue;
+            }
+            // a rule answering its own echo or a device repeating its question stops
+            if is_reply {
+                if !self.replying || trigger.replies >= self.max_replies {
+                    continue;
+                }
+                trigger.replies += 1;
+                if partial {
+                    self.answered.push(i);
+                }
+            }
+            matching.push(i);
+        }
+        matching
+    }
+}
+
+// An --auto-respond reply waiting for --auto-respond-delay to pass
+pub struct PendingReply {
+    pub due_at: Instant,
+    pub data: Vec<u8>,
+    // what it is shown and logged as
+    pub note: String,
+}
+
+// Run an exec action without waiting for it, it gets the line on stdin and in $SCIPIO_MATCH
+pub fn spawn_command(command: &str, line: &str) -> std::io::Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("SCIPIO_MATCH", line)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let input = format!("{}\n", line);
+    thread::spawn(move || {
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(input.as_bytes());
+        }
+        // reaped here so finished commands don't pile up as zombies
+        let _ = child.wait();
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply_of(trigger: &Trigger) -> &[u8] {
+        match trigger.action() {
+            TriggerAction::Reply(reply) => reply,
+            _ => panic!("not a reply"),
+        }
+    }
+
+    #[test]
+    fn reply_splits_at_the_first_equals_sign() {
+        let trigger = Trigger::reply("Press y to continue=y\\r").unwrap();
+        assert_eq!(trigger.pattern(), "Press y to continue");
+        assert_eq!(reply_of(&trigger), b"y\r");
+        // the rest after the first = is the reply
+        let trigger = Trigger::reply("a=b=c").unwrap();
+        assert_eq!(trigger.pattern(), "a");
+        assert_eq!(reply_of(&trigger), b"b=c");
+    }
+
+    #[test]
+    fn reply_takes_escaped_equals_signs_into_the_regex() {
+        let trigger = Trigger::reply("mode\\=1\\?=y").unwrap();
+        assert_eq!(trigger.pattern(), "mode=1\\?");
+        assert_eq!(reply_of(&trigger), b"y");
+        let mut triggers = Triggers::new(vec![trigger], 5);
+        assert_eq!(triggers.feed(b"mode=1?").len(), 1);
+        // a doubled backslash is one in the regex, the = after it ends it
+        let trigger = Trigger::reply("C:\\\\=y").unwrap();
+        assert_eq!(trigger.pattern(), "C:\\\\");
+        assert_eq!(reply_of(&trigger), b"y");
+    }
+
+    #[test]
+    fn reply_needs_a_separator_and_a_valid_regex() {
+        assert!(Trigger::reply("no separator").is_err());
+        assert!(Trigger::reply("only\\=escaped").is_err());
+        assert!(Trigger::reply("(unclosed=y").is_err());
+        let trigger = Trigger::reply("empty reply=").unwrap();
+        assert_eq!(reply_of(&trigger), b"");
+    }
+
+    #[test]
+    fn replies_answer_prompts_up_to_the_maximum() {
+        let mut triggers = Triggers::new(vec![Trigger::reply("continue\\?=y").unwrap()], 2);
+        assert_eq!(triggers.feed(b"continue?").len(), 1);
+        // the same line is answered once
+        assert!(triggers.feed(b" ").is_empty());
+        assert_eq!(triggers.feed(b"\r\ncontinue?\r\n").len(), 1);
+        assert!(triggers.feed(b"continue?\r\n").is_empty());
+    }
+}
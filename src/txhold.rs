@@ -0,0 +1,153 @@
+use std::sync::mpsc::Receiver;
+
+use crate::escape::{key_state_machine, EscapeCommand, EscapeState};
+use crate::{run_escape_command, write_status, Event, NextStep, Screen, Session, CTRL_C};
+
+// What typed keys are kept for while the port is busy, past that they are dropped
+const MAX_HELD: usize = 64 * 1024;
+
+// While a transfer, a script or the output of ~! owns the port, what is typed can't go out
+// between its bytes. With --tx-queue on it is kept and goes out as if typed once the port
+// is free again, with --tx-queue off it is dropped with a note. Ctrl-C, ~., ~p and ~a work
+// meanwhile, the other escape commands are refused.
+pub struct TxHold {
+    queue: bool,
+    // the keys to type once the port is free, without the escape commands
+    held: Vec<u8>,
+    dropped: usize,
+    // the note about the port being busy was written
+    noted: bool,
+    // of the keys read while the port is busy, ~. works without pressing Enter first
+    escape_state: EscapeState,
+    // the keys of an escape command read so far, for the note refusing it
+    pending: Vec<u8>,
+}
+
+impl TxHold {
+    pub fn new(queue: bool) -> TxHold {
+        TxHold {
+            queue,
+            held: Vec::new(),
+            dropped: 0,
+            noted: false,
+            escape_state: EscapeState::WaitForEC,
+            pending: Vec::new(),
+        }
+    }
+
+    fn keep(&mut self, keys: &[u8], screen: &mut Screen) {
+        let room = if self.queue {
+            MAX_HELD - self.held.len()
+        } else {
+            0
+        };
+        let kept = keys.len().min(room);
+        self.held.extend_from_slice(&keys[..kept]);
+        self.dropped += keys.len() - kept;
+        if self.noted || keys.is_empty() {
+            return;
+        }
+        self.noted = true;
+        let note = if !self.queue {
+            "the port is busy, what is typed is dropped"
+        } else if kept < keys.len() {
+            "the port is busy and the typed keys held are too many, dropping the rest"
+        } else {
+            "the port is busy, what is typed goes out after, ~a drops it"
+        };
+        write_status(screen, note);
+    }
+
+    // The keys held while the port was busy, between the main loop's events where nothing
+    // else owns the port
+    pub fn release(&mut self, screen: &mut Screen) -> Option<Vec<u8>> {
+        self.escape_state = EscapeState::WaitForEC;
+        self.pending.clear();
+        if !self.noted {
+            return None;
+        }
+        self.noted = false;
+        if self.dropped > 0 {
+            let msg = format!(
+                "dropped {} bytes typed while the port was busy",
+                self.dropped
+            );
+            write_status(screen, &msg);
+            self.dropped = 0;
+        }
+        (!self.held.is_empty()).then(|| std::mem::take(&mut self.held))
+    }
+}
+
+// What the keys read while the port is busy did
+pub enum HeldKeys {
+    Kept,
+    // Ctrl-C, stop what owns the port
+    Abort,
+    // ~. or the end of the session otherwise
+    Stop(NextStep),
+}
+
+// Take the keys read by a transfer, a script or ~! instead of sending them
+pub fn handle_keys(
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+    data: &[u8],
+) -> HeldKeys {
+    if !screen.is_terminal() {
+        session.tx_hold.keep(data, screen);
+        return HeldKeys::Kept;
+    }
+    let (escape_char, quit_key) = (session.escape_char, session.quit_key);
+    for &character in data {
+        if character == CTRL_C {
+            return HeldKeys::Abort;
+        }
+        session.tx_hold.pending.push(character);
+        let escape_state = &mut session.tx_hold.escape_state;
+        let step = key_state_machine(&character, escape_char, quit_key, escape_state);
+        // nothing typed goes out now, so any key may start a command
+        if let EscapeState::WaitForEnter = escape_state {
+            *escape_state = EscapeState::WaitForEC;
+        }
+        let next_step = match step {
+            NextStep::LoopContinue => continue,
+            NextStep::Command(EscapeCommand::AbortQueue) => {
+                let dropped = session.tx_hold.held.len() + session.tx_queue.clear();
+                session.tx_hold.held.clear();
+                match dropped {
+                    0 => write_status(screen, "nothing queued"),
+                    dropped => write_status(screen, &format!("dropped {} queued bytes", dropped)),
+                }
+                NextStep::None
+            }
+            NextStep::Command(EscapeCommand::TogglePause) => {
+                run_escape_command(EscapeCommand::TogglePause, session, screen, rx)
+            }
+            NextStep::Command(_) => {
+                let keys = String::from_utf8_lossy(&session.tx_hold.pending).into_owned();
+                write_status(
+                    screen,
+                    &format!("{} doesn't work while the port is busy", keys),
+                );
+                NextStep::None
+            }
+            NextStep::Send(keys) => {
+                session.tx_hold.keep(&keys, screen);
+                NextStep::None
+            }
+            NextStep::None => {
+                session.tx_hold.keep(&[character], screen);
+                NextStep::None
+            }
+            next_step => next_step,
+        };
+        session.tx_hold.pending.clear();
+        match next_step {
+            NextStep::None => {}
+            next_step => return HeldKeys::Stop(next_step),
+        }
+    }
+    HeldKeys::Kept
+}
@@ -0,0 +1,151 @@
+use std::str;
+
+use crate::ansi;
+
+// What U+FFFD is in UTF-8
+const REPLACEMENT: &[u8] = "\u{fffd}".as_bytes();
+
+#[derive(Clone, Copy)]
+pub enum InvalidStyle {
+    // U+FFFD for each broken sequence, like String::from_utf8_lossy
+    Replacement,
+    // <C3> for each byte, in reverse video
+    Hex,
+}
+
+// --utf8: shows what the device sends as UTF-8 text. A sequence split across reads is held
+// back until the rest arrives, so it is at most the 3 bytes of an incomplete character that
+// wait. Broken sequences and the C1 controls, which would take the terminal somewhere, are
+// shown in the --invalid-utf8 style. Only the screen gets this.
+pub struct Utf8Filter {
+    style: InvalidStyle,
+    // the start of a character the last data ended in
+    pending: Vec<u8>,
+}
+
+impl Utf8Filter {
+    pub fn new(style: InvalidStyle) -> Utf8Filter {
+        Utf8Filter {
+            style,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn filter(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut bytes = std::mem::take(&mut self.pending);
+        bytes.extend_from_slice(data);
+        let mut shown = Vec::with_capacity(bytes.len());
+        let mut rest = bytes.as_slice();
+        loop {
+            let err = match str::from_utf8(rest) {
+                Ok(text) => {
+                    self.push_text(&mut shown, text);
+                    break;
+                }
+                Err(err) => err,
+            };
+            let (valid, broken) = rest.split_at(err.valid_up_to());
+            if let Ok(text) = str::from_utf8(valid) {
+                self.push_text(&mut shown, text);
+            }
+            match err.error_len() {
+                Some(len) => {
+                    self.push_invalid(&mut shown, &broken[..len]);
+                    rest = &broken[len..];
+                }
+                // it may still be completed by the next data
+                None => {
+                    self.pending = broken.to_vec();
+                    break;
+                }
+            }
+        }
+        shown
+    }
+
+    // Valid text goes as it is but for the C1 controls, which are 0xC2 followed by 0x80 to 0x9F
+    fn push_text(&self, shown: &mut Vec<u8>, text: &str) {
+        if !text.as_bytes().contains(&0xc2) {
+            shown.extend_from_slice(text.as_bytes());
+            return;
+        }
+        let mut utf8 = [0; 4];
+        for c in text.chars() {
+            let encoded = c.encode_utf8(&mut utf8).as_bytes();
+            if ('\u{80}'..='\u{9f}').contains(&c) {
+                self.push_invalid(shown, encoded);
+            } else {
+                shown.extend_from_slice(encoded);
+            }
+        }
+    }
+
+    fn push_invalid(&self, shown: &mut Vec<u8>, bytes: &[u8]) {
+        match self.style {
+            InvalidStyle::Replacement => shown.extend_from_slice(REPLACEMENT),
+            InvalidStyle::Hex => {
+                for byte in bytes {
+                    let hex = format!("{}<{:02X}>{}", ansi::INVERT, byte, ansi::NO_INVERT);
+                    shown.extend_from_slice(hex.as_bytes());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shown(style: InvalidStyle, reads: &[&[u8]]) -> Vec<u8> {
+        let mut utf8 = Utf8Filter::new(style);
+        reads.iter().flat_map(|data| utf8.filter(data)).collect()
+    }
+
+    #[test]
+    fn a_character_split_across_reads_is_held_until_whole() {
+        let euro = "€".as_bytes();
+        let mut utf8 = Utf8Filter::new(InvalidStyle::Replacement);
+        assert_eq!(utf8.filter(&[b'a', euro[0]]), b"a");
+        assert_eq!(utf8.filter(&euro[1..2]), b"");
+        assert_eq!(utf8.filter(&[euro[2], b'b']), "€b".as_bytes());
+        let smile = "😀".as_bytes();
+        let reads: Vec<&[u8]> = smile.chunks(1).collect();
+        assert_eq!(shown(InvalidStyle::Replacement, &reads), smile);
+    }
+
+    #[test]
+    fn broken_sequences_are_replaced() {
+        // a lone continuation byte, a start byte cut short and a byte never in UTF-8
+        let data = b"a\x80b\xe2\x82c\xffd";
+        assert_eq!(
+            shown(InvalidStyle::Replacement, &[data]),
+            "a\u{fffd}b\u{fffd}c\u{fffd}d".as_bytes()
+        );
+        assert_eq!(
+            shown(InvalidStyle::Replacement, &[data]),
+            String::from_utf8_lossy(data).as_bytes()
+        );
+    }
+
+    #[test]
+    fn broken_bytes_are_shown_in_hex() {
+        let shown = shown(InvalidStyle::Hex, &[b"a\xe2\x82c"]);
+        let expected = format!("a{0}<E2>{1}{0}<82>{1}c", ansi::INVERT, ansi::NO_INVERT);
+        assert_eq!(shown, expected.as_bytes());
+    }
+
+    #[test]
+    fn c1_controls_are_shown_as_broken() {
+        // U+009B is the one byte CSI of some terminals
+        assert_eq!(
+            shown(InvalidStyle::Replacement, &["x\u{9b}2Jé".as_bytes()]),
+            "x\u{fffd}2Jé".as_bytes()
+        );
+        let expected = format!("{0}<C2>{1}{0}<9B>{1}", ansi::INVERT, ansi::NO_INVERT);
+        assert_eq!(
+            shown(InvalidStyle::Hex, &[&[0xc2], &[0x9b]]),
+            expected.as_bytes()
+        );
+    }
+}
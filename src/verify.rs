@@ -0,0 +1,199 @@
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use crate::chat::{self, ChatError};
+use crate::sha256::Sha256;
+use crate::transfer;
+use crate::{write_status, write_to_serial_port, Event, NextStep, Screen, Session};
+
+// Replaced in --verify-command by the path of the file on the device
+const REMOTE_PATH: &[u8] = b"{remote_path}";
+// The hex digits of a SHA-256
+const DIGEST_LEN: usize = 64;
+
+// How --send-file-verify and ~V have the device check a file sent to it
+pub struct FileVerify {
+    // --verify-command with its escapes turned into bytes
+    command: Vec<u8>,
+    // how long the device has to answer, --expect-timeout
+    timeout: Duration,
+}
+
+impl Default for FileVerify {
+    fn default() -> FileVerify {
+        FileVerify {
+            command: b"sha256sum {remote_path}\n".to_vec(),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+impl FileVerify {
+    // The value of --verify-command
+    pub fn new(command: &str, timeout: Duration) -> Result<FileVerify, String> {
+        let command = chat::parse_escapes(command, "--verify-command")?;
+        Ok(FileVerify { command, timeout })
+    }
+
+    // The command that checks the file at `remote_path`
+    fn command(&self, remote_path: &str) -> Vec<u8> {
+        let mut command = Vec::with_capacity(self.command.len() + remote_path.len());
+        let mut rest = &self.command[..];
+        while let Some(at) = rest
+            .windows(REMOTE_PATH.len())
+            .position(|window| window == REMOTE_PATH)
+        {
+            command.extend_from_slice(&rest[..at]);
+            command.extend_from_slice(remote_path.as_bytes());
+            rest = &rest[at + REMOTE_PATH.len()..];
+        }
+        command.extend_from_slice(rest);
+        command
+    }
+}
+
+// Send a file like ~f, then the verify command, and compare the SHA-256 the device answers
+// with that of the file. {remote_path} is `remote_path`, or else the name of the file. A
+// mismatch, an answer without a digest and Ctrl-C are errors. The digest is that of the
+// bytes sent, the file is read only once.
+pub fn send_verified(
+    path: &str,
+    remote_path: Option<&str>,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> Result<(), ChatError> {
+    let data =
+        fs::read(path).map_err(|err| ChatError::Failed(format!("reading {}: {}", path, err)))?;
+    let mut sha256 = Sha256::new();
+    sha256.update(&data);
+    let local = sha256.finish();
+    if !transfer::send_data(path, &data, session, screen, rx).map_err(chat::stopped)? {
+        return Err(ChatError::Cancelled);
+    }
+
+    let remote_path = match remote_path {
+        Some(remote_path) => remote_path.to_string(),
+        None => Path::new(path)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+    };
+    let command = session.file_verify.command(&remote_path);
+    let timeout = session.file_verify.timeout;
+    write_status(screen, &format!("checking {} on the device", remote_path));
+    // only what the device answers to the command can have the digest
+    session.chat_input = Some(Vec::new());
+    let found = match write_to_serial_port(session, screen, &command) {
+        NextStep::None => chat::wait_for(find_digest, timeout, session, screen, rx),
+        next_step => Err(chat::stopped(next_step)),
+    };
+    session.chat_input = None;
+    let remote = found?.ok_or_else(|| {
+        ChatError::Failed(format!(
+            "no SHA-256 in the answer to the verify command within {} s",
+            timeout.as_secs()
+        ))
+    })?;
+
+    if remote != local {
+        return Err(ChatError::Failed(format!(
+            "{} doesn't match on the device: SHA-256 {} there, {} here",
+            path, remote, local
+        )));
+    }
+    write_status(screen, &format!("verified {}: SHA-256 {}", path, local));
+    Ok(())
+}
+
+// ~V, a failed check is shown and the session goes on
+pub fn send_and_verify(
+    path: &str,
+    session: &mut Session,
+    screen: &mut Screen,
+    rx: &Receiver<Event>,
+) -> NextStep {
+    match send_verified(path, None, session, screen, rx) {
+        Ok(()) => NextStep::None,
+        Err(ChatError::Failed(reason)) | Err(ChatError::Timeout(reason)) => {
+            write_status(screen, &reason);
+            NextStep::None
+        }
+        Err(ChatError::Cancelled) => {
+            write_status(screen, &format!("verifying {} cancelled", path));
+            NextStep::None
+        }
+        Err(ChatError::Disconnected) => NextStep::Disconnected,
+        Err(ChatError::Stopped(next_step)) => next_step,
+    }
+}
+
+// The first run of exactly 64 hex digits that ended, in lowercase with where it ends. The
+// echo of the command and the prompts around the answer have none, and a longer run like a
+// SHA-512 is not taken for one.
+fn find_digest(data: &[u8]) -> Option<(usize, String)> {
+    let mut start = 0;
+    for (i, byte) in data.iter().enumerate() {
+        if byte.is_ascii_hexdigit() {
+            continue;
+        }
+        if i - start == DIGEST_LEN {
+            let digest = String::from_utf8_lossy(&data[start..i]).to_ascii_lowercase();
+            return Some((i, digest));
+        }
+        start = i + 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DIGEST: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    #[test]
+    fn the_remote_path_goes_in_place_of_each_placeholder() {
+        let verify = FileVerify::default();
+        assert_eq!(verify.command("fw.bin"), b"sha256sum fw.bin\n");
+        let verify = FileVerify::new(
+            r"cd /tmp && sha256sum {remote_path} # {remote_path}\r",
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        assert_eq!(
+            verify.command("/data/a b"),
+            b"cd /tmp && sha256sum /data/a b # /data/a b\r"
+        );
+        let verify = FileVerify::new("md5 -q {path}", Duration::from_secs(1)).unwrap();
+        assert_eq!(verify.command("fw.bin"), b"md5 -q {path}");
+        assert!(FileVerify::new(r"sha256sum \x", Duration::from_secs(1)).is_err());
+    }
+
+    #[test]
+    fn the_digest_is_found_past_the_echo_and_before_the_prompt() {
+        let answer = format!("sha256sum fw.bin\r\n{}  fw.bin\r\n# ", DIGEST);
+        let (end, digest) = find_digest(answer.as_bytes()).unwrap();
+        assert_eq!(digest, DIGEST);
+        assert_eq!(&answer[end..end + 2], "  ");
+        // busybox prompts and uppercase digits
+        let answer = format!("root@dev:~# {}\n", DIGEST.to_ascii_uppercase());
+        assert_eq!(find_digest(answer.as_bytes()).unwrap().1, DIGEST);
+    }
+
+    #[test]
+    fn a_digest_still_arriving_or_of_another_length_is_none() {
+        // not ended yet, more digits may follow
+        assert_eq!(find_digest(DIGEST.as_bytes()), None);
+        assert_eq!(find_digest(format!("{} ", &DIGEST[..63]).as_bytes()), None);
+        let sha512 = format!("{}{} -\n", DIGEST, DIGEST);
+        assert_eq!(find_digest(sha512.as_bytes()), None);
+        assert_eq!(
+            find_digest(b"sha256sum: fw.bin: No such file or directory\r\n"),
+            None
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use std::path::Path;
+use std::sync::mpsc::Sender;
+
+use crate::Event;
+
+// Send Event::DeviceReady once `path` may have appeared.
+// Returns false when the platform can't notify, so the caller has to poll.
+#[cfg(target_os = "linux")]
+pub fn spawn_device_watcher(path: &Path, tx: Sender<Event>) -> bool {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::thread;
+
+    let (dir, name) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => (dir, name.to_os_string()),
+        _ => return false,
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let dir = match CString::new(dir.as_os_str().as_bytes()) {
+        Ok(dir) => dir,
+        Err(_) => return false,
+    };
+
+    let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+    if fd < 0 {
+        return false;
+    }
+    // udev creates the node before fixing its permissions, so watch attribute changes too
+    let mask = libc::IN_CREATE | libc::IN_ATTRIB | libc::IN_MOVED_TO;
+    if unsafe { libc::inotify_add_watch(fd, dir.as_ptr(), mask) } < 0 {
+        unsafe { libc::close(fd) };
+        return false;
+    }
+    let path = path.to_path_buf();
+
+    thread::spawn(move || {
+        // the device may have appeared before the watch was added
+        if !path.exists() {
+            let mut buf = [0u8; 4096];
+            'read: loop {
+                let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+                if n <= 0 {
+                    break;
+                }
+                let mut offset = 0;
+                while offset + std::mem::size_of::<libc::inotify_event>() <= n as usize {
+                    let event = unsafe {
+                        std::ptr::read_unaligned(
+                            buf[offset..].as_ptr() as *const libc::inotify_event
+                        )
+                    };
+                    let name_start = offset + std::mem::size_of::<libc::inotify_event>();
+                    let name_bytes = &buf[name_start..name_start + event.len as usize];
+                    let event_name = name_bytes.split(|&b| b == 0).next().unwrap_or_default();
+                    if event_name == name.as_bytes() {
+                        break 'read;
+                    }
+                    offset = name_start + event.len as usize;
+                }
+            }
+        }
+        unsafe { libc::close(fd) };
+        let _ = tx.send(Event::DeviceReady);
+    });
+    true
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn spawn_device_watcher(_path: &Path, _tx: Sender<Event>) -> bool {
+    false
+}
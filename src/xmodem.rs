@@ -0,0 +1,506 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use log::{debug, trace};
+
+pub const SOH: u8 = 0x01;
+pub const STX: u8 = 0x02;
+pub const EOT: u8 = 0x04;
+pub const ACK: u8 = 0x06;
+pub const NAK: u8 = 0x15;
+pub const CAN: u8 = 0x18;
+pub const CRC_REQUEST: u8 = b'C';
+pub const PAD: u8 = 0x1a;
+
+pub const MAX_RETRIES: u32 = 10;
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+pub const RESPONSE_TIMEOUT: Duration = Duration::from_secs(10);
+pub const BYTE_TIMEOUT: Duration = Duration::from_secs(1);
+
+// The byte stream a transfer runs over
+pub trait Link {
+    // Read one byte, waiting at most `timeout`; Ok(None) if nothing arrived
+    fn read_byte(&mut self, timeout: Duration) -> io::Result<Option<u8>>;
+    fn write_all(&mut self, data: &[u8]) -> io::Result<()>;
+    // Whether the local user asked to abort the transfer
+    fn cancelled(&mut self) -> bool;
+    fn progress(&mut self, progress: &Progress);
+    // A line of its own about the transfer, like the file being received
+    fn status(&mut self, msg: &str);
+}
+
+#[derive(Clone, Copy, Default)]
+pub struct Progress {
+    pub blocks: u32,
+    pub bytes: u64,
+    pub retries: u32,
+}
+
+pub enum XmodemError {
+    Cancelled,
+    PeerCancelled,
+    Timeout,
+    TooManyRetries,
+    OutOfSequence,
+    Io(io::Error),
+}
+
+impl fmt::Display for XmodemError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            XmodemError::Cancelled => write!(f, "cancelled"),
+            XmodemError::PeerCancelled => write!(f, "cancelled by the other side"),
+            XmodemError::Timeout => write!(f, "timed out waiting for the other side"),
+            XmodemError::TooManyRetries => write!(f, "too many retries"),
+            XmodemError::OutOfSequence => write!(f, "block out of sequence"),
+            XmodemError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<io::Error> for XmodemError {
+    fn from(err: io::Error) -> XmodemError {
+        XmodemError::Io(err)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    Checksum,
+    Crc16,
+}
+
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+}
+
+// Send `data` as 128 byte blocks, using CRC-16 if the receiver asks for it
+pub fn send(link: &mut impl Link, data: &mut impl Read) -> Result<Progress, XmodemError> {
+    let result = send_blocks(link, data);
+    abort_on_error(link, result)
+}
+
+// Receive into `out`, dropping the trailing padding of the last block if `strip_eof`
+pub fn receive(
+    link: &mut impl Link,
+    out: &mut impl Write,
+    strip_eof: bool,
+) -> Result<Progress, XmodemError> {
+    let result = receive_blocks(link, out, strip_eof);
+    abort_on_error(link, result)
+}
+
+// Tell the other side to stop if `result` is a failure it didn't cause itself
+pub fn abort_on_error<T>(
+    link: &mut impl Link,
+    result: Result<T, XmodemError>,
+) -> Result<T, XmodemError> {
+    if let Err(err) = &result {
+        if !matches!(err, XmodemError::PeerCancelled) {
+            // best effort, the transfer failed already
+            let _ = link.write_all(&[CAN; 8]);
+        }
+    }
+    result
+}
+
+fn send_blocks(link: &mut impl Link, data: &mut impl Read) -> Result<Progress, XmodemError> {
+    let check = wait_for_handshake(link)?;
+    let mut progress = Progress::default();
+    let mut block_number: u8 = 1;
+    loop {
+        let mut block = [PAD; 128];
+        let n = read_full(data, &mut block)?;
+        if n == 0 {
+            break;
+        }
+
+        let packet = block_packet(block_number, &block, check);
+        send_until_acked(link, &packet, &mut progress)?;
+
+        progress.blocks += 1;
+        progress.bytes += n as u64;
+        link.progress(&progress);
+        block_number = block_number.wrapping_add(1);
+    }
+    send_until_acked(link, &[EOT], &mut progress)?;
+    Ok(progress)
+}
+
+// The packet carrying `block`, which is 128 or 1024 bytes long
+pub fn block_packet(number: u8, block: &[u8], check: Check) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(block.len() + 5);
+    packet.push(if block.len() == 1024 { STX } else { SOH });
+    packet.push(number);
+    packet.push(!number);
+    packet.extend_from_slice(block);
+    match check {
+        Check::Checksum => packet.push(checksum(block)),
+        Check::Crc16 => packet.extend_from_slice(&crc16(block).to_be_bytes()),
+    }
+    packet
+}
+
+fn wait_for_handshake(link: &mut impl Link) -> Result<Check, XmodemError> {
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    while Instant::now() < deadline {
+        if link.cancelled() {
+            return Err(XmodemError::Cancelled);
+        }
+        match link.read_byte(BYTE_TIMEOUT)? {
+            Some(NAK) => {
+                debug!("xmodem: receiver asked for checksums");
+                return Ok(Check::Checksum);
+            }
+            Some(CRC_REQUEST) => {
+                debug!("xmodem: receiver asked for CRC-16");
+                return Ok(Check::Crc16);
+            }
+            Some(CAN) if second_can(link)? => return Err(XmodemError::PeerCancelled),
+            _ => {}
+        }
+    }
+    Err(XmodemError::Timeout)
+}
+
+pub fn send_until_acked(
+    link: &mut impl Link,
+    packet: &[u8],
+    progress: &mut Progress,
+) -> Result<(), XmodemError> {
+    for attempt in 0..=MAX_RETRIES {
+        if attempt > 0 {
+            debug!("xmodem: sending again, attempt {}", attempt + 1);
+            progress.retries += 1;
+            link.progress(progress);
+        }
+        link.write_all(packet)?;
+        let deadline = Instant::now() + RESPONSE_TIMEOUT;
+        loop {
+            if link.cancelled() {
+                return Err(XmodemError::Cancelled);
+            }
+            if Instant::now() >= deadline {
+                break;
+            }
+            match link.read_byte(BYTE_TIMEOUT)? {
+                Some(ACK) => {
+                    trace!("xmodem: acknowledged");
+                    return Ok(());
+                }
+                Some(NAK) => {
+                    debug!("xmodem: receiver rejected the packet");
+                    break;
+                }
+                Some(CAN) if second_can(link)? => return Err(XmodemError::PeerCancelled),
+                _ => {}
+            }
+        }
+    }
+    Err(XmodemError::TooManyRetries)
+}
+
+pub fn read_full(data: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match data.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+fn receive_blocks(
+    link: &mut impl Link,
+    out: &mut impl Write,
+    strip_eof: bool,
+) -> Result<Progress, XmodemError> {
+    let mut progress = Progress::default();
+    let mut check = Check::Crc16;
+    let mut expected: u8 = 1;
+    let mut started = false;
+    let mut errors: u32 = 0;
+    // the last block is held back until EOT so its padding can be stripped
+    let mut held: Option<Vec<u8>> = None;
+
+    loop {
+        if link.cancelled() {
+            return Err(XmodemError::Cancelled);
+        }
+        if !started {
+            // ask for CRC-16 a few times before falling back to checksums
+            if errors >= 4 {
+                check = Check::Checksum;
+            }
+            if errors >= MAX_RETRIES {
+                return Err(XmodemError::Timeout);
+            }
+            link.write_all(&[match check {
+                Check::Crc16 => CRC_REQUEST,
+                Check::Checksum => NAK,
+            }])?;
+        }
+
+        let timeout = if started {
+            RESPONSE_TIMEOUT
+        } else {
+            Duration::from_secs(3)
+        };
+        let block_size = match link.read_byte(timeout)? {
+            Some(SOH) => 128,
+            Some(STX) => 1024,
+            Some(EOT) if started => {
+                debug!("xmodem: end of transfer after {} bytes", progress.bytes);
+                link.write_all(&[ACK])?;
+                if let Some(mut block) = held.take() {
+                    if strip_eof {
+                        while block.last() == Some(&PAD) {
+                            block.pop();
+                        }
+                    }
+                    out.write_all(&block)?;
+                }
+                out.flush()?;
+                return Ok(progress);
+            }
+            Some(CAN) if second_can(link)? => return Err(XmodemError::PeerCancelled),
+            Some(_) => continue,
+            None => {
+                errors += 1;
+                trace!("xmodem: nothing from the sender, {} errors", errors);
+                if started {
+                    if errors > MAX_RETRIES {
+                        return Err(XmodemError::TooManyRetries);
+                    }
+                    link.write_all(&[NAK])?;
+                }
+                continue;
+            }
+        };
+        started = true;
+
+        let (number, block) = match read_block(link, block_size, check)? {
+            Some(read) => read,
+            None => {
+                debug!("xmodem: bad block after block {}", expected.wrapping_sub(1));
+                errors += 1;
+                progress.retries += 1;
+                link.progress(&progress);
+                if errors > MAX_RETRIES {
+                    return Err(XmodemError::TooManyRetries);
+                }
+                purge(link)?;
+                link.write_all(&[NAK])?;
+                continue;
+            }
+        };
+
+        if number == expected {
+            if let Some(previous) = held.replace(block) {
+                out.write_all(&previous)?;
+            }
+            expected = expected.wrapping_add(1);
+            errors = 0;
+            progress.blocks += 1;
+            progress.bytes += block_size as u64;
+            link.progress(&progress);
+            trace!("xmodem: block {} of {} bytes", number, block_size);
+        } else if number != expected.wrapping_sub(1) {
+            debug!("xmodem: block {} where {} was expected", number, expected);
+            return Err(XmodemError::OutOfSequence);
+        } else {
+            debug!("xmodem: block {} once more", number);
+        }
+        // a repeated block means our ACK got lost
+        link.write_all(&[ACK])?;
+    }
+}
+
+// The number and data of the block whose SOH or STX was just read, None if it is cut short
+// or garbled
+pub fn read_block(
+    link: &mut impl Link,
+    block_size: usize,
+    check: Check,
+) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let check_len = match check {
+        Check::Checksum => 1,
+        Check::Crc16 => 2,
+    };
+    let mut packet = vec![0u8; 2 + block_size + check_len];
+    for byte in packet.iter_mut() {
+        match link.read_byte(BYTE_TIMEOUT)? {
+            Some(b) => *byte = b,
+            None => return Ok(None),
+        }
+    }
+    let (number, complement) = (packet[0], packet[1]);
+    let block = &packet[2..2 + block_size];
+    let valid = number == !complement
+        && match check {
+            Check::Checksum => packet[2 + block_size] == checksum(block),
+            Check::Crc16 => packet[2 + block_size..] == crc16(block).to_be_bytes(),
+        };
+    Ok(valid.then(|| (number, block.to_vec())))
+}
+
+// A single CAN may be line noise, two in a row cancel the transfer
+pub fn second_can(link: &mut impl Link) -> io::Result<bool> {
+    Ok(link.read_byte(BYTE_TIMEOUT)? == Some(CAN))
+}
+
+// Wait until the line goes quiet after a garbled block
+pub fn purge(link: &mut impl Link) -> io::Result<()> {
+    while link.read_byte(BYTE_TIMEOUT)?.is_some() {}
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    // The other side answers with `input`, a None is a pause, then goes silent at once
+    #[derive(Default)]
+    struct MockLink {
+        input: VecDeque<Option<u8>>,
+        written: Vec<u8>,
+        cancel: bool,
+    }
+
+    impl MockLink {
+        fn new(input: &[u8]) -> MockLink {
+            MockLink {
+                input: input.iter().copied().map(Some).collect(),
+                ..MockLink::default()
+            }
+        }
+
+        // More input after the line was quiet for a moment
+        fn then(mut self, input: &[u8]) -> MockLink {
+            self.input.push_back(None);
+            self.input.extend(input.iter().copied().map(Some));
+            self
+        }
+    }
+
+    impl Link for MockLink {
+        fn read_byte(&mut self, _timeout: Duration) -> io::Result<Option<u8>> {
+            Ok(self.input.pop_front().flatten())
+        }
+        fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+            self.written.extend_from_slice(data);
+            Ok(())
+        }
+        fn cancelled(&mut self) -> bool {
+            self.cancel
+        }
+        fn progress(&mut self, _progress: &Progress) {}
+        fn status(&mut self, _msg: &str) {}
+    }
+
+    #[test]
+    fn crc16_is_xmodem_crc() {
+        assert_eq!(crc16(b"123456789"), 0x31c3);
+    }
+
+    #[test]
+    fn blocks_go_through_both_ways() {
+        let data: Vec<u8> = (0..300).map(|i| i as u8).collect();
+        let mut sender = MockLink::new(&[CRC_REQUEST, ACK, ACK, ACK, ACK]);
+        let sent = send(&mut sender, &mut &data[..]).ok().unwrap();
+        assert_eq!((sent.blocks, sent.bytes, sent.retries), (3, 300, 0));
+        assert_eq!(sender.written.len(), 3 * 133 + 1);
+        assert_eq!(&sender.written[..3], &[SOH, 1, 0xfe]);
+        assert_eq!(sender.written.last(), Some(&EOT));
+
+        let mut receiver = MockLink::new(&sender.written);
+        let mut out = Vec::new();
+        let received = receive(&mut receiver, &mut out, true).ok().unwrap();
+        assert_eq!(received.blocks, 3);
+        assert_eq!(out, data);
+        assert_eq!(receiver.written, [CRC_REQUEST, ACK, ACK, ACK, ACK]);
+    }
+
+    #[test]
+    fn checksums_are_used_when_asked_for() {
+        let mut sender = MockLink::new(&[NAK, ACK, ACK]);
+        send(&mut sender, &mut &b"hi"[..]).ok().unwrap();
+        let packet = &sender.written[..132];
+        assert_eq!(packet[131], checksum(&packet[3..131]));
+        assert_eq!(
+            read_block(&mut MockLink::new(&packet[1..]), 128, Check::Checksum)
+                .unwrap()
+                .map(|(number, _)| number),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn a_nak_sends_the_block_again() {
+        let mut sender = MockLink::new(&[CRC_REQUEST, NAK, ACK, ACK]);
+        let progress = send(&mut sender, &mut &b"hello"[..]).ok().unwrap();
+        assert_eq!(progress.retries, 1);
+        let packet = block_packet(
+            1,
+            &[b"hello".as_slice(), &[PAD; 123]].concat(),
+            Check::Crc16,
+        );
+        assert_eq!(sender.written, [&packet[..], &packet, &[EOT]].concat());
+    }
+
+    #[test]
+    fn a_garbled_block_is_nakked_and_taken_again() {
+        let packet = block_packet(1, &[b'x'; 128], Check::Crc16);
+        let mut garbled = packet.clone();
+        garbled[10] ^= 0xff;
+        let input = [&packet[..], &[EOT]].concat();
+        let mut receiver = MockLink::new(&garbled).then(&input);
+        let mut out = Vec::new();
+        let progress = receive(&mut receiver, &mut out, false).ok().unwrap();
+        assert_eq!(progress.retries, 1);
+        assert_eq!(out, [b'x'; 128]);
+        assert_eq!(receiver.written, [CRC_REQUEST, NAK, ACK, ACK]);
+    }
+
+    #[test]
+    fn two_cans_cancel_the_transfer() {
+        let mut sender = MockLink::new(&[CRC_REQUEST, CAN, CAN]);
+        let result = send(&mut sender, &mut &b"data"[..]);
+        assert!(matches!(result, Err(XmodemError::PeerCancelled)));
+        // the other side stopped already, it isn't told to
+        assert!(!sender.written.contains(&CAN));
+
+        let mut receiver = MockLink::new(&[CAN, CAN]);
+        let result = receive(&mut receiver, &mut Vec::new(), true);
+        assert!(matches!(result, Err(XmodemError::PeerCancelled)));
+    }
+
+    #[test]
+    fn a_local_abort_cancels_the_other_side() {
+        let mut sender = MockLink::new(&[CRC_REQUEST]);
+        sender.cancel = true;
+        let result = send(&mut sender, &mut &b"data"[..]);
+        assert!(matches!(result, Err(XmodemError::Cancelled)));
+        assert_eq!(sender.written, [CAN; 8]);
+    }
+}
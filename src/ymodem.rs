@@ -0,0 +1,451 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::debug;
+
+use crate::xmodem::{
+    abort_on_error, block_packet, purge, read_block, read_full, second_can, send_until_acked,
+    Check, Link, Progress, XmodemError, ACK, BYTE_TIMEOUT, CAN, CRC_REQUEST, EOT,
+    HANDSHAKE_TIMEOUT, MAX_RETRIES, NAK, PAD, RESPONSE_TIMEOUT, SOH, STX,
+};
+use crate::zmodem::{create_file, ReceivedFile};
+
+// Asks for YMODEM-G, the blocks are streamed without waiting for an ACK each
+const STREAM_REQUEST: u8 = b'G';
+
+// A file to send, `data` giving `size` bytes
+pub struct Outgoing<R> {
+    pub name: String,
+    pub size: u64,
+    pub mtime: Option<SystemTime>,
+    pub data: R,
+}
+
+// Send a batch of files, each announced by its name, size and modification time in block 0.
+// The receiver decides whether the blocks are acknowledged or streamed as YMODEM-G.
+pub fn send<R: Read>(
+    link: &mut impl Link,
+    files: Vec<Outgoing<R>>,
+) -> Result<Progress, XmodemError> {
+    let result = send_batch(link, files);
+    abort_on_error(link, result)
+}
+
+// Receive a batch of files into `dir`, asking for YMODEM-G if `streaming`
+pub fn receive(
+    link: &mut impl Link,
+    dir: &Path,
+    overwrite: bool,
+    streaming: bool,
+) -> Result<Vec<ReceivedFile>, XmodemError> {
+    let result = receive_batch(link, dir, overwrite, streaming);
+    abort_on_error(link, result)
+}
+
+fn send_batch<R: Read>(
+    link: &mut impl Link,
+    files: Vec<Outgoing<R>>,
+) -> Result<Progress, XmodemError> {
+    let mut total = Progress::default();
+    for mut file in files {
+        let streaming = wait_for_request(link)?;
+        link.status(&format!(
+            "ymodem sending {} ({} bytes)",
+            file.name, file.size
+        ));
+        let mut progress = Progress::default();
+        let header = header_block(&file.name, file.size, file.mtime);
+        send_block(
+            link,
+            &block_packet(0, &header, Check::Crc16),
+            streaming,
+            &mut progress,
+        )?;
+        // the receiver asks again once it opened the file
+        let streaming = wait_for_request(link)?;
+
+        let mut block_number: u8 = 1;
+        loop {
+            let mut block = [PAD; 1024];
+            let n = read_full(&mut file.data, &mut block)?;
+            if n == 0 {
+                break;
+            }
+            // a short last block goes out as a 128 byte one
+            let block = if n <= 128 { &block[..128] } else { &block[..] };
+            let packet = block_packet(block_number, block, Check::Crc16);
+            send_block(link, &packet, streaming, &mut progress)?;
+            progress.blocks += 1;
+            progress.bytes += n as u64;
+            link.progress(&progress);
+            block_number = block_number.wrapping_add(1);
+        }
+        send_until_acked(link, &[EOT], &mut progress)?;
+        total.blocks += progress.blocks;
+        total.bytes += progress.bytes;
+        total.retries += progress.retries;
+    }
+
+    // an empty block 0 ends the batch
+    let streaming = wait_for_request(link)?;
+    let packet = block_packet(0, &[0; 128], Check::Crc16);
+    send_block(link, &packet, streaming, &mut total)?;
+    if streaming {
+        // the receiver may still acknowledge it, that shouldn't show up as data
+        link.read_byte(BYTE_TIMEOUT)?;
+    }
+    Ok(total)
+}
+
+// Wait for the receiver to ask for the next block 0 or the data, returning whether it
+// asked for YMODEM-G
+fn wait_for_request(link: &mut impl Link) -> Result<bool, XmodemError> {
+    let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+    while Instant::now() < deadline {
+        if link.cancelled() {
+            return Err(XmodemError::Cancelled);
+        }
+        match link.read_byte(BYTE_TIMEOUT)? {
+            Some(CRC_REQUEST) => {
+                debug!("ymodem: receiver asked for CRC-16 blocks");
+                return Ok(false);
+            }
+            Some(STREAM_REQUEST) => {
+                debug!("ymodem: receiver asked for YMODEM-G");
+                return Ok(true);
+            }
+            Some(CAN) if second_can(link)? => return Err(XmodemError::PeerCancelled),
+            _ => {}
+        }
+    }
+    Err(XmodemError::Timeout)
+}
+
+fn send_block(
+    link: &mut impl Link,
+    packet: &[u8],
+    streaming: bool,
+    progress: &mut Progress,
+) -> Result<(), XmodemError> {
+    if streaming {
+        if link.cancelled() {
+            return Err(XmodemError::Cancelled);
+        }
+        return Ok(link.write_all(packet)?);
+    }
+    send_until_acked(link, packet, progress)
+}
+
+// Block 0: the name, then the size in decimal and the modification time in octal seconds
+fn header_block(name: &str, size: u64, mtime: Option<SystemTime>) -> Vec<u8> {
+    let mut header = name.as_bytes().to_vec();
+    header.push(0);
+    header.extend_from_slice(size.to_string().as_bytes());
+    if let Some(secs) = mtime.and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok()) {
+        header.extend_from_slice(format!(" {:o}", secs.as_secs()).as_bytes());
+    }
+    // cut short if a name doesn't even fit into a 1K block
+    let len = if header.len() <= 128 { 128 } else { 1024 };
+    header.resize(len, 0);
+    header
+}
+
+// The name, size and modification time in block 0, an empty name ends the batch
+fn parse_header(header: &[u8]) -> (String, Option<u64>, Option<SystemTime>) {
+    let mut fields = header.splitn(2, |&byte| byte == 0);
+    let name = String::from_utf8_lossy(fields.next().unwrap_or_default()).into_owned();
+    let info = fields.next().unwrap_or_default();
+    let info = info.split(|&byte| byte == 0).next().unwrap_or_default();
+    let info = String::from_utf8_lossy(info);
+    let mut info = info.split_whitespace();
+    let size = info.next().and_then(|size| size.parse().ok());
+    let mtime = info
+        .next()
+        .and_then(|mtime| u64::from_str_radix(mtime, 8).ok())
+        .filter(|&secs| secs > 0)
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+    (name, size, mtime)
+}
+
+fn receive_batch(
+    link: &mut impl Link,
+    dir: &Path,
+    overwrite: bool,
+    streaming: bool,
+) -> Result<Vec<ReceivedFile>, XmodemError> {
+    let request = if streaming {
+        STREAM_REQUEST
+    } else {
+        CRC_REQUEST
+    };
+    let mut files = Vec::new();
+    loop {
+        let header = receive_header(link, request)?;
+        let (name, size, mtime) = parse_header(&header);
+        debug!("ymodem: header for {:?}, size {:?}", name, size);
+        if name.is_empty() {
+            return Ok(files);
+        }
+        let file_name = Path::new(&name).file_name().ok_or_else(|| {
+            let msg = format!("unusable file name {:?}", name);
+            io::Error::new(io::ErrorKind::InvalidData, msg)
+        })?;
+        let (path, file) = create_file(&dir.join(file_name), overwrite)?;
+        let size_text = size.map_or(String::new(), |size| format!(" ({} bytes)", size));
+        link.status(&format!("ymodem receiving {}{}", path.display(), size_text));
+        let mut out = BufWriter::new(file);
+        let bytes = receive_data(link, &mut out, size, request, streaming)?;
+        debug!("ymodem: {} bytes of {:?}", bytes, name);
+        let file: File = out.into_inner().map_err(|err| err.into_error())?;
+        if let Some(mtime) = mtime {
+            // the data is what matters, a file system without times still takes it
+            let _ = file.set_modified(mtime);
+        }
+        link.status(&format!("ymodem {}: {} bytes done", path.display(), bytes));
+        files.push(ReceivedFile { path, bytes });
+    }
+}
+
+// Ask for block 0 until it comes and acknowledge it
+fn receive_header(link: &mut impl Link, request: u8) -> Result<Vec<u8>, XmodemError> {
+    let mut errors: u32 = 0;
+    loop {
+        if link.cancelled() {
+            return Err(XmodemError::Cancelled);
+        }
+        if errors >= MAX_RETRIES {
+            return Err(XmodemError::Timeout);
+        }
+        link.write_all(&[request])?;
+        let block_size = match link.read_byte(Duration::from_secs(3))? {
+            Some(SOH) => 128,
+            Some(STX) => 1024,
+            Some(CAN) if second_can(link)? => return Err(XmodemError::PeerCancelled),
+            Some(_) => continue,
+            None => {
+                errors += 1;
+                continue;
+            }
+        };
+        match read_block(link, block_size, Check::Crc16)? {
+            Some((0, header)) => {
+                link.write_all(&[ACK])?;
+                return Ok(header);
+            }
+            _ => {
+                errors += 1;
+                purge(link)?;
+            }
+        }
+    }
+}
+
+// Take the data blocks of a file until EOT, keeping no more than the `size` announced
+fn receive_data(
+    link: &mut impl Link,
+    out: &mut impl Write,
+    size: Option<u64>,
+    request: u8,
+    streaming: bool,
+) -> Result<u64, XmodemError> {
+    let mut progress = Progress::default();
+    let mut expected: u8 = 1;
+    let mut errors: u32 = 0;
+    let mut written: u64 = 0;
+    link.write_all(&[request])?;
+    loop {
+        if link.cancelled() {
+            return Err(XmodemError::Cancelled);
+        }
+        let block_size = match link.read_byte(RESPONSE_TIMEOUT)? {
+            Some(SOH) => 128,
+            Some(STX) => 1024,
+            Some(EOT) => {
+                link.write_all(&[ACK])?;
+                out.flush()?;
+                return Ok(written);
+            }
+            Some(CAN) if second_can(link)? => return Err(XmodemError::PeerCancelled),
+            Some(_) => continue,
+            None if streaming => return Err(XmodemError::Timeout),
+            None => {
+                errors += 1;
+                if errors > MAX_RETRIES {
+                    return Err(XmodemError::TooManyRetries);
+                }
+                link.write_all(&[NAK])?;
+                continue;
+            }
+        };
+
+        let (number, block) = match read_block(link, block_size, Check::Crc16)? {
+            Some(read) => read,
+            // YMODEM-G has no way to send a block again
+            None if streaming => {
+                let msg = "garbled block, YMODEM-G can't repeat it";
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg).into());
+            }
+            None => {
+                errors += 1;
+                progress.retries += 1;
+                link.progress(&progress);
+                if errors > MAX_RETRIES {
+                    return Err(XmodemError::TooManyRetries);
+                }
+                purge(link)?;
+                link.write_all(&[NAK])?;
+                continue;
+            }
+        };
+
+        if number == expected {
+            // the padding of the last block is beyond the size
+            let keep = match size {
+                Some(size) => size.saturating_sub(written).min(block.len() as u64) as usize,
+                None => block.len(),
+            };
+            out.write_all(&block[..keep])?;
+            written += keep as u64;
+            expected = expected.wrapping_add(1);
+            errors = 0;
+            progress.blocks += 1;
+            progress.bytes = written;
+            link.progress(&progress);
+        } else if number != expected.wrapping_sub(1) {
+            return Err(XmodemError::OutOfSequence);
+        }
+        if !streaming {
+            // a repeated block means our ACK got lost
+            link.write_all(&[ACK])?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+    use std::thread;
+
+    // One end of an in-memory line between a sender and a receiver
+    struct PipeLink {
+        rx: Receiver<u8>,
+        tx: Sender<u8>,
+    }
+
+    fn pipe() -> (PipeLink, PipeLink) {
+        let (a_tx, a_rx) = channel();
+        let (b_tx, b_rx) = channel();
+        (
+            PipeLink { rx: a_rx, tx: b_tx },
+            PipeLink { rx: b_rx, tx: a_tx },
+        )
+    }
+
+    impl Link for PipeLink {
+        fn read_byte(&mut self, timeout: Duration) -> io::Result<Option<u8>> {
+            match self.rx.recv_timeout(timeout) {
+                Ok(byte) => Ok(Some(byte)),
+                Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => Ok(None),
+            }
+        }
+        fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+            for &byte in data {
+                // the other side may be done already
+                let _ = self.tx.send(byte);
+            }
+            Ok(())
+        }
+        fn cancelled(&mut self) -> bool {
+            false
+        }
+        fn progress(&mut self, _progress: &Progress) {}
+        fn status(&mut self, _msg: &str) {}
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("scip-ymodem-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn transfer(name: &str, data: &[u8], size: u64, streaming: bool) -> Vec<u8> {
+        let dir = temp_dir(name);
+        let (mut sender, mut receiver) = pipe();
+        let file = Outgoing {
+            name: "sub/dir/data.bin".to_string(),
+            size,
+            mtime: None,
+            data: io::Cursor::new(data.to_vec()),
+        };
+        let sending = thread::spawn(move || {
+            send(&mut sender, vec![file])
+                .map(|progress| progress.bytes)
+                .ok()
+        });
+        let files = receive(&mut receiver, &dir, false, streaming).ok().unwrap();
+        assert_eq!(sending.join().unwrap(), Some(data.len() as u64));
+        // only the name of the file is taken, not where it was
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, dir.join("data.bin"));
+        let received = fs::read(&files[0].path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        received
+    }
+
+    #[test]
+    fn block_0_has_the_name_size_and_time() {
+        let mtime = UNIX_EPOCH + Duration::from_secs(0o14371573270);
+        let header = header_block("data.bin", 1234, Some(mtime));
+        assert_eq!(header.len(), 128);
+        assert!(header.starts_with(b"data.bin\x001234 14371573270\x00"));
+        assert_eq!(
+            parse_header(&header),
+            ("data.bin".to_string(), Some(1234), Some(mtime))
+        );
+    }
+
+    #[test]
+    fn block_0_may_leave_out_the_size_and_time() {
+        assert_eq!(
+            parse_header(b"data.bin\x00\x00\x00"),
+            ("data.bin".to_string(), None, None)
+        );
+        // a time of 0 is unknown
+        assert_eq!(parse_header(b"a\x0012 0\x00").2, None);
+        // a long name takes a 1K block
+        assert_eq!(header_block(&"n".repeat(200), 1, None).len(), 1024);
+    }
+
+    #[test]
+    fn an_empty_block_0_ends_the_batch() {
+        let (mut pipe_end, mut receiver) = pipe();
+        pipe_end
+            .write_all(&block_packet(0, &[0; 128], Check::Crc16))
+            .unwrap();
+        let files = receive(&mut receiver, Path::new("/nonexistent"), false, false)
+            .ok()
+            .unwrap();
+        assert!(files.is_empty());
+        let answer: Vec<u8> = pipe_end.rx.try_iter().collect();
+        assert_eq!(answer, [CRC_REQUEST, ACK]);
+    }
+
+    #[test]
+    fn the_size_cuts_off_the_padding() {
+        let data: Vec<u8> = (0..1500).map(|i| i as u8).collect();
+        assert_eq!(transfer("acked", &data, 1500, false), data);
+        assert_eq!(transfer("streamed", &data, 1500, true), data);
+    }
+
+    #[test]
+    fn no_more_than_the_size_is_kept() {
+        let data = [b'x'; 200];
+        assert_eq!(transfer("shorter", &data, 150, false), [b'x'; 150]);
+    }
+}
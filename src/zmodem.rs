@@ -0,0 +1,788 @@
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::{debug, trace};
+
+use crate::xmodem::{crc16, Link, Progress};
+
+const ZPAD: u8 = b'*';
+const ZDLE: u8 = 0x18;
+const CAN: u8 = 0x18;
+const XON: u8 = 0x11;
+const XOFF: u8 = 0x13;
+
+// Header formats after ZPAD ZDLE
+const ZBIN: u8 = b'A';
+const ZHEX: u8 = b'B';
+const ZBIN32: u8 = b'C';
+
+// Frame types
+const ZRQINIT: u8 = 0;
+const ZRINIT: u8 = 1;
+const ZSINIT: u8 = 2;
+const ZACK: u8 = 3;
+const ZFILE: u8 = 4;
+const ZSKIP: u8 = 5;
+const ZNAK: u8 = 6;
+const ZABORT: u8 = 7;
+const ZFIN: u8 = 8;
+const ZRPOS: u8 = 9;
+const ZDATA: u8 = 10;
+const ZEOF: u8 = 11;
+const ZFERR: u8 = 12;
+const ZCAN: u8 = 16;
+
+// Ends of a data subpacket, after a ZDLE
+const ZCRCE: u8 = b'h';
+const ZCRCG: u8 = b'i';
+const ZCRCQ: u8 = b'j';
+const ZCRCW: u8 = b'k';
+const ZRUB0: u8 = b'l';
+const ZRUB1: u8 = b'm';
+
+// ZRINIT flags: full duplex, receiving while writing the file, 32 bit CRCs
+const CANFDX: u8 = 0x01;
+const CANOVIO: u8 = 0x02;
+const CANFC32: u8 = 0x20;
+
+// What `sz` starts with, a hex ZRQINIT header
+const START_SEQUENCE: &[u8] = b"**\x18B00";
+
+const MAX_SUBPACKET: usize = 8192;
+const MAX_RETRIES: u32 = 10;
+const HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+const BYTE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub enum ZmodemError {
+    Cancelled,
+    PeerCancelled,
+    Timeout,
+    TooManyRetries,
+    Io(io::Error),
+}
+
+impl fmt::Display for ZmodemError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ZmodemError::Cancelled => write!(f, "cancelled"),
+            ZmodemError::PeerCancelled => write!(f, "cancelled by the other side"),
+            ZmodemError::Timeout => write!(f, "timed out waiting for the other side"),
+            ZmodemError::TooManyRetries => write!(f, "too many retries"),
+            ZmodemError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<io::Error> for ZmodemError {
+    fn from(err: io::Error) -> ZmodemError {
+        ZmodemError::Io(err)
+    }
+}
+
+// Why a header or subpacket couldn't be read, the sender is asked to repeat those
+enum ReadError {
+    Garbled,
+    Timeout,
+    Fatal(ZmodemError),
+}
+
+impl From<io::Error> for ReadError {
+    fn from(err: io::Error) -> ReadError {
+        ReadError::Fatal(ZmodemError::Io(err))
+    }
+}
+
+enum Escaped {
+    Byte(u8),
+    // ZDLE and one of ZCRCE, ZCRCG, ZCRCQ or ZCRCW
+    FrameEnd(u8),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Crc {
+    Crc16,
+    Crc32,
+}
+
+struct Header {
+    kind: u8,
+    data: [u8; 4],
+    // the check used by the data subpackets that follow
+    crc: Crc,
+}
+
+impl Header {
+    fn position(&self) -> u32 {
+        u32::from_le_bytes(self.data)
+    }
+}
+
+// Spots `sz` starting in the received data, even when the start is split across reads
+pub struct StartDetector {
+    tail: Vec<u8>,
+}
+
+impl Default for StartDetector {
+    fn default() -> StartDetector {
+        StartDetector::new()
+    }
+}
+
+impl StartDetector {
+    pub fn new() -> StartDetector {
+        StartDetector { tail: Vec::new() }
+    }
+
+    // How much of `data` comes before the start, and the bytes from the start on
+    pub fn find(&mut self, data: &[u8]) -> Option<(usize, Vec<u8>)> {
+        let mut joined = std::mem::take(&mut self.tail);
+        let carried = joined.len();
+        joined.extend_from_slice(data);
+        match joined
+            .windows(START_SEQUENCE.len())
+            .position(|window| window == START_SEQUENCE)
+        {
+            Some(start) => Some((start.saturating_sub(carried), joined.split_off(start))),
+            None => {
+                let keep = joined.len().min(START_SEQUENCE.len() - 1);
+                self.tail = joined.split_off(joined.len() - keep);
+                None
+            }
+        }
+    }
+}
+
+pub struct ReceivedFile {
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xffff_ffff;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+// Receive the files `sz` sends into `dir`, `pending` is what was read while detecting the start
+pub fn receive(
+    link: &mut impl Link,
+    pending: &[u8],
+    dir: &Path,
+    overwrite: bool,
+) -> Result<Vec<ReceivedFile>, ZmodemError> {
+    let mut receiver = Receiver {
+        link,
+        pending: pending.iter().rev().copied().collect(),
+    };
+    let result = receiver.receive_files(dir, overwrite);
+    if let Err(err) = &result {
+        if !matches!(err, ZmodemError::PeerCancelled) {
+            // best effort, the transfer failed already
+            let mut abort = vec![CAN; 10];
+            abort.extend_from_slice(&[0x08; 10]);
+            let _ = receiver.link.write_all(&abort);
+        }
+    }
+    result
+}
+
+struct Receiver<'a, L: Link> {
+    link: &'a mut L,
+    // bytes to read before the link's, in reverse order
+    pending: Vec<u8>,
+}
+
+impl<L: Link> Receiver<'_, L> {
+    fn receive_files(
+        &mut self,
+        dir: &Path,
+        overwrite: bool,
+    ) -> Result<Vec<ReceivedFile>, ZmodemError> {
+        let mut files = Vec::new();
+        let mut errors: u32 = 0;
+        loop {
+            if self.link.cancelled() {
+                return Err(ZmodemError::Cancelled);
+            }
+            let header = match self.read_header() {
+                Ok(header) => header,
+                Err(ReadError::Fatal(err)) => return Err(err),
+                Err(err) => {
+                    errors += 1;
+                    if errors > MAX_RETRIES {
+                        return Err(match err {
+                            ReadError::Timeout => ZmodemError::Timeout,
+                            _ => ZmodemError::TooManyRetries,
+                        });
+                    }
+                    self.send_hex_header(ZRINIT, [0, 0, 0, CANFDX | CANOVIO | CANFC32])?;
+                    continue;
+                }
+            };
+            errors = 0;
+            debug!("zmodem: header {} with {:02x?}", header.kind, header.data);
+            match header.kind {
+                ZRQINIT => self.send_hex_header(ZRINIT, [0, 0, 0, CANFDX | CANOVIO | CANFC32])?,
+                // the attention string isn't needed, commands are never run
+                ZSINIT => match self.read_subpacket(header.crc) {
+                    Ok(_) => self.send_hex_header(ZACK, [0; 4])?,
+                    Err(ReadError::Fatal(err)) => return Err(err),
+                    Err(_) => self.send_hex_header(ZNAK, [0; 4])?,
+                },
+                ZFILE => {
+                    let info = match self.read_subpacket(header.crc) {
+                        Ok((info, _)) => info,
+                        Err(ReadError::Fatal(err)) => return Err(err),
+                        Err(_) => {
+                            self.send_hex_header(ZNAK, [0; 4])?;
+                            continue;
+                        }
+                    };
+                    let (name, size) = parse_file_info(&info);
+                    debug!("zmodem: file {:?}, size {:?}", name, size);
+                    let file_name = match Path::new(&name).file_name() {
+                        Some(file_name) => file_name.to_owned(),
+                        None => {
+                            self.link.status(&format!("zmodem: skipping {:?}", name));
+                            self.send_hex_header(ZSKIP, [0; 4])?;
+                            continue;
+                        }
+                    };
+                    let (path, file) = create_file(&dir.join(file_name), overwrite)?;
+                    let size_text = size.map_or(String::new(), |size| format!(" ({} bytes)", size));
+                    self.link
+                        .status(&format!("zmodem receiving {}{}", path.display(), size_text));
+                    let bytes = self.receive_file(&mut BufWriter::new(file))?;
+                    self.link
+                        .status(&format!("zmodem {}: {} bytes done", path.display(), bytes));
+                    files.push(ReceivedFile { path, bytes });
+                    self.send_hex_header(ZRINIT, [0, 0, 0, CANFDX | CANOVIO | CANFC32])?;
+                }
+                ZFIN => {
+                    self.send_hex_header(ZFIN, [0; 4])?;
+                    // the sender's "OO" is optional, don't show it if it comes
+                    for _ in 0..2 {
+                        if self.link.read_byte(Duration::from_millis(500))?.is_none() {
+                            break;
+                        }
+                    }
+                    return Ok(files);
+                }
+                ZABORT | ZCAN | ZFERR => return Err(ZmodemError::PeerCancelled),
+                _ => self.send_hex_header(ZRINIT, [0, 0, 0, CANFDX | CANOVIO | CANFC32])?,
+            }
+        }
+    }
+
+    // Ask for the data from the start and take it until ZEOF, returning the file size
+    fn receive_file(&mut self, out: &mut impl Write) -> Result<u64, ZmodemError> {
+        let mut position: u32 = 0;
+        let mut progress = Progress::default();
+        self.send_hex_header(ZRPOS, position.to_le_bytes())?;
+        loop {
+            if self.link.cancelled() {
+                return Err(ZmodemError::Cancelled);
+            }
+            let header = match self.read_header() {
+                Ok(header) => header,
+                Err(ReadError::Fatal(err)) => return Err(err),
+                Err(_) => {
+                    self.retry(&mut progress, position)?;
+                    continue;
+                }
+            };
+            match header.kind {
+                ZDATA if header.position() != position => self.retry(&mut progress, position)?,
+                ZDATA => loop {
+                    let (data, end) = match self.read_subpacket(header.crc) {
+                        Ok(subpacket) => subpacket,
+                        Err(ReadError::Fatal(err)) => return Err(err),
+                        Err(_) => {
+                            self.retry(&mut progress, position)?;
+                            break;
+                        }
+                    };
+                    out.write_all(&data)?;
+                    position = position.wrapping_add(data.len() as u32);
+                    progress.blocks += 1;
+                    progress.bytes += data.len() as u64;
+                    self.link.progress(&progress);
+                    match end {
+                        ZCRCW => {
+                            self.send_hex_header(ZACK, position.to_le_bytes())?;
+                            break;
+                        }
+                        ZCRCQ => self.send_hex_header(ZACK, position.to_le_bytes())?,
+                        ZCRCG => {}
+                        _ => break,
+                    }
+                },
+                // a stale ZEOF from before a retry is ignored
+                ZEOF if header.position() == position => {
+                    out.flush()?;
+                    return Ok(progress.bytes);
+                }
+                ZEOF => {}
+                ZFIN | ZABORT | ZCAN | ZFERR => return Err(ZmodemError::PeerCancelled),
+                _ => self.retry(&mut progress, position)?,
+            }
+        }
+    }
+
+    fn retry(&mut self, progress: &mut Progress, position: u32) -> Result<(), ZmodemError> {
+        debug!("zmodem: asking again from {}", position);
+        progress.retries += 1;
+        self.link.progress(progress);
+        if progress.retries > MAX_RETRIES * 10 {
+            return Err(ZmodemError::TooManyRetries);
+        }
+        self.send_hex_header(ZRPOS, position.to_le_bytes())
+    }
+
+    fn read_raw(&mut self, timeout: Duration) -> Result<u8, ReadError> {
+        if let Some(byte) = self.pending.pop() {
+            return Ok(byte);
+        }
+        match self.link.read_byte(timeout)? {
+            Some(byte) => Ok(byte),
+            // reading stops early when the user cancels
+            None if self.link.cancelled() => Err(ReadError::Fatal(ZmodemError::Cancelled)),
+            None => Err(ReadError::Timeout),
+        }
+    }
+
+    // Skip to the next header, anything in between is line noise or left over
+    fn read_header(&mut self) -> Result<Header, ReadError> {
+        let mut cans = 0;
+        let mut pads = 0;
+        loop {
+            let byte = self.read_raw(HEADER_TIMEOUT)?;
+            if byte == CAN {
+                cans += 1;
+                if cans >= 5 {
+                    return Err(ReadError::Fatal(ZmodemError::PeerCancelled));
+                }
+            } else {
+                cans = 0;
+            }
+            match byte {
+                ZPAD | 0xaa => {
+                    pads += 1;
+                    continue;
+                }
+                ZDLE if pads > 0 => pads = 0,
+                _ => {
+                    pads = 0;
+                    continue;
+                }
+            }
+            // data that only looks like the start of a header is skipped like other noise
+            let format = self.read_raw(BYTE_TIMEOUT)?;
+            match format & 0x7f {
+                ZHEX => return self.read_hex_header(),
+                ZBIN => return self.read_binary_header(Crc::Crc16),
+                ZBIN32 => return self.read_binary_header(Crc::Crc32),
+                _ if format == CAN => {
+                    cans += 1;
+                    if cans >= 5 {
+                        return Err(ReadError::Fatal(ZmodemError::PeerCancelled));
+                    }
+                }
+                _ => cans = 0,
+            }
+        }
+    }
+
+    fn read_hex_header(&mut self) -> Result<Header, ReadError> {
+        let mut bytes = [0u8; 7];
+        for byte in bytes.iter_mut() {
+            let high = hex_value(self.read_raw(BYTE_TIMEOUT)?).ok_or(ReadError::Garbled)?;
+            let low = hex_value(self.read_raw(BYTE_TIMEOUT)?).ok_or(ReadError::Garbled)?;
+            *byte = high << 4 | low;
+        }
+        if crc16(&bytes[..5]).to_be_bytes() != bytes[5..] {
+            return Err(ReadError::Garbled);
+        }
+        // CR LF and maybe an XON follow, a data subpacket could come right after
+        let after = self.read_raw(BYTE_TIMEOUT)?;
+        if after & 0x7f == b'\r' {
+            let lf = self.read_raw(BYTE_TIMEOUT)?;
+            if lf & 0x7f != b'\n' {
+                self.pending.push(lf);
+            }
+        } else {
+            self.pending.push(after);
+        }
+        Ok(Header {
+            kind: bytes[0],
+            data: [bytes[1], bytes[2], bytes[3], bytes[4]],
+            crc: Crc::Crc16,
+        })
+    }
+
+    fn read_binary_header(&mut self, crc: Crc) -> Result<Header, ReadError> {
+        let crc_len = match crc {
+            Crc::Crc16 => 2,
+            Crc::Crc32 => 4,
+        };
+        let mut bytes = Vec::with_capacity(5 + crc_len);
+        while bytes.len() < 5 + crc_len {
+            match self.read_escaped()? {
+                Escaped::Byte(byte) => bytes.push(byte),
+                Escaped::FrameEnd(_) => return Err(ReadError::Garbled),
+            }
+        }
+        if !crc_matches(crc, &bytes[..5], &bytes[5..]) {
+            return Err(ReadError::Garbled);
+        }
+        Ok(Header {
+            kind: bytes[0],
+            data: [bytes[1], bytes[2], bytes[3], bytes[4]],
+            crc,
+        })
+    }
+
+    // Data up to a frame end and the frame end itself
+    fn read_subpacket(&mut self, crc: Crc) -> Result<(Vec<u8>, u8), ReadError> {
+        let mut data = Vec::new();
+        let end = loop {
+            match self.read_escaped()? {
+                Escaped::Byte(byte) => data.push(byte),
+                Escaped::FrameEnd(end) => break end,
+            }
+            if data.len() > MAX_SUBPACKET {
+                return Err(ReadError::Garbled);
+            }
+        };
+        let crc_len = match crc {
+            Crc::Crc16 => 2,
+            Crc::Crc32 => 4,
+        };
+        let mut check = Vec::with_capacity(crc_len);
+        while check.len() < crc_len {
+            match self.read_escaped()? {
+                Escaped::Byte(byte) => check.push(byte),
+                Escaped::FrameEnd(_) => return Err(ReadError::Garbled),
+            }
+        }
+        data.push(end);
+        if !crc_matches(crc, &data, &check) {
+            return Err(ReadError::Garbled);
+        }
+        data.pop();
+        Ok((data, end))
+    }
+
+    fn read_escaped(&mut self) -> Result<Escaped, ReadError> {
+        loop {
+            let byte = self.read_raw(BYTE_TIMEOUT)?;
+            match byte {
+                // flow control from the line, never data
+                XON | XOFF | 0x91 | 0x93 => continue,
+                ZDLE => {}
+                _ => return Ok(Escaped::Byte(byte)),
+            }
+            let mut escaped = self.read_raw(BYTE_TIMEOUT)?;
+            let mut cans = 1;
+            while escaped == CAN {
+                cans += 1;
+                if cans >= 5 {
+                    return Err(ReadError::Fatal(ZmodemError::PeerCancelled));
+                }
+                escaped = self.read_raw(BYTE_TIMEOUT)?;
+            }
+            return Ok(match escaped {
+                ZCRCE | ZCRCG | ZCRCQ | ZCRCW => Escaped::FrameEnd(escaped),
+                ZRUB0 => Escaped::Byte(0x7f),
+                ZRUB1 => Escaped::Byte(0xff),
+                XON | XOFF | 0x91 | 0x93 => continue,
+                _ if escaped & 0x60 == 0x40 => Escaped::Byte(escaped ^ 0x40),
+                _ => return Err(ReadError::Garbled),
+            });
+        }
+    }
+
+    fn send_hex_header(&mut self, kind: u8, data: [u8; 4]) -> Result<(), ZmodemError> {
+        trace!("zmodem: sending header {} with {:02x?}", kind, data);
+        let mut bytes = vec![kind];
+        bytes.extend_from_slice(&data);
+        let crc = crc16(&bytes);
+        bytes.extend_from_slice(&crc.to_be_bytes());
+        let mut frame = vec![ZPAD, ZPAD, ZDLE, ZHEX];
+        for byte in bytes {
+            frame.extend_from_slice(format!("{:02x}", byte).as_bytes());
+        }
+        frame.extend_from_slice(b"\r\x8a");
+        if kind != ZACK && kind != ZFIN {
+            frame.push(XON);
+        }
+        Ok(self.link.write_all(&frame)?)
+    }
+}
+
+fn crc_matches(crc: Crc, data: &[u8], check: &[u8]) -> bool {
+    match crc {
+        Crc::Crc16 => crc16(data).to_be_bytes() == check,
+        Crc::Crc32 => crc32(data).to_le_bytes() == check,
+    }
+}
+
+fn hex_value(byte: u8) -> Option<u8> {
+    (byte as char).to_digit(16).map(|digit| digit as u8)
+}
+
+// The file name and, if the sender gave it, the size from a ZFILE subpacket
+fn parse_file_info(info: &[u8]) -> (String, Option<u64>) {
+    let mut fields = info.splitn(2, |&byte| byte == 0);
+    let name = String::from_utf8_lossy(fields.next().unwrap_or_default()).into_owned();
+    let size = fields
+        .next()
+        .map(|rest| String::from_utf8_lossy(rest).into_owned())
+        .and_then(|rest| rest.split_whitespace().next()?.parse().ok());
+    (name, size)
+}
+
+// Open `path` for writing, or the first free `path.N` unless `overwrite`
+pub fn create_file(path: &Path, overwrite: bool) -> io::Result<(PathBuf, File)> {
+    if overwrite {
+        return Ok((path.to_path_buf(), File::create(path)?));
+    }
+    let mut candidate = path.to_path_buf();
+    let mut suffix = 1;
+    loop {
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+        {
+            Ok(file) => return Ok((candidate, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && suffix < 1000 => {
+                let mut name = path.as_os_str().to_owned();
+                name.push(format!(".{}", suffix));
+                candidate = PathBuf::from(name);
+                suffix += 1;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::fs;
+
+    // The sender's side of the line: `input` to read, then silence
+    #[derive(Default)]
+    struct MockLink {
+        input: VecDeque<u8>,
+        written: Vec<u8>,
+    }
+
+    impl Link for MockLink {
+        fn read_byte(&mut self, _timeout: Duration) -> io::Result<Option<u8>> {
+            Ok(self.input.pop_front())
+        }
+        fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+            self.written.extend_from_slice(data);
+            Ok(())
+        }
+        fn cancelled(&mut self) -> bool {
+            false
+        }
+        fn progress(&mut self, _progress: &Progress) {}
+        fn status(&mut self, _msg: &str) {}
+    }
+
+    fn hex_header(kind: u8, data: [u8; 4]) -> Vec<u8> {
+        let mut link = MockLink::default();
+        let mut sender = Receiver {
+            link: &mut link,
+            pending: Vec::new(),
+        };
+        sender.send_hex_header(kind, data).ok().unwrap();
+        link.written
+    }
+
+    // ZDLE in front of the bytes the line can't carry as they are
+    fn escape(data: &[u8]) -> Vec<u8> {
+        let mut escaped = Vec::new();
+        for &byte in data {
+            match byte {
+                ZDLE | XON | XOFF | 0x10 | 0x90 | 0x91 | 0x93 => {
+                    escaped.extend_from_slice(&[ZDLE, byte ^ 0x40])
+                }
+                _ => escaped.push(byte),
+            }
+        }
+        escaped
+    }
+
+    fn subpacket(data: &[u8], end: u8) -> Vec<u8> {
+        let mut checked = data.to_vec();
+        checked.push(end);
+        let mut packet = escape(data);
+        packet.extend_from_slice(&[ZDLE, end]);
+        packet.extend_from_slice(&escape(&crc16(&checked).to_be_bytes()));
+        packet
+    }
+
+    fn read<T>(input: &[u8], f: impl FnOnce(&mut Receiver<MockLink>) -> T) -> T {
+        let mut link = MockLink {
+            input: input.iter().copied().collect(),
+            ..MockLink::default()
+        };
+        f(&mut Receiver {
+            link: &mut link,
+            pending: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn crc32_is_that_of_zip() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn the_start_of_sz_is_spotted() {
+        let mut detector = StartDetector::new();
+        assert!(detector.find(b"$ sz firmware.bin\r\n").is_none());
+        let (before, start) = detector
+            .find(b"rz\r**\x18B00000000000000\r\x8a\x11")
+            .unwrap();
+        assert_eq!(before, 3);
+        assert!(start.starts_with(START_SEQUENCE));
+        // other headers don't start a transfer
+        assert!(StartDetector::new().find(b"**\x18B01").is_none());
+    }
+
+    #[test]
+    fn the_start_of_sz_is_spotted_across_reads() {
+        let mut detector = StartDetector::new();
+        assert!(detector.find(b"rz\r*").is_none());
+        assert!(detector.find(b"*\x18").is_none());
+        let (before, start) = detector.find(b"B0000").unwrap();
+        // the start began in an earlier read
+        assert_eq!(before, 0);
+        assert_eq!(start, b"**\x18B0000");
+
+        // and one byte at a time
+        let mut detector = StartDetector::new();
+        let found: Vec<_> = b"rz\r**\x18B00"
+            .iter()
+            .filter_map(|&byte| detector.find(&[byte]))
+            .collect();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].1, START_SEQUENCE);
+    }
+
+    #[test]
+    fn hex_headers_are_decoded() {
+        let frame = hex_header(ZRPOS, 1234u32.to_le_bytes());
+        assert!(frame.starts_with(b"**\x18B09d2040000"));
+        let header = read(&frame, |receiver| receiver.read_header().ok().unwrap());
+        assert_eq!((header.kind, header.position()), (ZRPOS, 1234));
+
+        let mut garbled = frame.clone();
+        garbled[6] = b'8';
+        assert!(matches!(
+            read(&garbled, |receiver| receiver.read_header()),
+            Err(ReadError::Garbled)
+        ));
+    }
+
+    #[test]
+    fn binary_headers_with_crc32_are_decoded() {
+        let data = [ZDATA, 0x18, 0, 0, 0];
+        let mut frame = vec![ZPAD, ZDLE, ZBIN32];
+        frame.extend_from_slice(&escape(&data));
+        frame.extend_from_slice(&escape(&crc32(&data).to_le_bytes()));
+        // line noise before it is skipped
+        let input = [b"noise*".as_slice(), &frame].concat();
+        let header = read(&input, |receiver| receiver.read_header().ok().unwrap());
+        assert_eq!(header.kind, ZDATA);
+        assert_eq!(header.position(), 0x18);
+        assert!(header.crc == Crc::Crc32);
+    }
+
+    #[test]
+    fn subpackets_are_unescaped_and_checked() {
+        let data = b"a\x18b\x11c\x7f";
+        let packet = subpacket(data, ZCRCW);
+        let (read_data, end) = read(&packet, |receiver| {
+            receiver.read_subpacket(Crc::Crc16).ok().unwrap()
+        });
+        assert_eq!((read_data.as_slice(), end), (data.as_slice(), ZCRCW));
+
+        let mut garbled = packet;
+        garbled[0] = b'A';
+        assert!(matches!(
+            read(&garbled, |receiver| receiver.read_subpacket(Crc::Crc16)),
+            Err(ReadError::Garbled)
+        ));
+    }
+
+    #[test]
+    fn five_cans_cancel() {
+        assert!(matches!(
+            read(&[CAN; 5], |receiver| receiver.read_header()),
+            Err(ReadError::Fatal(ZmodemError::PeerCancelled))
+        ));
+    }
+
+    #[test]
+    fn a_file_is_received() {
+        let dir = std::env::temp_dir().join(format!("scip-zmodem-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let data = b"hello from sz\n";
+        let start = hex_header(ZRQINIT, [0; 4]);
+        let input = [
+            &start[START_SEQUENCE.len()..],
+            &hex_header(ZFILE, [0; 4]),
+            &subpacket(b"up/hello.txt\x0014 0 0\x00", ZCRCW),
+            &hex_header(ZDATA, [0; 4]),
+            &subpacket(data, ZCRCE),
+            &hex_header(ZEOF, (data.len() as u32).to_le_bytes()),
+            &hex_header(ZFIN, [0; 4]),
+            b"OO",
+        ]
+        .concat();
+        let mut link = MockLink {
+            input: input.into_iter().collect(),
+            ..MockLink::default()
+        };
+        // what the detector passed on comes first
+        let files = receive(&mut link, START_SEQUENCE, &dir, false)
+            .ok()
+            .unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].path, dir.join("hello.txt"));
+        assert_eq!(files[0].bytes, data.len() as u64);
+        assert_eq!(fs::read(&files[0].path).unwrap(), data);
+        // ZRINIT, ZRPOS from 0, ZRINIT after the file, then ZFIN
+        let zrinit = hex_header(ZRINIT, [0, 0, 0, CANFDX | CANOVIO | CANFC32]);
+        let expected = [
+            &zrinit[..],
+            &hex_header(ZRPOS, [0; 4]),
+            &zrinit,
+            &hex_header(ZFIN, [0; 4]),
+        ]
+        .concat();
+        assert_eq!(link.written, expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
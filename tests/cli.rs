@@ -0,0 +1,165 @@
+// The scip binary run the way a shell, a script or a supervisor runs it
+
+use std::process::{Command, Stdio};
+#[cfg(unix)]
+use std::{
+    fs::File,
+    io::{Read, Write},
+    os::unix::io::FromRawFd,
+    os::unix::net::UnixListener,
+    path::PathBuf,
+    process::Child,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+fn scip(args: &[&str]) -> (Option<i32>, String, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_scip"))
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    (
+        output.status.code(),
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(output.stderr).unwrap(),
+    )
+}
+
+// A Unix socket standing in for the device, scip connects to it like to QEMU's console
+#[cfg(unix)]
+fn device(name: &str) -> (PathBuf, UnixListener) {
+    let path = std::env::temp_dir().join(format!("scip-{}-{}.sock", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    (path, listener)
+}
+
+#[cfg(unix)]
+fn start(device: &PathBuf, args: &[&str], stdin: Stdio, stdout: Stdio) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_scip"))
+        .arg(device)
+        .args(args)
+        .stdin(stdin)
+        .stdout(stdout)
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap()
+}
+
+#[cfg(unix)]
+fn wait(child: &mut Child) -> Option<i32> {
+    let until = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < until {
+        if let Some(status) = child.try_wait().unwrap() {
+            return status.code();
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    let _ = child.kill();
+    panic!("scip is still running");
+}
+
+// A pseudo-terminal, the master side for the test and the slave for scip
+#[cfg(unix)]
+fn pty() -> (File, File) {
+    let (mut master, mut slave) = (0, 0);
+    let size = libc::winsize {
+        ws_row: 24,
+        ws_col: 80,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let opened = unsafe {
+        libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            &size,
+        )
+    };
+    assert_eq!(opened, 0);
+    // scip mustn't inherit the master, the slave would never see its end
+    unsafe { libc::fcntl(master, libc::F_SETFD, libc::FD_CLOEXEC) };
+    unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) }
+}
+
+#[cfg(unix)]
+#[test]
+fn exit_on_eof_ends_a_piped_session_at_the_end_of_stdin() {
+    let (path, listener) = device("eof-pipe");
+    let mut scip = start(&path, &["--exit-on-eof"], Stdio::piped(), Stdio::piped());
+    let (mut port, _) = listener.accept().unwrap();
+    scip.stdin.take().unwrap().write_all(b"data\n").unwrap();
+    assert_eq!(wait(&mut scip), Some(0));
+    let mut sent = Vec::new();
+    port.read_to_end(&mut sent).unwrap();
+    assert_eq!(sent, b"data\n");
+    let _ = std::fs::remove_file(&path);
+}
+
+#[cfg(unix)]
+#[test]
+fn exit_on_eof_leaves_a_session_on_a_terminal_running() {
+    let (path, listener) = device("eof-terminal");
+    let (input, input_slave) = pty();
+    let (mut output, output_slave) = pty();
+    let mut scip = start(
+        &path,
+        &["--exit-on-eof"],
+        Stdio::from(input_slave),
+        Stdio::from(output_slave),
+    );
+    let (mut port, _) = listener.accept().unwrap();
+    let shown = Arc::new(Mutex::new(Vec::new()));
+    let reader = shown.clone();
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        while let Ok(n @ 1..) = output.read(&mut buf) {
+            reader.lock().unwrap().extend_from_slice(&buf[..n]);
+        }
+    });
+    let shows = |what: &str| {
+        let until = Instant::now() + Duration::from_secs(10);
+        while !String::from_utf8_lossy(&shown.lock().unwrap()).contains(what) {
+            assert!(Instant::now() < until, "{} wasn't shown", what);
+            thread::sleep(Duration::from_millis(20));
+        }
+    };
+    // the end of the terminal's input once the session runs on it
+    shows("Welcome");
+    drop(input);
+    thread::sleep(Duration::from_millis(300));
+    assert_eq!(scip.try_wait().unwrap(), None);
+    port.write_all(b"still here").unwrap();
+    shows("still here");
+    // the device closing its end disconnects, which ends the session
+    drop(port);
+    assert_ne!(wait(&mut scip), Some(0));
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn complete_devices_lists_the_ports_one_per_line() {
+    let ports: Vec<String> = serialport::available_ports()
+        .unwrap()
+        .into_iter()
+        .map(|port| port.port_name)
+        .collect();
+    let (code, out, err) = scip(&["--complete-devices"]);
+    assert_eq!(code, Some(0), "{}", err);
+    assert_eq!(out.lines().collect::<Vec<_>>(), ports);
+    assert!(out.is_empty() || out.ends_with('\n'));
+    assert_eq!(err, "");
+}
+
+#[test]
+fn complete_devices_leaves_the_given_device_alone() {
+    // the device doesn't exist, opening it would fail
+    let (code, out, err) = scip(&["--complete-devices", "/dev/scip-no-such-port"]);
+    assert_eq!(code, Some(0), "{}", err);
+    assert!(!out.contains("scip-no-such-port"));
+    assert_eq!(err, "");
+}
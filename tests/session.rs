@@ -0,0 +1,538 @@
+// A Session driven against a transport in memory, the way a frontend embeds it
+
+use std::io::{self, Read, Write};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serial_console::ansi::{TO_ALTERNATE_SCREEN, TO_MAIN_SCREEN};
+use serial_console::screen::OutputSink;
+use serial_console::transport::{Device, PortParity, PortSettings, Transport};
+use serial_console::{app, Event, Session, SessionConfig, EXIT_IO_ERROR, EXIT_SCRIPT_FAILED};
+use serialport::{DataBits, FlowControl, StopBits};
+
+// What the session wrote to the port, and what the device sends to it
+struct MemoryPort {
+    sent: Arc<Mutex<Vec<u8>>>,
+    received: Arc<Mutex<Receiver<Vec<u8>>>>,
+    writes: Writes,
+    attempts: usize,
+    // the line changes and writes in order
+    calls: Arc<Mutex<Vec<String>>>,
+}
+
+// How the port takes what is written to it
+#[derive(Clone, Copy)]
+enum Writes {
+    All,
+    // a few bytes at a time, and every third write times out
+    Flaky,
+    // nothing, the device holds the line with flow control
+    Stuck,
+}
+
+impl Write for MemoryPort {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.attempts += 1;
+        let len = match self.writes {
+            Writes::All => buf.len(),
+            Writes::Flaky if self.attempts.is_multiple_of(3) => {
+                return Err(io::ErrorKind::TimedOut.into())
+            }
+            Writes::Flaky => buf.len().min(7),
+            Writes::Stuck => return Err(io::ErrorKind::TimedOut.into()),
+        };
+        self.sent.lock().unwrap().extend_from_slice(&buf[..len]);
+        let call = format!("write {}", String::from_utf8_lossy(&buf[..len]));
+        self.calls.lock().unwrap().push(call);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Transport for MemoryPort {
+    fn set_break(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+    fn clear_break(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+    fn write_data_terminal_ready(&mut self, level: bool) -> io::Result<()> {
+        self.calls.lock().unwrap().push(format!("DTR {}", level));
+        Ok(())
+    }
+    fn write_request_to_send(&mut self, level: bool) -> io::Result<()> {
+        self.calls.lock().unwrap().push(format!("RTS {}", level));
+        Ok(())
+    }
+    fn set_baud_rate(&mut self, _baud_rate: u32) -> io::Result<()> {
+        Ok(())
+    }
+    fn set_flow_control(&mut self, _flow_control: FlowControl) -> io::Result<()> {
+        Ok(())
+    }
+    fn set_data_bits(&mut self, _data_bits: DataBits) -> io::Result<()> {
+        Ok(())
+    }
+    fn set_parity(&mut self, _parity: PortParity) -> io::Result<()> {
+        Ok(())
+    }
+    fn set_stop_bits(&mut self, _stop_bits: StopBits) -> io::Result<()> {
+        Ok(())
+    }
+    fn settings(&self) -> io::Result<PortSettings> {
+        Ok(PortSettings {
+            baud_rate: 115200,
+            data_bits: DataBits::Eight,
+            parity: PortParity::None,
+            stop_bits: StopBits::One,
+            flow_control: FlowControl::None,
+            timeout: Duration::from_millis(100),
+        })
+    }
+    fn reader(&self) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(MemoryReader(self.received.clone())))
+    }
+}
+
+struct MemoryReader(Arc<Mutex<Receiver<Vec<u8>>>>);
+
+impl Read for MemoryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self
+            .0
+            .lock()
+            .unwrap()
+            .recv_timeout(Duration::from_millis(20))
+        {
+            Ok(data) => {
+                // the tests send less than a read takes
+                buf[..data.len()].copy_from_slice(&data);
+                Ok(data.len())
+            }
+            Err(RecvTimeoutError::Timeout) => Err(io::ErrorKind::TimedOut.into()),
+            // the device stays silent once the test is done with it
+            Err(RecvTimeoutError::Disconnected) => {
+                thread::sleep(Duration::from_millis(20));
+                Err(io::ErrorKind::TimedOut.into())
+            }
+        }
+    }
+}
+
+// The terminal of the session, collecting what is shown
+#[derive(Clone, Default)]
+struct MemorySink(Arc<Mutex<Vec<u8>>>);
+
+impl Write for MemorySink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl OutputSink for MemorySink {}
+
+// A terminal that goes away once `fail` is shown on it, like a closed ssh connection
+#[derive(Clone, Default)]
+struct BreakingSink(Arc<Mutex<Vec<u8>>>);
+
+impl Write for BreakingSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.windows(4).any(|w| w == b"fail") {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl OutputSink for BreakingSink {}
+
+// The other end of a MemoryPort
+struct Remote {
+    sent: Arc<Mutex<Vec<u8>>>,
+    tx: Sender<Vec<u8>>,
+    calls: Arc<Mutex<Vec<String>>>,
+}
+
+fn memory_port(writes: Writes) -> (Box<dyn Transport>, Remote) {
+    let (tx, rx) = channel();
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let port = MemoryPort {
+        sent: sent.clone(),
+        received: Arc::new(Mutex::new(rx)),
+        writes,
+        attempts: 0,
+        calls: calls.clone(),
+    };
+    (Box::new(port), Remote { sent, tx, calls })
+}
+
+fn session(args: &[&str]) -> (Session, Remote) {
+    session_writing(args, Writes::All)
+}
+
+fn session_writing(args: &[&str], writes: Writes) -> (Session, Remote) {
+    let args = ["scip", "memory", "--no-history"].iter().chain(args);
+    let matches = app().try_get_matches_from(args).unwrap();
+    let config = SessionConfig::from_matches(&matches).unwrap();
+    let (port, remote) = memory_port(writes);
+    let device = Device::parse("memory").unwrap();
+    (Session::new(&config, device, port).unwrap(), remote)
+}
+
+// Wait for `what` to show up in `data`
+fn wait_for(data: &Arc<Mutex<Vec<u8>>>, what: &[u8]) -> bool {
+    let until = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < until {
+        if data.lock().unwrap().windows(what.len()).any(|w| w == what) {
+            return true;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    false
+}
+
+#[test]
+fn shows_received_sends_typed_and_quits() {
+    let (session, remote) = session(&[]);
+    let input = session.events();
+    let sink = MemorySink::default();
+    let shown = sink.0.clone();
+    let driver = thread::spawn(move || {
+        remote.tx.send(b"login: ".to_vec()).unwrap();
+        assert!(wait_for(&shown, b"login: "));
+        input.send(Event::Stdin(b"root\r".to_vec())).unwrap();
+        assert!(wait_for(&remote.sent, b"root\r"));
+        input.send(Event::Stdin(b"~.".to_vec())).unwrap();
+        remote
+    });
+    let status = session.run(move |_| Ok(Box::new(sink) as Box<dyn OutputSink>));
+    let remote = driver.join().unwrap();
+    assert_eq!(status, 0);
+    assert_eq!(remote.sent.lock().unwrap().as_slice(), b"root\r");
+}
+
+#[test]
+fn runs_the_chat_script_in_order() {
+    let (session, remote) = session(&[
+        "--expect",
+        "login:",
+        "--send",
+        "root\\r",
+        "--expect",
+        "#",
+        "--script-only",
+    ]);
+    let driver = thread::spawn(move || {
+        remote.tx.send(b"login: ".to_vec()).unwrap();
+        assert!(wait_for(&remote.sent, b"root\r"));
+        remote.tx.send(b"# ".to_vec()).unwrap();
+        remote
+    });
+    let status = session.run(|_| Ok(Box::new(MemorySink::default()) as Box<dyn OutputSink>));
+    let remote = driver.join().unwrap();
+    assert_eq!(status, 0);
+    assert_eq!(remote.sent.lock().unwrap().as_slice(), b"root\r");
+}
+
+#[test]
+fn a_chat_script_times_out() {
+    let (session, _remote) = session(&["--expect", "login:", "--expect-timeout", "1"]);
+    let status = session.run(|_| Ok(Box::new(MemorySink::default()) as Box<dyn OutputSink>));
+    assert_eq!(status, EXIT_SCRIPT_FAILED);
+}
+
+#[test]
+fn the_chat_script_needs_its_order() {
+    let config: SessionConfig =
+        clap::Parser::try_parse_from(["scip", "memory", "--expect", "login:", "--send", "root"])
+            .unwrap();
+    let (port, _remote) = memory_port(Writes::All);
+    let device = Device::parse("memory").unwrap();
+    assert!(Session::new(&config, device, port).is_err());
+}
+
+// Type `keys` in the given chunks and return the exit status and what reached the port
+fn type_chunks(chunks: &[&[u8]]) -> (u8, Vec<u8>) {
+    let (session, remote) = session(&[]);
+    let input = session.events();
+    for chunk in chunks {
+        input.send(Event::Stdin(chunk.to_vec())).unwrap();
+    }
+    let status = session.run(|_| Ok(Box::new(MemorySink::default()) as Box<dyn OutputSink>));
+    let sent = remote.sent.lock().unwrap().clone();
+    (status, sent)
+}
+
+#[test]
+fn the_quit_sequence_works_in_a_single_read() {
+    assert_eq!(type_chunks(&[b"ls\r~.rm -rf\r"]), (0, b"ls\r".to_vec()));
+}
+
+#[test]
+fn the_quit_sequence_works_one_byte_at_a_time() {
+    let keys: Vec<&[u8]> = b"ls\r~.".chunks(1).collect();
+    assert_eq!(type_chunks(&keys), (0, b"ls\r".to_vec()));
+}
+
+#[test]
+fn the_keys_around_an_escape_command_go_out_in_order() {
+    // ~~ sends a single ~, then the line goes on
+    let (status, sent) = type_chunks(&[b"a\r~~b~.c\r", b"~."]);
+    assert_eq!(status, 0);
+    assert_eq!(sent, b"a\r~b~.c\r");
+}
+
+#[test]
+fn short_writes_and_timeouts_lose_nothing() {
+    let (session, remote) = session_writing(&[], Writes::Flaky);
+    let input = session.events();
+    let pasted: Vec<u8> = (0..2000).map(|i| b'a' + (i % 26) as u8).collect();
+    input.send(Event::Stdin(pasted.clone())).unwrap();
+    input.send(Event::Stdin(b"\r~.".to_vec())).unwrap();
+    let status = session.run(|_| Ok(Box::new(MemorySink::default()) as Box<dyn OutputSink>));
+    assert_eq!(status, 0);
+    assert_eq!(*remote.sent.lock().unwrap(), [&pasted[..], b"\r"].concat());
+}
+
+#[test]
+fn a_write_gives_up_after_the_write_timeout() {
+    let (session, _remote) = session_writing(&["--write-timeout", "100"], Writes::Stuck);
+    let input = session.events();
+    let sink = MemorySink::default();
+    let shown = sink.0.clone();
+    input.send(Event::Stdin(b"hello".to_vec())).unwrap();
+    let driver = thread::spawn(move || {
+        assert!(wait_for(&shown, b"write timed out, dropped 5 of 5 bytes"));
+        // the session goes on
+        input.send(Event::Stdin(b"\r~.".to_vec())).unwrap();
+    });
+    let status = session.run(move |_| Ok(Box::new(sink) as Box<dyn OutputSink>));
+    driver.join().unwrap();
+    assert_eq!(status, 0);
+}
+
+#[test]
+fn hangup_on_exit_drops_the_lines_after_the_last_write() {
+    let (session, remote) = session(&["--hangup-on-exit"]);
+    let input = session.events();
+    input.send(Event::Stdin(b"bye\r~.".to_vec())).unwrap();
+    let status = session.run(|_| Ok(Box::new(MemorySink::default()) as Box<dyn OutputSink>));
+    assert_eq!(status, 0);
+    assert_eq!(
+        *remote.calls.lock().unwrap(),
+        ["write bye\r", "DTR false", "RTS false"]
+    );
+}
+
+#[test]
+fn the_lines_are_left_alone_on_exit_by_default() {
+    let (session, remote) = session(&["--no-reset"]);
+    let input = session.events();
+    input.send(Event::Stdin(b"bye\r~.".to_vec())).unwrap();
+    let status = session.run(|_| Ok(Box::new(MemorySink::default()) as Box<dyn OutputSink>));
+    assert_eq!(status, 0);
+    assert_eq!(*remote.calls.lock().unwrap(), ["write bye\r"]);
+}
+
+// Type while the pause of --init owns the port, then more once the port has `sent` and the
+// screen `shown`, and quit
+fn type_during_init(
+    tx_queue: &str,
+    sent: &'static [u8],
+    shown: &'static [u8],
+) -> (Vec<u8>, Vec<u8>) {
+    let (session, remote) = session(&["--init", "AT\\~300", "--tx-queue", tx_queue]);
+    let input = session.events();
+    let sink = MemorySink::default();
+    let screen = sink.0.clone();
+    input.send(Event::Stdin(b"ab".to_vec())).unwrap();
+    input.send(Event::Stdin(b"c".to_vec())).unwrap();
+    let driver = thread::spawn(move || {
+        assert!(wait_for(&screen, shown));
+        assert!(wait_for(&remote.sent, sent));
+        input.send(Event::Stdin(b"d\r~.".to_vec())).unwrap();
+        (remote, screen)
+    });
+    let status = session.run(move |_| Ok(Box::new(sink) as Box<dyn OutputSink>));
+    let (remote, shown) = driver.join().unwrap();
+    assert_eq!(status, 0);
+    let sent = remote.sent.lock().unwrap().clone();
+    let shown = shown.lock().unwrap().clone();
+    (sent, shown)
+}
+
+#[test]
+fn keys_typed_while_the_port_is_busy_go_out_after_in_order() {
+    let (sent, shown) = type_during_init(
+        "on",
+        b"ATabc",
+        b"the port is busy, what is typed goes out after",
+    );
+    assert_eq!(sent, b"ATabcd\r");
+    assert!(!String::from_utf8_lossy(&shown).contains("dropped"));
+}
+
+#[test]
+fn keys_typed_while_the_port_is_busy_are_dropped_with_tx_queue_off() {
+    let (sent, shown) = type_during_init(
+        "off",
+        b"AT",
+        b"dropped 3 bytes typed while the port was busy",
+    );
+    assert_eq!(sent, b"ATd\r");
+    assert!(String::from_utf8_lossy(&shown).contains("the port is busy, what is typed is dropped"));
+}
+
+#[test]
+fn a_daemon_logs_without_a_terminal_and_stops_cleanly() {
+    let log = std::env::temp_dir().join(format!("scip-daemon-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&log);
+    let (session, remote) = session(&["--daemon", "--log", log.to_str().unwrap()]);
+    let input = session.events();
+    let path = log.clone();
+    let driver = thread::spawn(move || {
+        remote.tx.send(b"booting\r\n".to_vec()).unwrap();
+        let until = Instant::now() + Duration::from_secs(5);
+        while std::fs::read(&path).unwrap_or_default() != b"booting\r\n" && Instant::now() < until {
+            thread::sleep(Duration::from_millis(10));
+        }
+        // SIGTERM
+        input.send(Event::Interrupted).unwrap();
+    });
+    let status = session.run(|_| panic!("a daemon has no terminal"));
+    driver.join().unwrap();
+    assert_eq!(status, 0);
+    assert_eq!(std::fs::read(&log).unwrap(), b"booting\r\n");
+    std::fs::remove_file(&log).unwrap();
+}
+
+#[test]
+fn a_terminal_that_breaks_ends_the_session_and_leaves_the_alternate_screen() {
+    let (session, remote) = session(&[]);
+    let sink = BreakingSink::default();
+    let shown = sink.0.clone();
+    remote.tx.send(b"ok\r\n".to_vec()).unwrap();
+    remote.tx.send(b"fail".to_vec()).unwrap();
+    let status = session.run(move |_| Ok(Box::new(sink) as Box<dyn OutputSink>));
+    assert_eq!(status, EXIT_IO_ERROR);
+    let shown = shown.lock().unwrap();
+    assert!(shown.starts_with(TO_ALTERNATE_SCREEN.as_bytes()));
+    assert!(shown.windows(4).any(|w| w == b"ok\r\n"));
+    assert!(shown.ends_with(TO_MAIN_SCREEN.as_bytes()));
+}
+
+#[test]
+fn a_stdin_error_ends_the_session_and_leaves_the_alternate_screen() {
+    let (session, _remote) = session(&[]);
+    let input = session.events();
+    let sink = MemorySink::default();
+    let shown = sink.0.clone();
+    input
+        .send(Event::StdinError(io::ErrorKind::Other.into()))
+        .unwrap();
+    let status = session.run(move |_| Ok(Box::new(sink) as Box<dyn OutputSink>));
+    assert_eq!(status, EXIT_IO_ERROR);
+    assert!(shown.lock().unwrap().ends_with(TO_MAIN_SCREEN.as_bytes()));
+}
+
+#[cfg(unix)]
+#[test]
+fn what_arrives_while_in_the_shell_is_shown_by_tilde_v() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let shell = std::env::temp_dir().join(format!("scip-shell-{}.sh", std::process::id()));
+    std::fs::write(&shell, "#!/bin/sh\nsleep 0.5\n").unwrap();
+    std::fs::set_permissions(&shell, std::fs::Permissions::from_mode(0o755)).unwrap();
+    std::env::set_var("SHELL", &shell);
+    let (session, remote) = session(&[]);
+    let input = session.events();
+    let sink = MemorySink::default();
+    let shown = sink.0.clone();
+    let driver = thread::spawn(move || {
+        input.send(Event::Stdin(b"\r~z".to_vec())).unwrap();
+        remote.tx.send(b"while away\r\n".to_vec()).unwrap();
+        assert!(wait_for(&shown, b"12 bytes received while in the shell"));
+        let away = b"while away";
+        assert!(!shown.lock().unwrap().windows(away.len()).any(|w| w == away));
+        input.send(Event::Stdin(b"\r~v".to_vec())).unwrap();
+        assert!(wait_for(&shown, b"while away\r\n"));
+        input.send(Event::Stdin(b"\r~.".to_vec())).unwrap();
+    });
+    let status = session.run(|_| Ok(Box::new(sink) as Box<dyn OutputSink>));
+    driver.join().unwrap();
+    std::fs::remove_file(&shell).unwrap();
+    assert_eq!(status, 0);
+}
+
+// 10 MB of numbered lines, without a ~ to start an escape command
+fn bulk_data() -> Vec<u8> {
+    let mut data = Vec::with_capacity(10 << 20);
+    let mut line = 0;
+    while data.len() < 10 << 20 {
+        data.extend_from_slice(format!("{:010} the quick brown fox\r\n", line).as_bytes());
+        line += 1;
+    }
+    data.truncate(10 << 20);
+    data
+}
+
+#[test]
+fn ten_megabytes_each_way_arrive_without_loss() {
+    let data = bulk_data();
+    let (session, remote) = session(&["--read-buffer", "16384"]);
+    let input = session.events();
+    let sink = MemorySink::default();
+    let shown = sink.0.clone();
+    let (sent, on_screen) = (data.clone(), shown.clone());
+    let driver = thread::spawn(move || {
+        for chunk in sent.chunks(16384) {
+            remote.tx.send(chunk.to_vec()).unwrap();
+            input.send(Event::Stdin(chunk.to_vec())).unwrap();
+        }
+        // only the end, a search through megabytes would hold up the session
+        let until = Instant::now() + Duration::from_secs(60);
+        while (remote.sent.lock().unwrap().len() < sent.len()
+            || !on_screen
+                .lock()
+                .unwrap()
+                .ends_with(&sent[sent.len() - 32..]))
+            && Instant::now() < until
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        input.send(Event::Stdin(b"\r~.".to_vec())).unwrap();
+        remote
+    });
+    let status = session.run(|_| Ok(Box::new(sink) as Box<dyn OutputSink>));
+    let remote = driver.join().unwrap();
+    assert_eq!(status, 0);
+    let sent = remote.sent.lock().unwrap();
+    assert!(sent[..data.len()] == data[..], "the typed bytes differ");
+    assert_eq!(&sent[data.len()..], b"\r");
+    let shown = shown.lock().unwrap();
+    // after the banner
+    let start = shown
+        .windows(32)
+        .position(|window| window == &data[..32])
+        .unwrap();
+    assert!(
+        shown[start..start + data.len()] == data[..],
+        "the received bytes differ"
+    );
+}